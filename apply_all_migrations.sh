@@ -15,4 +15,5 @@ fi
 ./resource-server/db/apply_migrations.sh
 ./ingestion/db/apply_migrations.sh
 ./audit/db/apply_migrations.sh
+./webhooks/db/apply_migrations.sh
 
@@ -0,0 +1 @@
+ALTER TABLE audit_event DROP COLUMN schema_version;
\ No newline at end of file
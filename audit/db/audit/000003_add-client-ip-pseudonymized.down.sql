@@ -0,0 +1 @@
+ALTER TABLE audit_event DROP COLUMN client_ip_pseudonymized;
\ No newline at end of file
@@ -0,0 +1 @@
+ALTER TABLE audit_event ADD COLUMN client_ip_pseudonymized BOOLEAN NOT NULL DEFAULT false;
\ No newline at end of file
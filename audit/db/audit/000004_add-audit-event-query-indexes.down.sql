@@ -0,0 +1,6 @@
+DROP INDEX audit_event_user_id_idx;
+DROP INDEX audit_event_event_action_idx;
+DROP INDEX audit_event_action_target_idx;
+DROP INDEX audit_event_client_ip_idx;
+DROP INDEX audit_event_event_timestamp_idx;
+DROP INDEX audit_event_additional_info_idx;
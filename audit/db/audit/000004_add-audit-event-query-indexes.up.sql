@@ -0,0 +1,7 @@
+-- the query API filters by these and pages by id, newest first
+CREATE INDEX audit_event_user_id_idx ON audit_event (user_id, id DESC);
+CREATE INDEX audit_event_event_action_idx ON audit_event (event_action, id DESC);
+CREATE INDEX audit_event_action_target_idx ON audit_event (action_target, id DESC);
+CREATE INDEX audit_event_client_ip_idx ON audit_event (client_ip, id DESC);
+CREATE INDEX audit_event_event_timestamp_idx ON audit_event (event_timestamp);
+CREATE INDEX audit_event_additional_info_idx ON audit_event USING GIN (additional_info jsonb_path_ops);
@@ -0,0 +1,2 @@
+ALTER TABLE audit_event DROP COLUMN row_hash;
+ALTER TABLE audit_event DROP COLUMN prev_hash;
@@ -0,0 +1,3 @@
+-- events inserted before the chain was introduced have no hashes, and are not covered by it
+ALTER TABLE audit_event ADD COLUMN prev_hash VARCHAR(64) NULL;
+ALTER TABLE audit_event ADD COLUMN row_hash VARCHAR(64) NULL;
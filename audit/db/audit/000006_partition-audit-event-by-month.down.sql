@@ -0,0 +1,45 @@
+ALTER TABLE audit_event RENAME TO audit_event_partitioned;
+ALTER TABLE audit_event_partitioned RENAME CONSTRAINT audit_event_pkey TO audit_event_partitioned_pkey;
+
+DROP INDEX audit_event_user_id_idx;
+DROP INDEX audit_event_event_action_idx;
+DROP INDEX audit_event_action_target_idx;
+DROP INDEX audit_event_client_ip_idx;
+DROP INDEX audit_event_event_timestamp_idx;
+DROP INDEX audit_event_additional_info_idx;
+
+CREATE TABLE audit_event (
+    id INTEGER PRIMARY KEY DEFAULT nextval('audit_event_id_seq'),
+    user_id uuid NULL,
+    client_ip VARCHAR(255) NOT NULL,
+    event_action VARCHAR(255) NOT NULL,
+    action_target uuid NULL,
+    additional_info jsonb,
+    event_timestamp TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    schema_version INTEGER NOT NULL DEFAULT 1,
+    client_ip_pseudonymized BOOLEAN NOT NULL DEFAULT false,
+    prev_hash VARCHAR(64) NULL,
+    row_hash VARCHAR(64) NULL
+);
+
+ALTER SEQUENCE audit_event_id_seq OWNED BY audit_event.id;
+
+INSERT INTO audit_event (
+    id, user_id, client_ip, event_action, action_target, additional_info, event_timestamp, created_at, updated_at,
+    schema_version, client_ip_pseudonymized, prev_hash, row_hash
+)
+SELECT
+    id, user_id, client_ip, event_action, action_target, additional_info, event_timestamp, created_at, updated_at,
+    schema_version, client_ip_pseudonymized, prev_hash, row_hash
+FROM audit_event_partitioned;
+
+DROP TABLE audit_event_partitioned;
+
+CREATE INDEX audit_event_user_id_idx ON audit_event (user_id, id DESC);
+CREATE INDEX audit_event_event_action_idx ON audit_event (event_action, id DESC);
+CREATE INDEX audit_event_action_target_idx ON audit_event (action_target, id DESC);
+CREATE INDEX audit_event_client_ip_idx ON audit_event (client_ip, id DESC);
+CREATE INDEX audit_event_event_timestamp_idx ON audit_event (event_timestamp);
+CREATE INDEX audit_event_additional_info_idx ON audit_event USING GIN (additional_info jsonb_path_ops);
@@ -0,0 +1,11 @@
+ALTER TABLE audit_event ADD COLUMN action_target uuid NULL;
+
+-- the targets that are not UUIDs are lost
+UPDATE audit_event SET action_target = target_id::uuid
+WHERE target_id ~* '^[0-9a-f]{8}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{4}-[0-9a-f]{12}$';
+
+DROP INDEX audit_event_target_idx;
+ALTER TABLE audit_event DROP COLUMN target_kind;
+ALTER TABLE audit_event DROP COLUMN target_id;
+
+CREATE INDEX audit_event_action_target_idx ON audit_event (action_target, id DESC);
@@ -0,0 +1,17 @@
+-- the targets are no longer required to be UUIDs, e.g. the S3 multipart upload IDs are not
+ALTER TABLE audit_event ADD COLUMN target_kind VARCHAR(32) NULL;
+ALTER TABLE audit_event ADD COLUMN target_id TEXT NULL;
+
+-- the kinds of the existing targets, by the event type. Must match legacy_target_kind in the audit service.
+UPDATE audit_event SET
+    target_id = action_target::text,
+    target_kind = CASE
+        WHEN event_action IN ('file_upload', 'virus_scan', 'resource_public_status_updated') THEN 'resource'
+        WHEN event_action IN ('init_chunk_upload', 'chunk_upload', 'complete_chunk_upload') THEN 'upload'
+    END
+WHERE action_target IS NOT NULL;
+
+DROP INDEX audit_event_action_target_idx;
+ALTER TABLE audit_event DROP COLUMN action_target;
+
+CREATE INDEX audit_event_target_idx ON audit_event (target_id, id DESC);
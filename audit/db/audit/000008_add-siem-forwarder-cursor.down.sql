@@ -0,0 +1 @@
+DROP TABLE siem_forwarder_cursor;
\ No newline at end of file
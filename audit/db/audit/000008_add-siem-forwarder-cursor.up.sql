@@ -0,0 +1,5 @@
+CREATE TABLE siem_forwarder_cursor (
+    forwarder VARCHAR(255) PRIMARY KEY,
+    last_event_id INTEGER NOT NULL,
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
\ No newline at end of file
@@ -0,0 +1,9 @@
+-- the queue messages each consumer has handled, so that a redelivered audit event is not stored twice
+CREATE TABLE processed_message (
+    consumer VARCHAR(255) NOT NULL,
+    message_id VARCHAR(255) NOT NULL,
+    processed_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    PRIMARY KEY (consumer, message_id)
+);
+
+CREATE INDEX processed_message_processed_at_idx ON processed_message (processed_at);
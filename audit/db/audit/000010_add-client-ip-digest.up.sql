@@ -0,0 +1,4 @@
+-- the hash chain covers this digest instead of the client IP, so that it does not depend on the redaction key.
+-- it is an HMAC with AUDIT_CHAIN_KEY, so that the pseudonymized IPs cannot be recovered from it
+-- the events chained before it was added have none, and are verified as they were hashed
+ALTER TABLE audit_event ADD COLUMN client_ip_digest VARCHAR(64) NULL;
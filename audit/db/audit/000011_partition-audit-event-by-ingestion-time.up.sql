@@ -0,0 +1,87 @@
+-- audit_event is partitioned by month on created_at, when the audit service stored the event, instead of on
+-- event_timestamp, which comes from the client and can arrive late. The hash chain is in the id order, which
+-- follows created_at, so the retention only ever drops the oldest events of the chain.
+ALTER TABLE audit_event RENAME TO audit_event_by_event_time;
+ALTER TABLE audit_event_by_event_time RENAME CONSTRAINT audit_event_pkey TO audit_event_by_event_time_pkey;
+
+DROP INDEX audit_event_user_id_idx;
+DROP INDEX audit_event_event_action_idx;
+DROP INDEX audit_event_target_idx;
+DROP INDEX audit_event_client_ip_idx;
+DROP INDEX audit_event_event_timestamp_idx;
+DROP INDEX audit_event_additional_info_idx;
+
+-- the new partitions get the same names
+DO $$
+DECLARE
+    partition_name TEXT;
+BEGIN
+    FOR partition_name IN
+        SELECT child.relname FROM pg_inherits
+        JOIN pg_class child ON child.oid = pg_inherits.inhrelid
+        JOIN pg_class parent ON parent.oid = pg_inherits.inhparent
+        WHERE parent.relname = 'audit_event_by_event_time'
+    LOOP
+        EXECUTE format('ALTER TABLE %I RENAME TO %I', partition_name, partition_name || '_by_event_time');
+    END LOOP;
+END $$;
+
+CREATE TABLE audit_event (
+    id INTEGER NOT NULL DEFAULT nextval('audit_event_id_seq'),
+    user_id uuid NULL,
+    client_ip VARCHAR(255) NOT NULL,
+    event_action VARCHAR(255) NOT NULL,
+    additional_info jsonb,
+    event_timestamp TIMESTAMP WITH TIME ZONE NOT NULL,
+    created_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    updated_at TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now(),
+    schema_version INTEGER NOT NULL DEFAULT 1,
+    client_ip_pseudonymized BOOLEAN NOT NULL DEFAULT false,
+    prev_hash VARCHAR(64) NULL,
+    row_hash VARCHAR(64) NULL,
+    target_kind VARCHAR(32) NULL,
+    target_id TEXT NULL,
+    client_ip_digest VARCHAR(64) NULL,
+    PRIMARY KEY (id, created_at)
+) PARTITION BY RANGE (created_at);
+
+ALTER SEQUENCE audit_event_id_seq OWNED BY audit_event.id;
+
+-- catches the events outside of the created partitions, the audit service moves them to their own partitions
+CREATE TABLE audit_event_default PARTITION OF audit_event DEFAULT;
+
+CREATE INDEX audit_event_user_id_idx ON audit_event (user_id, id DESC);
+CREATE INDEX audit_event_event_action_idx ON audit_event (event_action, id DESC);
+CREATE INDEX audit_event_target_idx ON audit_event (target_id, id DESC);
+CREATE INDEX audit_event_client_ip_idx ON audit_event (client_ip, id DESC);
+CREATE INDEX audit_event_event_timestamp_idx ON audit_event (event_timestamp);
+CREATE INDEX audit_event_additional_info_idx ON audit_event USING GIN (additional_info jsonb_path_ops);
+
+-- monthly partitions (in UTC) for the existing events, and for the current and the next month
+DO $$
+DECLARE
+    month_start TIMESTAMP;
+BEGIN
+    month_start := date_trunc('month', COALESCE((SELECT min(created_at) FROM audit_event_by_event_time), now()) AT TIME ZONE 'UTC');
+
+    WHILE month_start <= date_trunc('month', now() AT TIME ZONE 'UTC') + INTERVAL '1 month' LOOP
+        EXECUTE format(
+            'CREATE TABLE %I PARTITION OF audit_event FOR VALUES FROM (%L) TO (%L)',
+            'audit_event_y' || to_char(month_start, 'YYYY') || 'm' || to_char(month_start, 'MM'),
+            month_start || '+00',
+            (month_start + INTERVAL '1 month') || '+00'
+        );
+        month_start := month_start + INTERVAL '1 month';
+    END LOOP;
+END $$;
+
+INSERT INTO audit_event (
+    id, user_id, client_ip, event_action, additional_info, event_timestamp, created_at, updated_at, schema_version,
+    client_ip_pseudonymized, prev_hash, row_hash, target_kind, target_id, client_ip_digest
+)
+SELECT
+    id, user_id, client_ip, event_action, additional_info, event_timestamp, created_at, updated_at, schema_version,
+    client_ip_pseudonymized, prev_hash, row_hash, target_kind, target_id, client_ip_digest
+FROM audit_event_by_event_time;
+
+DROP TABLE audit_event_by_event_time;
@@ -0,0 +1,144 @@
+//! Load test of the audit event consumer.
+//!
+//! Sends a number of audit events to `AUDIT_EVENT_QUEUE_URL`, and measures how fast the running audit service
+//! drains the queue. Run against the docker compose setup, e.g.
+//!
+//! ```sh
+//! AUDIT_EVENT_QUEUE_URL=http://sqs.us-east-1.localhost.localstack.cloud:4566/000000000000/audit-event-queue \
+//! AWS_ENDPOINT_URL=http://localhost:4566 AWS_REGION=us-east-1 AWS_ACCESS_KEY_ID=keyid AWS_SECRET_ACCESS_KEY=supersecretkey \
+//! cargo run --release --example load_test -- 20000 16
+//! ```
+//!
+//! The arguments are the number of events (10000 by default) and the number of concurrent senders (8 by default).
+
+use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use audit_lib::{AuditEvent, AuditEventKind, AuditTarget, LoginFailureDetails, LoginFailureReason};
+use aws_sdk_sqs::types::{QueueAttributeName, SendMessageBatchRequestEntry};
+use tokio::sync::Semaphore;
+
+
+// how often the queue depth is sampled while draining
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+// the maximum SQS allows per batch
+const SEND_BATCH_SIZE: usize = 10;
+
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().collect();
+    let event_count = args.get(1).map(|v| v.parse::<usize>().expect("Invalid event count")).unwrap_or(10000);
+    let concurrency = args.get(2).map(|v| v.parse::<usize>().expect("Invalid concurrency")).unwrap_or(8);
+
+    let queue_url = env::var("AUDIT_EVENT_QUEUE_URL").expect("AUDIT_EVENT_QUEUE_URL not set");
+    let client = aws_sdk_sqs::Client::new(&aws_config::load_from_env().await);
+
+    let initial_depth = queue_depth(&client, &queue_url).await;
+    if initial_depth > 0 {
+        println!("Queue already has {} messages, they are included in the drain time", initial_depth);
+    }
+
+    // -- sending
+    let started = Instant::now();
+    let semaphore = Arc::new(Semaphore::new(concurrency));
+    let mut tasks = vec![];
+
+    for batch_start in (0..event_count).step_by(SEND_BATCH_SIZE) {
+        let permit = semaphore.clone().acquire_owned().await.unwrap();
+        let client = client.clone();
+        let queue_url = queue_url.clone();
+        let batch_end = (batch_start + SEND_BATCH_SIZE).min(event_count);
+
+        tasks.push(tokio::spawn(async move {
+            let entries = (batch_start..batch_end)
+                .map(|i| SendMessageBatchRequestEntry::builder()
+                    .id(i.to_string())
+                    .message_body(test_event(i))
+                    .build()
+                    .unwrap())
+                .collect::<Vec<_>>();
+
+            let output = client.send_message_batch()
+                .queue_url(queue_url)
+                .set_entries(Some(entries))
+                .send()
+                .await
+                .expect("Failed to send the events");
+
+            drop(permit);
+            output.failed().len()
+        }));
+    }
+
+    let mut failed = 0;
+    for task in tasks {
+        failed += task.await.unwrap();
+    }
+
+    let send_time = started.elapsed();
+    println!(
+        "Sent {} events in {:.1} s ({:.0} events/s), {} failed",
+        event_count - failed,
+        send_time.as_secs_f64(),
+        (event_count - failed) as f64 / send_time.as_secs_f64(),
+        failed
+    );
+
+    // -- draining
+    let mut previous_depth = queue_depth(&client, &queue_url).await;
+    let mut previous_sample = Instant::now();
+
+    while previous_depth > 0 {
+        tokio::time::sleep(SAMPLE_INTERVAL).await;
+
+        let depth = queue_depth(&client, &queue_url).await;
+        let rate = previous_depth.saturating_sub(depth) as f64 / previous_sample.elapsed().as_secs_f64();
+        println!("{} events in the queue, consumed {:.0} events/s", depth, rate);
+
+        previous_depth = depth;
+        previous_sample = Instant::now();
+    }
+
+    let total_time = started.elapsed();
+    println!(
+        "Queue drained in {:.1} s after the start, {:.0} events/s overall",
+        total_time.as_secs_f64(),
+        (initial_depth + event_count - failed) as f64 / total_time.as_secs_f64()
+    );
+}
+
+// a failed login from one of 65,536 addresses, so that the alert rules get something to do as well
+fn test_event(i: usize) -> String {
+    let client_ip = format!("10.0.{}.{}", i / 256 % 256, i % 256);
+    let event = AuditEvent::new(
+        AuditEventKind::LoginFailure(LoginFailureDetails {
+            username: format!("load-test-{}", i),
+            reason: LoginFailureReason::UserNotFound,
+        }),
+        None,
+        &client_ip,
+        Some(AuditTarget::user("00000000-0000-0000-0000-000000000000")),
+    );
+
+    serde_json::to_string(&event).unwrap()
+}
+
+// visible and in-flight messages, i.e. the ones not yet deleted by the consumer
+async fn queue_depth(client: &aws_sdk_sqs::Client, queue_url: &str) -> usize {
+    let output = client.get_queue_attributes()
+        .queue_url(queue_url)
+        .attribute_names(QueueAttributeName::ApproximateNumberOfMessages)
+        .attribute_names(QueueAttributeName::ApproximateNumberOfMessagesNotVisible)
+        .send()
+        .await
+        .expect("Failed to get the queue attributes");
+
+    let attributes = output.attributes().cloned().unwrap_or_default();
+    [QueueAttributeName::ApproximateNumberOfMessages, QueueAttributeName::ApproximateNumberOfMessagesNotVisible]
+        .iter()
+        .filter_map(|name| attributes.get(name).and_then(|v| v.parse::<usize>().ok()))
+        .sum()
+}
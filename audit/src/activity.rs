@@ -0,0 +1,310 @@
+use axum::{
+    extract::{Extension, Json, Query},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+
+use audit_lib::{AuditEventKind, PasswordChangeFailureReason, TargetKind, UploadError};
+use auth_check::UserInfo;
+
+use crate::db::{self, AuditEventFilter, AuditEventRecord};
+use crate::models::PageQuery;
+
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+// the events the users can see about themselves. The rest are either internal, or not attributed to the user
+const USER_ACTIVITY_ACTIONS: [&str; 7] = [
+    "login_success",
+    "password_change_success",
+    "password_change_failed",
+    "file_upload",
+    "complete_chunk_upload",
+    "resource_public_status_updated",
+    "resource_deleted",
+];
+
+
+/// An audit event as shown to the user it is about. Only the description is derived from the event details,
+/// the details themselves are never returned.
+#[derive(Debug, serde::Serialize)]
+pub struct ActivityItem {
+    pub id: i32,
+    pub timestamp: chrono::DateTime<chrono::Utc>,
+    pub action: String,
+    pub description: String,
+    pub target_kind: Option<String>,
+    pub target_id: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct ActivityPage {
+    pub items: Vec<ActivityItem>,
+    pub next_cursor: Option<i32>,
+}
+
+
+/// Lists the caller's own activity, newest first.
+///
+/// Paginated like the admin event list, pass `next_cursor` as the `cursor` query parameter to get the next page.
+pub async fn list_my_activity(user_info: Extension<UserInfo>, Query(page): Query<PageQuery>) -> Response {
+    let user_id = match uuid::Uuid::parse_str(&user_info.user_id) {
+        Ok(user_id) => user_id,
+        Err(_) => {
+            tracing::warn!("Invalid user ID {} in the activity request", user_info.user_id);
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+
+    let filter = AuditEventFilter {
+        user_id: Some(user_id),
+        event_actions: Some(USER_ACTIVITY_ACTIONS.iter().map(|action| action.to_string()).collect()),
+        ..Default::default()
+    };
+
+    let limit = page.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    match db::query_audit_events(&filter, page.cursor, limit) {
+        Ok(events) => {
+            let next_cursor = if events.len() as i64 == limit {
+                events.last().map(|event| event.id)
+            } else {
+                None
+            };
+
+            let items = events.into_iter().map(activity_item).collect();
+
+            (StatusCode::OK, Json(ActivityPage { items, next_cursor })).into_response()
+        }
+        Err(err) => {
+            tracing::error!("Error querying the activity of user {}: {}", user_id, err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+
+fn activity_item(event: AuditEventRecord) -> ActivityItem {
+    // the upload targets are multipart upload ids of the object storage, which mean nothing to the user. The
+    // chunked uploads were recorded with them before they were recorded with the resource
+    let (target_kind, target_id) = match event.target_kind.as_deref() {
+        Some(kind) if kind == TargetKind::Upload.as_str() => (None, None),
+        _ => (event.target_kind.clone(), event.target_id.clone()),
+    };
+
+    ActivityItem {
+        description: describe(&event),
+        id: event.id,
+        timestamp: event.event_timestamp,
+        action: event.event_action,
+        target_kind,
+        target_id,
+    }
+}
+
+fn describe(event: &AuditEventRecord) -> String {
+    let mut kind = serde_json::Map::new();
+    kind.insert("event_type".to_string(), serde_json::Value::String(event.event_action.clone()));
+    if let Some(details) = &event.additional_info {
+        kind.insert("event_details".to_string(), details.clone());
+    }
+
+    // the details may not parse, if they are from before the typed events, or a field has been redacted
+    match serde_json::from_value::<AuditEventKind>(serde_json::Value::Object(kind)) {
+        Ok(kind) => describe_kind(&kind).unwrap_or_else(|| generic_description(&event.event_action)),
+        Err(_) => generic_description(&event.event_action),
+    }
+}
+
+fn describe_kind(kind: &AuditEventKind) -> Option<String> {
+    let description = match kind {
+        AuditEventKind::LoginSuccess => "Signed in".to_string(),
+        AuditEventKind::PasswordChangeSuccess => "Changed the password".to_string(),
+        AuditEventKind::PasswordChangeFailed(details) => {
+            let reason = match details.reason {
+                PasswordChangeFailureReason::InvalidCurrentPassword => "the current password was wrong",
+                PasswordChangeFailureReason::WeakPassword => "the new password was too weak",
+                PasswordChangeFailureReason::SamePassword => "the new password was the same as the current one",
+                PasswordChangeFailureReason::PasswordMismatch => "the new password and the confirmation did not match",
+                PasswordChangeFailureReason::EmptyFields => "some of the fields were empty",
+                PasswordChangeFailureReason::JwtVerificationFailed
+                | PasswordChangeFailureReason::UserNotFound => "of an internal error",
+            };
+            format!("Password change failed, because {}", reason)
+        }
+        AuditEventKind::FileUpload(details) => describe_upload(Some(&details.file_name), details.error.as_ref()),
+        AuditEventKind::CompleteChunkUpload(details) => describe_upload(details.file_name.as_deref(), details.error.as_ref()),
+        AuditEventKind::ResourcePublicStatusUpdated(details) => {
+            if details.is_public {
+                "Made a video public".to_string()
+            } else {
+                "Made a video private".to_string()
+            }
+        }
+        AuditEventKind::ResourceDeleted => "Deleted a video".to_string(),
+        _ => return None,
+    };
+
+    Some(description)
+}
+
+fn describe_upload(file_name: Option<&str>, error: Option<&UploadError>) -> String {
+    let file = file_name.map(|name| format!("'{}'", name)).unwrap_or_else(|| "a file".to_string());
+
+    match error {
+        None => format!("Uploaded {}", file),
+        Some(UploadError::QuotaExceeded) => format!("Upload of {} was rejected, because the storage quota was exceeded", file),
+    }
+}
+
+fn generic_description(event_action: &str) -> String {
+    match event_action {
+        "login_success" => "Signed in",
+        "password_change_success" => "Changed the password",
+        "password_change_failed" => "Password change failed",
+        "file_upload" | "complete_chunk_upload" => "Uploaded a file",
+        "resource_public_status_updated" => "Changed the visibility of a video",
+        "resource_deleted" => "Deleted a video",
+        _ => "Other activity",
+    }.to_string()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use audit_lib::{
+        CompleteChunkUploadDetails, FileUploadDetails, PasswordChangeFailedDetails, ResourcePublicStatusUpdatedDetails,
+    };
+    use chrono::DateTime;
+
+    use super::*;
+
+    fn event(kind: &AuditEventKind) -> AuditEventRecord {
+        let mut value = serde_json::to_value(kind).unwrap();
+
+        AuditEventRecord {
+            id: 7,
+            event_timestamp: DateTime::UNIX_EPOCH,
+            event_action: kind.event_type().to_string(),
+            user_id: Some(uuid::Uuid::nil()),
+            client_ip: "192.0.2.1".to_string(),
+            target_kind: Some("resource".to_string()),
+            target_id: Some("5f0c6e0e-8f8e-4a49-9d5a-1c2a0e6b9f3d".to_string()),
+            schema_version: 1,
+            additional_info: value.get_mut("event_details").map(serde_json::Value::take),
+        }
+    }
+
+    fn upload(error: Option<UploadError>) -> AuditEventKind {
+        AuditEventKind::FileUpload(FileUploadDetails { file_name: "holiday.mp4".to_string(), file_size: 73519, error })
+    }
+
+    #[test]
+    fn shows_only_the_user_facing_actions() {
+        let actions = [
+            ("login_success", true),
+            ("login_failure", false),
+            ("token_verification_failure", false),
+            ("password_change_success", true),
+            ("password_change_failed", true),
+            ("file_upload", true),
+            ("init_chunk_upload", false),
+            ("chunk_upload", false),
+            ("complete_chunk_upload", true),
+            ("resource_public_status_updated", true),
+            ("resource_reprocessed", false),
+            ("resource_deleted", true),
+            ("virus_scan", false),
+        ];
+
+        for (action, shown) in actions {
+            assert_eq!(USER_ACTIVITY_ACTIONS.contains(&action), shown, "{}", action);
+        }
+        assert_eq!(USER_ACTIVITY_ACTIONS.len(), actions.iter().filter(|(_, shown)| *shown).count());
+    }
+
+    #[test]
+    fn describes_each_kind() {
+        let descriptions = [
+            (AuditEventKind::LoginSuccess, "Signed in"),
+            (AuditEventKind::PasswordChangeSuccess, "Changed the password"),
+            (
+                AuditEventKind::PasswordChangeFailed(PasswordChangeFailedDetails { reason: PasswordChangeFailureReason::WeakPassword }),
+                "Password change failed, because the new password was too weak",
+            ),
+            (
+                AuditEventKind::PasswordChangeFailed(PasswordChangeFailedDetails { reason: PasswordChangeFailureReason::UserNotFound }),
+                "Password change failed, because of an internal error",
+            ),
+            (upload(None), "Uploaded 'holiday.mp4'"),
+            (
+                upload(Some(UploadError::QuotaExceeded)),
+                "Upload of 'holiday.mp4' was rejected, because the storage quota was exceeded",
+            ),
+            (
+                AuditEventKind::CompleteChunkUpload(CompleteChunkUploadDetails {
+                    file_name: Some("holiday.mp4".to_string()),
+                    file_size: Some(73519),
+                    error: None,
+                }),
+                "Uploaded 'holiday.mp4'",
+            ),
+            (
+                AuditEventKind::CompleteChunkUpload(CompleteChunkUploadDetails {
+                    file_name: None,
+                    file_size: None,
+                    error: Some(UploadError::QuotaExceeded),
+                }),
+                "Upload of a file was rejected, because the storage quota was exceeded",
+            ),
+            (AuditEventKind::ResourcePublicStatusUpdated(ResourcePublicStatusUpdatedDetails { is_public: true }), "Made a video public"),
+            (AuditEventKind::ResourcePublicStatusUpdated(ResourcePublicStatusUpdatedDetails { is_public: false }), "Made a video private"),
+            (AuditEventKind::ResourceDeleted, "Deleted a video"),
+        ];
+
+        for (kind, description) in descriptions {
+            assert_eq!(describe(&event(&kind)), description, "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn describes_unparseable_details_generically() {
+        let redacted = AuditEventRecord {
+            additional_info: Some(serde_json::json!({ "file_name": "[redacted]" })),
+            ..event(&upload(None))
+        };
+
+        assert_eq!(describe(&redacted), "Uploaded a file");
+    }
+
+    #[test]
+    fn never_returns_the_details() {
+        let item = serde_json::to_value(activity_item(event(&upload(None)))).unwrap();
+
+        let mut fields: Vec<&str> = item.as_object().unwrap().keys().map(String::as_str).collect();
+        fields.sort();
+        assert_eq!(fields, ["action", "description", "id", "target_id", "target_kind", "timestamp"]);
+        assert!(!item.to_string().contains("73519"));
+    }
+
+    #[test]
+    fn omits_the_upload_ids() {
+        let chunked = AuditEventRecord {
+            target_kind: Some("upload".to_string()),
+            target_id: Some("2~kGq1dVxYvq9mX3uWl0QeH7".to_string()),
+            ..event(&AuditEventKind::CompleteChunkUpload(CompleteChunkUploadDetails {
+                file_name: Some("holiday.mp4".to_string()),
+                file_size: Some(73519),
+                error: None,
+            }))
+        };
+
+        let item = activity_item(chunked);
+        assert_eq!((item.target_kind, item.target_id), (None, None));
+
+        let item = activity_item(event(&upload(None)));
+        assert_eq!(item.target_kind.as_deref(), Some("resource"));
+        assert_eq!(item.target_id.as_deref(), Some("5f0c6e0e-8f8e-4a49-9d5a-1c2a0e6b9f3d"));
+    }
+}
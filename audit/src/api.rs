@@ -0,0 +1,313 @@
+use std::sync::Arc;
+
+use axum::{
+    body::{Body, Bytes},
+    extract::{Extension, Json, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+use futures_util::stream;
+
+use auth_check::UserInfo;
+
+use crate::db::{self, AuditEventFilter, AuditEventRecord};
+use crate::settings::Settings;
+use crate::stats;
+use crate::models::{AuditEventFilterQuery, AuditEventPage, ExportFormat, ExportQuery, PageQuery};
+
+
+const DEFAULT_PAGE_SIZE: i64 = 100;
+const MAX_PAGE_SIZE: i64 = 1000;
+
+// the export reads the results in pages of this size, so that the whole result is never in memory at once
+const EXPORT_PAGE_SIZE: i64 = 1000;
+
+
+/// Middleware for the admin-only routes. Must be layered after `auth_middleware`, as it relies on the `UserInfo`.
+///
+/// The admins are listed in `ADMIN_USER_IDS`, a comma separated list of user ids.
+pub async fn admin_middleware(
+    State(settings): State<Arc<Settings>>,
+    user_info: Extension<UserInfo>,
+    req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    if settings.is_admin(&user_info.user_id) {
+        Ok(next.run(req).await)
+    } else {
+        tracing::warn!("User {} tried to access the audit API without admin rights", user_info.user_id);
+        Err(StatusCode::FORBIDDEN)
+    }
+}
+
+
+/// Lists the audit events matching the query, newest first.
+///
+/// The response contains `next_cursor` if there may be more events. Pass it as the `cursor` query parameter
+/// to get the next page.
+pub async fn list_audit_events(Query(filter): Query<AuditEventFilterQuery>, Query(page): Query<PageQuery>) -> Response {
+    let filter = match filter.to_filter() {
+        Ok(filter) => filter,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+
+    let limit = page.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    match db::query_audit_events(&filter, page.cursor, limit) {
+        Ok(events) => {
+            let next_cursor = if events.len() as i64 == limit {
+                events.last().map(|event| event.id)
+            } else {
+                None
+            };
+
+            (StatusCode::OK, Json(AuditEventPage { events, next_cursor })).into_response()
+        }
+        Err(err) => {
+            tracing::error!("Error querying audit events: {}", err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+
+/// Exports all the audit events matching the query as CSV or JSONL, newest first.
+///
+/// The result is streamed page by page.
+pub async fn export_audit_events(Query(filter): Query<AuditEventFilterQuery>, Query(export): Query<ExportQuery>) -> Response {
+    let filter = match filter.to_filter() {
+        Ok(filter) => filter,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+
+    let format = export.format;
+
+    let (content_type, file_name) = match format {
+        ExportFormat::Csv => ("text/csv", "audit-events.csv"),
+        ExportFormat::Jsonl => ("application/x-ndjson", "audit-events.jsonl"),
+    };
+
+    // the CSV header goes out first, then the pages until there are no more events
+    let header_chunk = match format {
+        ExportFormat::Csv => Some(Bytes::from(format!("{}\n", CSV_HEADER))),
+        ExportFormat::Jsonl => None,
+    };
+
+    let pages = stream::unfold(Some(None), move |cursor: Option<Option<i32>>| {
+        let filter = filter.clone();
+        async move {
+            let cursor = cursor?;
+
+            match db::query_audit_events(&filter, cursor, EXPORT_PAGE_SIZE) {
+                Ok(events) if events.is_empty() => None,
+                Ok(events) => {
+                    let next = if events.len() as i64 == EXPORT_PAGE_SIZE {
+                        events.last().map(|event| Some(event.id))
+                    } else {
+                        None
+                    };
+                    Some((Ok(Bytes::from(render_events(&events, format))), next))
+                }
+                Err(err) => {
+                    tracing::error!("Error exporting audit events: {}", err);
+                    Some((Err(std::io::Error::other(err)), None))
+                }
+            }
+        }
+    });
+
+    let body = futures_util::StreamExt::chain(
+        stream::iter(header_chunk.map(Ok::<_, std::io::Error>)),
+        pages,
+    );
+
+    (
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", file_name)),
+        ],
+        Body::from_stream(body),
+    ).into_response()
+}
+
+
+/// Counters of the received, rejected and partially parsed events since the service started.
+pub async fn ingest_stats() -> Json<stats::IngestStats> {
+    Json(stats::ingest_stats())
+}
+
+
+const CSV_HEADER: &str = "id,event_timestamp,event_action,user_id,client_ip,target_kind,target_id,schema_version,additional_info";
+
+fn render_events(events: &[AuditEventRecord], format: ExportFormat) -> String {
+    let mut out = String::new();
+
+    for event in events {
+        match format {
+            ExportFormat::Jsonl => {
+                out.push_str(&serde_json::to_string(event).expect("Failed to serialize audit event"));
+            }
+            ExportFormat::Csv => {
+                let fields = [
+                    event.id.to_string(),
+                    event.event_timestamp.to_rfc3339(),
+                    event.event_action.clone(),
+                    event.user_id.map(|id| id.to_string()).unwrap_or_default(),
+                    event.client_ip.clone(),
+                    event.target_kind.clone().unwrap_or_default(),
+                    event.target_id.clone().unwrap_or_default(),
+                    event.schema_version.to_string(),
+                    event.additional_info.as_ref().map(|info| info.to_string()).unwrap_or_default(),
+                ];
+                let fields: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+                out.push_str(&fields.join(","));
+            }
+        }
+        out.push('\n');
+    }
+
+    out
+}
+
+// quotes the field if needed, doubling any quotes inside it
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+impl AuditEventFilterQuery {
+    fn to_filter(&self) -> Result<AuditEventFilter, String> {
+        let parse_uuid = |name: &str, value: &Option<String>| {
+            value
+                .as_deref()
+                .map(|value| uuid::Uuid::parse_str(value).map_err(|_| format!("Invalid {}", name)))
+                .transpose()
+        };
+
+        let additional_info = self.details
+            .as_deref()
+            .map(|details| serde_json::from_str::<serde_json::Value>(details).map_err(|err| format!("Invalid details: {}", err)))
+            .transpose()?;
+
+        Ok(AuditEventFilter {
+            user_id: parse_uuid("user_id", &self.user_id)?,
+            event_action: self.event_action.clone(),
+            event_actions: None,
+            target_kind: self.target_kind.clone(),
+            target_id: self.target.clone(),
+            client_ip: self.client_ip.clone(),
+            from: self.from,
+            to: self.to,
+            additional_info,
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use axum::{middleware::from_fn_with_state, routing::get, Router};
+    use chrono::DateTime;
+    use serde_json::json;
+    use tower::ServiceExt;
+
+    use super::*;
+
+    fn settings(admin_user_ids: &str) -> Arc<Settings> {
+        let variables = [
+            ("DATABASE_URL", "postgres://localhost/audit_db"),
+            ("ADMIN_USER_IDS", admin_user_ids),
+            ("AUTH_SERVICE_URL", "http://auth:3000"),
+            ("SERVICE_NAME", "audit"),
+            ("SERVICE_SIGNING_KEY", r#"{"kty":"OKP","crv":"Ed25519","d":"EOS9d0bU6Vn6LEx2fMsTTgCGpkkfKluvPCRu4zHxsrA","x":"2gVqnU5rNCqeFlJfuQFi12TBQTnrHZ57MqLlJZgrhdM"}"#),
+            ("AUDIT_CHAIN_KEY", "chain key"),
+            ("AUDIT_EVENT_QUEUE_URL", "http://localstack:4566/000000000000/audit-events"),
+        ];
+        let source = config::Source::from_variables(variables.map(|(name, value)| (name.to_string(), value.to_string()))).unwrap();
+
+        Arc::new(config::load_from(source, Settings::from_source).unwrap())
+    }
+
+    async fn request_as(user_id: &str, settings: Arc<Settings>) -> StatusCode {
+        let user_info = UserInfo {
+            user_id: user_id.to_string(),
+            display_name: "Test User".to_string(),
+            email: "test@example.com".to_string(),
+        };
+        let app = Router::new()
+            .route("/audit/events", get(|| async { "ok" }))
+            .layer(from_fn_with_state(settings, admin_middleware))
+            .layer(Extension(user_info));
+
+        let request = Request::builder().uri("/audit/events").body(Body::empty()).unwrap();
+        app.oneshot(request).await.unwrap().status()
+    }
+
+    fn event(target_id: &str, additional_info: Option<serde_json::Value>) -> AuditEventRecord {
+        AuditEventRecord {
+            id: 7,
+            event_timestamp: DateTime::UNIX_EPOCH,
+            event_action: "file_upload".to_string(),
+            user_id: None,
+            client_ip: "192.0.2.1".to_string(),
+            target_kind: Some("resource".to_string()),
+            target_id: Some(target_id.to_string()),
+            schema_version: 1,
+            additional_info,
+        }
+    }
+
+    #[tokio::test]
+    async fn lets_the_admins_through() {
+        assert_eq!(request_as("admin-2", settings("admin-1,admin-2")).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn forbids_the_other_users() {
+        assert_eq!(request_as("user-1", settings("admin-1,admin-2")).await, StatusCode::FORBIDDEN);
+        assert_eq!(request_as("admin", settings("admin-1")).await, StatusCode::FORBIDDEN);
+        assert_eq!(request_as("", settings("")).await, StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn leaves_plain_csv_fields_as_is() {
+        assert_eq!(csv_field("file_upload"), "file_upload");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn quotes_csv_fields_with_commas_quotes_and_newlines() {
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("line\nbreak"), "\"line\nbreak\"");
+        assert_eq!(csv_field("line\r\nbreak"), "\"line\r\nbreak\"");
+    }
+
+    #[test]
+    fn renders_csv_rows() {
+        let events = [
+            event("videos/a.mp4", None),
+            event("videos/b,c.mp4", Some(json!({ "name": "b" }))),
+        ];
+
+        assert_eq!(
+            render_events(&events, ExportFormat::Csv),
+            "7,1970-01-01T00:00:00+00:00,file_upload,,192.0.2.1,resource,videos/a.mp4,1,\n\
+             7,1970-01-01T00:00:00+00:00,file_upload,,192.0.2.1,resource,\"videos/b,c.mp4\",1,\"{\"\"name\"\":\"\"b\"\"}\"\n"
+        );
+    }
+
+    #[test]
+    fn renders_jsonl_rows() {
+        let rendered = render_events(&[event("videos/a\nb.mp4", None)], ExportFormat::Jsonl);
+
+        assert_eq!(rendered.lines().count(), 1);
+        let row: serde_json::Value = serde_json::from_str(rendered.trim_end()).unwrap();
+        assert_eq!(row["target_id"], "videos/a\nb.mp4");
+    }
+}
@@ -0,0 +1,744 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::SecondsFormat;
+use config::Secret;
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::db;
+use crate::redaction::RedactionPolicy;
+use crate::settings::{CheckpointDestination, Settings};
+
+
+/// Previous hash of the first event in the chain
+pub const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+// how many events are read at a time when verifying the chain
+const VERIFY_PAGE_SIZE: i64 = 1000;
+
+// the lock of the checkpoint writer, see `db::try_lock_job`
+const CHECKPOINT_JOB: &str = "chain-checkpoints";
+
+
+/// The fields of an audit event that are covered by the hash chain.
+pub struct ChainedEvent<'a> {
+    pub event_timestamp: chrono::DateTime<chrono::Utc>,
+    pub event_action: &'a str,
+    pub user_id: Option<Uuid>,
+    pub client_ip: &'a str,
+    pub client_ip_pseudonymized: bool,
+    /// `ChainHasher::client_ip_digest` of the original client IP, None for the events chained before it was stored
+    pub client_ip_digest: Option<&'a str>,
+    pub target_kind: Option<&'a str>,
+    pub target_id: Option<&'a str>,
+    pub additional_info: Option<&'a Value>,
+    pub schema_version: i32,
+}
+
+// canonical form of the event that is hashed. The fields are serialized in the declaration order, and
+// serde_json sorts the keys of the details, so the same event always produces the same JSON.
+#[derive(serde::Serialize)]
+struct CanonicalEvent<'a> {
+    prev_hash: &'a str,
+    event_timestamp: String,
+    event_action: &'a str,
+    user_id: Option<Uuid>,
+    client_ip: String,
+    // kept the original name, so that the older events still hash the same
+    action_target: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    target_kind: Option<&'a str>,
+    additional_info: Option<&'a Value>,
+    schema_version: i32,
+}
+
+/// Computes the hashes of the audit event chain.
+pub struct ChainHasher {
+    redaction_policy: Arc<RedactionPolicy>,
+    chain_key: Secret<String>,
+}
+
+impl ChainHasher {
+    /// # Arguments
+    /// * `chain_key` - Keys the client IP digests, see `client_ip_digest`
+    pub fn new(redaction_policy: Arc<RedactionPolicy>, chain_key: &Secret<String>) -> Self {
+        ChainHasher { redaction_policy, chain_key: chain_key.clone() }
+    }
+
+    /// The digest of the client IP that the chain covers, stored with the event.
+    ///
+    /// It is kept when the client IP is pseudonymized, so it is keyed: there are few enough IPv4 addresses to
+    /// recover the IP from an unkeyed hash by trying them all.
+    pub fn client_ip_digest(&self, client_ip: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.chain_key.expose().as_bytes())
+            .expect("HMAC accepts keys of any size");
+        mac.update(client_ip.as_bytes());
+        to_hex(&mac.finalize().into_bytes())
+    }
+
+    /// Computes the hash of the event, chained to the hash of the previous event.
+    pub fn row_hash(&self, event: &ChainedEvent, prev_hash: &str) -> String {
+        let canonical = CanonicalEvent {
+            prev_hash,
+            event_timestamp: event.event_timestamp.to_rfc3339_opts(SecondsFormat::Micros, true),
+            event_action: event.event_action,
+            user_id: event.user_id,
+            client_ip: self.client_ip_for_hash(event),
+            action_target: event.target_id,
+            // the events before schema version 3 had no target kind when they were hashed
+            target_kind: if event.schema_version >= 3 { event.target_kind } else { None },
+            additional_info: event.additional_info,
+            schema_version: event.schema_version,
+        };
+
+        let json = serde_json::to_string(&canonical).expect("Failed to serialize canonical audit event");
+        to_hex(&Sha256::digest(json.as_bytes()))
+    }
+
+    // The client IPs are pseudonymized after a while, which would break the chain if we hashed the IP as is.
+    // So the chain covers the digest of the IP, which is kept when the IP is pseudonymized, and does not depend
+    // on the redaction key. While the IP is not pseudonymized, the digest is computed from it, so that changing
+    // the IP breaks the chain.
+    fn client_ip_for_hash(&self, event: &ChainedEvent) -> String {
+        match event.client_ip_digest {
+            Some(digest) if event.client_ip_pseudonymized => digest.to_string(),
+            Some(_) => self.client_ip_digest(event.client_ip),
+            // chained before the digest was stored, when the chain covered the pseudonym if the key was set
+            None if event.client_ip_pseudonymized || !self.redaction_policy.can_pseudonymize() => {
+                event.client_ip.to_string()
+            }
+            None => self.redaction_policy.pseudonymize_ip(event.client_ip),
+        }
+    }
+}
+
+
+/// The last event of an archived part of the chain, the stored chain continues from it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChainAnchor {
+    pub event_id: i32,
+    pub row_hash: String,
+}
+
+/// Walks the whole chain, and reports the first event where it is broken.
+///
+/// The chain starts from the genesis hash, or from the last anchor before the first stored event, when the
+/// older events have been archived by the retention. A chain recomputed after a change, or with its latest
+/// events removed, is intact by itself, so it is also checked against the latest signed checkpoint, if given.
+///
+/// # Returns
+/// * `Ok(count)` with the number of verified events, if the chain is intact
+/// * `Err` describing the first break
+pub fn verify_chain(hasher: &ChainHasher, anchors: &[ChainAnchor], checkpoint: Option<&Checkpoint>) -> Result<usize, String> {
+    verify_pages(hasher, anchors, checkpoint, |cursor| {
+        db::get_chained_events(cursor, VERIFY_PAGE_SIZE).map_err(|err| format!("Error reading audit events: {}", err))
+    })
+}
+
+// `next_page` returns the events after the cursor in the chain order, or all of them from the first one
+fn verify_pages(
+    hasher: &ChainHasher,
+    anchors: &[ChainAnchor],
+    checkpoint: Option<&Checkpoint>,
+    mut next_page: impl FnMut(Option<i32>) -> Result<Vec<db::ChainedEventRecord>, String>,
+) -> Result<usize, String> {
+    let mut prev_hash: Option<String> = None;
+    let mut first_event_id = None;
+    let mut cursor = None;
+    let mut checkpoint_found = false;
+    let mut verified = 0;
+
+    loop {
+        let events = next_page(cursor)?;
+
+        if events.is_empty() {
+            break;
+        }
+
+        first_event_id.get_or_insert(events[0].id);
+        let prev_hash = prev_hash.get_or_insert_with(|| chain_start(anchors, events[0].id));
+        verified += verify_events(hasher, &events, prev_hash)?;
+
+        if let Some(checkpoint) = checkpoint {
+            checkpoint_found |= verify_checkpointed_event(&events, checkpoint)?;
+        }
+        cursor = events.last().map(|event| event.id);
+    }
+
+    if let Some(checkpoint) = checkpoint {
+        verify_checkpoint_reached(checkpoint, first_event_id, cursor, checkpoint_found)?;
+    }
+
+    Ok(verified)
+}
+
+// the hash the first stored event must follow
+fn chain_start(anchors: &[ChainAnchor], first_event_id: i32) -> String {
+    anchors.iter()
+        .filter(|anchor| anchor.event_id < first_event_id)
+        .max_by_key(|anchor| anchor.event_id)
+        .map_or_else(|| GENESIS_HASH.to_string(), |anchor| anchor.row_hash.clone())
+}
+
+// the checkpointed event still has the hash it had when the checkpoint was signed, if it is on the page
+fn verify_checkpointed_event(events: &[db::ChainedEventRecord], checkpoint: &Checkpoint) -> Result<bool, String> {
+    match events.iter().find(|event| event.id == checkpoint.event_id) {
+        Some(event) if event.row_hash != checkpoint.row_hash => Err(format!(
+            "Chain broken at event {}: hash is {}, the checkpoint of {} has {}. The chain has been recomputed after a change.",
+            event.id, event.row_hash, checkpoint.created_at, checkpoint.row_hash
+        )),
+        Some(_) => Ok(true),
+        None => Ok(false),
+    }
+}
+
+// the chain reaches the checkpointed event, unless the event has been archived
+fn verify_checkpoint_reached(
+    checkpoint: &Checkpoint,
+    first_event_id: Option<i32>,
+    head_event_id: Option<i32>,
+    checkpoint_found: bool,
+) -> Result<(), String> {
+    if head_event_id.is_none_or(|head| head < checkpoint.event_id) {
+        return Err(format!(
+            "Chain truncated: the chain ends at event {}, before event {} of the checkpoint of {}. The latest events have been removed.",
+            head_event_id.map_or("-".to_string(), |head| head.to_string()), checkpoint.event_id, checkpoint.created_at
+        ));
+    }
+
+    if !checkpoint_found && first_event_id.is_some_and(|first| first <= checkpoint.event_id) {
+        return Err(format!(
+            "Chain broken at event {}: the event of the checkpoint of {} has been removed.",
+            checkpoint.event_id, checkpoint.created_at
+        ));
+    }
+
+    Ok(())
+}
+
+// verifies a page of events in the chain order, `prev_hash` is the hash of the last event before the page
+fn verify_events(
+    hasher: &ChainHasher,
+    events: &[db::ChainedEventRecord],
+    prev_hash: &mut String,
+) -> Result<usize, String> {
+    for event in events {
+        let expected_prev_hash = prev_hash.as_str();
+
+        if event.prev_hash != expected_prev_hash {
+            return Err(format!(
+                "Chain broken at event {}: previous hash is {}, expected {}. An event before it has been removed or modified.",
+                event.id, event.prev_hash, expected_prev_hash
+            ));
+        }
+
+        let row_hash = hasher.row_hash(&event.as_chained(), &event.prev_hash);
+        if event.row_hash != row_hash {
+            return Err(format!(
+                "Chain broken at event {}: hash is {}, expected {}. The event has been modified.",
+                event.id, event.row_hash, row_hash
+            ));
+        }
+
+        *prev_hash = event.row_hash.clone();
+    }
+
+    Ok(events.len())
+}
+
+
+/// Checkpoint of the chain head. As long as the checkpoints are stored outside of the database, rewriting
+/// the chain from an earlier event onwards can be detected, too.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct Checkpoint {
+    pub event_id: i32,
+    pub row_hash: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// HMAC-SHA256 of `<event_id>:<row_hash>:<created_at>`
+    pub signature: String,
+}
+
+/// Periodically writes a signed checkpoint of the chain head to `AUDIT_CHECKPOINT_DESTINATION`, which is
+/// `s3://<bucket>/<prefix>`, `file://<directory>` or a path to a local file. Returns right away if no destination
+/// is set.
+///
+/// The instances take turns through an advisory lock, and an instance skips the round if another one is
+/// already writing.
+pub async fn write_checkpoints_periodically(settings: Arc<Settings>) {
+    let Some(checkpoints) = &settings.checkpoint else {
+        return;
+    };
+
+    let mut interval = tokio::time::interval(Duration::from_secs(checkpoints.interval_minutes * 60));
+    let mut last_checkpoint: Option<i32> = None;
+
+    loop {
+        interval.tick().await;
+
+        // held until the checkpoint is written
+        let _lock = match db::try_lock_job(CHECKPOINT_JOB) {
+            Ok(Some(lock)) => lock,
+            Ok(None) => continue,
+            Err(err) => {
+                tracing::error!("Error locking the audit chain checkpoints: {}", err);
+                continue;
+            }
+        };
+
+        let head = match db::get_chain_head() {
+            Ok(Some(head)) => head,
+            Ok(None) => continue,
+            Err(err) => {
+                tracing::error!("Error reading the audit chain head: {}", err);
+                continue;
+            }
+        };
+
+        // nothing new since the last checkpoint
+        if last_checkpoint == Some(head.0) {
+            continue;
+        }
+
+        let checkpoint = sign_checkpoint(head.0, head.1, checkpoints.signing_key.expose());
+
+        match write_checkpoint(&checkpoints.destination, &checkpoint).await {
+            Ok(()) => {
+                tracing::info!("Wrote audit chain checkpoint at event {}", checkpoint.event_id);
+                last_checkpoint = Some(checkpoint.event_id);
+            }
+            Err(err) => tracing::error!("Error writing audit chain checkpoint: {}", err),
+        }
+    }
+}
+
+impl Checkpoint {
+    /// # Returns
+    /// * `Err` if the checkpoint was not signed with the key, or has been modified since
+    pub fn verify_signature(&self, signing_key: &str) -> Result<(), String> {
+        if self.signature != checkpoint_signature(self.event_id, &self.row_hash, &self.created_at, signing_key) {
+            return Err(format!(
+                "The checkpoint of {} at event {} has an invalid signature. It has been modified, or signed with another key.",
+                self.created_at, self.event_id
+            ));
+        }
+        Ok(())
+    }
+}
+
+fn sign_checkpoint(event_id: i32, row_hash: String, signing_key: &str) -> Checkpoint {
+    let created_at = chrono::Utc::now();
+
+    Checkpoint {
+        event_id,
+        signature: checkpoint_signature(event_id, &row_hash, &created_at, signing_key),
+        row_hash,
+        created_at,
+    }
+}
+
+fn checkpoint_signature(event_id: i32, row_hash: &str, created_at: &chrono::DateTime<chrono::Utc>, signing_key: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(signing_key.as_bytes()).expect("HMAC accepts keys of any size");
+    mac.update(format!("{}:{}:{}", event_id, row_hash, created_at.to_rfc3339_opts(SecondsFormat::Micros, true)).as_bytes());
+    to_hex(&mac.finalize().into_bytes())
+}
+
+/// Reads the checkpoint written last, the one of the latest chain head.
+///
+/// # Returns
+/// * `None` if no checkpoint has been written yet
+pub async fn latest_checkpoint(destination: &CheckpointDestination) -> Result<Option<Checkpoint>, Box<dyn std::error::Error>> {
+    let json = match destination {
+        CheckpointDestination::Location(location) => {
+            // the event ids in the keys are zero-padded, so the last key is the latest checkpoint
+            let Some(object) = location.store.list(&location.key("checkpoint-")).await?.pop() else {
+                return Ok(None);
+            };
+            String::from_utf8(location.store.get(&object.key, None).await?.bytes().await?.to_vec())?
+        }
+        CheckpointDestination::File(path) => {
+            let contents = match tokio::fs::read_to_string(path).await {
+                Ok(contents) => contents,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+                Err(err) => return Err(err.into()),
+            };
+            let Some(line) = contents.lines().rfind(|line| !line.trim().is_empty()) else {
+                return Ok(None);
+            };
+            line.to_string()
+        }
+    };
+
+    let checkpoint = serde_json::from_str(&json).map_err(|err| format!("Invalid checkpoint: {}", err))?;
+    Ok(Some(checkpoint))
+}
+
+async fn write_checkpoint(destination: &CheckpointDestination, checkpoint: &Checkpoint) -> Result<(), Box<dyn std::error::Error>> {
+    let json = serde_json::to_string(checkpoint)?;
+
+    match destination {
+        CheckpointDestination::Location(location) => {
+            let key = location.key(&format!("checkpoint-{:010}.json", checkpoint.event_id));
+            location.store.put(&key, json.into_bytes().into(), Some("application/json")).await?;
+        }
+        CheckpointDestination::File(path) => {
+            use tokio::io::AsyncWriteExt;
+
+            let mut file = tokio::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .await?;
+            file.write_all(format!("{}\n", json).as_bytes()).await?;
+            file.flush().await?;
+        }
+    }
+
+    Ok(())
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHAIN_KEY: &str = "chain key";
+
+    fn hasher(hmac_key: Option<&str>) -> ChainHasher {
+        let hmac_key = hmac_key.map(|key| Secret::new(key.to_string()));
+        ChainHasher::new(
+            Arc::new(RedactionPolicy::new(Vec::new(), hmac_key.as_ref())),
+            &Secret::new(CHAIN_KEY.to_string()),
+        )
+    }
+
+    // chains the events the way insert_audit_events does
+    fn chain(hasher: &ChainHasher, actions: &[&str]) -> Vec<db::ChainedEventRecord> {
+        let mut prev_hash = GENESIS_HASH.to_string();
+
+        actions.iter().enumerate().map(|(i, action)| {
+            let client_ip = format!("10.0.0.{}", i);
+            let mut event = db::ChainedEventRecord {
+                id: i as i32 + 1,
+                event_timestamp: chrono::Utc::now(),
+                event_action: action.to_string(),
+                user_id: Some(Uuid::new_v4()),
+                client_ip_digest: Some(hasher.client_ip_digest(&client_ip)),
+                client_ip,
+                client_ip_pseudonymized: false,
+                target_kind: Some("resource".to_string()),
+                target_id: Some(format!("resource-{}", i)),
+                additional_info: Some(serde_json::json!({ "size": i })),
+                schema_version: 3,
+                prev_hash: prev_hash.clone(),
+                row_hash: String::new(),
+            };
+            event.row_hash = hasher.row_hash(&event.as_chained(), &prev_hash);
+            prev_hash = event.row_hash.clone();
+            event
+        }).collect()
+    }
+
+    fn verify(hasher: &ChainHasher, events: &[db::ChainedEventRecord]) -> Result<usize, String> {
+        verify_events(hasher, events, &mut GENESIS_HASH.to_string())
+    }
+
+    // pages through the events two at a time, the way verify_chain pages through the database
+    fn verify_with_checkpoint(
+        hasher: &ChainHasher,
+        events: &[db::ChainedEventRecord],
+        checkpoint: &Checkpoint,
+    ) -> Result<usize, String> {
+        verify_pages(hasher, &[], Some(checkpoint), |cursor| {
+            Ok(events.iter().filter(|event| cursor.is_none_or(|id| event.id > id)).take(2).cloned().collect())
+        })
+    }
+
+    // recomputes the hashes from the given event onwards, as someone covering up a change would
+    fn rechain(hasher: &ChainHasher, events: &mut [db::ChainedEventRecord], from: usize) {
+        let mut prev_hash = if from == 0 { GENESIS_HASH.to_string() } else { events[from - 1].row_hash.clone() };
+        for event in &mut events[from..] {
+            event.prev_hash = prev_hash.clone();
+            event.row_hash = hasher.row_hash(&event.as_chained(), &prev_hash);
+            prev_hash = event.row_hash.clone();
+        }
+    }
+
+    #[test]
+    fn verifies_an_intact_chain() {
+        let hasher = hasher(None);
+        let events = chain(&hasher, &["login_success", "file_upload", "resource_deleted"]);
+
+        assert_eq!(verify(&hasher, &events), Ok(3));
+    }
+
+    #[test]
+    fn verifies_a_chain_split_into_pages() {
+        let hasher = hasher(None);
+        let events = chain(&hasher, &["login_success", "file_upload", "resource_deleted"]);
+
+        let mut prev_hash = GENESIS_HASH.to_string();
+        assert_eq!(verify_events(&hasher, &events[..2], &mut prev_hash), Ok(2));
+        assert_eq!(verify_events(&hasher, &events[2..], &mut prev_hash), Ok(1));
+    }
+
+    #[test]
+    fn reports_a_modified_event() {
+        let hasher = hasher(None);
+        let mut events = chain(&hasher, &["login_success", "file_upload", "resource_deleted"]);
+        events[1].event_action = "login_success".to_string();
+
+        let err = verify(&hasher, &events).unwrap_err();
+        assert!(err.starts_with("Chain broken at event 2: hash is"), "{}", err);
+    }
+
+    #[test]
+    fn reports_a_modified_client_ip() {
+        let hasher = hasher(None);
+        let mut events = chain(&hasher, &["login_success", "file_upload"]);
+        events[0].client_ip = "192.0.2.1".to_string();
+
+        let err = verify(&hasher, &events).unwrap_err();
+        assert!(err.starts_with("Chain broken at event 1: hash is"), "{}", err);
+    }
+
+    #[test]
+    fn reports_a_removed_event() {
+        let hasher = hasher(None);
+        let mut events = chain(&hasher, &["login_success", "file_upload", "resource_deleted"]);
+        events.remove(1);
+
+        let err = verify(&hasher, &events).unwrap_err();
+        assert!(err.starts_with("Chain broken at event 3: previous hash is"), "{}", err);
+    }
+
+    #[test]
+    fn reports_a_modified_previous_hash() {
+        let hasher = hasher(None);
+        let mut events = chain(&hasher, &["login_success", "file_upload"]);
+        events[1].prev_hash = GENESIS_HASH.to_string();
+
+        let err = verify(&hasher, &events).unwrap_err();
+        assert!(err.starts_with("Chain broken at event 2: previous hash is"), "{}", err);
+    }
+
+    #[test]
+    fn pseudonymizing_the_client_ips_keeps_the_chain_intact() {
+        let hasher = hasher(Some("key"));
+        let policy = RedactionPolicy::new(Vec::new(), Some(&Secret::new("key".to_string())));
+        let mut events = chain(&hasher, &["login_success", "file_upload"]);
+
+        for event in &mut events {
+            event.client_ip = policy.pseudonymize_ip(&event.client_ip);
+            event.client_ip_pseudonymized = true;
+        }
+
+        assert_eq!(verify(&hasher, &events), Ok(2));
+    }
+
+    #[test]
+    fn setting_or_rotating_the_redaction_key_keeps_the_chain_intact() {
+        let events = chain(&hasher(None), &["login_success", "file_upload"]);
+
+        assert_eq!(verify(&hasher(Some("key")), &events), Ok(2));
+        assert_eq!(verify(&hasher(Some("rotated key")), &events), Ok(2));
+    }
+
+    #[test]
+    fn reports_a_modified_client_ip_digest_of_a_pseudonymized_event() {
+        let hasher = hasher(Some("key"));
+        let mut events = chain(&hasher, &["login_success", "file_upload"]);
+        events[1].client_ip = "hmac:00".to_string();
+        events[1].client_ip_pseudonymized = true;
+        events[1].client_ip_digest = Some(hasher.client_ip_digest("192.0.2.1"));
+
+        let err = verify(&hasher, &events).unwrap_err();
+        assert!(err.starts_with("Chain broken at event 2: hash is"), "{}", err);
+    }
+
+    #[test]
+    fn client_ip_digests_are_keyed_with_the_chain_key() {
+        let other = ChainHasher::new(
+            Arc::new(RedactionPolicy::new(Vec::new(), None)),
+            &Secret::new("other chain key".to_string()),
+        );
+
+        assert_eq!(hasher(None).client_ip_digest("192.0.2.1"), hasher(Some("key")).client_ip_digest("192.0.2.1"));
+        assert_ne!(hasher(None).client_ip_digest("192.0.2.1"), other.client_ip_digest("192.0.2.1"));
+        assert_ne!(hasher(None).client_ip_digest("192.0.2.1"), to_hex(&Sha256::digest(b"192.0.2.1")));
+    }
+
+    #[test]
+    fn pseudonymized_client_ips_cannot_be_recovered_without_the_chain_key() {
+        let hasher = hasher(Some("key"));
+        let policy = RedactionPolicy::new(Vec::new(), Some(&Secret::new("key".to_string())));
+        let mut events = chain(&hasher, &["login_success"]);
+        let original_ip = events[0].client_ip.clone();
+        events[0].client_ip = policy.pseudonymize_ip(&original_ip);
+        events[0].client_ip_pseudonymized = true;
+
+        let stored = serde_json::to_string(&(&events[0].client_ip, &events[0].client_ip_digest)).unwrap();
+        assert!(!stored.contains(&original_ip));
+
+        // trying every address of the network, with the derivations that do not need the chain key
+        let guessed_key = ChainHasher::new(Arc::new(RedactionPolicy::new(Vec::new(), None)), &Secret::new(String::new()));
+        let digest = events[0].client_ip_digest.clone().unwrap();
+        let candidates: Vec<String> = (0..=255).map(|i| format!("10.0.0.{}", i)).collect();
+        assert!(candidates.iter().all(|ip| {
+            to_hex(&Sha256::digest(ip.as_bytes())) != digest
+                && to_hex(&Sha256::digest(format!("audit-chain-client-ip:{}", ip).as_bytes())) != digest
+                && guessed_key.client_ip_digest(ip) != digest
+        }));
+
+        // the same search finds it with the chain key, so the key has to be kept apart from the events
+        assert_eq!(candidates.iter().find(|ip| hasher.client_ip_digest(ip) == digest), Some(&original_ip));
+        assert_eq!(verify(&hasher, &events), Ok(1));
+    }
+
+    #[test]
+    fn reports_a_removed_first_event() {
+        let hasher = hasher(None);
+        let events = chain(&hasher, &["login_success", "file_upload", "resource_deleted"]);
+
+        let err = verify(&hasher, &events[1..]).unwrap_err();
+        assert!(err.starts_with("Chain broken at event 2: previous hash is"), "{}", err);
+    }
+
+    #[test]
+    fn the_chain_continues_from_the_last_archived_event() {
+        let hasher = hasher(None);
+        let events = chain(&hasher, &["login_success", "file_upload", "resource_deleted", "logout"]);
+        let anchors = vec![
+            ChainAnchor { event_id: events[0].id, row_hash: events[0].row_hash.clone() },
+            ChainAnchor { event_id: events[1].id, row_hash: events[1].row_hash.clone() },
+        ];
+
+        assert_eq!(chain_start(&anchors, events[2].id), events[1].row_hash);
+        assert_eq!(chain_start(&anchors, events[1].id), events[0].row_hash);
+        assert_eq!(chain_start(&anchors, events[0].id), GENESIS_HASH);
+
+        let mut prev_hash = chain_start(&anchors, events[2].id);
+        assert_eq!(verify_events(&hasher, &events[2..], &mut prev_hash), Ok(2));
+
+        // the first event after the archived ones removed, too
+        let mut prev_hash = chain_start(&anchors, events[3].id);
+        let err = verify_events(&hasher, &events[3..], &mut prev_hash).unwrap_err();
+        assert!(err.starts_with("Chain broken at event 4: previous hash is"), "{}", err);
+    }
+
+    #[test]
+    fn verifies_a_chain_that_has_grown_past_the_checkpoint() {
+        let hasher = hasher(None);
+        let events = chain(&hasher, &["login_success", "file_upload", "resource_deleted", "logout", "login_success"]);
+
+        for checkpointed in &events {
+            let checkpoint = sign_checkpoint(checkpointed.id, checkpointed.row_hash.clone(), "checkpoint key");
+            assert_eq!(verify_with_checkpoint(&hasher, &events, &checkpoint), Ok(5));
+        }
+    }
+
+    #[test]
+    fn reports_a_chain_recomputed_after_a_change() {
+        let hasher = hasher(None);
+        let mut events = chain(&hasher, &["login_success", "file_upload", "resource_deleted", "logout"]);
+        let checkpoint = sign_checkpoint(events[2].id, events[2].row_hash.clone(), "checkpoint key");
+
+        events[1].event_action = "login_success".to_string();
+        rechain(&hasher, &mut events, 1);
+
+        // intact by itself
+        assert_eq!(verify(&hasher, &events), Ok(4));
+
+        let err = verify_with_checkpoint(&hasher, &events, &checkpoint).unwrap_err();
+        assert!(err.starts_with("Chain broken at event 3: hash is"), "{}", err);
+        assert!(err.ends_with("The chain has been recomputed after a change."), "{}", err);
+    }
+
+    #[test]
+    fn reports_a_removed_checkpointed_event() {
+        let hasher = hasher(None);
+        let mut events = chain(&hasher, &["login_success", "file_upload", "resource_deleted", "logout"]);
+        let checkpoint = sign_checkpoint(events[2].id, events[2].row_hash.clone(), "checkpoint key");
+
+        events.remove(2);
+        rechain(&hasher, &mut events, 2);
+        assert_eq!(verify(&hasher, &events), Ok(3));
+
+        let err = verify_with_checkpoint(&hasher, &events, &checkpoint).unwrap_err();
+        assert!(err.starts_with("Chain broken at event 3: the event of the checkpoint"), "{}", err);
+    }
+
+    #[test]
+    fn reports_a_truncated_chain() {
+        let hasher = hasher(None);
+        let events = chain(&hasher, &["login_success", "file_upload", "resource_deleted", "logout"]);
+        let checkpoint = sign_checkpoint(events[3].id, events[3].row_hash.clone(), "checkpoint key");
+
+        // the removed latest events leave an intact chain behind
+        assert_eq!(verify(&hasher, &events[..2]), Ok(2));
+
+        let err = verify_with_checkpoint(&hasher, &events[..2], &checkpoint).unwrap_err();
+        assert!(err.starts_with("Chain truncated: the chain ends at event 2, before event 4"), "{}", err);
+
+        let err = verify_with_checkpoint(&hasher, &[], &checkpoint).unwrap_err();
+        assert!(err.starts_with("Chain truncated: the chain ends at event -, before event 4"), "{}", err);
+    }
+
+    #[test]
+    fn the_checkpointed_event_may_have_been_archived() {
+        let hasher = hasher(None);
+        let events = chain(&hasher, &["login_success", "file_upload", "resource_deleted", "logout"]);
+        let checkpoint = sign_checkpoint(events[0].id, events[0].row_hash.clone(), "checkpoint key");
+        let anchors = vec![ChainAnchor { event_id: events[1].id, row_hash: events[1].row_hash.clone() }];
+
+        let verified = verify_pages(&hasher, &anchors, Some(&checkpoint), |cursor| {
+            Ok(events[2..].iter().filter(|event| cursor.is_none_or(|id| event.id > id)).cloned().collect())
+        });
+        assert_eq!(verified, Ok(2));
+    }
+
+    #[test]
+    fn checks_the_signature_of_the_checkpoint() {
+        let checkpoint = sign_checkpoint(3, "hash of 3".to_string(), "checkpoint key");
+        assert_eq!(checkpoint.verify_signature("checkpoint key"), Ok(()));
+        assert!(checkpoint.verify_signature("another key").is_err());
+
+        let forged = Checkpoint { row_hash: "recomputed hash of 3".to_string(), ..checkpoint };
+        let err = forged.verify_signature("checkpoint key").unwrap_err();
+        assert!(err.ends_with("has an invalid signature. It has been modified, or signed with another key."), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn reads_the_latest_checkpoint() {
+        let root = std::env::temp_dir().join(format!("audit-checkpoint-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+
+        let file = CheckpointDestination::File(root.join("checkpoints.jsonl"));
+        let location = CheckpointDestination::Location(storage::Location {
+            store: Arc::new(storage::LocalStore::new(root.clone())),
+            prefix: "checkpoints/".to_string(),
+        });
+
+        for destination in [&file, &location] {
+            assert!(latest_checkpoint(destination).await.unwrap().is_none());
+
+            // the ids sort as numbers, not as text
+            for event_id in [9, 10] {
+                let checkpoint = sign_checkpoint(event_id, format!("hash of {}", event_id), "checkpoint key");
+                write_checkpoint(destination, &checkpoint).await.unwrap();
+            }
+
+            let latest = latest_checkpoint(destination).await.unwrap().unwrap();
+            assert_eq!((latest.event_id, latest.row_hash.as_str()), (10, "hash of 10"));
+            assert_eq!(latest.verify_signature("checkpoint key"), Ok(()));
+        }
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -1,60 +1,749 @@
 mod schema;
 
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
 use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
 use uuid::Uuid;
 use serde_json::Value;
 
 use schema::*;
 
+use crate::chain::{ChainHasher, ChainedEvent, GENESIS_HASH};
+use crate::stats;
+
+
+// arbitrary, but must be the same for all the audit service instances
+const AUDIT_CHAIN_LOCK_KEY: i64 = 0x0a0d17;
+
+// the background jobs are locked with the job name as the second key, see `try_lock_job`
+const AUDIT_JOB_LOCK_CLASS: i32 = 0x0a0d18;
+
+// the queue keeps a message for at most 14 days, so it cannot be delivered again after that
+const PROCESSED_MESSAGE_RETENTION_DAYS: i64 = 14;
+
+static POOL: OnceLock<Pool<ConnectionManager<PgConnection>>> = OnceLock::new();
+
 
 // provided by the active resources view, which filters out deleted resources
 #[derive(Debug, Insertable)]
 #[diesel(table_name = audit_event)]
 #[allow(dead_code)]
 pub struct InsertAuditEvent {
+    pub schema_version: i32,
     pub user_id: Option<Uuid>,
     pub client_ip: String,
     pub event_action: String,
-    pub action_target: Option<Uuid>,
+    pub target_kind: Option<String>,
+    pub target_id: Option<String>,
     pub additional_info: Option<Value>,
     pub event_timestamp: chrono::DateTime<chrono::Utc>,
+    /// When the event was stored, the partition key
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub prev_hash: String,
+    pub row_hash: String,
+    pub client_ip_digest: String,
 }
 
-pub fn insert_audit_event(
-    user_id: Option<&str>,
-    client_ip: &str,
-    event_action: String,
-    action_target: Option<&str>,
-    additional_info: Option<serde_json::Value>,
-    event_timestamp: chrono::DateTime<chrono::Utc>
-) -> Result<(), diesel::result::Error> {
+/// A received audit event, to be inserted with `insert_audit_events`
+#[derive(Debug)]
+pub struct NewAuditEvent<'a> {
+    /// Id of the queue message the event came in, see `worker::idempotency_key`
+    pub message_id: &'a str,
+    pub schema_version: u32,
+    pub user_id: Option<&'a str>,
+    pub client_ip: &'a str,
+    pub event_action: &'a str,
+    pub target_kind: Option<&'a str>,
+    pub target_id: Option<&'a str>,
+    pub additional_info: Option<&'a Value>,
+    pub event_timestamp: chrono::DateTime<chrono::Utc>,
+}
 
+/// Inserts the events in a single transaction, chained to the previously inserted event and to each other
+/// in the given order.
+///
+/// The inserts are serialized with a transaction-level advisory lock, so that the chain stays in the id order
+/// even when several audit service instances insert at the same time.
+///
+/// The messages of the events are recorded as handled by the consumer in the same transaction, and the events
+/// of the messages recorded already are skipped, so that a redelivered event is never chained twice.
+///
+/// # Returns
+/// * `Err` if any of the events could not be inserted, in which case none of them are
+pub fn insert_audit_events(
+    hasher: &ChainHasher,
+    consumer: &str,
+    events: &[NewAuditEvent],
+) -> Result<(), diesel::result::Error> {
+    if events.is_empty() {
+        return Ok(());
+    }
 
     let mut conn = get_connection();
 
-    let new_event = InsertAuditEvent {
-        user_id: user_id.map(|s| Uuid::parse_str(s).ok()).flatten(),
-        client_ip: client_ip.to_string(),
-        event_action,
-        action_target: action_target.map(|s| Uuid::parse_str(s).ok()).flatten(),
-        additional_info: additional_info.map(|mut v| {
+    let new_events: Vec<InsertAuditEvent> = events.iter().map(|event| {
+        let user_id = event.user_id.and_then(|s| match Uuid::parse_str(s) {
+            Ok(user_id) => Some(user_id),
+            Err(err) => {
+                stats::record_user_id_parse_error(event.event_action, s, err);
+                None
+            }
+        });
+        let additional_info = event.additional_info.map(|v| {
+            let mut v = v.clone();
             sanitize_json(&mut v);
             v
-        }),
-        event_timestamp,
-    };
+        });
+
+        InsertAuditEvent {
+            schema_version: event.schema_version as i32,
+            user_id,
+            client_ip: event.client_ip.to_string(),
+            event_action: event.event_action.to_string(),
+            target_kind: event.target_kind.map(|kind| kind.to_string()),
+            target_id: event.target_id.map(|id| id.to_string()),
+            additional_info,
+            event_timestamp: event.event_timestamp,
+            // set below, once the lock is held
+            created_at: chrono::Utc::now(),
+            prev_hash: String::new(),
+            row_hash: String::new(),
+            client_ip_digest: hasher.client_ip_digest(event.client_ip),
+        }
+    }).collect();
+
+    conn.transaction(|conn| {
+        diesel::sql_query("SELECT pg_advisory_xact_lock($1)")
+            .bind::<diesel::sql_types::BigInt, _>(AUDIT_CHAIN_LOCK_KEY)
+            .execute(conn)?;
+
+        // checked under the lock, so that a message delivered twice at the same time is not stored twice either
+        let message_ids: Vec<String> = events.iter().map(|event| event.message_id.to_string()).collect();
+        let processed: HashSet<String> = processed_message::table
+            .select(processed_message::message_id)
+            .filter(processed_message::consumer.eq(consumer))
+            .filter(processed_message::message_id.eq_any(&message_ids))
+            .load::<String>(conn)?
+            .into_iter()
+            .collect();
+
+        if !processed.is_empty() {
+            tracing::info!("Skipping {} audit events that have already been stored", processed.len());
+        }
+
+        let mut new_events: Vec<InsertAuditEvent> = new_events.into_iter()
+            .zip(events)
+            .filter(|(_, event)| !processed.contains(event.message_id))
+            .map(|(new_event, _)| new_event)
+            .collect();
+
+        let head = audit_event::table
+            .select((audit_event::row_hash, audit_event::created_at))
+            .filter(audit_event::row_hash.is_not_null())
+            .order(audit_event::id.desc())
+            .first::<(Option<String>, chrono::DateTime<chrono::Utc>)>(conn)
+            .optional()?;
+
+        // the events are partitioned by created_at, so it must not go back in the id order, or the retention
+        // would drop events from the middle of the chain. It never precedes the head's, even if the clocks of
+        // the audit service instances are apart.
+        let now = chrono::Utc::now();
+        let created_at = head.as_ref().map_or(now, |(_, head_created_at)| now.max(*head_created_at));
+        let mut prev_hash = head
+            .and_then(|(row_hash, _)| row_hash)
+            .unwrap_or_else(|| GENESIS_HASH.to_string());
+
+        for new_event in new_events.iter_mut() {
+            let row_hash = hasher.row_hash(
+                &ChainedEvent {
+                    event_timestamp: new_event.event_timestamp,
+                    event_action: &new_event.event_action,
+                    user_id: new_event.user_id,
+                    client_ip: &new_event.client_ip,
+                    client_ip_pseudonymized: false,
+                    client_ip_digest: Some(&new_event.client_ip_digest),
+                    target_kind: new_event.target_kind.as_deref(),
+                    target_id: new_event.target_id.as_deref(),
+                    additional_info: new_event.additional_info.as_ref(),
+                    schema_version: new_event.schema_version,
+                },
+                &prev_hash,
+            );
+
+            new_event.created_at = created_at;
+            new_event.prev_hash = std::mem::replace(&mut prev_hash, row_hash.clone());
+            new_event.row_hash = row_hash;
+        }
+
+        // a single multi-row insert, the ids are assigned in the order of the rows
+        if !new_events.is_empty() {
+            diesel::insert_into(audit_event::table)
+                .values(&new_events)
+                .execute(conn)?;
+        }
+
+        record_processed_messages(conn, consumer, &message_ids)
+    })
+}
+
+
+/// An audit event together with its place in the hash chain
+#[derive(Debug, Clone, Queryable)]
+pub struct ChainedEventRecord {
+    pub id: i32,
+    pub event_timestamp: chrono::DateTime<chrono::Utc>,
+    pub event_action: String,
+    pub user_id: Option<Uuid>,
+    pub client_ip: String,
+    pub client_ip_pseudonymized: bool,
+    pub client_ip_digest: Option<String>,
+    pub target_kind: Option<String>,
+    pub target_id: Option<String>,
+    pub additional_info: Option<Value>,
+    pub schema_version: i32,
+    pub prev_hash: String,
+    pub row_hash: String,
+}
+
+impl ChainedEventRecord {
+    pub fn as_chained(&self) -> ChainedEvent<'_> {
+        ChainedEvent {
+            event_timestamp: self.event_timestamp,
+            event_action: &self.event_action,
+            user_id: self.user_id,
+            client_ip: &self.client_ip,
+            client_ip_pseudonymized: self.client_ip_pseudonymized,
+            client_ip_digest: self.client_ip_digest.as_deref(),
+            target_kind: self.target_kind.as_deref(),
+            target_id: self.target_id.as_deref(),
+            additional_info: self.additional_info.as_ref(),
+            schema_version: self.schema_version,
+        }
+    }
+}
+
+/// Reads the chained events in the chain order. The events inserted before the chain was introduced are skipped.
+///
+/// # Arguments
+/// * `cursor` - If set, only events after the event with this id are returned
+/// * `limit` - The maximum number of events to return
+pub fn get_chained_events(cursor: Option<i32>, limit: i64) -> Result<Vec<ChainedEventRecord>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    let mut query = audit_event::table
+        .select((
+            audit_event::id,
+            audit_event::event_timestamp,
+            audit_event::event_action,
+            audit_event::user_id,
+            audit_event::client_ip,
+            audit_event::client_ip_pseudonymized,
+            audit_event::client_ip_digest,
+            audit_event::target_kind,
+            audit_event::target_id,
+            audit_event::additional_info,
+            audit_event::schema_version,
+            audit_event::prev_hash.assume_not_null(),
+            audit_event::row_hash.assume_not_null(),
+        ))
+        .filter(audit_event::row_hash.is_not_null())
+        .into_boxed();
+
+    if let Some(cursor) = cursor {
+        query = query.filter(audit_event::id.gt(cursor));
+    }
+
+    query
+        .order(audit_event::id.asc())
+        .limit(limit)
+        .load(&mut conn)
+}
 
-    diesel::insert_into(audit_event::table)
-        .values(&new_event)
+/// Returns the id and hash of the latest event in the chain
+pub fn get_chain_head() -> Result<Option<(i32, String)>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    audit_event::table
+        .select((audit_event::id, audit_event::row_hash.assume_not_null()))
+        .filter(audit_event::row_hash.is_not_null())
+        .order(audit_event::id.desc())
+        .first(&mut conn)
+        .optional()
+}
+
+
+#[derive(Debug, Queryable, Selectable, serde::Serialize)]
+#[diesel(table_name = audit_event)]
+pub struct AuditEventRecord {
+    pub id: i32,
+    pub event_timestamp: chrono::DateTime<chrono::Utc>,
+    pub event_action: String,
+    pub user_id: Option<Uuid>,
+    pub client_ip: String,
+    pub target_kind: Option<String>,
+    pub target_id: Option<String>,
+    pub schema_version: i32,
+    pub additional_info: Option<Value>,
+}
+
+/// Filters for querying the audit events. All the set filters must match.
+#[derive(Debug, Clone, Default)]
+pub struct AuditEventFilter {
+    pub user_id: Option<Uuid>,
+    pub event_action: Option<String>,
+    /// Any of these actions
+    pub event_actions: Option<Vec<String>>,
+    pub target_kind: Option<String>,
+    pub target_id: Option<String>,
+    pub client_ip: Option<String>,
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+    /// JSON the `additional_info` must contain, e.g. `{"reason": "User not found"}`
+    pub additional_info: Option<Value>,
+}
+
+/// Queries the audit events matching the filter, newest first.
+///
+/// # Arguments
+/// * `filter` - The filters to apply
+/// * `cursor` - If set, only events older than the event with this id are returned (the id of the last event of the previous page)
+/// * `limit` - The maximum number of events to return
+pub fn query_audit_events(
+    filter: &AuditEventFilter,
+    cursor: Option<i32>,
+    limit: i64,
+) -> Result<Vec<AuditEventRecord>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    let mut query = audit_event::table
+        .select(AuditEventRecord::as_select())
+        .into_boxed();
+
+    if let Some(user_id) = filter.user_id {
+        query = query.filter(audit_event::user_id.eq(user_id));
+    }
+    if let Some(event_action) = &filter.event_action {
+        query = query.filter(audit_event::event_action.eq(event_action.clone()));
+    }
+    if let Some(event_actions) = &filter.event_actions {
+        query = query.filter(audit_event::event_action.eq_any(event_actions.clone()));
+    }
+    if let Some(target_kind) = &filter.target_kind {
+        query = query.filter(audit_event::target_kind.eq(target_kind.clone()));
+    }
+    if let Some(target_id) = &filter.target_id {
+        query = query.filter(audit_event::target_id.eq(target_id.clone()));
+    }
+    if let Some(client_ip) = &filter.client_ip {
+        query = query.filter(audit_event::client_ip.eq(client_ip.clone()));
+    }
+    if let Some(from) = filter.from {
+        query = query.filter(audit_event::event_timestamp.ge(from));
+    }
+    if let Some(to) = filter.to {
+        query = query.filter(audit_event::event_timestamp.lt(to));
+    }
+    if let Some(additional_info) = &filter.additional_info {
+        query = query.filter(audit_event::additional_info.contains(additional_info.clone()));
+    }
+    if let Some(cursor) = cursor {
+        query = query.filter(audit_event::id.lt(cursor));
+    }
+
+    query
+        .order(audit_event::id.desc())
+        .limit(limit)
+        .load(&mut conn)
+}
+
+/// Reads the events after the event with the given id, oldest first.
+///
+/// The events are inserted in batches while holding the chain lock, so the ids are committed in order, and an
+/// event with a lower id cannot appear after a later one has been read.
+pub fn get_events_after(after_id: i32, limit: i64) -> Result<Vec<AuditEventRecord>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    audit_event::table
+        .select(AuditEventRecord::as_select())
+        .filter(audit_event::id.gt(after_id))
+        .order(audit_event::id.asc())
+        .limit(limit)
+        .load(&mut conn)
+}
+
+/// Returns the id of the last event the SIEM forwarder has delivered, if it has delivered anything
+pub fn get_forwarder_cursor(forwarder: &str) -> Result<Option<i32>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    siem_forwarder_cursor::table
+        .select(siem_forwarder_cursor::last_event_id)
+        .filter(siem_forwarder_cursor::forwarder.eq(forwarder))
+        .first(&mut conn)
+        .optional()
+}
+
+pub fn save_forwarder_cursor(forwarder: &str, last_event_id: i32) -> Result<(), diesel::result::Error> {
+    let mut conn = get_connection();
+
+    diesel::insert_into(siem_forwarder_cursor::table)
+        .values((
+            siem_forwarder_cursor::forwarder.eq(forwarder),
+            siem_forwarder_cursor::last_event_id.eq(last_event_id),
+        ))
+        .on_conflict(siem_forwarder_cursor::forwarder)
+        .do_update()
+        .set((
+            siem_forwarder_cursor::last_event_id.eq(last_event_id),
+            siem_forwarder_cursor::updated_at.eq(diesel::dsl::now),
+        ))
         .execute(&mut conn)?;
 
     Ok(())
 }
 
+/// # Returns
+/// * The ids of the given messages that the consumer has already handled
+pub fn get_processed_messages(consumer: &str, message_ids: &[String]) -> Result<Vec<String>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    processed_message::table
+        .select(processed_message::message_id)
+        .filter(processed_message::consumer.eq(consumer))
+        .filter(processed_message::message_id.eq_any(message_ids))
+        .load(&mut conn)
+}
+
+/// Records the messages as handled by the consumer, and forgets the messages too old to be delivered again.
+pub fn insert_processed_messages(consumer: &str, message_ids: &[String]) -> Result<(), diesel::result::Error> {
+    let mut conn = get_connection();
+
+    record_processed_messages(&mut conn, consumer, message_ids)
+}
+
+fn record_processed_messages(
+    conn: &mut PgConnection,
+    consumer: &str,
+    message_ids: &[String],
+) -> Result<(), diesel::result::Error> {
+    let rows: Vec<_> = message_ids.iter()
+        .map(|message_id| (
+            processed_message::consumer.eq(consumer),
+            processed_message::message_id.eq(message_id),
+        ))
+        .collect();
+
+    diesel::insert_into(processed_message::table)
+        .values(&rows)
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+
+    let cutoff = chrono::Utc::now() - chrono::Duration::days(PROCESSED_MESSAGE_RETENTION_DAYS);
+    diesel::delete(processed_message::table.filter(processed_message::processed_at.lt(cutoff)))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Replaces the client IPs of the events older than `cutoff` with a pseudonym, at most `limit` events at a time.
+///
+/// # Returns
+/// * The number of events updated, 0 when there is nothing left to pseudonymize
+pub fn pseudonymize_client_ips(
+    cutoff: chrono::DateTime<chrono::Utc>,
+    limit: i64,
+    pseudonymize: impl Fn(&str) -> String,
+) -> Result<usize, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    conn.transaction(|conn| {
+        let events = audit_event::table
+            .select((audit_event::id, audit_event::client_ip))
+            .filter(audit_event::event_timestamp.lt(cutoff))
+            .filter(audit_event::client_ip_pseudonymized.eq(false))
+            .order(audit_event::id)
+            .limit(limit)
+            .for_update()
+            .load::<(i32, String)>(conn)?;
+
+        for (id, client_ip) in &events {
+            diesel::update(audit_event::table.find(id))
+                .set((
+                    audit_event::client_ip.eq(pseudonymize(client_ip)),
+                    audit_event::client_ip_pseudonymized.eq(true),
+                    audit_event::updated_at.eq(diesel::dsl::now),
+                ))
+                .execute(conn)?;
+        }
+
+        Ok(events.len())
+    })
+}
+
+#[derive(Debug, QueryableByName)]
+pub struct ArchivedEventRow {
+    #[diesel(sql_type = diesel::sql_types::Integer)]
+    pub id: i32,
+    /// The whole row as JSON, keyed by the column names
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    pub row: String,
+}
+
+#[derive(Debug, QueryableByName)]
+struct LockResult {
+    #[diesel(sql_type = diesel::sql_types::Bool)]
+    locked: bool,
+}
+
+#[derive(Debug, QueryableByName)]
+struct PartitionName {
+    #[diesel(sql_type = diesel::sql_types::Text)]
+    name: String,
+}
+
+#[derive(Debug, QueryableByName)]
+struct PartitionMonth {
+    #[diesel(sql_type = diesel::sql_types::Date)]
+    month: chrono::NaiveDate,
+}
+
+#[derive(Debug, QueryableByName)]
+struct RowCount {
+    #[diesel(sql_type = diesel::sql_types::BigInt)]
+    count: i64,
+}
+
+/// Session-level advisory lock of a background job, released when dropped.
+///
+/// Keeps its own connection for as long as it is held, as the lock belongs to the session.
+pub struct JobLock {
+    conn: PooledConnection<ConnectionManager<PgConnection>>,
+    job: String,
+}
+
+impl JobLock {
+    /// Checks that the lock is still held, for the jobs that hold it for long. The lock is gone along with the
+    /// session if the connection was lost, and another instance may have taken it since.
+    pub fn is_held(&mut self) -> bool {
+        let held = diesel::sql_query(
+            "SELECT count(*) > 0 AS locked FROM pg_locks \
+             WHERE locktype = 'advisory' AND pid = pg_backend_pid() AND granted \
+             AND classid = $1::oid AND objid = hashtext($2)::oid AND objsubid = 2"
+        )
+            .bind::<diesel::sql_types::Integer, _>(AUDIT_JOB_LOCK_CLASS)
+            .bind::<diesel::sql_types::Text, _>(&self.job)
+            .get_result::<LockResult>(&mut self.conn);
+
+        matches!(held, Ok(LockResult { locked: true }))
+    }
+}
+
+impl Drop for JobLock {
+    fn drop(&mut self) {
+        let unlocked = diesel::sql_query("SELECT pg_advisory_unlock($1, hashtext($2)) AS locked")
+            .bind::<diesel::sql_types::Integer, _>(AUDIT_JOB_LOCK_CLASS)
+            .bind::<diesel::sql_types::Text, _>(&self.job)
+            .get_result::<LockResult>(&mut self.conn);
+
+        // an error here means a lost connection, and the lock was released along with the session
+        if !matches!(unlocked, Ok(LockResult { locked: true })) {
+            tracing::error!("Failed to release the {} lock: {:?}", self.job, unlocked);
+        }
+    }
+}
+
+/// Takes the lock of the background job, so that only one audit service instance runs it at a time, e.g. the
+/// partition maintenance or the forwarding to a SIEM.
+///
+/// # Arguments
+/// * `job` - Name of the job, the same in all the instances
+///
+/// # Returns
+/// * `None` if another instance holds the lock
+pub fn try_lock_job(job: &str) -> Result<Option<JobLock>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    let result = diesel::sql_query("SELECT pg_try_advisory_lock($1, hashtext($2)) AS locked")
+        .bind::<diesel::sql_types::Integer, _>(AUDIT_JOB_LOCK_CLASS)
+        .bind::<diesel::sql_types::Text, _>(job)
+        .get_result::<LockResult>(&mut conn)?;
+
+    Ok(result.locked.then(|| JobLock { conn, job: job.to_string() }))
+}
+
+/// Creates the monthly partition starting at `month_start`, unless it already exists.
+///
+/// # Arguments
+/// * `partition` - Name of the partition, e.g. "audit_event_y2025m01"
+/// * `month_start` - First day of the month
+///
+/// # Returns
+/// * The number of events moved to the new partition from the default partition, where they were stored while
+///   the month had no partition
+pub fn create_partition(partition: &str, month_start: chrono::NaiveDate) -> Result<usize, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    let month_end = month_start + chrono::Months::new(1);
+
+    conn.transaction(|conn| {
+        let exists = diesel::sql_query("SELECT count(*) AS count FROM pg_class WHERE relname = $1")
+            .bind::<diesel::sql_types::Text, _>(partition)
+            .get_result::<RowCount>(conn)?
+            .count > 0;
+        if exists {
+            return Ok(0);
+        }
+
+        // a partition cannot be added while the default partition has rows in its range, so they are moved to
+        // the new table before it is attached. No more can be inserted to the default partition in between.
+        diesel::sql_query("LOCK TABLE audit_event_default IN ACCESS EXCLUSIVE MODE").execute(conn)?;
+        diesel::sql_query(format!("CREATE TABLE {} (LIKE audit_event INCLUDING DEFAULTS)", partition)).execute(conn)?;
+
+        let moved = diesel::sql_query(format!(
+            "WITH moved AS (DELETE FROM audit_event_default WHERE created_at >= '{} 00:00:00+00' AND created_at < '{} 00:00:00+00' RETURNING *) \
+             INSERT INTO {} SELECT * FROM moved",
+            month_start, month_end, partition
+        ))
+        .execute(conn)?;
+
+        diesel::sql_query(format!(
+            "ALTER TABLE audit_event ATTACH PARTITION {} FOR VALUES FROM ('{} 00:00:00+00') TO ('{} 00:00:00+00')",
+            partition, month_start, month_end
+        ))
+        .execute(conn)?;
+
+        Ok(moved)
+    })
+}
+
+/// Returns the months of the events in the default partition, i.e. the months whose partition was not created
+/// in time.
+pub fn get_default_partition_months() -> Result<Vec<chrono::NaiveDate>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    let months = diesel::sql_query(
+        "SELECT DISTINCT date_trunc('month', created_at AT TIME ZONE 'UTC')::date AS month \
+         FROM audit_event_default ORDER BY month"
+    )
+    .load::<PartitionMonth>(&mut conn)?;
+
+    Ok(months.into_iter().map(|month| month.month).collect())
+}
+
+/// Returns the names of the partitions of `audit_event`, including the default partition
+pub fn get_partitions() -> Result<Vec<String>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    let partitions = diesel::sql_query(
+        "SELECT child.relname::text AS name FROM pg_inherits \
+         JOIN pg_class child ON child.oid = pg_inherits.inhrelid \
+         JOIN pg_class parent ON parent.oid = pg_inherits.inhparent \
+         WHERE parent.relname = 'audit_event'"
+    )
+    .load::<PartitionName>(&mut conn)?;
+
+    Ok(partitions.into_iter().map(|partition| partition.name).collect())
+}
+
+/// Reads the rows of a partition as JSON, in the id order.
+///
+/// # Arguments
+/// * `partition` - Name of the partition
+/// * `cursor` - If set, only the rows after the row with this id are returned
+/// * `limit` - The maximum number of rows to return
+pub fn get_partition_rows(partition: &str, cursor: Option<i32>, limit: i64) -> Result<Vec<ArchivedEventRow>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    diesel::sql_query(format!(
+        "SELECT e.id, row_to_json(e)::text AS row FROM {} e WHERE e.id > $1 ORDER BY e.id LIMIT $2",
+        partition
+    ))
+    .bind::<diesel::sql_types::Integer, _>(cursor.unwrap_or(i32::MIN))
+    .bind::<diesel::sql_types::BigInt, _>(limit)
+    .load(&mut conn)
+}
+
+pub fn count_rows(table: &str) -> Result<i64, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    diesel::sql_query(format!("SELECT count(*) AS count FROM {}", table))
+        .get_result::<RowCount>(&mut conn)
+        .map(|row| row.count)
+}
+
+/// Detaches the partition from `audit_event` and drops it, if it still has exactly `expected_rows` rows, and
+/// its events are the oldest of the hash chain.
+pub fn drop_partition(partition: &str, expected_rows: i64) -> Result<(), Box<dyn std::error::Error>> {
+    let mut conn = get_connection();
+
+    conn.transaction::<_, Box<dyn std::error::Error>, _>(|conn| {
+        diesel::sql_query(format!("ALTER TABLE audit_event DETACH PARTITION {}", partition)).execute(conn)?;
+
+        // the rows are counted after detaching, so that nothing can be inserted in between
+        let rows = diesel::sql_query(format!("SELECT count(*) AS count FROM {}", partition))
+            .get_result::<RowCount>(conn)?
+            .count;
+
+        if rows != expected_rows {
+            return Err(format!("Partition {} has {} rows, but {} were archived", partition, rows, expected_rows).into());
+        }
+
+        // an event left before the last one of the partition would be cut off from the rest of the chain
+        let last_id = diesel::sql_query(format!("SELECT coalesce(max(id), 0)::bigint AS count FROM {}", partition))
+            .get_result::<RowCount>(conn)?
+            .count;
+        let earlier = diesel::sql_query("SELECT count(*) AS count FROM (SELECT 1 FROM audit_event WHERE id < $1 LIMIT 1) AS earlier")
+            .bind::<diesel::sql_types::Integer, _>(last_id as i32)
+            .get_result::<RowCount>(conn)?
+            .count;
+        if earlier > 0 {
+            return Err(format!("Partition {} is not the oldest part of the hash chain, other partitions have events before its event {}", partition, last_id).into());
+        }
+
+        diesel::sql_query(format!("DROP TABLE {}", partition)).execute(conn)?;
+        Ok(())
+    })
+}
+
+/// Creates an empty standalone table with the same columns as `audit_event`, for restoring archived events.
+pub fn create_restore_table(table: &str) -> Result<(), diesel::result::Error> {
+    let mut conn = get_connection();
+
+    diesel::sql_query(format!("CREATE TABLE {} (LIKE audit_event INCLUDING INDEXES)", table)).execute(&mut conn)?;
+    Ok(())
+}
+
+/// Inserts the archived rows (JSON objects keyed by the column names) to the table.
+pub fn insert_archived_rows(table: &str, rows: &[String]) -> Result<usize, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    diesel::sql_query(format!(
+        "INSERT INTO {table} SELECT * FROM jsonb_populate_recordset(NULL::{table}, $1::jsonb)"
+    ))
+    .bind::<diesel::sql_types::Text, _>(format!("[{}]", rows.join(",")))
+    .execute(&mut conn)
+}
+
+/// Creates the shared pool the functions take their connections from, must be called once at startup.
+///
+/// The connections are made when they are first needed.
+pub fn init(database_url: &str, pool_size: u32) {
+    let pool = Pool::builder()
+        .max_size(pool_size)
+        .build_unchecked(ConnectionManager::new(database_url));
+
+    POOL.set(pool).unwrap_or_else(|_| panic!("The database pool is already created"));
+}
+
+/// Returns a connection from the shared pool.
+///
+/// # Panics
+/// * If `init` was not called, or no connection could be made within the pool timeout
+fn get_connection() -> PooledConnection<ConnectionManager<PgConnection>> {
+    let pool = POOL.get().expect("db::init was not called");
 
-fn get_connection() -> PgConnection {
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url))
+    pool.get().unwrap_or_else(|err| panic!("Error getting a database connection: {}", err))
 }
 
 // null bytes are not allowed in PostgreSQL jsonb fields. Sanitize the JSON value by removing null bytes.
@@ -3,14 +3,35 @@
 diesel::table! {
     audit_event (id) {
         id -> Integer,
-        user_id -> Uuid,
+        user_id -> Nullable<Uuid>,
         client_ip -> Varchar,
         event_action -> Varchar,
-        action_target -> Nullable<Uuid>,
         additional_info -> Nullable<Jsonb>,
         event_timestamp -> Timestamptz,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        schema_version -> Integer,
+        client_ip_pseudonymized -> Bool,
+        prev_hash -> Nullable<Varchar>,
+        row_hash -> Nullable<Varchar>,
+        target_kind -> Nullable<Varchar>,
+        target_id -> Nullable<Text>,
+        client_ip_digest -> Nullable<Varchar>,
     }
 }
 
+diesel::table! {
+    siem_forwarder_cursor (forwarder) {
+        forwarder -> Varchar,
+        last_event_id -> Integer,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    processed_message (consumer, message_id) {
+        consumer -> Varchar,
+        message_id -> Varchar,
+        processed_at -> Timestamptz,
+    }
+}
@@ -1,13 +1,43 @@
+mod activity;
+mod alerting;
+mod api;
+mod chain;
 mod models;
 mod db;
+mod redaction;
+mod retention;
+mod settings;
+mod siem;
+mod stats;
 
+use std::collections::HashSet;
 use std::env;
-use aws_sdk_sqs::Client;
+use std::sync::Arc;
 
+use async_trait::async_trait;
 use tracing_subscriber::filter;
+use tower::ServiceBuilder;
 
+use axum::{
+    middleware::from_fn_with_state,
+    routing::get,
+    Router
+};
 
+use auth_check::auth_middleware;
+use queue::ReceivedMessage;
+use worker::{BatchJobHandler, CancellationToken, IdempotencyStore, JobError, Worker, WorkerSettings};
+
+
+use alerting::{AlertEngine, AlertEvent};
+use chain::{ChainHasher, Checkpoint};
 use models::*;
+use redaction::RedactionPolicy;
+use settings::{CheckpointSettings, Settings};
+
+
+// the worker name, under which the handled audit messages are recorded
+const AUDIT_CONSUMER: &str = "audit";
 
 
 #[tokio::main]
@@ -19,93 +49,271 @@ async fn main() {
         .init();
 
 
-    let queue_url = env::var("AUDIT_EVENT_QUEUE_URL").expect("AUDIT_EVENT_QUEUE_URL not set");
-    let client = aws_sdk_sqs::Client::new(&aws_config::load_from_env().await);
+    let settings = Arc::new(Settings::load());
+    tracing::info!("Loaded settings {:?}", settings);
 
-    loop {
-       let audit_event_opt = receive_audit_event(&client, &queue_url).await
-            .unwrap_or_else(|err| {
-                tracing::error!("Error receiving audit event notification: {}", err);
-                None
-            });
+    db::init(settings.database_url.expose(), settings.database_pool_size);
 
-        if let Some(audit_event) = audit_event_opt {
+    let redaction_policy = Arc::new(RedactionPolicy::new(settings.redaction_rules.clone(), settings.redaction_hmac_key.as_ref()));
+    let chain_hasher = Arc::new(ChainHasher::new(redaction_policy.clone(), &settings.chain_key));
 
-            db::insert_audit_event(
-                audit_event.message.user_id.as_deref(),
-                &audit_event.message.client_ip,
-                audit_event.message.event_type,
-                audit_event.message.target.as_deref(),
-                audit_event.message.event_details,
-                audit_event.timestamp
-            ).unwrap_or_else(|err| {
-                tracing::error!("Error inserting audit event into database: {}", err);
-            });
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(|command| command.as_str()) {
+        // `audit verify-chain` checks the hash chain of the stored events, and exits
+        Some("verify-chain") => {
+            // the archived partitions are checked when restored, the stored chain continues from their manifests
+            let anchors = match &settings.archive_destination {
+                Some(destination) => retention::chain_anchors(destination).await.unwrap_or_else(|err| {
+                    tracing::error!("Error reading the archive manifests: {}", err);
+                    std::process::exit(1);
+                }),
+                None => Vec::new(),
+            };
 
+            // the head must not be behind the latest checkpoint, nor the chain recomputed since
+            let checkpoint = match &settings.checkpoint {
+                Some(checkpoints) => read_latest_checkpoint(checkpoints).await,
+                None => {
+                    tracing::warn!("AUDIT_CHECKPOINT_DESTINATION not set, a recomputed or truncated chain cannot be detected");
+                    None
+                }
+            };
 
-            delete_message(&client, &queue_url, &audit_event.receipt_handle).await
-                .unwrap_or_else(|err| {
-                    tracing::error!("Error deleting message: {}", err);
-                });
+            match chain::verify_chain(&chain_hasher, &anchors, checkpoint.as_ref()) {
+                Ok(count) => {
+                    tracing::info!("Audit event chain is intact, verified {} events", count);
+                    return;
+                }
+                Err(err) => {
+                    tracing::error!("{}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        // `audit restore-archive 2025-01` loads an archived month back for investigation, and exits
+        Some("restore-archive") => {
+            let month = args.get(2).expect("Usage: audit restore-archive <YYYY-MM>");
+            match retention::restore_archive(&settings, month).await {
+                Ok(table) => {
+                    tracing::info!("Restored the audit events of {} to table {}", month, table);
+                    return;
+                }
+                Err(err) => {
+                    tracing::error!("Error restoring the audit events of {}: {}", month, err);
+                    std::process::exit(1);
+                }
+            }
         }
+        _ => {}
+    }
+
+    // optional, client IPs are kept as is if not set
+    if let Some(after_days) = settings.pseudonymize_ip_after_days {
+        tokio::spawn(redaction::pseudonymize_ips_periodically(redaction_policy.clone(), after_days));
+    }
 
+    tokio::spawn(chain::write_checkpoints_periodically(settings.clone()));
+    tokio::spawn(retention::maintain_partitions_periodically(settings.clone()));
 
-        // Sleep for a while before checking the queue again
-        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+    for forwarder in settings.siem_forwarders.iter().cloned() {
+        tokio::spawn(siem::forward_events(forwarder, settings.hostname.clone()));
     }
+
+    // optional, no alerts are fired if AUDIT_ALERT_RULES_PATH is not set
+    let alert_engine = settings.alert_rules_path.clone().map(|path| Arc::new(AlertEngine::load(path)));
+    if let Some(alert_engine) = &alert_engine {
+        tokio::spawn(alert_engine.clone().reload_periodically());
+    }
+
+    let app = Router::new()
+        .route("/audit/health", get(|| async { "OK" }))
+        .route("/audit/activity", get(activity::list_my_activity).layer(from_fn_with_state(settings.auth.clone(), auth_middleware)))
+        .nest(
+            "/audit",
+            Router::new()
+                .route("/events", get(api::list_audit_events))
+                .route("/events/export", get(api::export_audit_events))
+                .route("/stats", get(api::ingest_stats))
+                .layer(
+                    ServiceBuilder::new()
+                        .layer(from_fn_with_state(settings.auth.clone(), auth_middleware))
+                        .layer(from_fn_with_state(settings.clone(), api::admin_middleware))
+                )
+        );
+
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", settings.port)).await
+        .expect("Failed to bind TCP listener");
+
+    let shutdown = worker::shutdown_token();
+
+    tracing::info!("Listening on port {}", settings.port);
+    tokio::join!(
+        audit_event_listener(&settings.worker, redaction_policy, chain_hasher, alert_engine, shutdown.clone()),
+        async {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown.cancelled_owned())
+                .await
+                .expect("Failed to start server");
+        }
+    );
 }
 
 
-async fn receive_audit_event(client: &Client, queue_url: &str) -> Result<Option<AuditEvent>, aws_sdk_sqs::Error> {
-    let rcv_message_output = client
-        .receive_message()
-        .queue_url(queue_url)
-        .message_system_attribute_names(aws_sdk_sqs::types::MessageSystemAttributeName::SentTimestamp)
-        .max_number_of_messages(1)
-        .send()
-        .await?;
-    
-    for message in rcv_message_output.messages.unwrap_or_default() {
-        let body = match message.body {
-            Some(ref body) => body,
-            None => {
-                tracing::warn!("Received message with no body, skipping.");
-                continue;
-            }
-        };
+/// Reads the latest checkpoint and checks its signature, exiting if either fails
+async fn read_latest_checkpoint(checkpoints: &CheckpointSettings) -> Option<Checkpoint> {
+    let checkpoint = chain::latest_checkpoint(&checkpoints.destination).await.unwrap_or_else(|err| {
+        tracing::error!("Error reading the latest audit chain checkpoint: {}", err);
+        std::process::exit(1);
+    });
 
-        let audit_message: AuditMessage = match serde_json::from_str(body){
-            Ok(msg) => msg,
-            Err(err) => {
-                tracing::error!("Failed to parse message body as JSON: {}", err);
-                continue;
+    match checkpoint {
+        Some(checkpoint) => {
+            if let Err(err) = checkpoint.verify_signature(checkpoints.signing_key.expose()) {
+                tracing::error!("{}", err);
+                std::process::exit(1);
             }
-        };
+            Some(checkpoint)
+        }
+        None => {
+            tracing::warn!("No audit chain checkpoints have been written yet, the chain is verified without one");
+            None
+        }
+    }
+}
+
 
-        tracing::info!("Message attributes: {:?}", message.attributes());
-        let sent_timestamp = message
-            .attributes()
-           .unwrap()[&aws_sdk_sqs::types::MessageSystemAttributeName::SentTimestamp].clone().parse::<i64>().unwrap();
+async fn audit_event_listener(
+    settings: &WorkerSettings,
+    redaction_policy: Arc<RedactionPolicy>,
+    chain_hasher: Arc<ChainHasher>,
+    alert_engine: Option<Arc<AlertEngine>>,
+    shutdown: CancellationToken,
+) {
+    let handler = AuditEventHandler {
+        redaction_policy,
+        chain_hasher,
+        alert_engine,
+    };
+
+    // each receive is stored in a single transaction
+    Worker::batched(AUDIT_CONSUMER, settings.queue.open(), handler)
+        .with_settings(settings)
+        .with_idempotency_store(Arc::new(DbIdempotencyStore))
+        .run(shutdown)
+        .await;
+}
 
-        return Ok(Some(AuditEvent {
-            message: audit_message,
-            receipt_handle: message.receipt_handle.unwrap_or_default(),
-            timestamp: chrono::DateTime::from_timestamp_millis(sent_timestamp).unwrap(),
-        }));         
 
+/// Keeps track of the handled queue messages in the audit database
+struct DbIdempotencyStore;
+
+#[async_trait]
+impl IdempotencyStore for DbIdempotencyStore {
+    async fn handled(&self, consumer: &str, message_ids: &[String]) -> Result<HashSet<String>, String> {
+        db::get_processed_messages(consumer, message_ids)
+            .map(|handled| handled.into_iter().collect())
+            .map_err(|err| err.to_string())
     }
 
-    Ok(None)
+    async fn mark_handled(&self, consumer: &str, message_ids: &[String]) -> Result<(), String> {
+        db::insert_processed_messages(consumer, message_ids).map_err(|err| err.to_string())
+    }
 }
 
-async fn delete_message(client: &Client, queue_url: &str, receipt_handle: &str) -> Result<(), aws_sdk_sqs::Error> {
-    client
-        .delete_message()
-        .queue_url(queue_url)
-        .receipt_handle(receipt_handle)
-        .send()
-        .await?;
-
-    tracing::info!("Message deleted successfully");
-    Ok(())
-}
\ No newline at end of file
+
+struct AuditEventHandler {
+    redaction_policy: Arc<RedactionPolicy>,
+    chain_hasher: Arc<ChainHasher>,
+    alert_engine: Option<Arc<AlertEngine>>,
+}
+
+#[async_trait]
+impl BatchJobHandler for AuditEventHandler {
+    /// Stores the valid events of the batch. The invalid ones can never be stored, so they are dead-lettered
+    /// right away, and the ones that fail to be stored are retried.
+    async fn handle_batch(&self, messages: &[ReceivedMessage]) -> Vec<Result<(), JobError>> {
+        let mut results = Vec::with_capacity(messages.len());
+        let mut audit_events = vec![];
+        // index of each event's message
+        let mut message_indices = vec![];
+
+        for (i, message) in messages.iter().enumerate() {
+            match parse_audit_event(message) {
+                Ok(audit_event) => {
+                    audit_events.push(audit_event);
+                    message_indices.push(i);
+                    results.push(Ok(()));
+                }
+                Err(err) => results.push(Err(err)),
+            }
+        }
+
+        for audit_event in audit_events.iter_mut() {
+            if let Some(details) = audit_event.message.event_details.as_mut() {
+                self.redaction_policy.apply(&audit_event.message.event_type, details);
+            }
+        }
+
+        let new_events: Vec<db::NewAuditEvent> = audit_events.iter().map(|event| event.as_new_event()).collect();
+
+        if let Err(err) = db::insert_audit_events(&self.chain_hasher, AUDIT_CONSUMER, &new_events) {
+            // find out which of the events cannot be stored, so that they do not hold back the rest
+            tracing::error!("Error inserting {} audit events into database, inserting them one by one: {}", new_events.len(), err);
+
+            for (new_event, &i) in new_events.iter().zip(&message_indices) {
+                if let Err(err) = db::insert_audit_events(&self.chain_hasher, AUDIT_CONSUMER, std::slice::from_ref(new_event)) {
+                    tracing::error!("Error inserting audit event into database, leaving it for redelivery: {}", err);
+                    results[i] = Err(JobError::Retryable(format!("Error inserting audit event into database: {}", err)));
+                }
+            }
+        }
+
+        if let Some(alert_engine) = &self.alert_engine {
+            for (audit_event, &i) in audit_events.iter().zip(&message_indices) {
+                if results[i].is_err() {
+                    continue;
+                }
+
+                let (target_kind, target_id) = audit_event.message.target();
+                alert_engine.evaluate(&AlertEvent {
+                    event_timestamp: audit_event.timestamp,
+                    event_type: &audit_event.message.event_type,
+                    user_id: audit_event.message.user_id.as_deref(),
+                    client_ip: &audit_event.message.client_ip,
+                    target_kind,
+                    target_id,
+                    event_details: audit_event.message.event_details.as_ref(),
+                });
+            }
+        }
+
+        results
+    }
+}
+
+fn parse_audit_event(message: &ReceivedMessage) -> Result<AuditEvent, JobError> {
+    stats::record_received_event();
+
+    let audit_message: AuditMessage = serde_json::from_str(&message.body).map_err(|err| {
+        tracing::error!("Failed to parse message body as JSON: {}", err);
+        stats::record_rejected_event();
+        JobError::Permanent(format!("Failed to parse message body as JSON: {}", err))
+    })?;
+
+    if let Err(err) = audit_message.validate() {
+        tracing::error!("Rejecting audit event: {}", err);
+        stats::record_rejected_event();
+        return Err(JobError::Permanent(format!("Rejected audit event: {}", err)));
+    }
+
+    // the event may have been buffered or spooled by the sender for a while, so the queue's time is only a fallback
+    let timestamp = audit_message.occurred_at
+        .or_else(|| message.sent_timestamp.and_then(chrono::DateTime::from_timestamp_millis))
+        .unwrap_or_else(chrono::Utc::now);
+
+    Ok(AuditEvent {
+        message_id: worker::idempotency_key(message),
+        message: audit_message,
+        timestamp,
+    })
+}
@@ -1,18 +1,151 @@
+use audit_lib::{AuditEventKind, TargetKind, AUDIT_SCHEMA_VERSION};
+
 
 #[derive(Debug, Clone)]
 #[allow(dead_code)]
 pub struct AuditEvent {
+    /// See `worker::idempotency_key`
+    pub message_id: String,
     pub message: AuditMessage,
-    pub receipt_handle: String,
     pub timestamp: chrono::DateTime<chrono::Utc>,
 }
 
+impl AuditEvent {
+    pub fn as_new_event(&self) -> crate::db::NewAuditEvent<'_> {
+        let (target_kind, target_id) = self.message.target();
+
+        crate::db::NewAuditEvent {
+            message_id: &self.message_id,
+            schema_version: self.message.schema_version,
+            user_id: self.message.user_id.as_deref(),
+            client_ip: &self.message.client_ip,
+            event_action: &self.message.event_type,
+            target_kind,
+            target_id,
+            additional_info: self.message.event_details.as_ref(),
+            event_timestamp: self.timestamp,
+        }
+    }
+}
+
 #[derive(Debug, Clone, serde::Deserialize)]
 #[allow(dead_code)]
 pub struct AuditMessage {
+    // missing in the version 1 events
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
     pub event_type: String,
     pub user_id: Option<String>,
     pub client_ip: String,
-    pub target: Option<String>,
+    pub target: Option<MessageTarget>,
     pub event_details: Option<serde_json::Value>, 
-}
\ No newline at end of file
+    // missing in the events of the older services, the time the event was queued is used instead
+    pub occurred_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+fn default_schema_version() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(untagged)]
+pub enum MessageTarget {
+    Typed {
+        kind: TargetKind,
+        id: String,
+    },
+    // schema versions 1 and 2 only had the ID
+    Plain(String),
+}
+
+impl AuditMessage {
+    /// Validates the event type and details against the schema version the event was produced with.
+    ///
+    /// Version 1 events are untyped, so those are accepted as is.
+    ///
+    /// # Returns
+    /// * `Err` with the reason, if the event does not match its schema or the version is unknown
+    pub fn validate(&self) -> Result<(), String> {
+        match self.schema_version {
+            1 => Ok(()),
+            2 | 3 => {
+                if self.schema_version == 3 && matches!(self.target, Some(MessageTarget::Plain(_))) {
+                    return Err(format!("Invalid {} event: target must have a kind", self.event_type));
+                }
+
+                let mut kind = serde_json::Map::new();
+                kind.insert("event_type".to_string(), serde_json::Value::String(self.event_type.clone()));
+                if let Some(details) = &self.event_details {
+                    kind.insert("event_details".to_string(), details.clone());
+                }
+
+                serde_json::from_value::<AuditEventKind>(serde_json::Value::Object(kind))
+                    .map(|_| ())
+                    .map_err(|err| format!("Invalid {} event: {}", self.event_type, err))
+            }
+            version => Err(format!(
+                "Unsupported schema version {} (latest known version is {})",
+                version, AUDIT_SCHEMA_VERSION
+            )),
+        }
+    }
+
+    /// The kind and the ID of the target.
+    ///
+    /// The older events only have the ID, so the kind is derived from the event type, where possible.
+    pub fn target(&self) -> (Option<&'static str>, Option<&str>) {
+        match &self.target {
+            Some(MessageTarget::Typed { kind, id }) => (Some(kind.as_str()), Some(id)),
+            Some(MessageTarget::Plain(id)) => (legacy_target_kind(&self.event_type).map(|kind| kind.as_str()), Some(id)),
+            None => (None, None),
+        }
+    }
+}
+
+// the targets the services used before the target kind was added, see also the 000007 migration
+fn legacy_target_kind(event_type: &str) -> Option<TargetKind> {
+    match event_type {
+        "file_upload" | "virus_scan" | "resource_public_status_updated" => Some(TargetKind::Resource),
+        "init_chunk_upload" | "chunk_upload" | "complete_chunk_upload" => Some(TargetKind::Upload),
+        _ => None,
+    }
+}
+
+
+/// Filters of the audit event queries, see `db::AuditEventFilter`
+#[derive(Debug, serde::Deserialize)]
+pub struct AuditEventFilterQuery {
+    pub user_id: Option<String>,
+    pub event_action: Option<String>,
+    pub target_kind: Option<String>,
+    pub target: Option<String>,
+    pub client_ip: Option<String>,
+    pub from: Option<chrono::DateTime<chrono::Utc>>,
+    pub to: Option<chrono::DateTime<chrono::Utc>>,
+    /// JSON object the event details must contain
+    pub details: Option<String>,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct PageQuery {
+    pub cursor: Option<i32>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+}
+
+#[derive(Debug, serde::Deserialize)]
+pub struct ExportQuery {
+    pub format: ExportFormat,
+}
+
+#[derive(Debug, serde::Serialize)]
+pub struct AuditEventPage {
+    pub events: Vec<crate::db::AuditEventRecord>,
+    pub next_cursor: Option<i32>,
+}
@@ -0,0 +1,348 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+use serde_json::Value;
+use sha2::Sha256;
+
+use config::Secret;
+
+use crate::db;
+
+
+// how often the pseudonymization job looks for expired client IPs
+const PSEUDONYMIZE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// how many events are pseudonymized per transaction
+const PSEUDONYMIZE_BATCH_SIZE: i64 = 1000;
+
+// the lock of the pseudonymization job, see `db::try_lock_job`
+const PSEUDONYMIZE_JOB: &str = "ip-pseudonymization";
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RedactionAction {
+    /// Removes the field altogether
+    Drop,
+    /// Replaces the value with a keyed HMAC of it, so that events can still be correlated by the value
+    Hash,
+    /// Keeps at most the given number of characters of a string value
+    Truncate(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct RedactionRule {
+    // None matches all event types
+    event_type: Option<String>,
+    path: Vec<String>,
+    action: RedactionAction,
+}
+
+impl RedactionRule {
+    pub fn hashes(&self) -> bool {
+        self.action == RedactionAction::Hash
+    }
+}
+
+/// Field-level redaction rules for the event details, applied before the events are stored.
+pub struct RedactionPolicy {
+    rules: Vec<RedactionRule>,
+    hmac_key: Option<Vec<u8>>,
+}
+
+impl RedactionPolicy {
+    /// # Arguments
+    /// * `rules` - See `parse_rules`
+    /// * `hmac_key` - The key used for hashing, and for pseudonymizing the client IPs
+    pub fn new(rules: Vec<RedactionRule>, hmac_key: Option<&Secret<String>>) -> Self {
+        RedactionPolicy {
+            rules,
+            hmac_key: hmac_key.map(|key| key.expose().clone().into_bytes()),
+        }
+    }
+
+    /// Applies the rules matching the event type to the event details.
+    pub fn apply(&self, event_type: &str, details: &mut Value) {
+        for rule in &self.rules {
+            if rule.event_type.as_deref().is_none_or(|rule_event_type| rule_event_type == event_type) {
+                self.apply_rule(details, &rule.path, &rule.action);
+            }
+        }
+    }
+
+    /// Whether `pseudonymize_ip` can be used, i.e. the HMAC key is set
+    pub fn can_pseudonymize(&self) -> bool {
+        self.hmac_key.is_some()
+    }
+
+    /// Replaces the client IP with a pseudonym. The same IP always maps to the same pseudonym.
+    ///
+    /// # Panics
+    /// * If `AUDIT_REDACTION_HMAC_KEY` is not set
+    pub fn pseudonymize_ip(&self, client_ip: &str) -> String {
+        self.hash(client_ip)
+    }
+
+    fn apply_rule(&self, value: &mut Value, path: &[String], action: &RedactionAction) {
+        match value {
+            // apply to each element, e.g. "files.name" matches the names of all the files
+            Value::Array(values) => {
+                for value in values {
+                    self.apply_rule(value, path, action);
+                }
+            }
+            Value::Object(map) => {
+                let Some((field, rest)) = path.split_first() else {
+                    return;
+                };
+
+                if !rest.is_empty() {
+                    if let Some(value) = map.get_mut(field) {
+                        self.apply_rule(value, rest, action);
+                    }
+                    return;
+                }
+
+                match action {
+                    RedactionAction::Drop => {
+                        map.remove(field);
+                    }
+                    RedactionAction::Hash => {
+                        if let Some(value) = map.get_mut(field) {
+                            let plain = match &*value {
+                                Value::String(s) => s.clone(),
+                                other => other.to_string(),
+                            };
+                            *value = Value::String(self.hash(&plain));
+                        }
+                    }
+                    RedactionAction::Truncate(length) => {
+                        if let Some(Value::String(s)) = map.get_mut(field)
+                            && let Some((index, _)) = s.char_indices().nth(*length) {
+                            s.truncate(index);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn hash(&self, value: &str) -> String {
+        let key = self.hmac_key.as_ref().expect("AUDIT_REDACTION_HMAC_KEY not set");
+
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any size");
+        mac.update(value.as_bytes());
+
+        let digest = mac.finalize().into_bytes();
+        let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+        format!("hmac:{}", hex)
+    }
+}
+
+/// Parses a comma separated list of `<event type>:<field>=<action>` rules, where the event type may be `*` to
+/// match all events, the field is a dot separated path into the event details, and the action is one of `drop`,
+/// `hash` or `truncate(<length>)`. For example
+/// `login_failure:username=hash,virus_scan:scan_response=truncate(256),*:password=drop`
+pub fn parse_rules(rules: &str) -> Result<Vec<RedactionRule>, String> {
+    rules
+        .split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(parse_rule)
+        .collect()
+}
+
+fn parse_rule(rule: &str) -> Result<RedactionRule, String> {
+    let (selector, action) = rule.split_once('=')
+        .ok_or_else(|| format!("Missing action in rule '{}'", rule))?;
+
+    let (event_type, path) = selector.split_once(':')
+        .ok_or_else(|| format!("Missing event type in rule '{}'", rule))?;
+
+    let event_type = match event_type.trim() {
+        "*" => None,
+        event_type => Some(event_type.to_string()),
+    };
+
+    let path: Vec<String> = path.trim().split('.').map(|part| part.to_string()).collect();
+    if path.iter().any(|part| part.is_empty()) {
+        return Err(format!("Invalid field in rule '{}'", rule));
+    }
+
+    let action = match action.trim() {
+        "drop" => RedactionAction::Drop,
+        "hash" => RedactionAction::Hash,
+        action => {
+            let length = action
+                .strip_prefix("truncate(")
+                .and_then(|rest| rest.strip_suffix(')'))
+                .and_then(|length| length.trim().parse::<usize>().ok())
+                .ok_or_else(|| format!("Unknown action in rule '{}'", rule))?;
+            RedactionAction::Truncate(length)
+        }
+    };
+
+    Ok(RedactionRule { event_type, path, action })
+}
+
+
+/// Periodically replaces the client IPs older than `after_days` days with a pseudonym.
+///
+/// The instances take turns through an advisory lock, and an instance skips the round if another one is
+/// already pseudonymizing.
+pub async fn pseudonymize_ips_periodically(policy: Arc<RedactionPolicy>, after_days: i64) {
+    let mut interval = tokio::time::interval(PSEUDONYMIZE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        // held until the end of the round
+        let _lock = match db::try_lock_job(PSEUDONYMIZE_JOB) {
+            Ok(Some(lock)) => lock,
+            Ok(None) => continue,
+            Err(err) => {
+                tracing::error!("Error locking the client IP pseudonymization: {}", err);
+                continue;
+            }
+        };
+
+        let cutoff = chrono::Utc::now() - chrono::Duration::days(after_days);
+
+        loop {
+            let pseudonymized = db::pseudonymize_client_ips(cutoff, PSEUDONYMIZE_BATCH_SIZE, |client_ip| {
+                policy.pseudonymize_ip(client_ip)
+            });
+
+            match pseudonymized {
+                Ok(0) => break,
+                Ok(count) => tracing::info!("Pseudonymized the client IP of {} audit events", count),
+                Err(err) => {
+                    tracing::error!("Error pseudonymizing client IPs: {}", err);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    const KEY: &str = "test key";
+
+    fn policy(rules: &str) -> RedactionPolicy {
+        RedactionPolicy::new(parse_rules(rules).unwrap(), Some(&Secret::new(KEY.to_string())))
+    }
+
+    fn redacted(rules: &str, event_type: &str, mut details: Value) -> Value {
+        policy(rules).apply(event_type, &mut details);
+        details
+    }
+
+    #[test]
+    fn parses_the_rules() {
+        let rules = parse_rules("login_failure:username=hash, virus_scan:scan_response=truncate(256),*:password=drop,").unwrap();
+
+        assert_eq!(rules.len(), 3);
+        assert_eq!(rules[0].event_type.as_deref(), Some("login_failure"));
+        assert_eq!(rules[0].path, vec!["username"]);
+        assert_eq!(rules[0].action, RedactionAction::Hash);
+        assert_eq!(rules[1].action, RedactionAction::Truncate(256));
+        assert_eq!(rules[2].event_type, None);
+        assert_eq!(rules[2].action, RedactionAction::Drop);
+    }
+
+    #[test]
+    fn rejects_invalid_rules() {
+        assert_eq!(parse_rules("login_failure:username").unwrap_err(), "Missing action in rule 'login_failure:username'");
+        assert_eq!(parse_rules("username=drop").unwrap_err(), "Missing event type in rule 'username=drop'");
+        assert_eq!(parse_rules("*:files..name=drop").unwrap_err(), "Invalid field in rule '*:files..name=drop'");
+        assert_eq!(parse_rules("*:password=remove").unwrap_err(), "Unknown action in rule '*:password=remove'");
+        assert_eq!(parse_rules("*:password=truncate(x)").unwrap_err(), "Unknown action in rule '*:password=truncate(x)'");
+    }
+
+    #[test]
+    fn drops_fields() {
+        let details = redacted("*:password=drop", "login_failure", json!({ "password": "hunter2", "username": "alice" }));
+
+        assert_eq!(details, json!({ "username": "alice" }));
+    }
+
+    #[test]
+    fn hashes_fields_with_the_key() {
+        let details = redacted(
+            "*:username=hash,*:attempts=hash",
+            "login_failure",
+            json!({ "username": "alice@example.com", "attempts": 42 }),
+        );
+
+        assert_eq!(details, json!({
+            "username": "hmac:7abb8a8bad6586d8f37f4b664b2745ebd95795b9737912f4cf44681d6581a67f",
+            // the values that are not strings are hashed as JSON
+            "attempts": "hmac:9367c20a70436230e2c77f46aaeaa22ed49baaa5d4c8e6f603446e0baeb95e32",
+        }));
+    }
+
+    #[test]
+    fn truncates_fields_by_characters() {
+        let details = redacted(
+            "*:reason=truncate(3),*:short=truncate(10),*:count=truncate(1)",
+            "virus_scan",
+            json!({ "reason": "äöüß", "short": "ok", "count": 12345 }),
+        );
+
+        assert_eq!(details, json!({ "reason": "äöü", "short": "ok", "count": 12345 }));
+    }
+
+    #[test]
+    fn only_applies_the_rules_of_the_event_type() {
+        let rules = "login_failure:username=drop";
+
+        assert_eq!(redacted(rules, "login_failure", json!({ "username": "alice" })), json!({}));
+        assert_eq!(redacted(rules, "login_success", json!({ "username": "alice" })), json!({ "username": "alice" }));
+    }
+
+    #[test]
+    fn follows_nested_fields_and_arrays() {
+        let details = redacted(
+            "file_upload:files.name=drop",
+            "file_upload",
+            json!({ "files": [{ "name": "a.mp4", "size": 1 }, { "name": "b.mp4", "size": 2 }], "name": "kept" }),
+        );
+
+        assert_eq!(details, json!({ "files": [{ "size": 1 }, { "size": 2 }], "name": "kept" }));
+    }
+
+    #[test]
+    fn leaves_missing_fields_alone() {
+        let details = redacted("*:files.name=drop,*:password=hash", "file_upload", json!({ "files": "not an object" }));
+
+        assert_eq!(details, json!({ "files": "not an object" }));
+    }
+
+    #[test]
+    fn pseudonymizes_ips_with_a_stable_hmac() {
+        let policy = policy("");
+
+        assert!(policy.can_pseudonymize());
+        assert_eq!(
+            policy.pseudonymize_ip("192.0.2.1"),
+            "hmac:78f3641c75c1381b174107b4060a4ea631c0ea20f28d0dac55e3dd98ea3bd968"
+        );
+        assert_eq!(policy.pseudonymize_ip("192.0.2.1"), policy.pseudonymize_ip("192.0.2.1"));
+        assert_ne!(policy.pseudonymize_ip("192.0.2.1"), policy.pseudonymize_ip("192.0.2.2"));
+    }
+
+    #[test]
+    fn pseudonyms_depend_on_the_key() {
+        let other = RedactionPolicy::new(Vec::new(), Some(&Secret::new("other key".to_string())));
+
+        assert_ne!(policy("").pseudonymize_ip("192.0.2.1"), other.pseudonymize_ip("192.0.2.1"));
+        assert!(!RedactionPolicy::new(Vec::new(), None).can_pseudonymize());
+    }
+}
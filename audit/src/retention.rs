@@ -0,0 +1,385 @@
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use chrono::{Datelike, Months, NaiveDate};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use sha2::{Digest, Sha256};
+
+use crate::chain::{to_hex, ChainAnchor};
+use crate::db;
+use crate::settings::Settings;
+use storage::Location;
+
+
+// how often the partitions are created and the old ones archived
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(24 * 60 * 60);
+
+// how many months of partitions are created ahead of time
+const PARTITIONS_AHEAD: u32 = 2;
+
+// how many rows are read from the database, or inserted back, at a time
+const ARCHIVE_PAGE_SIZE: i64 = 1000;
+
+const PARTITION_PREFIX: &str = "audit_event_y";
+
+// the lock of the partition maintenance, see `db::try_lock_job`
+const MAINTENANCE_JOB: &str = "partition-maintenance";
+
+
+/// Describes an archived partition. Stored next to the archived events.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct ArchiveManifest {
+    pub partition: String,
+    /// The month the archived events were stored in, e.g. "2025-01"
+    pub month: String,
+    pub format: String,
+    pub data_key: String,
+    /// SHA-256 of the compressed data file
+    pub data_sha256: String,
+    pub row_count: i64,
+    pub first_id: Option<i32>,
+    pub last_id: Option<i32>,
+    /// Hash of the last event in the archive, where the chain continues from
+    pub last_row_hash: Option<String>,
+    pub archived_at: chrono::DateTime<chrono::Utc>,
+}
+
+
+/// Periodically creates the upcoming monthly partitions, and archives and drops the partitions older than
+/// `AUDIT_RETENTION_MONTHS`, if set.
+///
+/// The events are partitioned by the month they were stored in, so that the partitions follow the hash chain, and
+/// the oldest partition always holds the oldest events of the chain. The events stored in the default partition,
+/// while their month had no partition, are moved to a partition of their own first, so they are archived, too.
+///
+/// The instances take turns through an advisory lock, and an instance skips the round if another one is
+/// already doing it.
+pub async fn maintain_partitions_periodically(settings: Arc<Settings>) {
+    let mut interval = tokio::time::interval(MAINTENANCE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        // held until the end of the round
+        let _lock = match db::try_lock_job(MAINTENANCE_JOB) {
+            Ok(Some(lock)) => lock,
+            Ok(None) => {
+                tracing::info!("Another instance is maintaining the audit event partitions, skipping");
+                continue;
+            }
+            Err(err) => {
+                tracing::error!("Error locking the audit event partition maintenance: {}", err);
+                continue;
+            }
+        };
+
+        create_upcoming_partitions();
+        create_default_partition_months();
+
+        // the destination is always set along with the retention, see `Settings::load`
+        if let (Some(months), Some(destination)) = (settings.retention_months, &settings.archive_destination) {
+            archive_expired_partitions(months, destination).await;
+        }
+    }
+}
+
+fn create_upcoming_partitions() {
+    let current_month = month_start(chrono::Utc::now().date_naive());
+
+    for ahead in 0..=PARTITIONS_AHEAD {
+        create_partition(current_month + Months::new(ahead));
+    }
+}
+
+// e.g. when no instance was running at the turn of the month
+fn create_default_partition_months() {
+    match db::get_default_partition_months() {
+        Ok(months) => months.into_iter().for_each(create_partition),
+        Err(err) => tracing::error!("Error reading the default audit event partition: {}", err),
+    }
+}
+
+fn create_partition(month: NaiveDate) {
+    match db::create_partition(&partition_name(month), month) {
+        Ok(0) => {}
+        Ok(moved) => tracing::info!("Moved {} audit events of {} from the default partition", moved, month),
+        Err(err) => tracing::error!("Error creating audit event partition for {}: {}", month, err),
+    }
+}
+
+async fn archive_expired_partitions(retention_months: u32, destination: &Location) {
+    let partitions = match db::get_partitions() {
+        Ok(partitions) => partitions,
+        Err(err) => {
+            tracing::error!("Error listing audit event partitions: {}", err);
+            return;
+        }
+    };
+
+    for (partition, month) in expired_partitions(partitions, retention_months, chrono::Utc::now().date_naive()) {
+        tracing::info!("Archiving audit event partition {}", partition);
+
+        match archive_partition(&partition, month, destination).await {
+            Ok(manifest) => {
+                if let Err(err) = db::drop_partition(&partition, manifest.row_count) {
+                    tracing::error!("Archived partition {}, but could not drop it: {}", partition, err);
+                    return;
+                }
+                tracing::info!("Archived and dropped partition {} ({} events)", partition, manifest.row_count);
+            }
+            Err(err) => {
+                // keep the order, a later month is never archived before an earlier one
+                tracing::error!("Error archiving partition {}: {}", partition, err);
+                return;
+            }
+        }
+    }
+}
+
+// the partitions older than the retention, oldest first
+fn expired_partitions(partitions: Vec<String>, retention_months: u32, today: NaiveDate) -> Vec<(String, NaiveDate)> {
+    let cutoff = month_start(today) - Months::new(retention_months);
+
+    let mut expired: Vec<(String, NaiveDate)> = partitions
+        .into_iter()
+        .filter_map(|partition| parse_partition_name(&partition).map(|month| (partition, month)))
+        .filter(|(_, month)| *month < cutoff)
+        .collect();
+    expired.sort_by_key(|(_, month)| *month);
+    expired
+}
+
+/// Exports the partition to the archive as gzip compressed JSONL, followed by the manifest.
+async fn archive_partition(partition: &str, month: NaiveDate, destination: &Location) -> Result<ArchiveManifest, Box<dyn std::error::Error>> {
+    let data_path = env::temp_dir().join(format!("{}.jsonl.gz", partition));
+    let mut encoder = GzEncoder::new(std::fs::File::create(&data_path)?, Compression::default());
+
+    let mut row_count = 0;
+    let mut first_id = None;
+    let mut last_id = None;
+    let mut last_row_hash = None;
+
+    loop {
+        let rows = db::get_partition_rows(partition, last_id, ARCHIVE_PAGE_SIZE)?;
+        if rows.is_empty() {
+            break;
+        }
+
+        for row in &rows {
+            encoder.write_all(row.row.as_bytes())?;
+            encoder.write_all(b"\n")?;
+        }
+
+        first_id = first_id.or(rows.first().map(|row| row.id));
+        last_id = rows.last().map(|row| row.id);
+        last_row_hash = rows.last()
+            .and_then(|row| serde_json::from_str::<serde_json::Value>(&row.row).ok())
+            .and_then(|row| row["row_hash"].as_str().map(|hash| hash.to_string()))
+            .or(last_row_hash);
+        row_count += rows.len() as i64;
+    }
+
+    encoder.finish()?.sync_all()?;
+
+    let data = tokio::fs::read(&data_path).await?;
+    let data_sha256 = to_hex(&Sha256::digest(&data));
+
+    let data_key = destination.key(&format!("{}/events.jsonl.gz", partition));
+    destination.store.put(&data_key, data.into(), Some("application/gzip")).await?;
+
+    let manifest = ArchiveManifest {
+        partition: partition.to_string(),
+        month: month.format("%Y-%m").to_string(),
+        format: "jsonl.gz".to_string(),
+        data_key,
+        data_sha256,
+        row_count,
+        first_id,
+        last_id,
+        last_row_hash,
+        archived_at: chrono::Utc::now(),
+    };
+
+    // the manifest is written last, so an archive without one is known to be incomplete
+    let manifest_key = destination.key(&format!("{}/manifest.json", partition));
+    destination.store.put(&manifest_key, serde_json::to_vec_pretty(&manifest)?.into(), Some("application/json")).await?;
+
+    tokio::fs::remove_file(&data_path).await?;
+
+    Ok(manifest)
+}
+
+
+/// Reads the last chained event of each archived partition from the manifests. The hash chain of the stored
+/// events continues from the last of these.
+pub async fn chain_anchors(destination: &Location) -> Result<Vec<ChainAnchor>, Box<dyn std::error::Error>> {
+    let mut anchors = Vec::new();
+
+    for object in destination.store.list(&destination.key(PARTITION_PREFIX)).await? {
+        if !object.key.ends_with("/manifest.json") {
+            continue;
+        }
+
+        let manifest = destination.store.get(&object.key, None).await?.bytes().await?;
+        let manifest: ArchiveManifest = serde_json::from_slice(&manifest)
+            .map_err(|err| format!("Invalid archive manifest {}: {}", object.key, err))?;
+
+        // the partitions of the events stored before the chain was introduced have no hashes
+        if let (Some(event_id), Some(row_hash)) = (manifest.last_id, manifest.last_row_hash) {
+            anchors.push(ChainAnchor { event_id, row_hash });
+        }
+    }
+
+    anchors.sort_by_key(|anchor| anchor.event_id);
+    Ok(anchors)
+}
+
+
+/// Loads an archived month back to the database, to a standalone table named `audit_event_restored_y<year>m<month>`.
+///
+/// The restored events are not added back to `audit_event`, so they do not affect the hash chain or the retention.
+///
+/// # Arguments
+/// * `month` - The month to restore, e.g. "2025-01"
+///
+/// # Returns
+/// * The name of the table the events were restored to
+pub async fn restore_archive(settings: &Settings, month: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let destination = settings.archive_destination.as_ref().ok_or("AUDIT_ARCHIVE_DESTINATION not set")?;
+
+    let month = NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d")
+        .map_err(|_| format!("Invalid month {}, expected e.g. 2025-01", month))?;
+    let partition = partition_name(month);
+
+    let manifest = destination.store.get(&destination.key(&format!("{}/manifest.json", partition)), None)
+        .await
+        .map_err(|err| format!("Could not read the manifest of {}: {}", partition, err))?
+        .bytes()
+        .await?;
+    let manifest: ArchiveManifest = serde_json::from_slice(&manifest)?;
+
+    let data = destination.store.get(&manifest.data_key, None)
+        .await?
+        .bytes()
+        .await?;
+
+    let data_sha256 = to_hex(&Sha256::digest(&data));
+    if data_sha256 != manifest.data_sha256 {
+        return Err(format!("Archive of {} is corrupted: SHA-256 is {}, expected {}", partition, data_sha256, manifest.data_sha256).into());
+    }
+
+    let table = partition.replacen(PARTITION_PREFIX, "audit_event_restored_y", 1);
+    db::create_restore_table(&table)?;
+
+    let mut batch = Vec::with_capacity(ARCHIVE_PAGE_SIZE as usize);
+    for line in BufReader::new(GzDecoder::new(&data[..])).lines() {
+        batch.push(line?);
+        if batch.len() as i64 >= ARCHIVE_PAGE_SIZE {
+            db::insert_archived_rows(&table, &batch)?;
+            batch.clear();
+        }
+    }
+    if !batch.is_empty() {
+        db::insert_archived_rows(&table, &batch)?;
+    }
+
+    let restored = db::count_rows(&table)?;
+    if restored != manifest.row_count {
+        return Err(format!("Restored {} events to {}, but the manifest lists {}", restored, table, manifest.row_count).into());
+    }
+
+    Ok(table)
+}
+
+
+fn month_start(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).expect("Every month has a first day")
+}
+
+fn partition_name(month: NaiveDate) -> String {
+    format!("{}{}", PARTITION_PREFIX, month.format("%Ym%m"))
+}
+
+// the default partition, and any other tables, do not parse
+fn parse_partition_name(partition: &str) -> Option<NaiveDate> {
+    let (year, month) = partition.strip_prefix(PARTITION_PREFIX)?.split_once('m')?;
+    NaiveDate::from_ymd_opt(year.parse().ok()?, month.parse().ok()?, 1)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use storage::LocalStore;
+
+    fn date(year: i32, month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(year, month, day).unwrap()
+    }
+
+    fn manifest(partition: &str, last_id: Option<i32>, last_row_hash: Option<&str>) -> ArchiveManifest {
+        ArchiveManifest {
+            partition: partition.to_string(),
+            month: "2025-01".to_string(),
+            format: "jsonl.gz".to_string(),
+            data_key: format!("{}/events.jsonl.gz", partition),
+            data_sha256: String::new(),
+            row_count: 10,
+            first_id: last_id.map(|id| id - 9),
+            last_id,
+            last_row_hash: last_row_hash.map(|hash| hash.to_string()),
+            archived_at: chrono::Utc::now(),
+        }
+    }
+
+    #[test]
+    fn partition_names_round_trip() {
+        assert_eq!(partition_name(date(2025, 1, 1)), "audit_event_y2025m01");
+        assert_eq!(parse_partition_name("audit_event_y2025m01"), Some(date(2025, 1, 1)));
+        assert_eq!(parse_partition_name("audit_event_default"), None);
+        assert_eq!(parse_partition_name("audit_event_y2025m13"), None);
+    }
+
+    #[test]
+    fn expires_the_partitions_older_than_the_retention_oldest_first() {
+        let partitions = vec![
+            "audit_event_y2025m03".to_string(),
+            "audit_event_y2025m01".to_string(),
+            "audit_event_default".to_string(),
+            "audit_event_y2025m04".to_string(),
+            "audit_event_y2025m02".to_string(),
+        ];
+
+        assert_eq!(expired_partitions(partitions, 1, date(2025, 4, 15)), vec![
+            ("audit_event_y2025m01".to_string(), date(2025, 1, 1)),
+            ("audit_event_y2025m02".to_string(), date(2025, 2, 1)),
+        ]);
+    }
+
+    #[tokio::test]
+    async fn reads_the_chain_anchors_from_the_manifests() {
+        let root = env::temp_dir().join(format!("audit-retention-test-{}", std::process::id()));
+        let destination = Location { store: Arc::new(LocalStore::new(root.clone())), prefix: "archive/".to_string() };
+
+        for manifest in [
+            manifest("audit_event_y2025m02", Some(20), Some("hash of 20")),
+            manifest("audit_event_y2025m01", Some(10), Some("hash of 10")),
+            // archived before the chain was introduced
+            manifest("audit_event_y2024m12", Some(5), None),
+        ] {
+            let key = destination.key(&format!("{}/manifest.json", manifest.partition));
+            destination.store.put(&key, serde_json::to_vec(&manifest).unwrap().into(), None).await.unwrap();
+        }
+        destination.store.put(&destination.key("audit_event_y2025m01/events.jsonl.gz"), "".into(), None).await.unwrap();
+
+        let anchors = chain_anchors(&destination).await.unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+
+        assert_eq!(anchors, vec![
+            ChainAnchor { event_id: 10, row_hash: "hash of 10".to_string() },
+            ChainAnchor { event_id: 20, row_hash: "hash of 20".to_string() },
+        ]);
+    }
+}
@@ -0,0 +1,133 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use auth_check::UserAuth;
+use config::{Secret, Source};
+use storage::Location;
+use worker::WorkerSettings;
+
+use crate::redaction::{self, RedactionRule};
+use crate::siem::SiemForwarder;
+
+
+/// The settings of the audit service, loaded once at startup and shared with the handlers and the background jobs.
+#[derive(Debug)]
+pub struct Settings {
+    pub port: u16,
+    pub database_url: Secret<String>,
+    pub database_pool_size: u32,
+    /// Receives the audit events of the other services
+    pub worker: WorkerSettings,
+    /// Can use the audit API, from the comma separated `ADMIN_USER_IDS`
+    pub admin_user_ids: Vec<String>,
+    /// Applied to the event details before they are stored, see `redaction::parse_rules`
+    pub redaction_rules: Vec<RedactionRule>,
+    /// Used for the hash rules and for pseudonymizing the client IPs
+    pub redaction_hmac_key: Option<Secret<String>>,
+    /// Keys the client IP digests the hash chain covers. Kept apart from the database, as the pseudonymized IPs
+    /// could be recovered from the digests with it
+    pub chain_key: Secret<String>,
+    /// The client IPs older than this are pseudonymized, or kept as is if not set
+    pub pseudonymize_ip_after_days: Option<i64>,
+    /// No checkpoints of the hash chain are written if not set
+    pub checkpoint: Option<CheckpointSettings>,
+    /// The partitions older than this are archived and dropped, or kept forever if not set
+    pub retention_months: Option<u32>,
+    /// `s3://<bucket>/<prefix>` or `file://<directory>` the expired partitions are archived to, and restored from
+    pub archive_destination: Option<Location>,
+    /// Where the stored events are forwarded to, see `SiemForwarder`
+    pub siem_forwarders: Vec<SiemForwarder>,
+    /// Sent as the host of the forwarded syslog messages
+    pub hostname: String,
+    /// No alerts are fired if not set
+    pub alert_rules_path: Option<PathBuf>,
+    /// Verifies the users with the auth service
+    pub auth: Arc<UserAuth>,
+}
+
+#[derive(Debug)]
+pub struct CheckpointSettings {
+    pub destination: CheckpointDestination,
+    pub interval_minutes: u64,
+    pub signing_key: Secret<String>,
+}
+
+#[derive(Debug)]
+pub enum CheckpointDestination {
+    /// `s3://<bucket>/<prefix>` or `file://<directory>`, each checkpoint is written to an object of its own
+    Location(Location),
+    /// Any other value is a path to a local file, the checkpoints are appended to it
+    File(PathBuf),
+}
+
+impl CheckpointDestination {
+    fn parse(value: &str, use_path_style_buckets: bool) -> Result<Self, String> {
+        if value.starts_with("s3://") || value.starts_with("file://") {
+            Location::parse(value, use_path_style_buckets).map(CheckpointDestination::Location)
+        } else {
+            Ok(CheckpointDestination::File(PathBuf::from(value)))
+        }
+    }
+}
+
+impl Settings {
+    /// Loads the settings from the environment and the optional `CONFIG_FILE`.
+    ///
+    /// Exits the process with every missing or invalid setting listed, if there are any.
+    pub fn load() -> Self {
+        config::load_or_exit(Settings::from_source)
+    }
+
+    /// Reads the settings from the source, reporting the missing and invalid ones to it.
+    pub fn from_source(source: &mut Source) -> Self {
+        // only for the S3 destinations, the audit service stores nothing else
+        let use_path_style_buckets = source.flag("USE_PATH_STYLE_BUCKETS", false);
+
+        let settings = Settings {
+            port: source.with_default("PORT", 3000),
+            database_url: source.secret("DATABASE_URL"),
+            database_pool_size: source.with_default("DATABASE_POOL_SIZE", 10),
+            worker: WorkerSettings::from_source(source, "AUDIT_EVENT_QUEUE_URL", "AUDIT_EVENT_DLQ_URL"),
+            admin_user_ids: source.list("ADMIN_USER_IDS"),
+            redaction_rules: source.parsed_with("AUDIT_REDACTION_RULES", vec![], redaction::parse_rules),
+            redaction_hmac_key: source.optional_secret("AUDIT_REDACTION_HMAC_KEY"),
+            chain_key: source.secret("AUDIT_CHAIN_KEY"),
+            pseudonymize_ip_after_days: source.optional("AUDIT_PSEUDONYMIZE_IP_AFTER_DAYS"),
+            checkpoint: source.parsed_with("AUDIT_CHECKPOINT_DESTINATION", None, |value| {
+                CheckpointDestination::parse(value, use_path_style_buckets).map(Some)
+            }).map(|destination| CheckpointSettings {
+                destination,
+                interval_minutes: source.with_default("AUDIT_CHECKPOINT_INTERVAL_MINUTES", 60),
+                signing_key: source.secret("AUDIT_CHECKPOINT_SIGNING_KEY"),
+            }),
+            retention_months: source.optional("AUDIT_RETENTION_MONTHS"),
+            archive_destination: source.parsed_with("AUDIT_ARCHIVE_DESTINATION", None, |value| {
+                Location::parse(value, use_path_style_buckets).map(Some)
+            }),
+            siem_forwarders: source.list("AUDIT_SIEM_FORWARDERS"),
+            hostname: source.with_default("HOSTNAME", "-".to_string()),
+            alert_rules_path: source.optional("AUDIT_ALERT_RULES_PATH"),
+            auth: Arc::new(UserAuth::from_source(source)),
+        };
+
+        if settings.redaction_hmac_key.is_none() {
+            if settings.redaction_rules.iter().any(RedactionRule::hashes) {
+                source.invalid("AUDIT_REDACTION_HMAC_KEY", "is not set, but AUDIT_REDACTION_RULES contains hash rules");
+            }
+            if settings.pseudonymize_ip_after_days.is_some() {
+                source.invalid("AUDIT_REDACTION_HMAC_KEY", "is not set, it is needed for pseudonymizing the client IPs");
+            }
+        }
+
+        // an invalid destination is reported as such, not as a missing one
+        if settings.retention_months.is_some() && source.optional::<String>("AUDIT_ARCHIVE_DESTINATION").is_none() {
+            source.invalid("AUDIT_ARCHIVE_DESTINATION", "is not set, it is needed for AUDIT_RETENTION_MONTHS");
+        }
+
+        settings
+    }
+
+    pub fn is_admin(&self, user_id: &str) -> bool {
+        self.admin_user_ids.iter().any(|admin| admin == user_id)
+    }
+}
@@ -0,0 +1,440 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::SecondsFormat;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::db::{self, AuditEventRecord};
+
+
+// how many events are read and sent at a time. The cursor is saved after each batch.
+const FORWARD_BATCH_SIZE: i64 = 500;
+
+// how long we wait before checking for new events, when everything has been forwarded
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// how long we wait before reconnecting, after the SIEM could not be reached
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(30);
+
+// how long we wait before trying again, while another instance is forwarding to the SIEM
+const LOCK_RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+const APP_NAME: &str = "videosite-audit";
+
+// private enterprise number reserved for documentation (RFC 5612)
+const SD_ID: &str = "audit@32473";
+
+// syslog facility 13, "log audit"
+const SYSLOG_FACILITY: u8 = 13;
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SiemFormat {
+    /// RFC 5424 syslog, with the event fields as structured data
+    Syslog,
+    /// ArcSight CEF, carried in the message part of an RFC 5424 syslog message
+    Cef,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SiemTransport {
+    Tcp,
+    Tls,
+}
+
+#[derive(Debug, Clone)]
+pub struct SiemForwarder {
+    /// The configuration entry, also used as the key of the persisted cursor
+    pub name: String,
+    pub format: SiemFormat,
+    pub transport: SiemTransport,
+    pub host: String,
+    pub port: u16,
+}
+
+/// Parses a `<format>=<transport>://<host>:<port>` entry of `AUDIT_SIEM_FORWARDERS`, where the format is `syslog`
+/// or `cef` and the transport is `tcp` or `tls`. For example `cef=tls://siem.example.com:6514`
+///
+/// Each forwarder keeps its own cursor, keyed by the entry. Changing the entry starts the forwarding
+/// from the first stored event again.
+impl FromStr for SiemForwarder {
+    type Err = String;
+
+    fn from_str(entry: &str) -> Result<Self, Self::Err> {
+        let (format, url) = entry.split_once('=').ok_or("expected <format>=<transport>://<host>:<port>")?;
+
+        let format = match format {
+            "syslog" => SiemFormat::Syslog,
+            "cef" => SiemFormat::Cef,
+            _ => return Err(format!("unknown format {}", format)),
+        };
+
+        let (transport, address) = url.split_once("://").ok_or("missing the transport")?;
+        let transport = match transport {
+            "tcp" => SiemTransport::Tcp,
+            "tls" => SiemTransport::Tls,
+            _ => return Err(format!("unknown transport {}", transport)),
+        };
+
+        let (host, port) = address.rsplit_once(':').ok_or("missing the port")?;
+        let port = port.parse::<u16>().map_err(|err| format!("invalid port: {}", err))?;
+
+        Ok(SiemForwarder {
+            name: entry.to_string(),
+            format,
+            transport,
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+
+/// Forwards the stored events to the SIEM, oldest first, and keeps doing so as new events arrive.
+///
+/// The cursor is saved only after a batch has been written and flushed, so after a restart or a lost connection
+/// the last batch may be sent again, but no events are skipped.
+///
+/// Only one instance forwards to each SIEM, the one holding its advisory lock. The others keep trying to take
+/// over, in case that instance goes away. The lock keeps a database connection for as long as it is held.
+///
+/// # Arguments
+/// * `hostname` - Sent as the host of the syslog messages
+pub async fn forward_events(forwarder: SiemForwarder, hostname: String) {
+    let job = format!("siem-forwarder:{}", forwarder.name);
+
+    loop {
+        let mut lock = match db::try_lock_job(&job) {
+            Ok(Some(lock)) => lock,
+            Ok(None) => {
+                tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+                continue;
+            }
+            Err(err) => {
+                tracing::error!("Error locking the forwarding to SIEM {}: {}", forwarder.name, err);
+                tokio::time::sleep(LOCK_RETRY_INTERVAL).await;
+                continue;
+            }
+        };
+
+        let mut connection = match connect(&forwarder).await {
+            Ok(connection) => connection,
+            Err(err) => {
+                tracing::error!("Failed to connect to SIEM {}: {}", forwarder.name, err);
+                tokio::time::sleep(RECONNECT_INTERVAL).await;
+                continue;
+            }
+        };
+
+        tracing::info!("Connected to SIEM {}", forwarder.name);
+
+        if let Err(err) = forward_batches(&forwarder, &hostname, &mut connection, &mut lock).await {
+            tracing::error!("Error forwarding audit events to SIEM {}: {}", forwarder.name, err);
+            tokio::time::sleep(RECONNECT_INTERVAL).await;
+        }
+    }
+}
+
+async fn forward_batches(
+    forwarder: &SiemForwarder,
+    hostname: &str,
+    connection: &mut (dyn AsyncWrite + Unpin + Send),
+    lock: &mut db::JobLock,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let mut cursor = db::get_forwarder_cursor(&forwarder.name)?.unwrap_or(0);
+
+    loop {
+        // another instance may have taken over, and moved the cursor
+        if !lock.is_held() {
+            return Err("Lost the forwarding lock".into());
+        }
+
+        let events = db::get_events_after(cursor, FORWARD_BATCH_SIZE)?;
+
+        if events.is_empty() {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            continue;
+        }
+
+        let mut data = Vec::new();
+        for event in &events {
+            let message = match forwarder.format {
+                SiemFormat::Syslog => format_syslog(event, hostname),
+                SiemFormat::Cef => format_cef(event, hostname),
+            };
+            // octet counting framing, RFC 6587
+            data.extend_from_slice(format!("{} {}", message.len(), message).as_bytes());
+        }
+
+        connection.write_all(&data).await?;
+        connection.flush().await?;
+
+        cursor = events.last().map(|event| event.id).unwrap_or(cursor);
+        db::save_forwarder_cursor(&forwarder.name, cursor)?;
+
+        tracing::debug!("Forwarded {} audit events to SIEM {}", events.len(), forwarder.name);
+    }
+}
+
+async fn connect(forwarder: &SiemForwarder) -> Result<Box<dyn AsyncWrite + Unpin + Send>, Box<dyn std::error::Error + Send + Sync>> {
+    let stream = TcpStream::connect((forwarder.host.as_str(), forwarder.port)).await?;
+
+    match forwarder.transport {
+        SiemTransport::Tcp => Ok(Box::new(stream)),
+        SiemTransport::Tls => {
+            let connector = tokio_native_tls::TlsConnector::from(native_tls::TlsConnector::new()?);
+            Ok(Box::new(connector.connect(&forwarder.host, stream).await?))
+        }
+    }
+}
+
+
+/// Formats the event as RFC 5424 syslog message.
+///
+/// `event_action` is the MSGID, the other fields are in the `audit@32473` structured data element,
+/// and `additional_info` is the message, as JSON.
+fn format_syslog(event: &AuditEventRecord, hostname: &str) -> String {
+    let mut structured_data = format!(
+        "[{} id=\"{}\" client_ip=\"{}\"",
+        SD_ID, event.id, escape_sd_value(&event.client_ip)
+    );
+    if let Some(user_id) = event.user_id {
+        structured_data.push_str(&format!(" user_id=\"{}\"", user_id));
+    }
+    if let Some(target_kind) = &event.target_kind {
+        structured_data.push_str(&format!(" target_kind=\"{}\"", escape_sd_value(target_kind)));
+    }
+    if let Some(target_id) = &event.target_id {
+        structured_data.push_str(&format!(" target_id=\"{}\"", escape_sd_value(target_id)));
+    }
+    structured_data.push(']');
+
+    let message = event.additional_info
+        .as_ref()
+        .map(|info| info.to_string())
+        .unwrap_or_default();
+
+    format!(
+        "{} {} {}",
+        syslog_header(event, hostname, &header_value(&event.event_action, 32)),
+        structured_data,
+        message
+    )
+}
+
+/// Formats the event as CEF message, in an RFC 5424 syslog message without structured data.
+///
+/// Mapping: `event_action` is the signature ID, name and `act`, `client_ip` is `src` (or `cs4` when it has been
+/// pseudonymized), `user_id` is `suid`, the target is `cs1` and `cs2`, and `additional_info` is `cs3`, as JSON.
+fn format_cef(event: &AuditEventRecord, hostname: &str) -> String {
+    let mut extension = vec![
+        format!("rt={}", event.event_timestamp.timestamp_millis()),
+        format!("externalId={}", event.id),
+        format!("act={}", escape_cef_extension(&event.event_action)),
+    ];
+
+    if event.client_ip.parse::<IpAddr>().is_ok() {
+        extension.push(format!("src={}", event.client_ip));
+    } else {
+        extension.push("cs4Label=clientIpPseudonym".to_string());
+        extension.push(format!("cs4={}", escape_cef_extension(&event.client_ip)));
+    }
+    if let Some(user_id) = event.user_id {
+        extension.push(format!("suid={}", user_id));
+    }
+    if let Some(target_kind) = &event.target_kind {
+        extension.push("cs1Label=targetKind".to_string());
+        extension.push(format!("cs1={}", escape_cef_extension(target_kind)));
+    }
+    if let Some(target_id) = &event.target_id {
+        extension.push("cs2Label=targetId".to_string());
+        extension.push(format!("cs2={}", escape_cef_extension(target_id)));
+    }
+    if let Some(info) = &event.additional_info {
+        extension.push("cs3Label=additionalInfo".to_string());
+        extension.push(format!("cs3={}", escape_cef_extension(&info.to_string())));
+    }
+
+    let cef = format!(
+        "CEF:0|Videosite|{}|{}|{}|{}|{}|{}",
+        APP_NAME,
+        env!("CARGO_PKG_VERSION"),
+        escape_cef_header(&event.event_action),
+        escape_cef_header(&event.event_action.replace('_', " ")),
+        cef_severity(&event.event_action),
+        extension.join(" ")
+    );
+
+    format!("{} - {}", syslog_header(event, hostname, "-"), cef)
+}
+
+fn syslog_header(event: &AuditEventRecord, hostname: &str, msg_id: &str) -> String {
+    let priority = SYSLOG_FACILITY * 8 + syslog_severity(&event.event_action);
+
+    format!(
+        "<{}>1 {} {} {} - {}",
+        priority,
+        event.event_timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+        header_value(hostname, 255),
+        APP_NAME,
+        msg_id
+    )
+}
+
+// failures are warnings (4), everything else is a notice (5)
+fn syslog_severity(event_action: &str) -> u8 {
+    if is_failure(event_action) { 4 } else { 5 }
+}
+
+fn cef_severity(event_action: &str) -> u8 {
+    if is_failure(event_action) { 7 } else { 3 }
+}
+
+fn is_failure(event_action: &str) -> bool {
+    event_action.ends_with("_failure") || event_action.ends_with("_failed")
+}
+
+// the header fields are printable ASCII without spaces, and have a maximum length
+fn header_value(value: &str, max_length: usize) -> String {
+    let value: String = value.chars()
+        .filter(|c| c.is_ascii_graphic())
+        .take(max_length)
+        .collect();
+
+    if value.is_empty() { "-".to_string() } else { value }
+}
+
+fn escape_sd_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace(']', "\\]")
+}
+
+fn escape_cef_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+fn escape_cef_extension(value: &str) -> String {
+    value.replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use chrono::DateTime;
+    use serde_json::json;
+
+    use super::*;
+
+    fn event(event_action: &str) -> AuditEventRecord {
+        AuditEventRecord {
+            id: 7,
+            event_timestamp: DateTime::from_timestamp_millis(1_700_000_000_123).unwrap(),
+            event_action: event_action.to_string(),
+            user_id: Some(uuid::Uuid::from_u128(1)),
+            client_ip: "192.0.2.1".to_string(),
+            target_kind: None,
+            target_id: None,
+            schema_version: 1,
+            additional_info: None,
+        }
+    }
+
+    #[test]
+    fn parses_forwarders() {
+        let forwarder: SiemForwarder = "cef=tls://siem.example.com:6514".parse().unwrap();
+
+        assert_eq!(forwarder.name, "cef=tls://siem.example.com:6514");
+        assert_eq!(forwarder.format, SiemFormat::Cef);
+        assert_eq!(forwarder.transport, SiemTransport::Tls);
+        assert_eq!(forwarder.host, "siem.example.com");
+        assert_eq!(forwarder.port, 6514);
+
+        assert!("syslog=udp://siem.example.com:514".parse::<SiemForwarder>().is_err());
+        assert!("json=tcp://siem.example.com:514".parse::<SiemForwarder>().is_err());
+        assert!("syslog=tcp://siem.example.com".parse::<SiemForwarder>().is_err());
+        assert!("tcp://siem.example.com:514".parse::<SiemForwarder>().is_err());
+    }
+
+    #[test]
+    fn formats_syslog() {
+        let event = AuditEventRecord {
+            target_kind: Some("resource".to_string()),
+            target_id: Some(r#"a"b]c\d"#.to_string()),
+            additional_info: Some(json!({ "reason": "bad password" })),
+            ..event("login_failure")
+        };
+
+        assert_eq!(
+            format_syslog(&event, "web-1"),
+            concat!(
+                r#"<108>1 2023-11-14T22:13:20.123Z web-1 videosite-audit - login_failure "#,
+                r#"[audit@32473 id="7" client_ip="192.0.2.1" user_id="00000000-0000-0000-0000-000000000001" "#,
+                r#"target_kind="resource" target_id="a\"b\]c\\d"] {"reason":"bad password"}"#,
+            )
+        );
+    }
+
+    #[test]
+    fn formats_syslog_without_the_optional_fields() {
+        let event = AuditEventRecord { user_id: None, ..event("file_upload") };
+
+        assert_eq!(
+            format_syslog(&event, ""),
+            r#"<109>1 2023-11-14T22:13:20.123Z - videosite-audit - file_upload [audit@32473 id="7" client_ip="192.0.2.1"] "#
+        );
+    }
+
+    #[test]
+    fn formats_cef() {
+        let event = AuditEventRecord {
+            target_kind: Some("resource".to_string()),
+            target_id: Some("a=b|c\\d\nline".to_string()),
+            additional_info: Some(json!({ "name": "x=y" })),
+            ..event("file_upload")
+        };
+
+        assert_eq!(
+            format_cef(&event, "web 1"),
+            format!(
+                concat!(
+                    r#"<109>1 2023-11-14T22:13:20.123Z web1 videosite-audit - - - "#,
+                    r#"CEF:0|Videosite|videosite-audit|{}|file_upload|file upload|3|"#,
+                    r#"rt=1700000000123 externalId=7 act=file_upload src=192.0.2.1 suid=00000000-0000-0000-0000-000000000001 "#,
+                    r#"cs1Label=targetKind cs1=resource cs2Label=targetId cs2=a\=b|c\\d\nline "#,
+                    r#"cs3Label=additionalInfo cs3={{"name":"x\=y"}}"#,
+                ),
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+    }
+
+    #[test]
+    fn formats_pseudonymized_ips_as_cef_custom_strings() {
+        let event = AuditEventRecord { client_ip: "hmac:abc".to_string(), user_id: None, ..event("login_failure") };
+        let cef = format_cef(&event, "web-1");
+
+        assert!(cef.ends_with("|login_failure|login failure|7|rt=1700000000123 externalId=7 act=login_failure cs4Label=clientIpPseudonym cs4=hmac:abc"));
+        assert!(cef.starts_with("<108>1 "));
+    }
+
+    #[test]
+    fn escapes_cef_headers() {
+        assert_eq!(escape_cef_header(r"a|b\c=d"), r"a\|b\\c=d");
+    }
+
+    #[test]
+    fn escapes_cef_extensions() {
+        assert_eq!(escape_cef_extension("a|b\\c=d\r\n"), r"a|b\\c\=d\r\n");
+    }
+
+    #[test]
+    fn limits_the_header_values() {
+        assert_eq!(header_value("web 1\u{e9}", 255), "web1");
+        assert_eq!(header_value("login_failure", 5), "login");
+        assert_eq!(header_value(" ", 255), "-");
+    }
+}
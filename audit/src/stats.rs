@@ -0,0 +1,41 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+
+static RECEIVED_EVENTS: AtomicU64 = AtomicU64::new(0);
+static REJECTED_EVENTS: AtomicU64 = AtomicU64::new(0);
+static USER_ID_PARSE_ERRORS: AtomicU64 = AtomicU64::new(0);
+
+
+/// Counters of the events received since the service started.
+#[derive(Debug, serde::Serialize)]
+pub struct IngestStats {
+    pub received_events: u64,
+    /// Events that were not stored, because they could not be parsed or did not match the schema
+    pub rejected_events: u64,
+    /// Events stored without the user, because the user ID was not a valid UUID
+    pub user_id_parse_errors: u64,
+}
+
+pub fn record_received_event() {
+    RECEIVED_EVENTS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_rejected_event() {
+    REJECTED_EVENTS.fetch_add(1, Ordering::Relaxed);
+}
+
+pub fn record_user_id_parse_error(event_action: &str, user_id: &str, err: uuid::Error) {
+    let count = USER_ID_PARSE_ERRORS.fetch_add(1, Ordering::Relaxed) + 1;
+    tracing::warn!(
+        "Invalid user ID '{}' in {} event, storing the event without the user ({} such errors so far): {}",
+        user_id, event_action, count, err
+    );
+}
+
+pub fn ingest_stats() -> IngestStats {
+    IngestStats {
+        received_events: RECEIVED_EVENTS.load(Ordering::Relaxed),
+        rejected_events: REJECTED_EVENTS.load(Ordering::Relaxed),
+        user_id_parse_errors: USER_ID_PARSE_ERRORS.load(Ordering::Relaxed),
+    }
+}
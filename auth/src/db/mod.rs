@@ -1,11 +1,16 @@
 mod schema;
 
+use std::sync::OnceLock;
+
 use diesel::prelude::*;
 use uuid::Uuid;
 
 use schema::active_users;
 
 
+static DATABASE_URL: OnceLock<String> = OnceLock::new();
+
+
 
 
 #[derive(Queryable, Selectable)]
@@ -26,13 +31,11 @@ pub fn get_user_by_email(user_email: &str) -> Option<User> {
 
     let mut connection = get_connection();
 
-    let result = active_users
+    active_users
         .filter(email.eq(user_email))
         .first::<User>(&mut connection)
         .optional()
-        .expect("Error loading user");
-
-    result
+        .expect("Error loading user")
 }
 
 pub fn get_user_by_id(user_id: &str) -> Option<User> {
@@ -40,13 +43,12 @@ pub fn get_user_by_id(user_id: &str) -> Option<User> {
 
     let mut connection = get_connection();
     let user_id_uuid = Uuid::parse_str(user_id).unwrap();
-    let result = active_users
+
+    active_users
         .filter(id.eq(user_id_uuid))
         .first::<User>(&mut connection)
         .optional()
-        .expect("Error loading user");
-
-    result
+        .expect("Error loading user")
 }
 
 pub fn update_user_password(user_id: Uuid, new_password_hash: &str) -> Result<(), diesel::result::Error> {
@@ -64,7 +66,13 @@ pub fn update_user_password(user_id: Uuid, new_password_hash: &str) -> Result<()
     Ok(())
 }
 
+/// Sets the database the functions connect to, must be called once at startup
+pub fn init(database_url: &str) {
+    DATABASE_URL.set(database_url.to_string()).expect("The database is already set");
+}
+
+// the URL is left out of the messages, it contains the password
 fn get_connection() -> PgConnection {
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url))
+    let database_url = DATABASE_URL.get().expect("db::init was not called");
+    PgConnection::establish(database_url).unwrap_or_else(|err| panic!("Error connecting to the database: {}", err))
 }
\ No newline at end of file
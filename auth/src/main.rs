@@ -1,6 +1,7 @@
 mod db;
+mod settings;
 
-use std::env;
+use std::sync::Arc;
 use std::time::Duration;
 use std::time::{ SystemTime };
 
@@ -17,11 +18,13 @@ use axum::{
     },
     http::StatusCode,
     response::{IntoResponse, Redirect},
+    middleware::from_fn_with_state,
+    extract::State,
 };
 
 use axum_extra::extract::cookie::CookieJar;
 
-use axum_client_ip::{ClientIp, ClientIpSource};
+use axum_client_ip::ClientIp;
 
 use josekit::JoseError;
 use josekit::{jws::{JwsHeader, HS256}, jwt::{self, JwtPayload}, Value};
@@ -31,16 +34,20 @@ use scrypt::{
     Scrypt,
 };
 
-use tracing;
 use tracing_subscriber::filter;
-use tracing_subscriber;
 
 use serde::{Deserialize, Serialize, ser::SerializeStruct };
 
 use db::{User, get_user_by_email};
+use settings::Settings;
 
 
-use audit::{send_audit_event, AuditEvent};
+use audit::{
+    send_audit_event, AuditEvent, AuditEventKind, LoginFailureDetails, LoginFailureReason,
+    PasswordChangeFailedDetails, PasswordChangeFailureReason,
+};
+
+use auth_check::service_auth_middleware;
 
 
 
@@ -96,7 +103,7 @@ impl Serialize for LoginResponse {
 ////// # Returns
 /// * `StatusCode::OK` if the token is valid.
 /// * `StatusCode::UNAUTHORIZED` if the token is missing or invalid.
-async fn verify_jwt_via_cookie(ClientIp(client_ip): ClientIp, cookie_jar: CookieJar) -> StatusCode {
+async fn verify_jwt_via_cookie(State(settings): State<Arc<Settings>>, ClientIp(client_ip): ClientIp, cookie_jar: CookieJar) -> StatusCode {
     let token = match cookie_jar.get("session") {
         Some(cookie) => cookie.value().to_string(),
         None => { 
@@ -105,7 +112,7 @@ async fn verify_jwt_via_cookie(ClientIp(client_ip): ClientIp, cookie_jar: Cookie
         }
     };
 
-    if verify_token(&token, &client_ip.to_string()).await {
+    if verify_token(&settings, &token, &client_ip.to_string()).await {
         tracing::debug!("Token verification successful");
         StatusCode::OK
     } else {
@@ -117,6 +124,8 @@ async fn verify_jwt_via_cookie(ClientIp(client_ip): ClientIp, cookie_jar: Cookie
 /// Verifies a JWT token provided in the request body.
 /// 
 /// This is used by the sibling services to verify the user is who they claim to be.
+/// The route is internal-only: the caller must present a valid service token (see `service_auth_middleware`),
+/// which is also what allows us to trust the X-Client-IP header set by the caller.
 /// 
 /// # Arguments
 /// * `headers`: The request headers containing the client IP address.
@@ -125,16 +134,17 @@ async fn verify_jwt_via_cookie(ClientIp(client_ip): ClientIp, cookie_jar: Cookie
 /// * `StatusCode::OK` if the token is valid.
 /// * `StatusCode::UNAUTHORIZED` if the token is invalid.car
 ///  
-async fn verify_jwt(headers: HeaderMap,  payload: Json<TokenVerificationRequest>) -> StatusCode {
+async fn verify_jwt(State(settings): State<Arc<Settings>>, headers: HeaderMap,  payload: Json<TokenVerificationRequest>) -> StatusCode {
 
     // this endpoint is not coming directly from the client, so Nginx stock headers are not useful
     // and we do not use the extractor. The auth check lib instead will set X-Client-IP header,
-    // and we use that to verify the token.
+    // and we use that to verify the token. The header can be trusted, as the service middleware
+    // has already verified the caller is one of our services.
     let client_ip = headers.get("X-Client-IP")
         .and_then(|value| value.to_str().ok())
         .unwrap_or("unknown");
 
-    if verify_token(&payload.token, &client_ip.to_string()).await {
+    if verify_token(&settings, &payload.token, client_ip).await {
         StatusCode::OK
     } else {
         StatusCode::UNAUTHORIZED
@@ -151,7 +161,7 @@ async fn verify_jwt(headers: HeaderMap,  payload: Json<TokenVerificationRequest>
 /// * `StatusCode::OK` with a JSON response containing the result of the login attempt
 /// * `StatusCode::UNAUTHORIZED` if the credentials are invalid.
 /// 
-async fn login_handler(ClientIp(client_ip): ClientIp,Json(payload): Json<LoginRequest>) -> impl IntoResponse {
+async fn login_handler(State(settings): State<Arc<Settings>>, ClientIp(client_ip): ClientIp,Json(payload): Json<LoginRequest>) -> impl IntoResponse {
 
     // TODO: Fetch user from database and validate credentials
     // for now, hardcoded test user
@@ -166,24 +176,25 @@ async fn login_handler(ClientIp(client_ip): ClientIp,Json(payload): Json<LoginRe
         if password_equals(&user.password_hash, &payload.password) {
             tracing::debug!("User {} logged in successfully", user.id);
             user_id = user.id.to_string();
-            cookie = format!("session={}; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age=43200", generate_jwt(user));
+            cookie = format!("session={}; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age=43200", generate_jwt(&settings, user));
         } else {
             tracing::debug!("Invalid password for user: {}", user.id);
             let json = Json(LoginResponse {
                 res: Err("Invalid username or password".to_string()),
             });
             send_audit_event(
-                AuditEvent {
-                    event_type: "login_failure".to_string(),
-                    user_id: None,
-                    client_ip: &client_ip.to_string(),
-                    target: None,
-                    event_details: Some(serde_json::json!({
-                        "username": payload.username,
-                        "reason": "Invalid password"
-                    })),
-                }
-            ).await.unwrap();
+                AuditEvent::new(
+                    AuditEventKind::LoginFailure(LoginFailureDetails {
+                        username: payload.username,
+                        reason: LoginFailureReason::InvalidPassword,
+                    }),
+                    None,
+                    &client_ip.to_string(),
+                    None,
+                )
+            ).await.unwrap_or_else(|err| {
+                tracing::error!("Failed to send audit event: {}", err);
+            });
 
             return (StatusCode::OK, headers, json).into_response();
         } 
@@ -196,17 +207,18 @@ async fn login_handler(ClientIp(client_ip): ClientIp,Json(payload): Json<LoginRe
         });
 
         send_audit_event(
-            AuditEvent {
-                event_type: "login_failure".to_string(),
-                user_id: None, 
-                client_ip: &client_ip.to_string(),
-                target: None,
-                event_details: Some(serde_json::json!({
-                    "username": payload.username,
-                    "reason": "User not found"
-                })),
-            }
-        ).await.unwrap();
+            AuditEvent::new(
+                AuditEventKind::LoginFailure(LoginFailureDetails {
+                    username: payload.username,
+                    reason: LoginFailureReason::UserNotFound,
+                }),
+                None,
+                &client_ip.to_string(),
+                None,
+            )
+        ).await.unwrap_or_else(|err| {
+            tracing::error!("Failed to send audit event: {}", err);
+        });
         return (StatusCode::OK, headers, json).into_response();
     }
 
@@ -221,32 +233,29 @@ async fn login_handler(ClientIp(client_ip): ClientIp,Json(payload): Json<LoginRe
     });
 
     send_audit_event(
-        AuditEvent {
-            event_type: "login_success".to_string(),
-            user_id: Some(&user_id),
-            client_ip: &client_ip.to_string(),
-            target: None,
-            event_details: None,
-        }
-    ).await.unwrap();
+        AuditEvent::new(
+            AuditEventKind::LoginSuccess,
+            Some(&user_id),
+            &client_ip.to_string(),
+            None,
+        )
+    ).await.unwrap_or_else(|err| {
+        tracing::error!("Failed to send audit event: {}", err);
+    });
     (StatusCode::OK, headers, json).into_response()
 }
 
 /// Generates a JWT token for the given username.
 /// 
 /// # Arguments
+/// * `settings`: The signing key, issuer and audience of the token.
 /// * `username`: The username for which to generate the token.
 /// # Returns
 /// * A JWT token as a `String`.
 /// # Panics
-/// * If the environment variables `SIGNING_KEY`, `ISSUER`, or `AUDIENCE` are not set.
 /// * If the JWT encoding fails.
 ///
-fn generate_jwt(user: User) -> String {
-    let secret_key = env::var("SIGNING_KEY").expect("SIGNING_KEY environment variable not set");
-    let issuer = env::var("ISSUER").expect("ISSUER environment variable not set");
-    let audience = env::var("AUDIENCE").expect("AUDIENCE environment variable not set");
-
+fn generate_jwt(settings: &Settings, user: User) -> String {
     let now = SystemTime::now();
 
     let mut header = JwsHeader::new();
@@ -254,8 +263,8 @@ fn generate_jwt(user: User) -> String {
 
     let mut payload = JwtPayload::new();
 
-    payload.set_issuer(issuer.as_str());
-    payload.set_audience(vec![audience.as_str()]);
+    payload.set_issuer(settings.issuer.as_str());
+    payload.set_audience(vec![settings.audience.as_str()]);
     payload.set_subject(user.id);
     payload.set_issued_at(&now);
     payload.set_not_before(&now);
@@ -266,7 +275,7 @@ fn generate_jwt(user: User) -> String {
     // FIXME: Move the expiration time to an environment variable
     payload.set_expires_at(&now.checked_add(Duration::from_secs(12*60*60)).unwrap()); 
 
-    let signer = HS256.signer_from_bytes(secret_key.as_bytes())
+    let signer = HS256.signer_from_bytes(settings.signing_key.expose().as_bytes())
         .expect("Failed to create signer from secret key");
 
     jwt::encode_with_signer(&payload, &header, &signer)
@@ -276,32 +285,28 @@ fn generate_jwt(user: User) -> String {
 
 /// Verifies a JWT token.
 /// # Arguments
+/// * `settings`: The signing key, issuer and audience the token must match.
 /// * `token`: The JWT token to verify.
 /// # Returns
 /// * `true` if the token is valid.
 /// * `false` if the token is invalid.
-/// # Panics
-/// * If the environment variables `SIGNING_KEY`, `ISSUER`, or `AUDIENCE` are not set.
-/// * If the JWT decoding fails.
-async fn verify_token(token: &str, client_ip: &str) -> bool {
-    let issuer = env::var("ISSUER").expect("ISSUER environment variable not set");
-    let audience = env::var("AUDIENCE").expect("AUDIENCE environment variable");
+async fn verify_token(settings: &Settings, token: &str, client_ip: &str) -> bool {
     let now = SystemTime::now();
 
 
-    if let Ok((payload, _)) = get_payload(token) {
+    if let Ok((payload, _)) = get_payload(settings, token) {
 
         if payload.expires_at().is_none() || payload.expires_at().unwrap() <= now {
             tracing::debug!("Token verification failed: Token has expired");
             return false;
         }
 
-        if payload.issuer().is_none() || payload.issuer().unwrap() != &issuer {
+        if payload.issuer().is_none() || payload.issuer().unwrap() != settings.issuer {
             tracing::debug!("Token verification failed: Invalid issuer");
             return false;
         }
 
-        if payload.audience().is_none() || !payload.audience().unwrap().contains(&audience.as_str()) {
+        if payload.audience().is_none() || !payload.audience().unwrap().contains(&settings.audience.as_str()) {
             tracing::debug!("Token verification failed: Invalid audience");
             return false;
         }
@@ -322,7 +327,7 @@ async fn verify_token(token: &str, client_ip: &str) -> bool {
         }
 
         tracing::debug!("Token verification successful for user: {}", payload.subject().unwrap());
-        return true;
+        true
     } else {
         // signature verification failed, worth logging the event.
         tracing::warn!("Token verification failed: Invalid token");
@@ -366,27 +371,27 @@ async fn verify_token(token: &str, client_ip: &str) -> bool {
             }
         };
 
-        send_audit_event(AuditEvent {
-            event_type: "token_verification_failure".to_string(),
-            user_id: user_id.as_deref(),
-            client_ip: &client_ip.to_string(), // No client IP available for token verification failure
-            target: None,
-            event_details: None,
-        }).await.unwrap();
+        send_audit_event(AuditEvent::new(
+            AuditEventKind::TokenVerificationFailure,
+            user_id.as_deref(),
+            client_ip, // No client IP available for token verification failure
+            None,
+        )).await.unwrap_or_else(|err| {
+            tracing::error!("Failed to send audit event: {}", err);
+        });
 
         false
     }
 }
 
-fn get_payload(token: &str) -> Result<(JwtPayload, JwsHeader), JoseError> {
-    let secret_key = env::var("SIGNING_KEY").expect("SIGNING_KEY environment variable not set");
-    let verifier = HS256.verifier_from_bytes(secret_key.as_bytes())
+fn get_payload(settings: &Settings, token: &str) -> Result<(JwtPayload, JwsHeader), JoseError> {
+    let verifier = HS256.verifier_from_bytes(settings.signing_key.expose().as_bytes())
         .expect("Failed to create verifier from secret key");
 
-    jwt::decode_with_verifier(&token, &verifier)
+    jwt::decode_with_verifier(token, &verifier)
 }
 
-async fn user_info(ClientIp(client_ip): ClientIp, cookie_jar: CookieJar) -> impl IntoResponse {
+async fn user_info(State(settings): State<Arc<Settings>>, ClientIp(client_ip): ClientIp, cookie_jar: CookieJar) -> impl IntoResponse {
     let token = match cookie_jar.get("session") {
         Some(cookie) => cookie.value().to_string(),
         None => { 
@@ -395,8 +400,8 @@ async fn user_info(ClientIp(client_ip): ClientIp, cookie_jar: CookieJar) -> impl
         }
     };
 
-    if verify_token(&token, client_ip.to_string().as_str()).await {
-        let payload = get_payload(&token).expect("Failed to get payload from token").0;
+    if verify_token(&settings, &token, client_ip.to_string().as_str()).await {
+        let payload = get_payload(&settings, &token).expect("Failed to get payload from token").0;
 
         return Json(
             UserInfo { 
@@ -419,7 +424,7 @@ async fn user_info(ClientIp(client_ip): ClientIp, cookie_jar: CookieJar) -> impl
 ///
 /// Verify the fields + JWT, and if all is good, update the password hash in the database.
 ///  
-async fn change_password(ClientIp(client_ip): ClientIp, cookie_jar: CookieJar, form: axum::extract::Form<std::collections::HashMap<String, String>>) -> Redirect {
+async fn change_password(State(settings): State<Arc<Settings>>, ClientIp(client_ip): ClientIp, cookie_jar: CookieJar, form: axum::extract::Form<std::collections::HashMap<String, String>>) -> Redirect {
 
     let token = match cookie_jar.get("session") {
         Some(cookie) => cookie.value().to_string(),
@@ -430,22 +435,23 @@ async fn change_password(ClientIp(client_ip): ClientIp, cookie_jar: CookieJar, f
         }
     };
 
-    if !verify_token(&token, client_ip.to_string().as_str()).await {
+    if !verify_token(&settings, &token, client_ip.to_string().as_str()).await {
             send_audit_event(
-                AuditEvent {
-                    event_type: "password_change_failed".to_string(),
-                    user_id: None,
-                    client_ip: &client_ip.to_string(),
-                    target: None,
-                    event_details: Some(serde_json::json!({
-                        "reason": "JWT verification failed"
-                    })),
-                }
-            ).await.unwrap();
+                AuditEvent::new(
+                    AuditEventKind::PasswordChangeFailed(PasswordChangeFailedDetails {
+                        reason: PasswordChangeFailureReason::JwtVerificationFailed,
+                    }),
+                    None,
+                    &client_ip.to_string(),
+                    None,
+                )
+            ).await.unwrap_or_else(|err| {
+                tracing::error!("Failed to send audit event: {}", err);
+            });
             return Redirect::to("/login?error=unauthorized");
     }
 
-    let payload = get_payload(&token).expect("Failed to get payload from token").0;
+    let payload = get_payload(&settings, &token).expect("Failed to get payload from token").0;
     let user_id = payload.subject().unwrap();
 
     let current_password = form.get("current_password").unwrap_or(&"".to_string()).to_string();
@@ -454,64 +460,68 @@ async fn change_password(ClientIp(client_ip): ClientIp, cookie_jar: CookieJar, f
 
     if new_password.trim().is_empty() || current_password.trim().is_empty() || confirm_new_password.trim().is_empty() {
             send_audit_event(
-                AuditEvent {
-                    event_type: "password_change_failed".to_string(),
-                    user_id: Some(user_id),
-                    client_ip: &client_ip.to_string(),
-                    target: None,
-                    event_details: Some(serde_json::json!({
-                        "reason": "One or more fields are empty"
-                    })),
-                }
-            ).await.unwrap();
+                AuditEvent::new(
+                    AuditEventKind::PasswordChangeFailed(PasswordChangeFailedDetails {
+                        reason: PasswordChangeFailureReason::EmptyFields,
+                    }),
+                    Some(user_id),
+                    &client_ip.to_string(),
+                    None,
+                )
+            ).await.unwrap_or_else(|err| {
+                tracing::error!("Failed to send audit event: {}", err);
+            });
 
             return Redirect::to("/user.html?error=empty_fields");
     }
 
     if new_password != confirm_new_password {
             send_audit_event(
-                AuditEvent {
-                    event_type: "password_change_failed".to_string(),
-                    user_id: Some(user_id),
-                    client_ip: &client_ip.to_string(),
-                    target: None,
-                    event_details: Some(serde_json::json!({
-                        "reason": "New password and confirmation do not match"
-                    })),
-                }
-            ).await.unwrap();
+                AuditEvent::new(
+                    AuditEventKind::PasswordChangeFailed(PasswordChangeFailedDetails {
+                        reason: PasswordChangeFailureReason::PasswordMismatch,
+                    }),
+                    Some(user_id),
+                    &client_ip.to_string(),
+                    None,
+                )
+            ).await.unwrap_or_else(|err| {
+                tracing::error!("Failed to send audit event: {}", err);
+            });
 
             return Redirect::to("/user.html?error=password_mismatch");
     }
 
     if new_password == current_password {
             send_audit_event(
-                AuditEvent {
-                    event_type: "password_change_failed".to_string(),
-                    user_id: Some(user_id),
-                    client_ip: &client_ip.to_string(),
-                    target: None,
-                    event_details: Some(serde_json::json!({
-                        "reason": "New password is the same as the current password"
-                    })),
-                }
-            ).await.unwrap();
+                AuditEvent::new(
+                    AuditEventKind::PasswordChangeFailed(PasswordChangeFailedDetails {
+                        reason: PasswordChangeFailureReason::SamePassword,
+                    }),
+                    Some(user_id),
+                    &client_ip.to_string(),
+                    None,
+                )
+            ).await.unwrap_or_else(|err| {
+                tracing::error!("Failed to send audit event: {}", err);
+            });
 
             return Redirect::to("/user.html?error=same_password");
     }
 
     if estimate_password_strength(&new_password) < 70 {
             send_audit_event(
-                AuditEvent {
-                    event_type: "password_change_failed".to_string(),
-                    user_id: Some(user_id),
-                    client_ip: &client_ip.to_string(),
-                    target: None,
-                    event_details: Some(serde_json::json!({
-                        "reason": "New password too weak"
-                    })),
-                }
-            ).await.unwrap();
+                AuditEvent::new(
+                    AuditEventKind::PasswordChangeFailed(PasswordChangeFailedDetails {
+                        reason: PasswordChangeFailureReason::WeakPassword,
+                    }),
+                    Some(user_id),
+                    &client_ip.to_string(),
+                    None,
+                )
+            ).await.unwrap_or_else(|err| {
+                tracing::error!("Failed to send audit event: {}", err);
+            });
 
             return Redirect::to("/user.html?error=weak_password");
     }
@@ -520,16 +530,17 @@ async fn change_password(ClientIp(client_ip): ClientIp, cookie_jar: CookieJar, f
     if user_opt.is_none() {
         tracing::debug!("User not found: {}", user_id);
         send_audit_event(
-            AuditEvent {
-                event_type: "password_change_failed".to_string(),
-                user_id: Some(user_id),
-                client_ip: &client_ip.to_string(),
-                target: None,
-                event_details: Some(serde_json::json!({
-                    "reason": "User not found"
-                })),
-            }
-        ).await.unwrap();
+            AuditEvent::new(
+                AuditEventKind::PasswordChangeFailed(PasswordChangeFailedDetails {
+                    reason: PasswordChangeFailureReason::UserNotFound,
+                }),
+                Some(user_id),
+                &client_ip.to_string(),
+                None,
+            )
+        ).await.unwrap_or_else(|err| {
+            tracing::error!("Failed to send audit event: {}", err);
+        });
         return Redirect::to("/login?error=unauthorized");
     }
 
@@ -538,16 +549,17 @@ async fn change_password(ClientIp(client_ip): ClientIp, cookie_jar: CookieJar, f
     if !password_equals(&user.password_hash, &current_password) {
         tracing::debug!("Invalid current password for user: {}", user.id);
         send_audit_event(
-            AuditEvent {
-                event_type: "password_change_failed".to_string(),
-                user_id: Some(user_id),
-                client_ip: &client_ip.to_string(),
-                target: None,
-                event_details: Some(serde_json::json!({
-                    "reason": "Invalid current password"
-                })),
-            }
-        ).await.unwrap();
+            AuditEvent::new(
+                AuditEventKind::PasswordChangeFailed(PasswordChangeFailedDetails {
+                    reason: PasswordChangeFailureReason::InvalidCurrentPassword,
+                }),
+                Some(user_id),
+                &client_ip.to_string(),
+                None,
+            )
+        ).await.unwrap_or_else(|err| {
+            tracing::error!("Failed to send audit event: {}", err);
+        });
         return Redirect::to("/user.html?error=invalid_current_password");
     }
 
@@ -560,14 +572,15 @@ async fn change_password(ClientIp(client_ip): ClientIp, cookie_jar: CookieJar, f
     db::update_user_password(user.id, &password_hash).expect("Failed to update user password");
 
     send_audit_event(
-        AuditEvent {
-            event_type: "password_change_success".to_string(),
-            user_id: Some(user_id),
-            client_ip: &client_ip.to_string(),
-            target: None,
-            event_details: None,
-        }
-    ).await.unwrap();
+        AuditEvent::new(
+            AuditEventKind::PasswordChangeSuccess,
+            Some(user_id),
+            &client_ip.to_string(),
+            None,
+        )
+    ).await.unwrap_or_else(|err| {
+        tracing::error!("Failed to send audit event: {}", err);
+    });
 
     Redirect::to("/index.html")
 }
@@ -604,34 +617,33 @@ async fn main() {
         .with_max_level(filter::LevelFilter::INFO)
         .init();
 
-    let ip_source_env = env::var("IP_SOURCE").unwrap_or_else(|_| "nginx".to_string());
-    let ip_source = match ip_source_env.as_str() {
-        "nginx" => ClientIpSource::RightmostXForwardedFor,
-        "amazon" => ClientIpSource::CloudFrontViewerAddress,
-        "cloudflare" => ClientIpSource::CfConnectingIp,
-        _ => { 
-            tracing::warn!("Unknown IP source: {}, defaulting to Nginx", ip_source_env);
-            ClientIpSource::RightmostXForwardedFor
-        } 
-    };
+    let settings = Arc::new(Settings::load());
+    tracing::info!("Loaded settings {:?}", settings);
+
+    db::init(settings.database_url.expose());
+    audit::start(&settings.audit);
 
     let app = Router::new()
         .route("/auth/health", get(|| async { "OK" }))
         .route("/auth/status", get(verify_jwt_via_cookie))
-        .route("/auth/verify", post(verify_jwt))
+        .route("/auth/verify", post(verify_jwt).layer(from_fn_with_state(settings.service_auth.clone(), service_auth_middleware)))
         .route("/auth/login", post(login_handler))
         .route("/auth/info", get(user_info))
         .route("/auth/change_password", post(change_password))
-        .layer(ip_source.into_extension());
+        .layer(settings.ip_source.clone().into_extension())
+        .with_state(settings.clone());
         
         
-    let port = env::var("PORT").unwrap_or_else(|_| "3000".to_string());
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", settings.port)).await
         .expect("Failed to bind TCP listener");
 
+    // the audit events still buffered are delivered, or spooled, before the process exits
     axum::serve(listener, app)
+        .with_graceful_shutdown(worker::shutdown_token().cancelled_owned())
         .await
         .expect("Failed to start server");
+
+    audit::flush().await;
 }
 
 
@@ -0,0 +1,65 @@
+use std::sync::Arc;
+
+use axum_client_ip::ClientIpSource;
+
+use audit::AuditSettings;
+use auth_check::ServiceAuth;
+use config::{Secret, Source};
+
+
+// HS256 keys shorter than the hash are padded, and easier to guess
+const MIN_SIGNING_KEY_LENGTH: usize = 32;
+
+
+/// The settings of the auth service, loaded once at startup and shared with the handlers
+#[derive(Debug)]
+pub struct Settings {
+    pub port: u16,
+    pub database_url: Secret<String>,
+    /// Signs and verifies the session tokens
+    pub signing_key: Secret<String>,
+    pub issuer: String,
+    pub audience: String,
+    /// Where the client IP is taken from: `nginx` (the default), `amazon` or `cloudflare`
+    pub ip_source: ClientIpSource,
+    /// Checks the service tokens of the internal endpoints
+    pub service_auth: Arc<ServiceAuth>,
+    /// Where the logins and the password changes are audited, see `audit::start`
+    pub audit: AuditSettings,
+}
+
+impl Settings {
+    /// Loads the settings from the environment and the optional `CONFIG_FILE`.
+    ///
+    /// Exits the process with every missing or invalid setting listed, if there are any.
+    pub fn load() -> Self {
+        config::load_or_exit(|source| {
+            let settings = Settings {
+                port: source.with_default("PORT", 3000),
+                database_url: source.secret("DATABASE_URL"),
+                signing_key: source.secret("SIGNING_KEY"),
+                issuer: source.required("ISSUER"),
+                audience: source.required("AUDIENCE"),
+                ip_source: ip_source(source),
+                service_auth: Arc::new(ServiceAuth::from_source(source)),
+                audit: AuditSettings::from_source(source),
+            };
+
+            if !settings.signing_key.expose().is_empty() && settings.signing_key.expose().len() < MIN_SIGNING_KEY_LENGTH {
+                source.invalid("SIGNING_KEY", &format!("must be at least {} characters", MIN_SIGNING_KEY_LENGTH));
+            }
+
+            settings
+        })
+    }
+}
+
+
+fn ip_source(source: &mut Source) -> ClientIpSource {
+    source.parsed_with("IP_SOURCE", ClientIpSource::RightmostXForwardedFor, |value| match value {
+        "nginx" => Ok(ClientIpSource::RightmostXForwardedFor),
+        "amazon" => Ok(ClientIpSource::CloudFrontViewerAddress),
+        "cloudflare" => Ok(ClientIpSource::CfConnectingIp),
+        _ => Err("expected nginx, amazon or cloudflare".to_string()),
+    })
+}
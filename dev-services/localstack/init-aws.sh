@@ -1,11 +1,38 @@
 #!/bin/bash
 awslocal s3 mb s3://videosite-data/
+awslocal s3 mb s3://videosite-audit/
 
 
-awslocal sqs create-queue --queue-name upload-finished-queue
-awslocal sqs create-queue --queue-name virus-scan-clear-queue
-awslocal sqs create-queue --queue-name video-processing-queue
-awslocal sqs create-queue --queue-name audio-processing-queue
-awslocal sqs create-queue --queue-name image-processing-queue
-awslocal sqs create-queue --queue-name resource-status-queue
-awslocal sqs create-queue --queue-name audit-event-queue
\ No newline at end of file
+# the workers send the messages they cannot handle to the dead-letter queue themselves, with the error, after
+# WORKER_MAX_RECEIVES attempts. The redrive policy allows more receives, it only catches the messages of workers
+# that crash or get killed while handling them. See helpers/dead_letters for inspecting and redriving them.
+MAX_RECEIVE_COUNT=10
+
+create_queue() {
+    local dlq_url=$(awslocal sqs create-queue --queue-name "$1-dlq" --attributes MessageRetentionPeriod=1209600 --query QueueUrl --output text)
+    local dlq_arn=$(awslocal sqs get-queue-attributes --queue-url "$dlq_url" --attribute-names QueueArn --query Attributes.QueueArn --output text)
+
+    awslocal sqs create-queue --queue-name "$1" \
+        --attributes "{\"RedrivePolicy\": \"{\\\"deadLetterTargetArn\\\":\\\"$dlq_arn\\\",\\\"maxReceiveCount\\\":\\\"$MAX_RECEIVE_COUNT\\\"}\"}"
+}
+
+create_queue upload-finished-queue
+create_queue virus-scan-clear-queue
+create_queue video-processing-queue
+create_queue audio-processing-queue
+create_queue image-processing-queue
+create_queue resource-status-queue
+create_queue audit-event-queue
+create_queue domain-event-queue
+
+
+# the domain events are published on the bus, and a rule routes all of them to the webhook service's queue
+awslocal events create-event-bus --name videosite-events
+awslocal events put-rule --name domain-events-to-webhooks --event-bus-name videosite-events \
+    --event-pattern '{"source": ["videosite"]}'
+
+domain_event_queue_url=$(awslocal sqs get-queue-url --queue-name domain-event-queue --query QueueUrl --output text)
+domain_event_queue_arn=$(awslocal sqs get-queue-attributes --queue-url "$domain_event_queue_url" --attribute-names QueueArn --query Attributes.QueueArn --output text)
+
+awslocal events put-targets --rule domain-events-to-webhooks --event-bus-name videosite-events \
+    --targets "Id=webhooks,Arn=$domain_event_queue_arn"
@@ -34,6 +34,9 @@ case "$1" in
   audit)
     ./audit/db/drop_database.sh
     ;;
+  webhook)
+    ./webhooks/db/drop_database.sh
+    ;;
   *)
     echo "Unknown project: $1"
     exit 1
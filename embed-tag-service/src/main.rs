@@ -1,15 +1,20 @@
+mod settings;
+
 use axum::{
+    extract::State,
     routing::get,
     Router,
 };
 
 use axum_extra::extract::cookie::CookieJar;
-use reqwest;
 use urlencoding::encode as url_encode;
 
-use std::env;
+use std::sync::Arc;
+
+use auth_check::{service_token, SERVICE_TOKEN_HEADER};
+
+use settings::Settings;
 
-use tracing;
 use tracing_subscriber::filter;
 
 #[derive(Debug, Clone, serde::Deserialize)]
@@ -33,19 +38,21 @@ enum ResourceMetadata {
 // fetch metadata from resource server
 #[axum::debug_handler]
 async fn embed_html(
+    State(settings): State<Arc<Settings>>,
     cookies: CookieJar,
     axum::extract::Path(resource_id): axum::extract::Path<String>,
 ) -> Result<axum::response::Html<String>, axum::http::StatusCode> {
-    generate_html(&resource_id, cookies).await
+    generate_html(&settings, &resource_id, cookies).await
 }
 
 #[axum::debug_handler]
 async fn embed_html_query(
+    State(settings): State<Arc<Settings>>,
     cookies: CookieJar,
     axum::extract::Query(params): axum::extract::Query<std::collections::HashMap<String, String>>,
 ) -> Result<axum::response::Html<String>, axum::http::StatusCode> {
     if let Some(resource_id) = params.get("resource_id") {
-        generate_html(resource_id, cookies).await
+        generate_html(&settings, resource_id, cookies).await
     } else {
         Err(axum::http::StatusCode::BAD_REQUEST)
     }
@@ -62,23 +69,24 @@ async fn main() {
         .with_max_level(filter::LevelFilter::INFO)
         .init();
 
-    tracing::info!("Starting embed tag service...");
+    let settings = Arc::new(Settings::load());
+    tracing::info!("Starting embed tag service with settings {:?}", settings);
 
 
     let app = Router::new()
         .route("/embed-service/health", get(|| async { "OK" }))
         .route("/embed-service/{resource_id}/embed.html", get(embed_html))
         // laziness - do not want to rewrite the url in AWS
-        .route("/player.html", get(embed_html_query));
+        .route("/player.html", get(embed_html_query))
+        .with_state(settings.clone());
 
 
 
-    let port = env::var("PORT").unwrap_or_else(|_| "3000".to_string());
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", settings.port)).await
         .expect("Failed to bind TCP listener");
 
 
-    tracing::info!("Listening on port {}", port);
+    tracing::info!("Listening on port {}", settings.port);
     tokio::join!(
         axum::serve(
             listener, 
@@ -88,14 +96,14 @@ async fn main() {
 }
 
 
-async fn generate_html(resource_id: &str, cookies: CookieJar) -> Result<axum::response::Html<String>, axum::http::StatusCode> {
+async fn generate_html(settings: &Settings, resource_id: &str, cookies: CookieJar) -> Result<axum::response::Html<String>, axum::http::StatusCode> {
     let metadata_url = format!(
         "{}/resource/{}/metadata",
-        env::var("RESOURCE_SERVER_URL").expect("RESOURCE_SERVER_URL must be set"),
+        settings.resource_server_url,
         resource_id
     );
 
-    let domain = env::var("DOMAIN_URL").expect("DOMAIN_URL must be set");
+    let domain = &settings.domain_url;
 
     
     // get metadata. Forward any cookies we have, as we may have received a session cookie
@@ -107,9 +115,11 @@ async fn generate_html(resource_id: &str, cookies: CookieJar) -> Result<axum::re
         .collect::<Vec<String>>()
         .join("; ");
 
+    // the metadata endpoint is internal-only, so we need to identify ourselves to the resource server
     let metadata_response = reqwest::Client::new()
         .get(&metadata_url)
         .header("Cookie", hyper_cookies)
+        .header(SERVICE_TOKEN_HEADER, service_token(&settings.service_auth, "resource-server"))
         .send()
         .await
         .map_err(|err| {
@@ -139,7 +149,9 @@ async fn generate_html(resource_id: &str, cookies: CookieJar) -> Result<axum::re
         ResourceMetadata::Video{ width, height, .. } => {
             let player_url = format!("{}/player.html?resource_id={}", domain, metadata.id);
             let url_encoded_player_url = url_encode(&player_url);
-
+            let thumbnail_url = format!("{}/resource/{}/thumbnail.jpg", domain, metadata.id);
+            let video_url = format!("{}/resource/{}/master.m3u8", domain, metadata.id);
+            let oembed_url = format!("{}/resource/oembed.json?url={}", domain, url_encoded_player_url);
 
             format!(r#"<!DOCTYPE html>
 <html lang="en">
@@ -163,13 +175,13 @@ async fn generate_html(resource_id: &str, cookies: CookieJar) -> Result<axum::re
 </html>
 "#, 
     title = metadata.name,
-    thumbnail_url = format!("{}/resource/{}/thumbnail.jpg", domain, metadata.id),
+    thumbnail_url = thumbnail_url,
     url = player_url,
     description = description,
-    video_url = format!("{}/resource/{}/master.m3u8", domain, metadata.id),
+    video_url = video_url,
     width = width,
     height = height,
-    oembed_url = format!("{}/resource/oembed.json?url={}", domain, url_encoded_player_url)
+    oembed_url = oembed_url,
             )
         }
         // todo - other resource types
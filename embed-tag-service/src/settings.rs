@@ -0,0 +1,28 @@
+use auth_check::ServiceAuth;
+
+
+/// The settings of the embed tag service, loaded once at startup and shared with the handlers
+#[derive(Debug)]
+pub struct Settings {
+    pub port: u16,
+    /// The metadata of the resources is fetched from it
+    pub resource_server_url: String,
+    /// The public URL of the site, the tags link to it
+    pub domain_url: String,
+    /// Identifies the service to the resource server
+    pub service_auth: ServiceAuth,
+}
+
+impl Settings {
+    /// Loads the settings from the environment and the optional `CONFIG_FILE`.
+    ///
+    /// Exits the process with every missing or invalid setting listed, if there are any.
+    pub fn load() -> Self {
+        config::load_or_exit(|source| Settings {
+            port: source.with_default("PORT", 3000),
+            resource_server_url: source.required("RESOURCE_SERVER_URL"),
+            domain_url: source.required("DOMAIN_URL"),
+            service_auth: ServiceAuth::from_source(source),
+        })
+    }
+}
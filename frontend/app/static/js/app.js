@@ -6,9 +6,25 @@ function onLoad() {
     initQuota();
     checkBanners();
     setUserName();
-    setInterval(() => {
-        loadResources();
-    }, 5000);
+    subscribeToResourceEvents();
+}
+
+// the server pushes the changes of the resources, so the list is only loaded again when one of them changes
+function subscribeToResourceEvents() {
+    const events = new EventSource("/resource/events", { withCredentials: true });
+
+    // updates may have been missed while the stream was reconnecting
+    events.addEventListener("open", () => loadResources());
+    events.addEventListener("resync", () => loadResources());
+    events.addEventListener("status", () => loadResources());
+    events.addEventListener("progress", (event) => showProgress(JSON.parse(event.data)));
+}
+
+function showProgress(progress) {
+    const statusP = document.querySelector(`#resource-${progress.resource_id} .resource-status`);
+    if (statusP) {
+        statusP.textContent = `Status: processing (${progress.stage} ${progress.percent}%)`;
+    }
 }
 
 async function loadResources() {
@@ -135,6 +151,7 @@ function createGenericElement(resource) {
     typeP.textContent = `Type: ${resource.resource_type}`;
 
     const statusP = document.createElement("p");
+    statusP.className = "resource-status";
     statusP.textContent = `Status: ${resource.resource_status}`;
 
     const createdP = document.createElement("p");
@@ -0,0 +1,115 @@
+use std::env;
+use std::process::exit;
+use std::sync::Arc;
+
+use chrono::DateTime;
+
+use queue::SqsQueue;
+use worker::{DeadLetterQueue, StoredDeadLetter};
+
+
+const USAGE: &str = "Usage:
+  dead_letters <queue-url> list                      lists the messages in the dead-letter queue of the queue
+  dead_letters <queue-url> show <message-id>         shows a message, with its whole body
+  dead_letters <queue-url> redrive <message-id>...   sends the messages back to the queue
+  dead_letters <queue-url> redrive --all             sends all the messages back to the queue
+  dead_letters <queue-url> discard <message-id>...   deletes the messages for good
+
+The dead-letter queue of a queue is the queue with the same URL, followed by -dlq, see dev-services/localstack/init-aws.sh.
+For localstack, set AWS_ENDPOINT_URL=http://localhost:4566, AWS_REGION=us-east-1 and the test credentials.";
+
+
+// Inspects the dead-letter queues of the pipeline, and redrives their messages once the problem is fixed
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() < 2 {
+        usage();
+    }
+
+    let queue_url = &args[0];
+    let dead_letter_queue = DeadLetterQueue::new(
+        Arc::new(SqsQueue::new(format!("{}-dlq", queue_url))),
+        Arc::new(SqsQueue::new(queue_url.clone())),
+    );
+    let message_ids = &args[2..];
+
+    let result = match (args[1].as_str(), message_ids) {
+        ("list", []) => dead_letter_queue.list().await.map(|dead_letters| list(&dead_letters)),
+        ("show", [message_id]) => dead_letter_queue.list().await.map(|dead_letters| {
+            match dead_letters.iter().find(|stored| &stored.message_id == message_id) {
+                Some(stored) => show(stored),
+                None => println!("No message {} in the dead-letter queue", message_id),
+            }
+        }),
+        ("redrive", [all]) if all == "--all" => dead_letter_queue.redrive_all().await.map(|redriven| {
+            println!("Redrove {} messages", redriven.len());
+        }),
+        ("redrive", [_, ..]) => dead_letter_queue.redrive(message_ids).await.map(|redriven| {
+            report(message_ids, &redriven, "Redrove");
+        }),
+        ("discard", [_, ..]) => dead_letter_queue.discard(message_ids).await.map(|discarded| {
+            report(message_ids, &discarded, "Discarded");
+        }),
+        _ => usage(),
+    };
+
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        exit(1);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("{}", USAGE);
+    exit(2);
+}
+
+fn format_time(millis: i64) -> String {
+    DateTime::from_timestamp_millis(millis)
+        .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn list(dead_letters: &[StoredDeadLetter]) {
+    if dead_letters.is_empty() {
+        println!("The dead-letter queue is empty");
+        return;
+    }
+
+    println!("{:<40} {:<20} {:<18} {:<8} ERROR", "MESSAGE ID", "DEAD-LETTERED AT", "WORKER", "RECEIVES");
+    for stored in dead_letters {
+        let dead_letter = &stored.dead_letter;
+        let error = dead_letter.error.lines().next().unwrap_or_default();
+
+        println!(
+            "{:<40} {:<20} {:<18} {:<8} {}",
+            stored.message_id,
+            format_time(dead_letter.dead_lettered_at),
+            if dead_letter.worker.is_empty() { "-" } else { &dead_letter.worker },
+            dead_letter.receive_count,
+            error.chars().take(100).collect::<String>()
+        );
+    }
+}
+
+fn show(stored: &StoredDeadLetter) {
+    let dead_letter = &stored.dead_letter;
+
+    println!("Message ID:       {}", stored.message_id);
+    println!("Dead-lettered at: {}", format_time(dead_letter.dead_lettered_at));
+    println!("Worker:           {}", if dead_letter.worker.is_empty() { "-" } else { &dead_letter.worker });
+    println!("Receives:         {}", dead_letter.receive_count);
+    println!("Error:            {}", dead_letter.error);
+    println!("Body:\n{}", dead_letter.body);
+}
+
+fn report(message_ids: &[String], done: &[StoredDeadLetter], action: &str) {
+    println!("{} {} messages", action, done.len());
+
+    for message_id in message_ids {
+        if !done.iter().any(|stored| &stored.message_id == message_id) {
+            println!("No message {} in the dead-letter queue", message_id);
+        }
+    }
+}
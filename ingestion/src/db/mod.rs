@@ -1,5 +1,7 @@
 mod schema;
 
+use std::sync::OnceLock;
+
 use diesel::prelude::*;
 use uuid::Uuid;
 
@@ -7,9 +9,12 @@ use bigdecimal::{BigDecimal, ToPrimitive};
 
 use schema::*;
 
+
+static DATABASE_URL: OnceLock<String> = OnceLock::new();
+
+
 #[derive(Debug, Queryable, Selectable)]
 #[diesel(table_name = active_uploads)]
-
 #[allow(dead_code)]
 pub struct UserUpload {
     pub user_id: Uuid,
@@ -43,6 +48,7 @@ pub struct NewChunkUpload {
 
 #[derive(Debug, Queryable, Selectable)]
 #[diesel(table_name = active_chunk_upload)]
+#[allow(dead_code)]
 pub struct ActiveChunkUpload {
     pub object_name: Uuid,
     pub aws_upload_id: String,
@@ -58,6 +64,7 @@ pub struct ActiveChunkUpload {
 
 #[derive(Debug, Queryable, Selectable)]
 #[diesel(table_name = chunk_information)]
+#[allow(dead_code)]
 pub struct ChunkInformation {
     pub object_name: Uuid,
     pub part_number: i32,
@@ -245,6 +252,7 @@ pub fn delete_chunk_upload_record(user_id: &str, object_name: &str) {
         .expect("Error deleting chunk upload record");
 }
 
+#[allow(dead_code)]
 pub fn get_user_uploads(user_id: &str) -> Vec<UserUpload> {
     let mut conn = get_connection();
 
@@ -255,7 +263,13 @@ pub fn get_user_uploads(user_id: &str) -> Vec<UserUpload> {
 }
 
 
+/// Sets the database the functions connect to, must be called once at startup
+pub fn init(database_url: &str) {
+    DATABASE_URL.set(database_url.to_string()).expect("The database is already set");
+}
+
+// the URL is left out of the messages, it contains the password
 fn get_connection() -> PgConnection {
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url))
+    let database_url = DATABASE_URL.get().expect("db::init was not called");
+    PgConnection::establish(database_url).unwrap_or_else(|err| panic!("Error connecting to the database: {}", err))
 }
\ No newline at end of file
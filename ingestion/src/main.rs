@@ -1,29 +1,33 @@
 mod db;
 mod message;
 mod models;
+mod settings;
 mod upload;
 
 
-use std::{env, f64::consts::E};
+use std::sync::Arc;
 
 use axum::{
     extract::{
-        DefaultBodyLimit, Multipart,
-    }, http::StatusCode, middleware::from_fn, response::{IntoResponse, Redirect}, routing::{get, post}, Extension, Json, Router
+        DefaultBodyLimit, Multipart, State,
+    }, http::StatusCode, middleware::from_fn_with_state, response::{IntoResponse, Redirect}, routing::{get, post}, Extension, Json, Router
 };
 
-use axum_client_ip::{ClientIpSource, ClientIp};
+use axum_client_ip::ClientIp;
 
 
-use uuid;
 use tower::ServiceBuilder;
 
 use auth_check::{auth_middleware, UserInfo};
-use audit::{send_audit_event, AuditEvent};
+use audit::{
+    send_audit_event, AuditEvent, AuditEventKind, AuditTarget, ChunkUploadDetails, CompleteChunkUploadDetails,
+    FileUploadDetails, InitChunkUploadDetails, UploadError,
+};
 
 use tracing_subscriber::filter;
 
 use message::*;
+use settings::Settings;
 use upload::*;
 
 
@@ -33,38 +37,33 @@ fn get_object_path(object_name: &str) -> String {
     format!("{}/{}", UPLOAD_FOLDER, object_name)
 }
 
-fn s3_bucket() -> String {
-    env::var("S3_BUCKET_NAME").expect("S3_BUCKET_NAME must be set")
-}
-
 #[axum::debug_handler]
-async fn upload_handler(ClientIp(client_ip): ClientIp, user_info: Extension<UserInfo>, mut multipart: Multipart) -> Redirect {
+async fn upload_handler(State(settings): State<Arc<Settings>>, ClientIp(client_ip): ClientIp, user_info: Extension<UserInfo>, mut multipart: Multipart) -> Redirect {
     tracing::info!("Starting file upload handler for user: {}", user_info.user_id);
     let user_total_quota = db::user_quota(&user_info.user_id);
     let mut used_quota = db::used_user_quota(&user_info.user_id);
 
     while let Some(field) = multipart.next_field().await.unwrap() {
 
-        let (presigned_uri, object_name, file_name, file_size) = upload_file(field).await;
+        let (presigned_uri, object_name, file_name, file_size) = upload_file(&settings, field).await;
 
         used_quota += file_size as i64;
         if used_quota > user_total_quota {
             tracing::error!("User {} has exceeded their upload quota. Used: {}, Total: {}", user_info.user_id, used_quota, user_total_quota);
             // since we cannot get the size before storing the file, we need to delete the file from S3
-            delete_file(&object_name).await;
+            delete_file(&settings, &object_name).await;
             tracing::error!("File {} deleted from S3 due to quota exceeded", object_name);
 
-            send_audit_event(AuditEvent {
-                event_type: "file_upload".to_string(),
-                user_id: Some(&user_info.user_id),
-                client_ip: &client_ip.to_string(),
-                target: Some(&object_name),
-                event_details: Some(serde_json::json!({
-                    "file_name": file_name,
-                    "file_size": file_size,
-                    "error": "quota_exceeded"
-                })),
-            }).await.unwrap_or_else(|e| {
+            send_audit_event(AuditEvent::new(
+                AuditEventKind::FileUpload(FileUploadDetails {
+                    file_name,
+                    file_size,
+                    error: Some(UploadError::QuotaExceeded),
+                }),
+                Some(&user_info.user_id),
+                &client_ip.to_string(),
+                Some(AuditTarget::resource(&object_name)),
+            )).await.unwrap_or_else(|e| {
                 tracing::error!("Failed to send audit event: {}", e);
             });
 
@@ -77,18 +76,18 @@ async fn upload_handler(ClientIp(client_ip): ClientIp, user_info: Extension<User
             file_size as i64,
         );
         tracing::info!("File uploaded successfully, presigned URL: {}", presigned_uri);
-        queue_upload_event(&user_info, presigned_uri, &object_name, &file_name, file_size).await;
-
-        send_audit_event(AuditEvent {
-            event_type: "file_upload".to_string(),
-            user_id: Some(&user_info.user_id),
-            client_ip: &client_ip.to_string(),
-            target: Some(&object_name),
-            event_details: Some(serde_json::json!({
-                "file_name": file_name,
-                "file_size": file_size,
-            })),
-        }).await.unwrap_or_else(|e| {
+        queue_upload_event(&settings, &user_info, presigned_uri, &object_name, &file_name, file_size).await;
+
+        send_audit_event(AuditEvent::new(
+            AuditEventKind::FileUpload(FileUploadDetails {
+                file_name,
+                file_size,
+                error: None,
+            }),
+            Some(&user_info.user_id),
+            &client_ip.to_string(),
+            Some(AuditTarget::resource(&object_name)),
+        )).await.unwrap_or_else(|e| {
             tracing::error!("Failed to send audit event: {}", e);
         });
     }
@@ -116,6 +115,7 @@ async fn user_quota(user_info: Extension<UserInfo>) -> impl IntoResponse {
  
 #[axum::debug_handler]
 async fn init_chunk_upload(
+    State(settings): State<Arc<Settings>>,
     ClientIp(client_ip): ClientIp,
     user_info: Extension<UserInfo>,
     Json(payload): Json<models::NewChunkUploadRequest>,
@@ -133,21 +133,9 @@ async fn init_chunk_upload(
 
 
     let object_name = uuid::Uuid::new_v4().to_string();
-    let chunk_size = env::var("CHUNK_SIZE")
-        .unwrap_or_else(|_| "5242880".to_string()) // default to 5MB
-        .parse::<usize>()
-        .expect("CHUNK_SIZE must be a valid number");
-    if chunk_size < 5 * 1024 * 1024 {
-        tracing::error!("CHUNK_SIZE must be at least 5MB");
-        let error_response = models::ErrorResponse {
-                     error: "Internal server error".to_owned() 
-        };
-        return (StatusCode::INTERNAL_SERVER_ERROR, axum::Json(error_response)).into_response();
-    }
+    let chunk_size = settings.chunk_size;
 
-    let client = get_s3_client().await;
-    let upload = initiate_multipart_upload(&client, &object_name).await;
-    let aws_upload_id = upload.upload_id().expect("Upload ID not found");
+    let aws_upload_id = initiate_multipart_upload(&settings, &object_name).await;
         
     let response = models::NewChunkUploadResponse {
         upload_id: object_name.clone(),
@@ -159,24 +147,23 @@ async fn init_chunk_upload(
         &aws_upload_id,
         &user_info.user_id,
         &payload.file_name,
-        &payload.integrity_check_type.as_str(),
+        payload.integrity_check_type.as_str(),
         payload.integrity_check_value.as_deref(),
         chunk_size as i64,
     );
 
-    send_audit_event(AuditEvent {
-        event_type: "init_chunk_upload".to_string(),
-        user_id: Some(&user_info.user_id),
-        client_ip: &client_ip.to_string(),
-        target: Some(&object_name),
-        event_details: Some(serde_json::json!({
-            "file_name": payload.file_name,
-            "file_size": payload.file_size,
-            "integrity_check_type": payload.integrity_check_type.as_str(),
-            "integrity_check_value": payload.integrity_check_value,
-            "chunk_size": chunk_size,
-        })),
-    }).await.unwrap_or_else(|e| {
+    send_audit_event(AuditEvent::new(
+        AuditEventKind::InitChunkUpload(InitChunkUploadDetails {
+            file_name: payload.file_name,
+            file_size: payload.file_size,
+            integrity_check_type: payload.integrity_check_type.as_str().to_string(),
+            integrity_check_value: payload.integrity_check_value,
+            chunk_size,
+        }),
+        Some(&user_info.user_id),
+        &client_ip.to_string(),
+        Some(AuditTarget::upload(&aws_upload_id)),
+    )).await.unwrap_or_else(|e| {
         tracing::error!("Failed to send audit event: {}", e);
     });
 
@@ -185,6 +172,7 @@ async fn init_chunk_upload(
 
 #[axum::debug_handler]
 async fn chunk_upload(
+    State(settings): State<Arc<Settings>>,
     user_info: Extension<UserInfo>,
     ClientIp(client_ip): ClientIp,
     query_params: axum::extract::Query<std::collections::HashMap<String, String>>,
@@ -208,7 +196,7 @@ async fn chunk_upload(
         }
     };
 
-    let chunk_upload = db::get_active_chunk_upload(&user_info.user_id, &upload_id);
+    let chunk_upload = db::get_active_chunk_upload(&user_info.user_id, upload_id);
     if chunk_upload.is_none() {
         tracing::error!("No active chunk upload found for user {} and upload ID {}", user_info.user_id, upload_id);
         return StatusCode::NOT_FOUND;
@@ -247,24 +235,22 @@ async fn chunk_upload(
 
         // the initial quota check must have passed, so this is bit weird (could be just two parallel uploads). Regardless,
         // let's audit log it as this may cause at least people to ask what's going on
-        send_audit_event(AuditEvent {
-            event_type: "chunk_upload".to_string(),
-            user_id: Some(&user_info.user_id),
-            client_ip: &client_ip.to_string(),
-            target: Some(&upload_id),
-            event_details: Some(serde_json::json!({
-                "chunk_index": chunk_index,
-                "chunk_size": file_size,
-                "error": "quota_exceeded"
-            })),
-        }).await.unwrap_or_else(|e| {
+        send_audit_event(AuditEvent::new(
+            AuditEventKind::ChunkUpload(ChunkUploadDetails {
+                chunk_index,
+                chunk_size: file_size,
+                error: Some(UploadError::QuotaExceeded),
+            }),
+            Some(&user_info.user_id),
+            &client_ip.to_string(),
+            Some(AuditTarget::upload(&chunk_upload.aws_upload_id)),
+        )).await.unwrap_or_else(|e| {
             tracing::error!("Failed to send audit event: {}", e);
         });
         
         // cancel the upload in S3 and delete the file
         // TODO let's dryrun first without S3
-        let client = get_s3_client().await;
-        abort_chunk_upload(&client, &chunk_upload.object_name.to_string(),  &chunk_upload.aws_upload_id).await;
+        abort_chunk_upload(&settings, &chunk_upload.object_name.to_string(),  &chunk_upload.aws_upload_id).await;
         db::delete_chunks_for_upload(&user_info.user_id, &chunk_upload.object_name.to_string());
         db::delete_chunk_upload_record(&user_info.user_id, &chunk_upload.object_name.to_string());
 
@@ -279,9 +265,8 @@ async fn chunk_upload(
         return StatusCode::BAD_REQUEST;
     }
 
-    let client = get_s3_client().await;
     let completed_part = upload_chunk(
-        &client, 
+        &settings,
         buffer, 
         &chunk_upload.object_name.to_string(), 
         &chunk_upload.aws_upload_id, 
@@ -289,29 +274,29 @@ async fn chunk_upload(
     
     db::save_uploaded_chunk_information(
         &user_info.user_id, 
-        &upload_id, 
-        &completed_part.e_tag().expect("ETag not found"),
-        completed_part.part_number().expect("Part number not found") as usize,
+        upload_id, 
+        &completed_part.e_tag,
+        completed_part.part_number as usize,
     );
 
     db::update_received_bytes_for_chunk_upload(
         &user_info.user_id,
-        &upload_id,
+        upload_id,
         file_size as i64,
     );
     
 
     send_audit_event(
-        AuditEvent {
-            event_type: "chunk_upload".to_string(),
-            user_id: Some(&user_info.user_id),
-            client_ip: &client_ip.to_string(),
-            target: Some(&chunk_upload.aws_upload_id),
-            event_details: Some(serde_json::json!({
-                "chunk_index": chunk_index,
-                "chunk_size": file_size,
-            })),
-        }
+        AuditEvent::new(
+            AuditEventKind::ChunkUpload(ChunkUploadDetails {
+                chunk_index,
+                chunk_size: file_size,
+                error: None,
+            }),
+            Some(&user_info.user_id),
+            &client_ip.to_string(),
+            Some(AuditTarget::upload(&chunk_upload.aws_upload_id)),
+        )
     ).await.unwrap_or_else(|e| {
         tracing::error!("Failed to send audit event: {}", e);
     });
@@ -322,6 +307,7 @@ async fn chunk_upload(
 
 #[axum::debug_handler]
 async fn complete_chunk_upload_handler(
+    State(settings): State<Arc<Settings>>,
     ClientIp(client_ip): ClientIp,
     user_info: Extension<UserInfo>,
     Json(payload): Json<models::CompleteUploadRequest>,
@@ -333,6 +319,7 @@ async fn complete_chunk_upload_handler(
     }
 
     let active_upload = active_upload.unwrap();
+    let object_name = active_upload.object_name.to_string();
     let user_quota = db::user_quota(&user_info.user_id);
     let used_quota = db::used_user_quota(&user_info.user_id);
 
@@ -341,20 +328,20 @@ async fn complete_chunk_upload_handler(
     if used_quota > user_quota {
         tracing::error!("User {} has exceeded their upload quota. Used: {}, Total: {}", user_info.user_id, used_quota, user_quota);
 
-        send_audit_event(AuditEvent {
-            event_type: "complete_chunk_upload".to_string(),
-            user_id: Some(&user_info.user_id),
-            client_ip: &client_ip.to_string(),
-            target: Some(&payload.upload_id),
-            event_details: Some(serde_json::json!({
-                "error": "quota_exceeded"
-            })),
-        }).await.unwrap_or_else(|e| {
+        send_audit_event(AuditEvent::new(
+            AuditEventKind::CompleteChunkUpload(CompleteChunkUploadDetails {
+                file_name: None,
+                file_size: None,
+                error: Some(UploadError::QuotaExceeded),
+            }),
+            Some(&user_info.user_id),
+            &client_ip.to_string(),
+            Some(AuditTarget::resource(&object_name)),
+        )).await.unwrap_or_else(|e| {
             tracing::error!("Failed to send audit event: {}", e);
         });
 
-        let client = get_s3_client().await;
-        abort_chunk_upload(&client, &active_upload.object_name.to_string(),  &active_upload.aws_upload_id).await;
+        abort_chunk_upload(&settings, &active_upload.object_name.to_string(),  &active_upload.aws_upload_id).await;
         db::delete_chunks_for_upload(&user_info.user_id, &active_upload.object_name.to_string());
         db::delete_chunk_upload_record(&user_info.user_id, &active_upload.object_name.to_string());
         return StatusCode::PAYMENT_REQUIRED;
@@ -367,27 +354,22 @@ async fn complete_chunk_upload_handler(
         return StatusCode::BAD_REQUEST;
     }
 
-    let mut completed_parts: Vec<aws_sdk_s3::types::CompletedPart> = uploaded_parts.iter().map(|part| {
-        aws_sdk_s3::types::CompletedPart::builder()
-            .part_number(part.part_number as i32)
-            .e_tag(part.e_tag.clone())
-            .build()
+    let completed_parts: Vec<storage::CompletedPart> = uploaded_parts.iter().map(|part| {
+        storage::CompletedPart {
+            part_number: part.part_number,
+            e_tag: part.e_tag.clone(),
+        }
     }).collect();
 
-    let completed_multipart_upload = aws_sdk_s3::types::CompletedMultipartUpload::builder()
-        .set_parts(Some(completed_parts))
-        .build();
-
-    let client = get_s3_client().await;
     let result = complete_chunk_upload(
-        &client, 
+        &settings,
         &active_upload.object_name.to_string(),  
         &active_upload.aws_upload_id, 
-        completed_multipart_upload).await;
+        completed_parts).await;
 
     match active_upload.file_integrity_algorithm.as_str() {
         "crc32" => {
-            if let Some(checksum) = result.checksum_crc32() {
+            if let Some(checksum) = &result.checksum_crc32 {
                 if let Some(expected) = &active_upload.file_integrity_hash {
                     if checksum != expected {
                         tracing::error!("CRC32 checksum mismatch for upload {}: expected {}, got {}", payload.upload_id, expected, checksum);
@@ -397,7 +379,7 @@ async fn complete_chunk_upload_handler(
                     tracing::warn!("No expected CRC32 checksum provided for upload {}", payload.upload_id);
                 }
             } else {
-                tracing::warn!("No CRC32 checksum returned by S3 for upload {}", payload.upload_id);
+                tracing::warn!("No CRC32 checksum returned by the object store for upload {}", payload.upload_id);
             }
 
         },
@@ -409,11 +391,11 @@ async fn complete_chunk_upload_handler(
 
 
     db::complete_chunk_upload(&user_info.user_id, &payload.upload_id);
-    let client = get_s3_client().await;
     let expires_in_seconds = 7 * 60 * 60; // 7 hours
-    let presigned_uri = create_presigned_url(&client, &active_upload.object_name.to_string(), expires_in_seconds).await;
+    let presigned_uri = create_presigned_url(&settings, &active_upload.object_name.to_string(), expires_in_seconds).await;
 
     queue_upload_event(
+        &settings,
         &user_info, 
         presigned_uri,
         &active_upload.object_name.to_string(), 
@@ -421,16 +403,16 @@ async fn complete_chunk_upload_handler(
         active_upload.received_bytes as usize
     ).await;
 
-    send_audit_event(AuditEvent {
-        event_type: "complete_chunk_upload".to_string(),
-        user_id: Some(&user_info.user_id),
-        client_ip: &client_ip.to_string(),
-        target: Some(&payload.upload_id),
-        event_details: Some(serde_json::json!({
-            "file_name": active_upload.file_name,
-            "file_size": active_upload.received_bytes,
-        })),
-    }).await.unwrap_or_else(|e| {
+    send_audit_event(AuditEvent::new(
+        AuditEventKind::CompleteChunkUpload(CompleteChunkUploadDetails {
+            file_name: Some(active_upload.file_name),
+            file_size: Some(active_upload.received_bytes),
+            error: None,
+        }),
+        Some(&user_info.user_id),
+        &client_ip.to_string(),
+        Some(AuditTarget::resource(&object_name)),
+    )).await.unwrap_or_else(|e| {
         tracing::error!("Failed to send audit event: {}", e);
     });
 
@@ -451,16 +433,11 @@ async fn main() {
         .with_max_level(filter::LevelFilter::INFO)
         .init();
 
-    let ip_source_env = env::var("IP_SOURCE").unwrap_or_else(|_| "nginx".to_string());
-    let ip_source = match ip_source_env.as_str() {
-        "nginx" => ClientIpSource::RightmostXForwardedFor,
-        "amazon" => ClientIpSource::CloudFrontViewerAddress,
-        "cloudflare" => ClientIpSource::CfConnectingIp,
-        _ => { 
-            tracing::warn!("Unknown IP source: {}, defaulting to Nginx", ip_source_env);
-            ClientIpSource::RightmostXForwardedFor
-        } 
-    };
+    let settings = Arc::new(Settings::load());
+    tracing::info!("Loaded settings {:?}", settings);
+
+    db::init(settings.database_url.expose());
+    audit::start(&settings.audit);
 
     let app = Router::new()
         .route("/upload/health", get(|| async { "ok" }))
@@ -472,7 +449,7 @@ async fn main() {
             .layer(
                 ServiceBuilder::new()
                     .layer(DefaultBodyLimit::max(30*1024*1024))  // 30MB max per chunk
-                    .layer(from_fn(auth_middleware))
+                    .layer(from_fn_with_state(settings.auth.clone(), auth_middleware))
             )
         )
         .nest(
@@ -484,17 +461,21 @@ async fn main() {
 
             .layer(
                 ServiceBuilder::new()
-                    .layer(from_fn(auth_middleware))
+                    .layer(from_fn_with_state(settings.auth.clone(), auth_middleware))
             )
         )
-        .layer(ip_source.into_extension());
+        .layer(settings.ip_source.clone().into_extension())
+        .with_state(settings.clone());
     
-    let port = env::var("PORT").unwrap_or_else(|_| "3000".to_string());
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", settings.port)).await
         .expect("failed to bind tcp listener");
 
+    // the audit events still buffered are delivered, or spooled, before the process exits
     axum::serve(listener, app)
+        .with_graceful_shutdown(worker::shutdown_token().cancelled_owned())
         .await
         .expect("failed to start server");
+
+    audit::flush().await;
 }
 
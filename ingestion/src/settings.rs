@@ -0,0 +1,66 @@
+use std::sync::Arc;
+
+use axum_client_ip::ClientIpSource;
+
+use audit::AuditSettings;
+use auth_check::UserAuth;
+use config::{Secret, Source};
+use storage::{MIN_PART_SIZE, ObjectStore};
+
+
+/// The settings of the ingestion service, loaded once at startup and shared with the handlers
+#[derive(Debug)]
+pub struct Settings {
+    pub port: u16,
+    pub database_url: Secret<String>,
+    /// Where the uploaded files are stored, see `storage::store_from_source`
+    pub storage: Arc<dyn ObjectStore>,
+    /// The size of the chunks the clients upload, at least 5 MB
+    pub chunk_size: usize,
+    /// Starts the processing pipeline of the uploaded files
+    pub upload_queue_url: String,
+    /// Creates the resources of the uploaded files in the resource server
+    pub resource_status_queue_url: String,
+    /// Where the client IP is taken from: `nginx` (the default), `amazon` or `cloudflare`
+    pub ip_source: ClientIpSource,
+    /// Verifies the users with the auth service
+    pub auth: Arc<UserAuth>,
+    /// Where the uploads are audited, see `audit::start`
+    pub audit: AuditSettings,
+}
+
+impl Settings {
+    /// Loads the settings from the environment and the optional `CONFIG_FILE`.
+    ///
+    /// Exits the process with every missing or invalid setting listed, if there are any.
+    pub fn load() -> Self {
+        config::load_or_exit(|source| {
+            let chunk_size = source.with_default("CHUNK_SIZE", MIN_PART_SIZE);
+            if chunk_size < MIN_PART_SIZE {
+                source.invalid("CHUNK_SIZE", &format!("must be at least {} bytes", MIN_PART_SIZE));
+            }
+
+            Settings {
+                port: source.with_default("PORT", 3000),
+                database_url: source.secret("DATABASE_URL"),
+                storage: storage::store_from_source(source),
+                chunk_size,
+                upload_queue_url: source.required("UPLOAD_QUEUE_URL"),
+                resource_status_queue_url: source.required("RESOURCE_STATUS_QUEUE_URL"),
+                ip_source: ip_source(source),
+                auth: Arc::new(UserAuth::from_source(source)),
+                audit: AuditSettings::from_source(source),
+            }
+        })
+    }
+}
+
+
+fn ip_source(source: &mut Source) -> ClientIpSource {
+    source.parsed_with("IP_SOURCE", ClientIpSource::RightmostXForwardedFor, |value| match value {
+        "nginx" => Ok(ClientIpSource::RightmostXForwardedFor),
+        "amazon" => Ok(ClientIpSource::CloudFrontViewerAddress),
+        "cloudflare" => Ok(ClientIpSource::CfConnectingIp),
+        _ => Err("expected nginx, amazon or cloudflare".to_string()),
+    })
+}
@@ -1,4 +1,5 @@
 CREATE DATABASE user_db;
 CREATE DATABASE resource_db; 
 CREATE DATABASE ingestion_db;
-CREATE DATABASE audit_db;
\ No newline at end of file
+CREATE DATABASE audit_db;
+CREATE DATABASE webhook_db;
\ No newline at end of file
@@ -0,0 +1,214 @@
+use serde::{Deserialize, Serialize};
+
+
+/// Version of the audit event schema produced by this library.
+///
+/// Version 1 was the original, untyped format: free-form `event_type` string and arbitrary JSON
+/// in `event_details`, with no `schema_version` field at all.
+/// Version 2 is the typed format defined by `AuditEventKind`. It serializes to the same field names,
+/// so the version 1 consumers can still read it.
+/// Version 3 replaced the plain `target` string with a typed `AuditTarget`. The `occurred_at` time was added
+/// to it later, without a new version, as the consumers fall back to the time the event was queued.
+pub const AUDIT_SCHEMA_VERSION: u32 = 3;
+
+
+/// All the audit events we emit, together with their details.
+///
+/// Serialized as `"event_type": "<variant>", "event_details": { ... }` to stay compatible with
+/// the original format.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event_type", content = "event_details", rename_all = "snake_case")]
+pub enum AuditEventKind {
+    LoginSuccess,
+    LoginFailure(LoginFailureDetails),
+    TokenVerificationFailure,
+    PasswordChangeSuccess,
+    PasswordChangeFailed(PasswordChangeFailedDetails),
+    FileUpload(FileUploadDetails),
+    InitChunkUpload(InitChunkUploadDetails),
+    ChunkUpload(ChunkUploadDetails),
+    CompleteChunkUpload(CompleteChunkUploadDetails),
+    ResourcePublicStatusUpdated(ResourcePublicStatusUpdatedDetails),
+    ResourceReprocessed(ResourceReprocessedDetails),
+    ResourceDeleted,
+    VirusScan(VirusScanDetails),
+}
+
+impl AuditEventKind {
+    /// The event type as stored in the audit log, e.g. "login_failure"
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            AuditEventKind::LoginSuccess => "login_success",
+            AuditEventKind::LoginFailure(_) => "login_failure",
+            AuditEventKind::TokenVerificationFailure => "token_verification_failure",
+            AuditEventKind::PasswordChangeSuccess => "password_change_success",
+            AuditEventKind::PasswordChangeFailed(_) => "password_change_failed",
+            AuditEventKind::FileUpload(_) => "file_upload",
+            AuditEventKind::InitChunkUpload(_) => "init_chunk_upload",
+            AuditEventKind::ChunkUpload(_) => "chunk_upload",
+            AuditEventKind::CompleteChunkUpload(_) => "complete_chunk_upload",
+            AuditEventKind::ResourcePublicStatusUpdated(_) => "resource_public_status_updated",
+            AuditEventKind::ResourceReprocessed(_) => "resource_reprocessed",
+            AuditEventKind::ResourceDeleted => "resource_deleted",
+            AuditEventKind::VirusScan(_) => "virus_scan",
+        }
+    }
+}
+
+
+/// What kind of object the event target is
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetKind {
+    User,
+    Resource,
+    /// A chunked upload, identified by the multipart upload id of the object storage
+    Upload,
+    Session,
+}
+
+impl TargetKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TargetKind::User => "user",
+            TargetKind::Resource => "resource",
+            TargetKind::Upload => "upload",
+            TargetKind::Session => "session",
+        }
+    }
+}
+
+/// The object the event is about. The ID is kept as is, it is not required to be an UUID.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AuditTarget<'a> {
+    pub kind: TargetKind,
+    pub id: &'a str,
+}
+
+impl<'a> AuditTarget<'a> {
+    pub fn user(id: &'a str) -> Self {
+        AuditTarget { kind: TargetKind::User, id }
+    }
+
+    pub fn resource(id: &'a str) -> Self {
+        AuditTarget { kind: TargetKind::Resource, id }
+    }
+
+    pub fn upload(id: &'a str) -> Self {
+        AuditTarget { kind: TargetKind::Upload, id }
+    }
+
+    pub fn session(id: &'a str) -> Self {
+        AuditTarget { kind: TargetKind::Session, id }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoginFailureDetails {
+    pub username: String,
+    pub reason: LoginFailureReason,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LoginFailureReason {
+    #[serde(rename = "Invalid password")]
+    InvalidPassword,
+    #[serde(rename = "User not found")]
+    UserNotFound,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PasswordChangeFailedDetails {
+    pub reason: PasswordChangeFailureReason,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum PasswordChangeFailureReason {
+    #[serde(rename = "JWT verification failed")]
+    JwtVerificationFailed,
+    #[serde(rename = "One or more fields are empty")]
+    EmptyFields,
+    #[serde(rename = "New password and confirmation do not match")]
+    PasswordMismatch,
+    #[serde(rename = "New password is the same as the current password")]
+    SamePassword,
+    #[serde(rename = "New password too weak")]
+    WeakPassword,
+    #[serde(rename = "User not found")]
+    UserNotFound,
+    #[serde(rename = "Invalid current password")]
+    InvalidCurrentPassword,
+}
+
+/// Error recorded for the upload events, if the upload was rejected
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadError {
+    QuotaExceeded,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FileUploadDetails {
+    pub file_name: String,
+    pub file_size: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<UploadError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InitChunkUploadDetails {
+    pub file_name: String,
+    pub file_size: usize,
+    pub integrity_check_type: String,
+    pub integrity_check_value: Option<String>,
+    pub chunk_size: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunkUploadDetails {
+    pub chunk_index: usize,
+    pub chunk_size: usize,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<UploadError>,
+}
+
+/// On failure, only the error is known
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompleteChunkUploadDetails {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_size: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<UploadError>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourcePublicStatusUpdatedDetails {
+    pub is_public: bool,
+}
+
+/// The processing pipeline of a resource was started again, by its owner or an admin
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceReprocessedDetails {
+    /// The stage the pipeline was started from, e.g. "transcode"
+    pub from_stage: String,
+    pub revision: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renditions: Option<Vec<String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum VirusScanDetails {
+    Clean,
+    Skipped {
+        reason: String,
+        file_size: usize,
+        max_size: usize,
+    },
+    Infected {
+        scan_response: String,
+    },
+}
@@ -1,29 +1,59 @@
-use std::env;
-use aws_sdk_sqs::Client;
+mod event;
+mod sender;
+mod settings;
+mod sink;
+mod spool;
+
+pub use event::*;
+pub use sender::{flush, init, init_with, start};
+pub use settings::{AuditSettings, SinkSettings};
+pub use sink::*;
 
 
 #[derive(Debug, serde::Serialize)]
 pub struct AuditEvent<'a> {
-    pub event_type: String,
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub kind: AuditEventKind,
     pub user_id: Option<&'a str>,
     pub client_ip: &'a str,
-    pub target: Option<&'a str>,
-    pub event_details: Option<serde_json::Value>,
+    pub target: Option<AuditTarget<'a>>,
+    /// When the event happened, set by `send_audit_event` unless given. The event may be delivered much later,
+    /// when it is buffered or spooled.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub occurred_at: Option<chrono::DateTime<chrono::Utc>>,
 }
 
-pub async fn send_audit_event<'a>(event: AuditEvent<'a>) -> Result<(), Box<dyn std::error::Error>> {
-    let audit_event_queue_url = env::var("AUDIT_EVENT_QUEUE_URL")
-        .expect("AUDIT_EVENT_QUEUE_URL not set");
-
-    let serialized_event = serde_json::to_string(&event)?;
-    let client = Client::new(&aws_config::load_from_env().await);
-    client
-        .send_message()
-        .queue_url(&audit_event_queue_url)
-        .message_body(serialized_event)
-        .send()
-        .await?;
+impl<'a> AuditEvent<'a> {
+    /// Creates an event using the current schema version
+    pub fn new(
+        kind: AuditEventKind,
+        user_id: Option<&'a str>,
+        client_ip: &'a str,
+        target: Option<AuditTarget<'a>>,
+    ) -> Self {
+        AuditEvent {
+            schema_version: AUDIT_SCHEMA_VERSION,
+            kind,
+            user_id,
+            client_ip,
+            target,
+            occurred_at: None,
+        }
+    }
+}
 
+/// Queues the audit event for delivery.
+///
+/// The event is sent in the background, batched together with other events. If the sink (SQS by default)
+/// is unavailable, the event is spooled to the local disk and replayed later, so this does not fail or block
+/// when the sink is down.
+///
+/// # Returns
+/// * `Err` only if the event could neither be buffered nor spooled
+pub async fn send_audit_event<'a>(mut event: AuditEvent<'a>) -> Result<(), Box<dyn std::error::Error>> {
+    event.occurred_at.get_or_insert_with(chrono::Utc::now);
 
-    Ok(())
-}
\ No newline at end of file
+    let serialized_event = serde_json::to_string(&event)?;
+    sender::enqueue(serialized_event).await
+}
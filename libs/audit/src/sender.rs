@@ -0,0 +1,214 @@
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use tokio::sync::{mpsc, oneshot};
+
+use crate::settings::AuditSettings;
+use crate::sink::{AuditSink, StdoutSink};
+use crate::spool::Spool;
+
+
+// how long we wait for more events before sending a partial batch
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+// how often we try to replay the spooled events
+const REPLAY_INTERVAL: Duration = Duration::from_secs(30);
+
+// how long the caller may be blocked when the buffer is full, before we spool the event directly
+const ENQUEUE_TIMEOUT: Duration = Duration::from_millis(100);
+
+
+enum Command {
+    Event(String),
+    Flush(oneshot::Sender<()>),
+}
+
+struct Sender {
+    tx: mpsc::Sender<Command>,
+    spool: Arc<Spool>,
+}
+
+static SENDER: OnceLock<Sender> = OnceLock::new();
+
+
+/// Starts the background sender with the sink of the settings. Called once at startup by the services that send
+/// audit events, before the first event is sent.
+///
+/// # Returns
+/// * `false` if the sender was already running, in which case the settings are not used
+///
+/// # Panics
+/// * If called outside of a Tokio runtime
+pub fn start(settings: &AuditSettings) -> bool {
+    init_with(settings.sink.open(), settings)
+}
+
+/// Starts the background sender with the given sink and the default settings, typically in tests with a
+/// `MemorySink`. This works once per process, see `MemorySink` for what that means for the tests.
+///
+/// # Returns
+/// * `false` if the sender was already running, in which case the sink is not used
+///
+/// # Panics
+/// * If called outside of a Tokio runtime
+pub fn init(sink: Arc<dyn AuditSink>) -> bool {
+    init_with(sink, &AuditSettings::default())
+}
+
+/// Like `init`, with the buffer and the spool of the settings
+pub fn init_with(sink: Arc<dyn AuditSink>, settings: &AuditSettings) -> bool {
+    let mut installed = false;
+    SENDER.get_or_init(|| {
+        installed = true;
+        spawn(sink, settings)
+    });
+    installed
+}
+
+/// Returns the shared sender. If it was not started, the events are written to stdout, so that they are not lost.
+///
+/// # Panics
+/// * If called outside of a Tokio runtime
+fn sender() -> &'static Sender {
+    SENDER.get_or_init(|| {
+        tracing::warn!("The audit sender was not started, writing the audit events to stdout");
+        spawn(Arc::new(StdoutSink), &AuditSettings::default())
+    })
+}
+
+fn spawn(sink: Arc<dyn AuditSink>, settings: &AuditSettings) -> Sender {
+    let spool = Arc::new(Spool::new(settings.spool_path.clone(), settings.spool_max_bytes));
+    let (tx, rx) = mpsc::channel(settings.buffer_size.max(1));
+
+    tokio::spawn(run(rx, sink, spool.clone()));
+
+    Sender { tx, spool }
+}
+
+/// Hands the serialized event over to the background sender.
+///
+/// Normally this just buffers the event. If the buffer stays full (SQS is slow or unavailable and the
+/// spool cannot keep up), the event is written to the spool directly instead.
+pub async fn enqueue(event: String) -> Result<(), Box<dyn std::error::Error>> {
+    let sender = sender();
+
+    match sender.tx.send_timeout(Command::Event(event), ENQUEUE_TIMEOUT).await {
+        Ok(()) => Ok(()),
+        Err(mpsc::error::SendTimeoutError::Timeout(Command::Event(event)))
+        | Err(mpsc::error::SendTimeoutError::Closed(Command::Event(event))) => {
+            tracing::warn!("Audit event buffer is full, spooling the event directly");
+            sender.spool.append(&[event]).await?;
+            Ok(())
+        }
+        Err(_) => unreachable!("Only events are enqueued"),
+    }
+}
+
+/// Waits until the events buffered so far have been either delivered or spooled.
+///
+/// Should be called before the process exits, so that the buffered events are not lost.
+pub async fn flush() {
+    let Some(sender) = SENDER.get() else {
+        // nothing has been sent, so nothing to flush
+        return;
+    };
+
+    let (done_tx, done_rx) = oneshot::channel();
+    if sender.tx.send(Command::Flush(done_tx)).await.is_ok() {
+        let _ = done_rx.await;
+    }
+}
+
+
+async fn run(mut rx: mpsc::Receiver<Command>, sink: Arc<dyn AuditSink>, spool: Arc<Spool>) {
+    let max_batch_size = sink.max_batch_size();
+
+    let mut batch: Vec<String> = Vec::with_capacity(max_batch_size);
+    let mut flush_interval = tokio::time::interval(FLUSH_INTERVAL);
+    let mut replay_interval = tokio::time::interval(REPLAY_INTERVAL);
+
+    loop {
+        tokio::select! {
+            command = rx.recv() => {
+                match command {
+                    Some(Command::Event(event)) => {
+                        batch.push(event);
+                        if batch.len() >= max_batch_size {
+                            deliver(sink.as_ref(), &spool, &mut batch).await;
+                        }
+                    }
+                    Some(Command::Flush(done)) => {
+                        deliver(sink.as_ref(), &spool, &mut batch).await;
+                        let _ = done.send(());
+                    }
+                    None => {
+                        deliver(sink.as_ref(), &spool, &mut batch).await;
+                        break;
+                    }
+                }
+            }
+            _ = flush_interval.tick() => {
+                deliver(sink.as_ref(), &spool, &mut batch).await;
+            }
+            _ = replay_interval.tick() => {
+                replay(sink.as_ref(), &spool).await;
+            }
+        }
+    }
+}
+
+/// Sends the batch, and spools whatever could not be sent. The batch is empty afterwards.
+async fn deliver(sink: &dyn AuditSink, spool: &Spool, batch: &mut Vec<String>) {
+    if batch.is_empty() {
+        return;
+    }
+
+    let events = std::mem::take(batch);
+    let failed = sink.send(events).await;
+
+    if !failed.is_empty() {
+        spool_events(spool, &failed).await;
+    }
+}
+
+/// Replays the spooled events. Anything that still fails goes back to the spool.
+async fn replay(sink: &dyn AuditSink, spool: &Spool) {
+    let events = match spool.take().await {
+        Ok(events) => events,
+        Err(err) => {
+            tracing::error!("Failed to read the audit spool: {}", err);
+            return;
+        }
+    };
+
+    if events.is_empty() {
+        return;
+    }
+
+    tracing::info!("Replaying {} spooled audit events", events.len());
+
+    let mut failed = vec![];
+    for chunk in events.chunks(sink.max_batch_size()) {
+        failed.extend(sink.send(chunk.to_vec()).await);
+    }
+
+    // spool the failures before removing the replay file, so that a crash in between only causes duplicates
+    if !failed.is_empty() {
+        tracing::warn!("{} spooled audit events could not be delivered, keeping them spooled", failed.len());
+        spool_events(spool, &failed).await;
+    }
+
+    spool.replay_done().await.unwrap_or_else(|err| {
+        tracing::error!("Failed to remove the replayed audit spool: {}", err);
+    });
+}
+
+async fn spool_events(spool: &Spool, events: &[String]) {
+    spool.append(events).await.unwrap_or_else(|err| {
+        // last resort, so that the events are at least somewhere
+        tracing::error!("Failed to spool {} audit events: {}", events.len(), err);
+        for event in events {
+            tracing::error!("Lost audit event: {}", event);
+        }
+    });
+}
@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use config::{Secret, Source};
+
+use crate::sink::{AuditSink, FileSink, HttpSink, SqsSink, StdoutSink};
+
+
+/// The settings of the audit sender, read once at startup with `AuditSettings::from_source` and passed to
+/// `audit::start`
+#[derive(Debug, Clone)]
+pub struct AuditSettings {
+    pub sink: SinkSettings,
+    /// How many events wait for the sink in memory, before they are spooled directly
+    pub buffer_size: usize,
+    /// Where the events the sink could not take are kept until they are replayed
+    pub spool_path: PathBuf,
+    pub spool_max_bytes: u64,
+}
+
+impl Default for AuditSettings {
+    fn default() -> Self {
+        AuditSettings {
+            sink: SinkSettings::Stdout,
+            buffer_size: 1000,
+            spool_path: PathBuf::from("/tmp/audit-spool.jsonl"),
+            spool_max_bytes: 100 * 1024 * 1024,
+        }
+    }
+}
+
+impl AuditSettings {
+    /// Reads the sink, see `SinkSettings::from_source`, and the optional `AUDIT_BUFFER_SIZE`, `AUDIT_SPOOL_PATH`
+    /// and `AUDIT_SPOOL_MAX_MEGABYTES`
+    pub fn from_source(source: &mut Source) -> Self {
+        let defaults = AuditSettings::default();
+
+        AuditSettings {
+            sink: SinkSettings::from_source(source),
+            buffer_size: source.with_default("AUDIT_BUFFER_SIZE", defaults.buffer_size),
+            spool_path: source.with_default("AUDIT_SPOOL_PATH", defaults.spool_path),
+            spool_max_bytes: source.with_default("AUDIT_SPOOL_MAX_MEGABYTES", 100) * 1024 * 1024,
+        }
+    }
+}
+
+
+/// Where the audit events are delivered
+#[derive(Debug, Clone)]
+pub enum SinkSettings {
+    Sqs { queue_url: String },
+    Stdout,
+    /// Appended to the file, which is rotated when it reaches `max_bytes`, keeping `max_files` of them
+    File { path: PathBuf, max_bytes: u64, max_files: usize },
+    /// Posted to the URL, with the token as a bearer token if set
+    Http { url: String, auth_token: Option<Secret<String>> },
+}
+
+impl SinkSettings {
+    /// Reads the sink selected by `AUDIT_SINK`, and its settings.
+    ///
+    /// Supported values are `sqs`, `stdout`, `file` and `http`. If `AUDIT_SINK` is not set, SQS is used when
+    /// `AUDIT_EVENT_QUEUE_URL` is set, and stdout otherwise, so that the services can be run without localstack.
+    pub fn from_source(source: &mut Source) -> Self {
+        let default = if source.optional::<String>("AUDIT_EVENT_QUEUE_URL").is_some() { "sqs" } else { "stdout" };
+        let sink = source.parsed_with("AUDIT_SINK", default, |value| match value {
+            "sqs" => Ok("sqs"),
+            "stdout" => Ok("stdout"),
+            "file" => Ok("file"),
+            "http" => Ok("http"),
+            _ => Err("expected sqs, stdout, file or http".to_string()),
+        });
+
+        match sink {
+            "sqs" => SinkSettings::Sqs { queue_url: source.required("AUDIT_EVENT_QUEUE_URL") },
+            "file" => SinkSettings::File {
+                path: source.with_default("AUDIT_FILE_PATH", PathBuf::from("audit-events.jsonl")),
+                max_bytes: source.with_default("AUDIT_FILE_MAX_MEGABYTES", 100) * 1024 * 1024,
+                max_files: source.with_default("AUDIT_FILE_MAX_FILES", 5),
+            },
+            "http" => SinkSettings::Http {
+                url: source.required("AUDIT_WEBHOOK_URL"),
+                auth_token: source.optional_secret("AUDIT_WEBHOOK_AUTH_TOKEN"),
+            },
+            _ => SinkSettings::Stdout,
+        }
+    }
+
+    pub fn open(&self) -> Arc<dyn AuditSink> {
+        match self {
+            SinkSettings::Sqs { queue_url } => Arc::new(SqsSink::new(queue_url.clone())),
+            SinkSettings::Stdout => Arc::new(StdoutSink),
+            SinkSettings::File { path, max_bytes, max_files } => Arc::new(FileSink::new(path.clone(), *max_bytes, *max_files)),
+            SinkSettings::Http { url, auth_token } => {
+                Arc::new(HttpSink::new(url.clone(), auth_token.as_ref().map(|token| token.expose().clone())))
+            }
+        }
+    }
+}
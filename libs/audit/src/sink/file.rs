@@ -0,0 +1,107 @@
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use super::AuditSink;
+
+
+/// Appends the events to a local file as JSON lines.
+///
+/// When the file would grow beyond `max_bytes`, it is rotated: `events.jsonl` becomes `events.jsonl.1`,
+/// `events.jsonl.1` becomes `events.jsonl.2` and so on. At most `max_files` rotated files are kept.
+pub struct FileSink {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+    lock: Mutex<()>,
+}
+
+impl FileSink {
+    pub fn new(path: PathBuf, max_bytes: u64, max_files: usize) -> Self {
+        FileSink {
+            path,
+            max_bytes,
+            max_files,
+            lock: Mutex::new(()),
+        }
+    }
+
+    async fn append(&self, data: &str) -> Result<(), std::io::Error> {
+        let _guard = self.lock.lock().await;
+
+        let current_size = fs::metadata(&self.path).await.map(|m| m.len()).unwrap_or(0);
+        if current_size > 0 && current_size + data.len() as u64 > self.max_bytes {
+            self.rotate().await?;
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+
+        file.write_all(data.as_bytes()).await?;
+        file.flush().await?;
+
+        Ok(())
+    }
+
+    async fn rotate(&self) -> Result<(), std::io::Error> {
+        if self.max_files == 0 {
+            return fs::remove_file(&self.path).await;
+        }
+
+        // the oldest one falls off the end
+        remove_if_exists(&rotated_path(&self.path, self.max_files)).await?;
+
+        for i in (1..self.max_files).rev() {
+            let from = rotated_path(&self.path, i);
+            if fs::try_exists(&from).await? {
+                fs::rename(&from, rotated_path(&self.path, i + 1)).await?;
+            }
+        }
+
+        fs::rename(&self.path, rotated_path(&self.path, 1)).await
+    }
+}
+
+#[async_trait]
+impl AuditSink for FileSink {
+    async fn send(&self, events: Vec<String>) -> Vec<String> {
+        let mut data = String::new();
+        for event in &events {
+            data.push_str(event);
+            data.push('\n');
+        }
+
+        match self.append(&data).await {
+            Ok(()) => vec![],
+            Err(err) => {
+                tracing::error!("Failed to write audit events to {}: {}", self.path.display(), err);
+                events
+            }
+        }
+    }
+
+    fn max_batch_size(&self) -> usize {
+        100
+    }
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut rotated: OsString = path.as_os_str().to_owned();
+    rotated.push(format!(".{}", index));
+    rotated.into()
+}
+
+async fn remove_if_exists(path: &Path) -> Result<(), std::io::Error> {
+    match fs::remove_file(path).await {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
@@ -0,0 +1,59 @@
+use async_trait::async_trait;
+
+use super::AuditSink;
+
+
+/// Posts the events to a webhook as a JSON array.
+///
+/// Any response other than 2xx is treated as a failure for the whole batch.
+pub struct HttpSink {
+    client: reqwest::Client,
+    url: String,
+    auth_token: Option<String>,
+}
+
+impl HttpSink {
+    pub fn new(url: String, auth_token: Option<String>) -> Self {
+        HttpSink {
+            client: reqwest::Client::builder()
+                .timeout(std::time::Duration::from_secs(10))
+                .build()
+                .expect("Failed to build HTTP client"),
+            url,
+            auth_token,
+        }
+    }
+}
+
+#[async_trait]
+impl AuditSink for HttpSink {
+    async fn send(&self, events: Vec<String>) -> Vec<String> {
+        // the events are already serialized, so just join them into an array
+        let body = format!("[{}]", events.join(","));
+
+        let mut request = self.client
+            .post(&self.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body);
+
+        if let Some(token) = &self.auth_token {
+            request = request.bearer_auth(token);
+        }
+
+        match request.send().await {
+            Ok(response) if response.status().is_success() => vec![],
+            Ok(response) => {
+                tracing::error!("Audit webhook {} responded with {}", self.url, response.status());
+                events
+            }
+            Err(err) => {
+                tracing::error!("Failed to send audit events to {}: {}", self.url, err);
+                events
+            }
+        }
+    }
+
+    fn max_batch_size(&self) -> usize {
+        100
+    }
+}
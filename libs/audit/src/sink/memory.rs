@@ -0,0 +1,76 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use crate::{AuditEventKind, TargetKind};
+
+use super::AuditSink;
+
+
+/// Owned copy of an emitted audit event, as recorded by `MemorySink`.
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct RecordedAuditEvent {
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub kind: AuditEventKind,
+    pub user_id: Option<String>,
+    pub client_ip: String,
+    pub target: Option<RecordedAuditTarget>,
+    pub occurred_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Deserialize)]
+pub struct RecordedAuditTarget {
+    pub kind: TargetKind,
+    pub id: String,
+}
+
+/// Keeps the events in memory, so that tests can assert which events were emitted.
+///
+/// Install it with `audit::init`, and call `audit::flush` before checking the events:
+///
+/// ```ignore
+/// let sink = Arc::new(MemorySink::new());
+/// audit::init(sink.clone());
+/// // ... call the handler ...
+/// audit::flush().await;
+/// assert_eq!(sink.events()[0].kind.event_type(), "login_success");
+/// ```
+///
+/// The sender can be started only once per process, so each test binary can install one sink, and
+/// `audit::init` returns `false` for the rest. The sender also runs on the runtime of the test that started it,
+/// and stops with it. Keep the audit assertions of a test binary in a single test, with a binary of their own
+/// under `tests/` if needed, and use `clear` between its steps.
+#[derive(Default)]
+pub struct MemorySink {
+    events: Mutex<Vec<String>>,
+}
+
+impl MemorySink {
+    pub fn new() -> Self {
+        MemorySink::default()
+    }
+
+    /// The events received so far, in the order they were sent
+    pub fn events(&self) -> Vec<RecordedAuditEvent> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|event| serde_json::from_str(event).expect("Recorded audit event is not valid"))
+            .collect()
+    }
+
+    /// Forgets the events received so far
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear();
+    }
+}
+
+#[async_trait]
+impl AuditSink for MemorySink {
+    async fn send(&self, events: Vec<String>) -> Vec<String> {
+        self.events.lock().unwrap().extend(events);
+        vec![]
+    }
+}
@@ -0,0 +1,33 @@
+mod file;
+mod http;
+mod memory;
+mod sqs;
+mod stdout;
+
+use async_trait::async_trait;
+
+pub use file::FileSink;
+pub use http::HttpSink;
+pub use memory::{MemorySink, RecordedAuditEvent, RecordedAuditTarget};
+pub use sqs::SqsSink;
+pub use stdout::StdoutSink;
+
+
+/// Destination of the audit events.
+///
+/// The background sender batches the serialized events and hands them over to the sink. Anything the sink
+/// reports as failed is spooled locally and retried later.
+#[async_trait]
+pub trait AuditSink: Send + Sync {
+    /// Delivers the serialized events.
+    ///
+    /// # Returns
+    /// * The events that could not be delivered
+    async fn send(&self, events: Vec<String>) -> Vec<String>;
+
+    /// The maximum number of events passed to a single `send` call
+    fn max_batch_size(&self) -> usize {
+        10
+    }
+}
+
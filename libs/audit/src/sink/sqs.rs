@@ -0,0 +1,73 @@
+use async_trait::async_trait;
+
+use aws_sdk_sqs::Client;
+use aws_sdk_sqs::types::SendMessageBatchRequestEntry;
+
+use tokio::sync::OnceCell;
+
+use super::AuditSink;
+
+
+/// Sends the events to the audit event queue, which is consumed by the audit service.
+pub struct SqsSink {
+    // loading the AWS config is async, so the client is created on the first send
+    client: OnceCell<Client>,
+    queue_url: String,
+}
+
+impl SqsSink {
+    pub fn new(queue_url: String) -> Self {
+        SqsSink {
+            client: OnceCell::new(),
+            queue_url,
+        }
+    }
+}
+
+#[async_trait]
+impl AuditSink for SqsSink {
+    /// Sends up to 10 events with a single SendMessageBatch call.
+    async fn send(&self, events: Vec<String>) -> Vec<String> {
+        let entries = events
+            .iter()
+            .enumerate()
+            .map(|(i, event)| {
+                SendMessageBatchRequestEntry::builder()
+                    .id(i.to_string())
+                    .message_body(event)
+                    .build()
+                    .expect("Failed to build batch entry")
+            })
+            .collect::<Vec<_>>();
+
+        let client = self.client
+            .get_or_init(|| async { Client::new(&aws_config::load_from_env().await) })
+            .await;
+
+        match client
+            .send_message_batch()
+            .queue_url(&self.queue_url)
+            .set_entries(Some(entries))
+            .send()
+            .await {
+            Ok(output) => {
+                output.failed
+                    .iter()
+                    .filter_map(|failure| {
+                        tracing::error!("SQS rejected audit event: {} ({})", failure.code, failure.message.as_deref().unwrap_or(""));
+                        failure.id.parse::<usize>().ok().and_then(|i| events.get(i).cloned())
+                    })
+                    .collect()
+            }
+            Err(err) => {
+                tracing::error!("Failed to send audit events: {}", aws_sdk_sqs::error::DisplayErrorContext(err));
+                events
+            }
+        }
+    }
+
+    // SQS limit for SendMessageBatch
+    fn max_batch_size(&self) -> usize {
+        10
+    }
+}
@@ -0,0 +1,31 @@
+use std::io::Write;
+
+use async_trait::async_trait;
+
+use super::AuditSink;
+
+
+/// Writes the events to stdout as JSON lines. Handy for local development, or when the log collector
+/// already ships stdout somewhere.
+pub struct StdoutSink;
+
+#[async_trait]
+impl AuditSink for StdoutSink {
+    async fn send(&self, events: Vec<String>) -> Vec<String> {
+        let mut stdout = std::io::stdout().lock();
+
+        for (i, event) in events.iter().enumerate() {
+            if let Err(err) = writeln!(stdout, "{}", event) {
+                tracing::error!("Failed to write audit event to stdout: {}", err);
+                return events[i..].to_vec();
+            }
+        }
+
+        let _ = stdout.flush();
+        vec![]
+    }
+
+    fn max_batch_size(&self) -> usize {
+        100
+    }
+}
@@ -0,0 +1,111 @@
+use std::path::PathBuf;
+
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+
+/// Local append-only spool for audit events that could not be delivered.
+///
+/// Each line is a single serialized event. Replaying renames the spool file first, so that new events
+/// can be spooled while the old ones are being sent. If we crash during the replay, the renamed file
+/// is picked up again on the next replay, so events are delivered at least once.
+pub struct Spool {
+    path: PathBuf,
+    replay_path: PathBuf,
+    max_bytes: u64,
+    // serializes the writers; both the background sender and the callers (when the buffer is full) append
+    lock: Mutex<()>,
+}
+
+impl Spool {
+    pub fn new(path: PathBuf, max_bytes: u64) -> Self {
+        let mut replay_path = path.clone().into_os_string();
+        replay_path.push(".replaying");
+
+        Spool {
+            path,
+            replay_path: replay_path.into(),
+            max_bytes,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Appends the events to the spool.
+    ///
+    /// # Returns
+    /// * `Err` if the spool could not be written, or if the spool would grow beyond the configured limit.
+    ///   The events are lost in this case, so the caller should at least log them.
+    pub async fn append(&self, events: &[String]) -> Result<(), std::io::Error> {
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        let _guard = self.lock.lock().await;
+
+        let mut data = String::new();
+        for event in events {
+            data.push_str(event);
+            data.push('\n');
+        }
+
+        let current_size = fs::metadata(&self.path).await.map(|m| m.len()).unwrap_or(0);
+        if current_size + data.len() as u64 > self.max_bytes {
+            return Err(std::io::Error::other(format!(
+                "Audit spool {} is full ({} bytes), refusing to spool {} events",
+                self.path.display(),
+                current_size,
+                events.len()
+            )));
+        }
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+
+        file.write_all(data.as_bytes()).await?;
+        file.flush().await?;
+
+        Ok(())
+    }
+
+    /// Takes the spooled events out of the spool for replaying.
+    ///
+    /// Events left over from an interrupted replay are returned first. The caller must call `replay_done`
+    /// once the events have been delivered (or spooled again).
+    pub async fn take(&self) -> Result<Vec<String>, std::io::Error> {
+        let _guard = self.lock.lock().await;
+
+        // previous replay was interrupted, finish that one first before taking more
+        if fs::try_exists(&self.replay_path).await? {
+            return read_lines(&self.replay_path).await;
+        }
+
+        if !fs::try_exists(&self.path).await? {
+            return Ok(vec![]);
+        }
+
+        fs::rename(&self.path, &self.replay_path).await?;
+        read_lines(&self.replay_path).await
+    }
+
+    /// Removes the events returned by `take` from the disk.
+    pub async fn replay_done(&self) -> Result<(), std::io::Error> {
+        let _guard = self.lock.lock().await;
+        match fs::remove_file(&self.replay_path).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        }
+    }
+}
+
+async fn read_lines(path: &PathBuf) -> Result<Vec<String>, std::io::Error> {
+    let content = fs::read_to_string(path).await?;
+    Ok(content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.to_string())
+        .collect())
+}
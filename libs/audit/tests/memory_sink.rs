@@ -0,0 +1,44 @@
+use std::sync::Arc;
+
+use audit::{
+    send_audit_event, AuditEvent, AuditEventKind, AuditTarget, MemorySink, ResourcePublicStatusUpdatedDetails,
+    TargetKind,
+};
+
+
+#[tokio::test]
+async fn memory_sink_records_sent_events() {
+    let sink = Arc::new(MemorySink::new());
+    assert!(audit::init(sink.clone()));
+
+    send_audit_event(AuditEvent::new(
+        AuditEventKind::LoginSuccess,
+        Some("user"),
+        "127.0.0.1",
+        None,
+    )).await.unwrap();
+
+    send_audit_event(AuditEvent::new(
+        AuditEventKind::ResourcePublicStatusUpdated(ResourcePublicStatusUpdatedDetails {
+            is_public: true,
+        }),
+        Some("user"),
+        "127.0.0.1",
+        Some(AuditTarget::resource("resource")),
+    )).await.unwrap();
+
+    audit::flush().await;
+
+    let events = sink.events();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0].kind, AuditEventKind::LoginSuccess);
+    assert_eq!(events[0].user_id.as_deref(), Some("user"));
+    assert!(events[0].occurred_at.is_some());
+    assert_eq!(
+        events[1].kind,
+        AuditEventKind::ResourcePublicStatusUpdated(ResourcePublicStatusUpdatedDetails { is_public: true })
+    );
+    let target = events[1].target.as_ref().unwrap();
+    assert_eq!(target.kind, TargetKind::Resource);
+    assert_eq!(target.id, "resource");
+}
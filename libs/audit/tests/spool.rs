@@ -0,0 +1,91 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use audit::{send_audit_event, AuditEvent, AuditEventKind, AuditSettings, AuditSink};
+
+
+/// Fails every send until it is made available, like SQS during an outage
+#[derive(Default)]
+struct UnavailableSink {
+    available: AtomicBool,
+    events: Mutex<Vec<String>>,
+}
+
+#[async_trait]
+impl AuditSink for UnavailableSink {
+    async fn send(&self, events: Vec<String>) -> Vec<String> {
+        if !self.available.load(Ordering::SeqCst) {
+            return events;
+        }
+        self.events.lock().unwrap().extend(events);
+        vec![]
+    }
+}
+
+fn event_types(events: &[String]) -> Vec<String> {
+    events.iter()
+        .map(|event| serde_json::from_str::<serde_json::Value>(event).unwrap()["event_type"].as_str().unwrap().to_string())
+        .collect()
+}
+
+// the events in the spool, and in the file of a replay in progress
+fn spooled_events(spool_path: &Path) -> Vec<String> {
+    let replay_path = PathBuf::from(format!("{}.replaying", spool_path.display()));
+
+    [spool_path, &replay_path].iter()
+        .filter_map(|path| std::fs::read_to_string(path).ok())
+        .flat_map(|content| content.lines().map(|line| line.to_string()).collect::<Vec<_>>())
+        .collect()
+}
+
+
+// the sender can only be started once per process, so the whole outage is a single test
+#[tokio::test(start_paused = true)]
+async fn spools_events_while_the_sink_fails_and_replays_them() {
+    let spool_path = std::env::temp_dir().join(format!("audit-spool-test-{}.jsonl", std::process::id()));
+    let replay_path = PathBuf::from(format!("{}.replaying", spool_path.display()));
+    std::fs::remove_file(&spool_path).ok();
+
+    // left over from a replay that was interrupted by a crash
+    let leftover = serde_json::to_string(&AuditEvent::new(AuditEventKind::TokenVerificationFailure, Some("user"), "127.0.0.1", None)).unwrap();
+    std::fs::write(&replay_path, format!("{}\n", leftover)).unwrap();
+
+    let sink = Arc::new(UnavailableSink::default());
+    assert!(audit::init_with(sink.clone(), &AuditSettings { spool_path: spool_path.clone(), ..AuditSettings::default() }));
+
+    for kind in [AuditEventKind::LoginSuccess, AuditEventKind::PasswordChangeSuccess] {
+        send_audit_event(AuditEvent::new(kind, Some("user"), "127.0.0.1", None)).await.unwrap();
+    }
+    audit::flush().await;
+
+    // nothing is lost while the sink is down
+    assert!(sink.events.lock().unwrap().is_empty());
+    let mut spooled = event_types(&spooled_events(&spool_path));
+    spooled.sort();
+    assert_eq!(spooled, ["login_success", "password_change_success", "token_verification_failure"]);
+
+    sink.available.store(true, Ordering::SeqCst);
+
+    // the replay runs every 30 seconds, the paused clock skips ahead while the test waits
+    for _ in 0..120 {
+        if sink.events.lock().unwrap().len() == 3 {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    let mut delivered = event_types(&sink.events.lock().unwrap());
+    delivered.sort();
+    assert_eq!(delivered, ["login_success", "password_change_success", "token_verification_failure"]);
+
+    // the spool is emptied once the replay is done
+    tokio::time::sleep(Duration::from_secs(1)).await;
+    assert!(spooled_events(&spool_path).is_empty());
+    assert!(!replay_path.exists());
+
+    std::fs::remove_file(&spool_path).ok();
+}
@@ -1,21 +1,24 @@
-use std::env;
+mod service;
 
 use std::collections::HashMap;
+use std::sync::Arc;
 
 use axum_extra::extract::CookieJar;
-use reqwest;
-
-use base64;
 
 use axum::{
     extract::{
-        Request
+        Request,
+        State,
     }, 
     http::StatusCode, 
     middleware::Next, 
     response::Response
 };
 
+pub use service::{service_auth_middleware, service_token, verify_service_token, ServiceAuth, ServiceIdentity, SERVICE_TOKEN_HEADER};
+
+use config::Source;
+
 
 #[derive(Debug, Clone, serde::Deserialize)]
 pub struct UserInfo {
@@ -25,17 +28,37 @@ pub struct UserInfo {
     pub email: String,
 }
 
+/// The settings of `auth_middleware` and `add_user_info_to_request`, loaded once at startup with
+/// `UserAuth::from_source`
+#[derive(Debug, Clone)]
+pub struct UserAuth {
+    /// The auth service verifies the user tokens, e.g. "http://auth:3000"
+    pub auth_service_url: String,
+    /// Identifies this service to the auth service
+    pub service: Arc<ServiceAuth>,
+}
+
+impl UserAuth {
+    /// Reads `AUTH_SERVICE_URL`, and the settings read by `ServiceAuth::from_source`
+    pub fn from_source(source: &mut Source) -> Self {
+        UserAuth {
+            auth_service_url: source.required("AUTH_SERVICE_URL"),
+            service: Arc::new(ServiceAuth::from_source(source)),
+        }
+    }
+}
+
 // implement a tower middleware that fetches the auth token from the cookies or Authorization header, and then delegates
 // the authentication to the authorization service
 
-pub async fn auth_middleware(mut req: Request, next: Next) -> Result<Response, StatusCode> {
+pub async fn auth_middleware(State(auth): State<Arc<UserAuth>>, mut req: Request, next: Next) -> Result<Response, StatusCode> {
 
     let client_ip = get_client_ip(&req);
 
     let token = get_token(&req);
 
     if let Some(token) = token {
-        let res =  is_authenticated(&token, client_ip).await;
+        let res =  is_authenticated(&auth, &token, client_ip).await;
         match res {
             Ok(true) => {
                 let claims = token.split('.').nth(1).unwrap();
@@ -67,6 +90,7 @@ pub async fn auth_middleware(mut req: Request, next: Next) -> Result<Response, S
 /// * `req` - The request to which the UserInfo will be added if the user is authenticated.
 ///
 pub async fn add_user_info_to_request(
+    State(auth): State<Arc<UserAuth>>,
     mut req: Request,
     next: Next,
 ) -> Result<Response, StatusCode> {
@@ -75,7 +99,7 @@ pub async fn add_user_info_to_request(
     let token = get_token(&req);
 
     if let Some(token) = token {
-    let res =  is_authenticated(&token, client_ip).await;
+    let res =  is_authenticated(&auth, &token, client_ip).await;
         match res {
             Ok(true) => {
                 let claims = token.split('.').nth(1).unwrap();
@@ -96,18 +120,16 @@ pub async fn add_user_info_to_request(
         req.extensions_mut().insert::<Option<UserInfo>>(None);
     }
 
-    return Ok(next.run(req).await);
+    Ok(next.run(req).await)
 }
 
 
 fn get_token(req: &Request) -> Option<String> {
     // check if we have an authorization header with a valid token
-    if let Some(auth_header) = req.headers().get("Authorization") {
-        if let Ok(auth_value) = auth_header.to_str() {
-            if auth_value.starts_with("Bearer ") {
-                return Some(auth_value.trim_start_matches("Bearer ").to_string());
-            }    
-        } 
+    if let Some(auth_header) = req.headers().get("Authorization")
+        && let Ok(auth_value) = auth_header.to_str()
+        && auth_value.starts_with("Bearer ") {
+        return Some(auth_value.trim_start_matches("Bearer ").to_string());
     }
 
   
@@ -119,18 +141,18 @@ fn get_token(req: &Request) -> Option<String> {
     None
 }
 
-async fn is_authenticated(token: &str, client_ip: String) -> Result<bool, reqwest::Error> {
+async fn is_authenticated(auth: &UserAuth, token: &str, client_ip: String) -> Result<bool, reqwest::Error> {
     
     let client = reqwest::Client::new();
-    let auth_server_url = env::var("AUTH_SERVICE_URL").expect("AUTH_SERVICE_URL must be set");
 
     let mut map = HashMap::new();
     map.insert("token", token);
 
     let response = client
-        .post(&format!("{}/auth/verify", auth_server_url))
+        .post(format!("{}/auth/verify", auth.auth_service_url))
         .header("Content-Type", "application/json")
         .header("X-Client-IP", client_ip)
+        .header(SERVICE_TOKEN_HEADER, service_token(&auth.service, "auth"))
         .json(&map)
         .send()
         .await?;
@@ -146,18 +168,16 @@ fn get_client_ip(req: &Request) -> String {
     // Prioritize Amazon headers for client IP
     // This uses the X-Forwarded-For header, which is set by the load balancer.
     // Grab the leftmost IP address from the comma-separated list
-    if let Some(forwarded_for) = req.headers().get("X-Forwarded-For") {
-        if let Ok(ip) = forwarded_for.to_str() {
-            return ip.split(',').next().unwrap_or("unknown").trim().to_string();
-        }
-    }   
+    if let Some(forwarded_for) = req.headers().get("X-Forwarded-For")
+        && let Ok(ip) = forwarded_for.to_str() {
+        return ip.split(',').next().unwrap_or("unknown").trim().to_string();
+    }
 
     // If not set, we are running in thhe local dev env which uses Nginx. This is configured
     // to set the X-Real-IP header to the client IP
-    if let Some(forwarded_for) = req.headers().get("X-Real-IP") {
-        if let Ok(ip) = forwarded_for.to_str() {
-            return ip.to_string();
-        }
+    if let Some(forwarded_for) = req.headers().get("X-Real-IP")
+        && let Ok(ip) = forwarded_for.to_str() {
+        return ip.to_string();
     }
 
     // remote address is not useful, we will always get the IP of the proxy server,
@@ -0,0 +1,347 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use axum::{
+    extract::{Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::Response,
+};
+
+use josekit::{
+    jwk::{alg::ed::EdCurve, Jwk, JwkSet},
+    jws::{alg::eddsa::{EddsaJwsSigner, EddsaJwsVerifier}, JwsHeader, JwsVerifier, EdDSA},
+    jwt::{self, JwtPayload},
+};
+
+use config::{Secret, Source};
+
+
+/// Header used by the services to present their identity to each other.
+///
+/// We do not reuse the Authorization header, as that one may already carry the end user token.
+pub const SERVICE_TOKEN_HEADER: &str = "X-Service-Token";
+
+// issuer used for all internal tokens, so that an user token can never be mistaken for a service token
+const SERVICE_TOKEN_ISSUER: &str = "videosite-internal";
+
+// internal tokens are minted per call, so they can be very short-lived
+const SERVICE_TOKEN_LIFETIME_SECONDS: u64 = 60;
+
+
+/// The identity of this service, and the services it accepts calls from. Loaded once at startup with
+/// `ServiceAuth::from_source`, and passed to `service_token`, `verify_service_token` and `service_auth_middleware`.
+///
+/// Each service signs its tokens with an Ed25519 key of its own, and the called services only hold the public
+/// keys of their callers, so a service can neither sign tokens as another one nor for a caller it verifies.
+#[derive(Debug, Clone)]
+pub struct ServiceAuth {
+    /// The name of this service, e.g. "resource-server"
+    pub service_name: String,
+    /// Signs the service tokens of this service
+    pub signing_key: Secret<EddsaJwsSigner>,
+    /// The public keys of the services that may call the internal endpoints of this one, by their names. The
+    /// tokens of any other service are rejected.
+    pub caller_keys: HashMap<String, EddsaJwsVerifier>,
+}
+
+impl ServiceAuth {
+    /// Reads `SERVICE_NAME`, `SERVICE_SIGNING_KEY`, the private Ed25519 JWK of this service, and the optional
+    /// `SERVICE_CALLER_KEYS`, a JWK set with the public keys of the allowed callers, their names as the key ids.
+    pub fn from_source(source: &mut Source) -> Self {
+        let service_name = source.required("SERVICE_NAME");
+
+        // the value is never included in the messages, like with the other secrets
+        let signing_key = Jwk::from_bytes(source.secret("SERVICE_SIGNING_KEY").expose())
+            .and_then(|key| EdDSA.signer_from_jwk(&key))
+            .unwrap_or_else(|err| {
+                source.invalid("SERVICE_SIGNING_KEY", &format!("is not a private Ed25519 JWK: {}", err));
+                // only a placeholder until the settings fail to load
+                generate_signing_key()
+            });
+
+        ServiceAuth {
+            service_name,
+            signing_key: Secret::new(signing_key),
+            caller_keys: source.parsed_with("SERVICE_CALLER_KEYS", HashMap::new(), parse_caller_keys),
+        }
+    }
+}
+
+fn parse_caller_keys(value: &str) -> Result<HashMap<String, EddsaJwsVerifier>, String> {
+    let keys = JwkSet::from_bytes(value).map_err(|err| format!("is not a JWK set: {}", err))?;
+
+    keys.keys().into_iter().map(|key| {
+        let caller = key.key_id().ok_or("a key has no key id, the name of the calling service")?;
+        let verifier = EdDSA.verifier_from_jwk(key)
+            .map_err(|err| format!("the key of {} is not a public Ed25519 JWK: {}", caller, err))?;
+        Ok((caller.to_string(), verifier))
+    }).collect()
+}
+
+fn generate_signing_key() -> EddsaJwsSigner {
+    Jwk::generate_ed_key(EdCurve::Ed25519)
+        .and_then(|key| EdDSA.signer_from_jwk(&key))
+        .expect("Failed to generate an Ed25519 key")
+}
+
+
+/// Identity of the service that called an internal endpoint.
+///
+/// Inserted to the request extensions by `service_auth_middleware`.
+#[derive(Debug, Clone)]
+pub struct ServiceIdentity {
+    pub name: String,
+}
+
+
+/// Creates a signed internal token, which identifies the current service to the target service.
+///
+/// # Arguments
+/// * `audience` - The name of the service that is being called, e.g. "auth" or "resource-server"
+///
+/// # Returns
+/// * The signed token as a `String`
+pub fn service_token(auth: &ServiceAuth, audience: &str) -> String {
+    let now = SystemTime::now();
+
+    // the key id tells the called service which of its caller keys verifies the token
+    let mut header = JwsHeader::new();
+    header.set_token_type("JWT");
+    header.set_key_id(&auth.service_name);
+
+    let mut payload = JwtPayload::new();
+    payload.set_issuer(SERVICE_TOKEN_ISSUER);
+    payload.set_subject(&auth.service_name);
+    payload.set_audience(vec![audience]);
+    payload.set_issued_at(&now);
+    payload.set_not_before(&now);
+    payload.set_expires_at(&(now + Duration::from_secs(SERVICE_TOKEN_LIFETIME_SECONDS)));
+
+    jwt::encode_with_signer(&payload, &header, auth.signing_key.expose())
+        .expect("Failed to encode service token")
+}
+
+
+/// Middleware for internal-only routes. Rejects the request unless it carries a valid service token
+/// issued for this service, by one of the services in `caller_keys`.
+///
+/// On success, `ServiceIdentity` is added to the request extensions.
+pub async fn service_auth_middleware(
+    State(auth): State<Arc<ServiceAuth>>,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+
+    let token = req.headers()
+        .get(SERVICE_TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.trim_start_matches("Bearer ").to_string());
+
+    let token = match token {
+        Some(token) => token,
+        None => {
+            tracing::warn!("Internal endpoint {} called without a service token", req.uri().path());
+            return Err(StatusCode::UNAUTHORIZED);
+        }
+    };
+
+    match verify_service_token(&auth, &token) {
+        Some(identity) => {
+            req.extensions_mut().insert(identity);
+            Ok(next.run(req).await)
+        }
+        None => {
+            tracing::warn!("Internal endpoint {} called with an invalid service token", req.uri().path());
+            Err(StatusCode::UNAUTHORIZED)
+        }
+    }
+}
+
+
+/// Verifies a service token, and returns the identity of the calling service if the token is valid.
+///
+/// The token is verified with the key of the service named by its key id, and must be issued by that service.
+pub fn verify_service_token(auth: &ServiceAuth, token: &str) -> Option<ServiceIdentity> {
+    let now = SystemTime::now();
+
+    let decoded = jwt::decode_with_verifier_selector(token, |header| {
+        Ok(header.key_id()
+            .and_then(|caller| auth.caller_keys.get(caller))
+            .map(|verifier| verifier as &dyn JwsVerifier))
+    });
+
+    let (payload, header) = match decoded {
+        Ok(decoded) => decoded,
+        Err(err) => {
+            tracing::debug!("Service token verification failed: {}", err);
+            return None;
+        }
+    };
+
+    if payload.issuer() != Some(SERVICE_TOKEN_ISSUER) {
+        tracing::debug!("Service token verification failed: Invalid issuer");
+        return None;
+    }
+
+    if !payload.audience().is_some_and(|audience| audience.contains(&auth.service_name.as_str())) {
+        tracing::debug!("Service token verification failed: Invalid audience");
+        return None;
+    }
+
+    if payload.expires_at().is_none_or(|expires_at| expires_at <= now) {
+        tracing::debug!("Service token verification failed: Token has expired");
+        return None;
+    }
+
+    if payload.not_before().is_none_or(|not_before| not_before > now) {
+        tracing::debug!("Service token verification failed: Invalid not before time");
+        return None;
+    }
+
+    // the key of one caller must not vouch for another
+    let caller = payload.subject()?.to_string();
+    if header.key_id() != Some(caller.as_str()) {
+        tracing::debug!("Service token verification failed: Service {} is not the owner of the key", caller);
+        return None;
+    }
+
+    Some(ServiceIdentity { name: caller })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, middleware::from_fn_with_state, routing::get, Extension, Router};
+    use tower::ServiceExt;
+
+    use super::*;
+
+    // a service, and the public key the services it calls verify its tokens with
+    fn service(name: &str, callers: &[(&str, &Jwk)]) -> (ServiceAuth, Jwk) {
+        let key = Jwk::generate_ed_key(EdCurve::Ed25519).unwrap();
+        let caller_keys = callers.iter()
+            .map(|(caller, key)| (caller.to_string(), EdDSA.verifier_from_jwk(key).unwrap()))
+            .collect();
+
+        let auth = ServiceAuth {
+            service_name: name.to_string(),
+            signing_key: Secret::new(EdDSA.signer_from_jwk(&key).unwrap()),
+            caller_keys,
+        };
+        (auth, key.to_public_key().unwrap())
+    }
+
+    // a token of the service with the given claims, signed with its key
+    fn token_with(auth: &ServiceAuth, key_id: &str, subject: &str, audience: &str, expires_at: SystemTime) -> String {
+        let mut header = JwsHeader::new();
+        header.set_token_type("JWT");
+        header.set_key_id(key_id);
+
+        let mut payload = JwtPayload::new();
+        payload.set_issuer(SERVICE_TOKEN_ISSUER);
+        payload.set_subject(subject);
+        payload.set_audience(vec![audience]);
+        payload.set_not_before(&(SystemTime::now() - Duration::from_secs(120)));
+        payload.set_expires_at(&expires_at);
+
+        jwt::encode_with_signer(&payload, &header, auth.signing_key.expose()).unwrap()
+    }
+
+    #[test]
+    fn accepts_the_tokens_of_the_callers() {
+        let (caller, caller_key) = service("embed-tag-service", &[]);
+        let (resource_server, _) = service("resource-server", &[("embed-tag-service", &caller_key)]);
+
+        let identity = verify_service_token(&resource_server, &service_token(&caller, "resource-server"));
+        assert_eq!(identity.map(|identity| identity.name), Some("embed-tag-service".to_string()));
+    }
+
+    #[test]
+    fn rejects_the_tokens_for_another_audience() {
+        let (caller, caller_key) = service("embed-tag-service", &[]);
+        let (resource_server, _) = service("resource-server", &[("embed-tag-service", &caller_key)]);
+
+        assert!(verify_service_token(&resource_server, &service_token(&caller, "auth")).is_none());
+    }
+
+    #[test]
+    fn rejects_the_expired_tokens() {
+        let (caller, caller_key) = service("embed-tag-service", &[]);
+        let (resource_server, _) = service("resource-server", &[("embed-tag-service", &caller_key)]);
+
+        let expired = token_with(&caller, "embed-tag-service", "embed-tag-service", "resource-server", SystemTime::now() - Duration::from_secs(1));
+        assert!(verify_service_token(&resource_server, &expired).is_none());
+    }
+
+    #[test]
+    fn rejects_the_callers_without_a_key() {
+        let (caller, _) = service("webhooks", &[]);
+        let (_, other_key) = service("embed-tag-service", &[]);
+        let (resource_server, _) = service("resource-server", &[("embed-tag-service", &other_key)]);
+
+        assert!(verify_service_token(&resource_server, &service_token(&caller, "resource-server")).is_none());
+    }
+
+    #[test]
+    fn a_caller_cannot_act_as_another() {
+        let (caller, caller_key) = service("webhooks", &[]);
+        let (_, other_key) = service("embed-tag-service", &[]);
+        let (resource_server, _) = service("resource-server", &[("webhooks", &caller_key), ("embed-tag-service", &other_key)]);
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+
+        // signed with its own key, but under the name of the other
+        let impersonating = token_with(&caller, "embed-tag-service", "embed-tag-service", "resource-server", expires_at);
+        assert!(verify_service_token(&resource_server, &impersonating).is_none());
+
+        // verified with its own key, but claiming to be the other
+        let impersonating = token_with(&caller, "webhooks", "embed-tag-service", "resource-server", expires_at);
+        assert!(verify_service_token(&resource_server, &impersonating).is_none());
+    }
+
+    #[test]
+    fn reads_the_caller_keys() {
+        let (_, without_key_id) = service("embed-tag-service", &[]);
+        let mut caller_key = without_key_id.clone();
+        caller_key.set_key_id("embed-tag-service");
+
+        let keys = parse_caller_keys(&format!(r#"{{"keys":[{}]}}"#, caller_key)).unwrap();
+        assert_eq!(keys.keys().collect::<Vec<_>>(), vec!["embed-tag-service"]);
+
+        assert!(parse_caller_keys(&format!(r#"{{"keys":[{}]}}"#, without_key_id)).is_err());
+        assert!(parse_caller_keys("embed-tag-service").is_err());
+    }
+
+    async fn call_internal_endpoint(resource_server: ServiceAuth, token: Option<String>) -> StatusCode {
+        let app = Router::new()
+            .route("/internal", get(|Extension(caller): Extension<ServiceIdentity>| async move { caller.name }))
+            .layer(from_fn_with_state(Arc::new(resource_server), service_auth_middleware));
+
+        let mut request = Request::builder().uri("/internal");
+        if let Some(token) = token {
+            request = request.header(SERVICE_TOKEN_HEADER, token);
+        }
+        app.oneshot(request.body(Body::empty()).unwrap()).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn the_middleware_lets_the_allowed_callers_through() {
+        let (caller, caller_key) = service("embed-tag-service", &[]);
+        let (resource_server, _) = service("resource-server", &[("embed-tag-service", &caller_key)]);
+
+        let token = service_token(&caller, "resource-server");
+        assert_eq!(call_internal_endpoint(resource_server, Some(token)).await, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn the_middleware_rejects_the_callers_not_allowed() {
+        let (caller, _) = service("webhooks", &[]);
+        let (_, other_key) = service("embed-tag-service", &[]);
+        let (resource_server, _) = service("resource-server", &[("embed-tag-service", &other_key)]);
+
+        let token = service_token(&caller, "resource-server");
+        assert_eq!(call_internal_endpoint(resource_server.clone(), Some(token)).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(call_internal_endpoint(resource_server, None).await, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,65 @@
+mod secret;
+mod source;
+
+pub use secret::Secret;
+pub use source::{CONFIG_FILE_VARIABLE, Source};
+
+
+/// Every problem found while loading the configuration, so that they can all be fixed at once
+#[derive(Debug)]
+pub struct ConfigError {
+    problems: Vec<String>,
+}
+
+impl ConfigError {
+    pub fn new(problems: Vec<String>) -> Self {
+        ConfigError { problems }
+    }
+
+    pub fn problems(&self) -> &[String] {
+        &self.problems
+    }
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Invalid configuration:")?;
+        for problem in &self.problems {
+            write!(f, "\n  - {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+
+/// Loads the settings of a service from the environment and the optional `CONFIG_FILE`, see `Source`.
+///
+/// Meant to be called once at startup, the settings are then passed to where they are needed, so that a missing
+/// or invalid setting stops the service right away instead of failing the requests that need it.
+///
+/// # Arguments
+/// * `build` - Reads the settings from the source
+///
+/// # Returns
+/// * `Err` with every setting that is missing or invalid
+pub fn load<T>(build: impl FnOnce(&mut Source) -> T) -> Result<T, ConfigError> {
+    load_from(Source::from_env()?, build)
+}
+
+/// Like `load`, but reads the settings from the given source
+pub fn load_from<T>(mut source: Source, build: impl FnOnce(&mut Source) -> T) -> Result<T, ConfigError> {
+    let settings = build(&mut source);
+    source.finish().map(|_| settings)
+}
+
+/// Loads the settings like `load`, and exits the process if any of them are missing or invalid.
+///
+/// The problems are printed to stderr, as this runs before the logging is set up in some services.
+pub fn load_or_exit<T>(build: impl FnOnce(&mut Source) -> T) -> T {
+    load(build).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        std::process::exit(1);
+    })
+}
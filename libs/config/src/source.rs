@@ -0,0 +1,247 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use crate::{ConfigError, Secret};
+
+
+/// The environment variable with the path of the optional configuration file
+pub const CONFIG_FILE_VARIABLE: &str = "CONFIG_FILE";
+
+
+/// Where the settings are read from: the environment variables, and the TOML file named by `CONFIG_FILE` if it
+/// is set. A variable that is set, and not empty, takes precedence over the file.
+///
+/// The file is a flat table, keyed by the names of the variables in any case, e.g. `s3_bucket_name = "videos"`
+/// for `S3_BUCKET_NAME`. Numbers and booleans are read as they would be from the environment, and arrays as
+/// comma separated lists.
+///
+/// The getters do not fail. A setting that is missing or invalid is recorded as a problem and replaced with a
+/// placeholder, so that every problem is reported at once by `finish`.
+pub struct Source {
+    variables: HashMap<String, String>,
+    file: HashMap<String, String>,
+    file_name: Option<String>,
+    problems: Vec<String>,
+}
+
+impl Source {
+    /// Reads the environment of the process, and the file named by its `CONFIG_FILE` variable.
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Source::from_variables(env::vars())
+    }
+
+    /// Reads the given variables instead of the environment, and the file named by their `CONFIG_FILE`.
+    ///
+    /// # Returns
+    /// * `Err` if the file cannot be read, or is not a flat TOML table
+    pub fn from_variables(variables: impl IntoIterator<Item = (String, String)>) -> Result<Self, ConfigError> {
+        let variables: HashMap<String, String> = variables.into_iter()
+            .filter(|(_, value)| !value.is_empty())
+            .collect();
+
+        let mut source = Source {
+            variables,
+            file: HashMap::new(),
+            file_name: None,
+            problems: Vec::new(),
+        };
+
+        if let Some(path) = source.variables.get(CONFIG_FILE_VARIABLE).cloned() {
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|err| ConfigError::new(vec![format!("Failed to read {} {}: {}", CONFIG_FILE_VARIABLE, path, err)]))?;
+            source.file = parse_file(&path, &contents)?;
+            source.file_name = Some(path);
+        }
+
+        Ok(source)
+    }
+
+    /// # Returns
+    /// * The value of the setting, and where it was read from, for the messages
+    fn lookup(&self, name: &str) -> Option<(String, String)> {
+        if let Some(value) = self.variables.get(name) {
+            return Some((value.clone(), "the environment".to_string()));
+        }
+
+        self.file.get(name).map(|value| (value.clone(), self.file_name.clone().unwrap_or_default()))
+    }
+
+    fn parse<T>(&mut self, name: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let (value, origin) = self.lookup(name)?;
+
+        match value.parse() {
+            Ok(value) => Some(value),
+            Err(err) => {
+                let problem = format!("{} is invalid ({:?} from {}): {}", name, value, origin, err);
+                self.problems.push(problem);
+                None
+            }
+        }
+    }
+
+    /// A setting the service cannot run without.
+    ///
+    /// # Returns
+    /// * The default of `T` if the setting is missing or invalid, only as a placeholder until `finish` fails
+    pub fn required<T>(&mut self, name: &str) -> T
+    where
+        T: FromStr + Default,
+        T::Err: Display,
+    {
+        if self.lookup(name).is_none() {
+            self.problems.push(format!("{} is not set", name));
+            return T::default();
+        }
+
+        self.parse(name).unwrap_or_default()
+    }
+
+    /// A setting that may be left out.
+    pub fn optional<T>(&mut self, name: &str) -> Option<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.parse(name)
+    }
+
+    /// A setting that falls back to the given default when it is left out.
+    pub fn with_default<T>(&mut self, name: &str, default: T) -> T
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.parse(name).unwrap_or(default)
+    }
+
+    /// A setting read with a parser of the service's own, e.g. for a name that maps to an enum.
+    ///
+    /// # Arguments
+    /// * `parse` - Returns the reason when the value is invalid
+    pub fn parsed_with<T>(&mut self, name: &str, default: T, parse: impl FnOnce(&str) -> Result<T, String>) -> T {
+        let Some((value, origin)) = self.lookup(name) else {
+            return default;
+        };
+
+        match parse(&value) {
+            Ok(value) => value,
+            Err(err) => {
+                let problem = format!("{} is invalid ({:?} from {}): {}", name, value, origin, err);
+                self.problems.push(problem);
+                default
+            }
+        }
+    }
+
+    /// A boolean setting, `true` or `false` in any case, falling back to the default when it is left out.
+    pub fn flag(&mut self, name: &str, default: bool) -> bool {
+        self.parsed_with(name, default, |value| match value.to_lowercase().as_str() {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err("expected true or false".to_string()),
+        })
+    }
+
+    /// A comma separated list, empty when it is left out. The items are trimmed, and empty ones are skipped.
+    pub fn list<T>(&mut self, name: &str) -> Vec<T>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        let Some((value, origin)) = self.lookup(name) else {
+            return Vec::new();
+        };
+
+        let mut items = Vec::new();
+        let mut problems = Vec::new();
+        for item in value.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+            match item.parse() {
+                Ok(item) => items.push(item),
+                Err(err) => problems.push(format!("{} has an invalid item ({:?} from {}): {}", name, item, origin, err)),
+            }
+        }
+
+        self.problems.extend(problems);
+        items
+    }
+
+    /// A required setting that must not be logged. Unlike the other getters, the messages never include its value.
+    pub fn secret(&mut self, name: &str) -> Secret<String> {
+        match self.lookup(name) {
+            Some((value, _)) => Secret::new(value),
+            None => {
+                self.problems.push(format!("{} is not set", name));
+                Secret::default()
+            }
+        }
+    }
+
+    /// A setting that must not be logged, and may be left out.
+    pub fn optional_secret(&mut self, name: &str) -> Option<Secret<String>> {
+        self.lookup(name).map(|(value, _)| Secret::new(value))
+    }
+
+    /// Records a problem the getters cannot find, e.g. a number that is out of range or a key that is too short.
+    ///
+    /// # Arguments
+    /// * `message` - What is wrong with the setting, after its name, e.g. "must be at least 32 characters"
+    pub fn invalid(&mut self, name: &str, message: &str) {
+        self.problems.push(format!("{} {}", name, message));
+    }
+
+    /// # Returns
+    /// * `Err` with every problem found by the getters, if there were any
+    pub fn finish(self) -> Result<(), ConfigError> {
+        if self.problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::new(self.problems))
+        }
+    }
+}
+
+
+fn parse_file(path: &str, contents: &str) -> Result<HashMap<String, String>, ConfigError> {
+    let table: toml::Table = contents.parse()
+        .map_err(|err| ConfigError::new(vec![format!("{} is not valid TOML: {}", path, err)]))?;
+
+    let mut values = HashMap::new();
+    let mut problems = Vec::new();
+
+    for (key, value) in table {
+        match file_value(&value) {
+            Some(value) => { values.insert(key.to_uppercase(), value); }
+            None => problems.push(format!("{} in {} must be a string, number, boolean or array of them", key, path)),
+        }
+    }
+
+    if problems.is_empty() {
+        Ok(values)
+    } else {
+        Err(ConfigError::new(problems))
+    }
+}
+
+// the value as it would be set in the environment
+fn file_value(value: &toml::Value) -> Option<String> {
+    match value {
+        toml::Value::String(value) => Some(value.clone()),
+        toml::Value::Integer(value) => Some(value.to_string()),
+        toml::Value::Float(value) => Some(value.to_string()),
+        toml::Value::Boolean(value) => Some(value.to_string()),
+        toml::Value::Array(items) => items.iter()
+            .map(|item| match item {
+                toml::Value::Array(_) => None,
+                item => file_value(item),
+            })
+            .collect::<Option<Vec<_>>>()
+            .map(|items| items.join(",")),
+        toml::Value::Datetime(_) | toml::Value::Table(_) => None,
+    }
+}
@@ -0,0 +1,153 @@
+use config::{Secret, Source};
+
+
+fn source(variables: &[(&str, &str)]) -> Source {
+    Source::from_variables(variables.iter().map(|(name, value)| (name.to_string(), value.to_string())))
+        .expect("Failed to create source")
+}
+
+// a file of its own per test, as the tests run in parallel
+fn config_file(name: &str, contents: &str) -> String {
+    let path = std::env::temp_dir().join(format!("config-test-{}-{}.toml", std::process::id(), name));
+    std::fs::write(&path, contents).expect("Failed to write config file");
+    path.to_string_lossy().to_string()
+}
+
+
+#[derive(Debug)]
+struct Settings {
+    port: u16,
+    bucket: String,
+    quota: Option<u64>,
+    quotas_enabled: bool,
+    admins: Vec<String>,
+    signing_key: Secret<String>,
+}
+
+fn settings(source: &mut Source) -> Settings {
+    Settings {
+        port: source.with_default("PORT", 3000),
+        bucket: source.required("S3_BUCKET_NAME"),
+        quota: source.optional("DAILY_DATA_QUOTA_MEGABYTES"),
+        quotas_enabled: source.flag("ENABLE_DATA_QUOTAS", false),
+        admins: source.list("ADMIN_USER_IDS"),
+        signing_key: source.secret("SIGNING_KEY"),
+    }
+}
+
+
+#[test]
+fn reads_typed_settings_from_the_environment() {
+    let source = source(&[
+        ("PORT", "8080"),
+        ("S3_BUCKET_NAME", "videos"),
+        ("DAILY_DATA_QUOTA_MEGABYTES", "500"),
+        ("ENABLE_DATA_QUOTAS", "TRUE"),
+        ("ADMIN_USER_IDS", "a, b,,c"),
+        ("SIGNING_KEY", "key"),
+    ]);
+
+    let settings = config::load_from(source, settings).expect("Failed to load settings");
+
+    assert_eq!(settings.port, 8080);
+    assert_eq!(settings.bucket, "videos");
+    assert_eq!(settings.quota, Some(500));
+    assert!(settings.quotas_enabled);
+    assert_eq!(settings.admins, vec!["a", "b", "c"]);
+    assert_eq!(settings.signing_key.expose(), "key");
+}
+
+#[test]
+fn uses_the_defaults_for_the_settings_left_out() {
+    let source = source(&[("S3_BUCKET_NAME", "videos"), ("SIGNING_KEY", "key"), ("PORT", "")]);
+
+    let settings = config::load_from(source, settings).expect("Failed to load settings");
+
+    assert_eq!(settings.port, 3000);
+    assert_eq!(settings.quota, None);
+    assert!(!settings.quotas_enabled);
+    assert!(settings.admins.is_empty());
+}
+
+#[test]
+fn reports_every_problem_at_once() {
+    let source = source(&[("PORT", "http"), ("ENABLE_DATA_QUOTAS", "yes")]);
+
+    let err = config::load_from(source, settings).expect_err("Settings should be invalid");
+
+    assert_eq!(err.problems().len(), 4, "{}", err);
+    assert!(err.problems()[0].starts_with("PORT is invalid (\"http\" from the environment)"), "{}", err);
+    assert_eq!(err.problems()[1], "S3_BUCKET_NAME is not set");
+    assert!(err.problems()[2].starts_with("ENABLE_DATA_QUOTAS is invalid"), "{}", err);
+    assert_eq!(err.problems()[3], "SIGNING_KEY is not set");
+}
+
+#[test]
+fn reads_the_config_file_with_the_environment_taking_precedence() {
+    let path = config_file("precedence", r#"
+        port = 8080
+        S3_BUCKET_NAME = "from-file"
+        enable_data_quotas = true
+        admin_user_ids = ["a", "b"]
+        signing_key = "file-key"
+    "#);
+    let source = source(&[("CONFIG_FILE", &path), ("S3_BUCKET_NAME", "from-env")]);
+
+    let settings = config::load_from(source, settings).expect("Failed to load settings");
+    std::fs::remove_file(path).ok();
+
+    assert_eq!(settings.port, 8080);
+    assert_eq!(settings.bucket, "from-env");
+    assert!(settings.quotas_enabled);
+    assert_eq!(settings.admins, vec!["a", "b"]);
+    assert_eq!(settings.signing_key.expose(), "file-key");
+}
+
+#[test]
+fn names_the_file_an_invalid_setting_came_from() {
+    let path = config_file("origin", "port = -1\ns3_bucket_name = \"videos\"\nsigning_key = \"key\"");
+    let source = source(&[("CONFIG_FILE", &path)]);
+
+    let err = config::load_from(source, settings).expect_err("Settings should be invalid");
+    std::fs::remove_file(&path).ok();
+
+    assert_eq!(err.problems().len(), 1, "{}", err);
+    assert!(err.problems()[0].starts_with(&format!("PORT is invalid (\"-1\" from {})", path)), "{}", err);
+}
+
+#[test]
+fn rejects_a_config_file_that_is_missing_or_not_flat() {
+    let missing = Source::from_variables([("CONFIG_FILE".to_string(), "/nonexistent/config.toml".to_string())]);
+    assert!(missing.is_err());
+
+    let path = config_file("nested", "[database]\nurl = \"postgres://\"");
+    let nested = Source::from_variables([("CONFIG_FILE".to_string(), path.clone())]);
+    std::fs::remove_file(path).ok();
+
+    let err = nested.err().expect("Nested tables should be rejected");
+    assert!(err.problems()[0].starts_with("database in"), "{}", err);
+}
+
+#[test]
+fn redacts_secrets() {
+    let source = source(&[("S3_BUCKET_NAME", "videos"), ("SIGNING_KEY", "very-secret-key")]);
+
+    let settings = config::load_from(source, settings).expect("Failed to load settings");
+    let printed = format!("{:?}", settings);
+
+    assert!(!printed.contains("very-secret-key"), "{}", printed);
+    assert!(printed.contains("signing_key: [redacted]"), "{}", printed);
+}
+
+#[test]
+fn records_the_problems_found_by_the_service() {
+    let mut source = source(&[("SIGNING_KEY", "short")]);
+
+    let key = source.secret("SIGNING_KEY");
+    if key.expose().len() < 32 {
+        source.invalid("SIGNING_KEY", "must be at least 32 characters");
+    }
+
+    let err = source.finish().expect_err("Key should be too short");
+    assert_eq!(err.to_string(), "Invalid configuration:\n  - SIGNING_KEY must be at least 32 characters");
+}
@@ -0,0 +1,66 @@
+use async_trait::async_trait;
+
+use aws_sdk_eventbridge::Client;
+use aws_sdk_eventbridge::error::DisplayErrorContext;
+use aws_sdk_eventbridge::primitives::DateTime;
+use aws_sdk_eventbridge::types::PutEventsRequestEntry;
+
+use tokio::sync::OnceCell;
+
+use crate::{DomainEvent, EVENT_SOURCE, EventError};
+
+use super::EventBus;
+
+
+/// Publishes the events on an EventBridge bus, with the event type as the detail type and the event as the
+/// detail. The rules of the bus route them on, e.g. to the queue of the webhook service.
+pub struct EventBridgeBus {
+    // loading the AWS config is async, so the client is created on the first publish
+    client: OnceCell<Client>,
+    bus_name: String,
+}
+
+impl EventBridgeBus {
+    pub fn new(bus_name: String) -> Self {
+        EventBridgeBus {
+            client: OnceCell::new(),
+            bus_name,
+        }
+    }
+}
+
+#[async_trait]
+impl EventBus for EventBridgeBus {
+    async fn publish(&self, event: &DomainEvent) -> Result<(), EventError> {
+        let entry = PutEventsRequestEntry::builder()
+            .event_bus_name(&self.bus_name)
+            .source(EVENT_SOURCE)
+            .detail_type(event.kind.event_type())
+            .detail(serde_json::to_string(event).expect("Failed to serialize domain event"))
+            .time(DateTime::from_millis(event.occurred_at.timestamp_millis()))
+            .build();
+
+        let client = self.client
+            .get_or_init(|| async { Client::new(&aws_config::load_from_env().await) })
+            .await;
+
+        let output = client
+            .put_events()
+            .entries(entry)
+            .send()
+            .await
+            .map_err(|err| EventError::Backend(DisplayErrorContext(err).to_string()))?;
+
+        // the request succeeds even when the entries are rejected
+        if let Some(failed) = output.entries().iter().find(|entry| entry.error_code().is_some()) {
+            return Err(EventError::Backend(format!(
+                "Event {} was rejected: {} {}",
+                event.id,
+                failed.error_code().unwrap_or_default(),
+                failed.error_message().unwrap_or_default()
+            )));
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,47 @@
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use crate::{DomainEvent, EventError};
+
+use super::EventBus;
+
+
+/// Keeps the events in memory, so that tests can assert which events were published.
+///
+/// Install it with `events::init` before the first event is published:
+///
+/// ```ignore
+/// let bus = Arc::new(MemoryBus::new());
+/// events::init(bus.clone());
+/// // ... call the handler ...
+/// assert_eq!(bus.events()[0].kind.event_type(), "resource.processed");
+/// ```
+#[derive(Default)]
+pub struct MemoryBus {
+    events: Mutex<Vec<DomainEvent>>,
+}
+
+impl MemoryBus {
+    pub fn new() -> Self {
+        MemoryBus::default()
+    }
+
+    /// The events published so far, in the order they were published
+    pub fn events(&self) -> Vec<DomainEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    /// Forgets the events published so far
+    pub fn clear(&self) {
+        self.events.lock().unwrap().clear();
+    }
+}
+
+#[async_trait]
+impl EventBus for MemoryBus {
+    async fn publish(&self, event: &DomainEvent) -> Result<(), EventError> {
+        self.events.lock().unwrap().push(event.clone());
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+mod eventbridge;
+mod memory;
+mod stdout;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use config::Source;
+
+pub use eventbridge::EventBridgeBus;
+pub use memory::MemoryBus;
+pub use stdout::StdoutBus;
+
+use crate::{DomainEvent, EventError};
+
+
+/// Where the domain events are published
+#[async_trait]
+pub trait EventBus: Send + Sync {
+    async fn publish(&self, event: &DomainEvent) -> Result<(), EventError>;
+}
+
+
+/// The bus the domain events are published on, read once at startup and passed to `events::start`
+#[derive(Debug, Clone, PartialEq)]
+pub enum BusSettings {
+    EventBridge { bus_name: String },
+    Stdout,
+}
+
+impl BusSettings {
+    /// Reads the bus selected by `EVENT_BUS`, and its settings.
+    ///
+    /// Supported values are `eventbridge` and `stdout`. If `EVENT_BUS` is not set, EventBridge is used when
+    /// `EVENT_BUS_NAME` is set, and stdout otherwise, so that the services can be run without localstack.
+    pub fn from_source(source: &mut Source) -> Self {
+        let default = if source.optional::<String>("EVENT_BUS_NAME").is_some() { "eventbridge" } else { "stdout" };
+        let bus = source.parsed_with("EVENT_BUS", default, |value| match value {
+            "eventbridge" => Ok("eventbridge"),
+            "stdout" => Ok("stdout"),
+            _ => Err("expected eventbridge or stdout".to_string()),
+        });
+
+        match bus {
+            "eventbridge" => BusSettings::EventBridge { bus_name: source.required("EVENT_BUS_NAME") },
+            _ => BusSettings::Stdout,
+        }
+    }
+
+    pub fn open(&self) -> Arc<dyn EventBus> {
+        match self {
+            BusSettings::EventBridge { bus_name } => Arc::new(EventBridgeBus::new(bus_name.clone())),
+            BusSettings::Stdout => Arc::new(StdoutBus),
+        }
+    }
+}
@@ -0,0 +1,23 @@
+use std::io::Write;
+
+use async_trait::async_trait;
+
+use crate::{DomainEvent, EventError};
+
+use super::EventBus;
+
+
+/// Writes the events to stdout as JSON lines, for local development without localstack.
+pub struct StdoutBus;
+
+#[async_trait]
+impl EventBus for StdoutBus {
+    async fn publish(&self, event: &DomainEvent) -> Result<(), EventError> {
+        let line = serde_json::to_string(event).expect("Failed to serialize domain event");
+
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}", line)
+            .and_then(|_| stdout.flush())
+            .map_err(|err| EventError::Backend(format!("Failed to write domain event to stdout: {}", err)))
+    }
+}
@@ -0,0 +1,121 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+
+/// Something that happened to a user's resource, published on the event bus for the other services, and
+/// delivered to the user's webhooks.
+///
+/// Serialized as `"type": "resource.processed", "data": { ... }` next to the other fields. The events are
+/// delivered at least once, the consumers recognize the events they have already seen by the `id`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DomainEvent {
+    pub id: String,
+    #[serde(flatten)]
+    pub kind: DomainEventKind,
+    /// The owner of the resource
+    pub user_id: String,
+    pub occurred_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl DomainEvent {
+    /// Creates an event with a new id, that happened now
+    pub fn new(kind: DomainEventKind, user_id: &str) -> Self {
+        DomainEvent {
+            id: Uuid::new_v4().to_string(),
+            kind,
+            user_id: user_id.to_string(),
+            occurred_at: chrono::Utc::now(),
+        }
+    }
+
+    /// Uses the given id instead of a new one, e.g. the id of the queue message the event is produced from,
+    /// so that the event produced again from a redelivered message has the same id.
+    pub fn with_id(mut self, id: &str) -> Self {
+        self.id = id.to_string();
+        self
+    }
+}
+
+
+/// All the domain events we publish, together with their data
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data")]
+pub enum DomainEventKind {
+    /// The upload has finished and the resource was created, its processing starts next
+    #[serde(rename = "resource.uploaded")]
+    ResourceUploaded(ResourceUploaded),
+    /// A revision of the resource was processed and its outputs are now served
+    #[serde(rename = "resource.processed")]
+    ResourceProcessed(ResourceProcessed),
+    /// Processing a revision of the resource failed. The outputs of an earlier revision, if any, are still served.
+    #[serde(rename = "resource.failed")]
+    ResourceFailed(ResourceFailed),
+    #[serde(rename = "resource.visibility_changed")]
+    ResourceVisibilityChanged(ResourceVisibilityChanged),
+    #[serde(rename = "resource.deleted")]
+    ResourceDeleted(ResourceDeleted),
+}
+
+impl DomainEventKind {
+    /// Every event type, e.g. for validating the event types a webhook subscribes to
+    pub const EVENT_TYPES: [&'static str; 5] = [
+        "resource.uploaded",
+        "resource.processed",
+        "resource.failed",
+        "resource.visibility_changed",
+        "resource.deleted",
+    ];
+
+    /// The event type, e.g. "resource.processed"
+    pub fn event_type(&self) -> &'static str {
+        match self {
+            DomainEventKind::ResourceUploaded(_) => "resource.uploaded",
+            DomainEventKind::ResourceProcessed(_) => "resource.processed",
+            DomainEventKind::ResourceFailed(_) => "resource.failed",
+            DomainEventKind::ResourceVisibilityChanged(_) => "resource.visibility_changed",
+            DomainEventKind::ResourceDeleted(_) => "resource.deleted",
+        }
+    }
+
+    /// The resource the event is about
+    pub fn resource_id(&self) -> &str {
+        match self {
+            DomainEventKind::ResourceUploaded(data) => &data.resource_id,
+            DomainEventKind::ResourceProcessed(data) => &data.resource_id,
+            DomainEventKind::ResourceFailed(data) => &data.resource_id,
+            DomainEventKind::ResourceVisibilityChanged(data) => &data.resource_id,
+            DomainEventKind::ResourceDeleted(data) => &data.resource_id,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceUploaded {
+    pub resource_id: String,
+    pub file_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceProcessed {
+    pub resource_id: String,
+    /// 0 for the upload, and one more for each reprocessing
+    pub revision: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceFailed {
+    pub resource_id: String,
+    pub revision: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceVisibilityChanged {
+    pub resource_id: String,
+    pub is_public: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ResourceDeleted {
+    pub resource_id: String,
+}
@@ -0,0 +1,81 @@
+mod bus;
+mod event;
+
+pub use bus::*;
+pub use event::*;
+
+use std::sync::{Arc, OnceLock};
+
+
+/// The EventBridge source of all our events, the bus rules match on it
+pub const EVENT_SOURCE: &str = "videosite";
+
+static BUS: OnceLock<Arc<dyn EventBus>> = OnceLock::new();
+
+
+#[derive(Debug)]
+pub enum EventError {
+    /// The bus could not be reached, or it rejected the event
+    Backend(String),
+    /// The message is not an EventBridge event carrying a domain event
+    Invalid(String),
+}
+
+impl std::fmt::Display for EventError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EventError::Backend(err) => write!(f, "Event bus error: {}", err),
+            EventError::Invalid(err) => write!(f, "Invalid domain event: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for EventError {}
+
+
+/// Uses the bus of the settings. Called once at startup by the services that publish events, before the first
+/// event is published.
+///
+/// # Returns
+/// * `false` if a bus was already in use, in which case the settings are not used
+pub fn start(settings: &BusSettings) -> bool {
+    init(settings.open())
+}
+
+/// Uses the given bus, typically in tests with a `MemoryBus`. Must be called before the first event is published.
+///
+/// # Returns
+/// * `false` if a bus was already in use, in which case the given bus is not used
+pub fn init(bus: Arc<dyn EventBus>) -> bool {
+    let mut installed = false;
+    BUS.get_or_init(|| {
+        installed = true;
+        bus
+    });
+    installed
+}
+
+/// Publishes the event on the bus of `start`. If no bus was started, the event is written to stdout, so that it
+/// is not lost.
+///
+/// Unlike the audit events, the events are not buffered or spooled, the caller decides whether a failed
+/// publish is retried.
+pub async fn publish(event: &DomainEvent) -> Result<(), EventError> {
+    BUS.get_or_init(|| {
+        tracing::warn!("The event bus was not started, writing the domain events to stdout");
+        Arc::new(StdoutBus)
+    }).publish(event).await
+}
+
+/// Parses the body of a queue message the bus has routed the event to. EventBridge wraps the event in an
+/// envelope, with the event itself as the `detail`.
+pub fn decode_eventbridge_message(body: &str) -> Result<DomainEvent, EventError> {
+    let mut envelope: serde_json::Value = serde_json::from_str(body)
+        .map_err(|err| EventError::Invalid(err.to_string()))?;
+
+    let detail = envelope.get_mut("detail")
+        .map(serde_json::Value::take)
+        .ok_or_else(|| EventError::Invalid("the message has no detail".to_string()))?;
+
+    serde_json::from_value(detail).map_err(|err| EventError::Invalid(err.to_string()))
+}
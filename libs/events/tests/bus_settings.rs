@@ -0,0 +1,30 @@
+use config::Source;
+use events::BusSettings;
+
+
+fn load(variables: &[(&str, &str)]) -> Result<BusSettings, Vec<String>> {
+    let source = Source::from_variables(variables.iter().map(|(name, value)| (name.to_string(), value.to_string()))).unwrap();
+    config::load_from(source, BusSettings::from_source).map_err(|err| err.problems().to_vec())
+}
+
+
+#[test]
+fn uses_stdout_without_a_bus_name() {
+    assert_eq!(load(&[]).unwrap(), BusSettings::Stdout);
+}
+
+#[test]
+fn uses_eventbridge_when_the_bus_name_is_set() {
+    let settings = load(&[("EVENT_BUS_NAME", "videosite")]).unwrap();
+
+    assert_eq!(settings, BusSettings::EventBridge { bus_name: "videosite".to_string() });
+}
+
+#[test]
+fn reports_a_missing_bus_name_and_an_unknown_bus() {
+    assert_eq!(load(&[("EVENT_BUS", "eventbridge")]).unwrap_err(), ["EVENT_BUS_NAME is not set"]);
+
+    let problems = load(&[("EVENT_BUS", "kafka")]).unwrap_err();
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].starts_with("EVENT_BUS is invalid") && problems[0].ends_with("expected eventbridge or stdout"), "{}", problems[0]);
+}
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use events::{
+    DomainEvent, DomainEventKind, EventError, MemoryBus, ResourceDeleted, ResourceProcessed,
+    ResourceVisibilityChanged, decode_eventbridge_message,
+};
+
+
+fn processed_event() -> DomainEvent {
+    DomainEvent::new(
+        DomainEventKind::ResourceProcessed(ResourceProcessed { resource_id: "resource".to_string(), revision: 2 }),
+        "user",
+    )
+}
+
+// what EventBridge sends to the queue targets of its rules
+fn eventbridge_envelope(detail: &serde_json::Value) -> String {
+    serde_json::json!({
+        "version": "0",
+        "id": "6a7e8feb-b491-4cf7-a9f1-bf3703467718",
+        "detail-type": "resource.processed",
+        "source": "videosite",
+        "account": "000000000000",
+        "time": "2025-07-01T12:00:00Z",
+        "region": "us-east-1",
+        "resources": [],
+        "detail": detail,
+    }).to_string()
+}
+
+
+#[test]
+fn events_are_serialized_with_their_type_and_data() {
+    let event = processed_event().with_id("event");
+
+    let value = serde_json::to_value(&event).unwrap();
+    assert_eq!(value["id"], "event");
+    assert_eq!(value["type"], "resource.processed");
+    assert_eq!(value["data"], serde_json::json!({ "resource_id": "resource", "revision": 2 }));
+    assert_eq!(value["user_id"], "user");
+}
+
+#[test]
+fn every_event_type_is_listed() {
+    let kinds = [
+        DomainEventKind::ResourceProcessed(ResourceProcessed { resource_id: "resource".to_string(), revision: 0 }),
+        DomainEventKind::ResourceVisibilityChanged(ResourceVisibilityChanged { resource_id: "resource".to_string(), is_public: true }),
+        DomainEventKind::ResourceDeleted(ResourceDeleted { resource_id: "resource".to_string() }),
+    ];
+
+    for kind in kinds {
+        assert!(DomainEventKind::EVENT_TYPES.contains(&kind.event_type()));
+        assert_eq!(kind.resource_id(), "resource");
+    }
+}
+
+#[test]
+fn events_are_decoded_from_the_eventbridge_envelope() {
+    let event = processed_event();
+    let body = eventbridge_envelope(&serde_json::to_value(&event).unwrap());
+
+    assert_eq!(decode_eventbridge_message(&body).unwrap(), event);
+}
+
+#[test]
+fn messages_without_a_domain_event_are_rejected() {
+    let without_detail = serde_json::json!({ "detail-type": "resource.processed" }).to_string();
+    assert!(matches!(decode_eventbridge_message(&without_detail), Err(EventError::Invalid(_))));
+
+    let unknown_type = eventbridge_envelope(&serde_json::json!({
+        "id": "event",
+        "type": "resource.renamed",
+        "data": { "resource_id": "resource" },
+        "user_id": "user",
+        "occurred_at": "2025-07-01T12:00:00Z",
+    }));
+    assert!(matches!(decode_eventbridge_message(&unknown_type), Err(EventError::Invalid(_))));
+
+    assert!(matches!(decode_eventbridge_message("not json"), Err(EventError::Invalid(_))));
+}
+
+#[tokio::test]
+async fn the_memory_bus_records_published_events() {
+    let bus = Arc::new(MemoryBus::new());
+    assert!(events::init(bus.clone()));
+
+    let event = processed_event();
+    events::publish(&event).await.unwrap();
+
+    assert_eq!(bus.events(), vec![event]);
+}
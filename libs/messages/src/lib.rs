@@ -0,0 +1,91 @@
+mod media;
+mod processing;
+mod resource_status;
+mod upload;
+
+pub use media::*;
+pub use processing::*;
+pub use resource_status::*;
+pub use upload::*;
+
+use serde::{Serialize, de::DeserializeOwned};
+use uuid::Uuid;
+
+
+const MESSAGE_ID_FIELD: &str = "message_id";
+
+
+/// A message sent over one of the processing pipeline queues.
+///
+/// The version is sent as a `version` field next to the message fields. Messages without one are version 1,
+/// which is the format the services used before this crate existed.
+pub trait QueueMessage: Serialize + DeserializeOwned {
+    /// The latest version of the message, produced by `encode`
+    const VERSION: u32;
+}
+
+#[derive(Debug)]
+pub enum MessageError {
+    /// The body is not valid JSON, or does not match the message type
+    Invalid(serde_json::Error),
+    /// The message was produced by a newer version of the sending service
+    UnsupportedVersion { version: u64, supported: u32 },
+}
+
+impl std::fmt::Display for MessageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MessageError::Invalid(err) => write!(f, "Invalid message: {}", err),
+            MessageError::UnsupportedVersion { version, supported } => {
+                write!(f, "Unsupported message version {} (latest known version is {})", version, supported)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MessageError {}
+
+
+/// Serializes the message into a queue message body, including the version and a unique message id.
+pub fn encode<T: QueueMessage>(message: &T) -> String {
+    let mut value = serde_json::to_value(message).expect("Failed to serialize message");
+
+    if let serde_json::Value::Object(map) = &mut value {
+        map.insert("version".to_string(), T::VERSION.into());
+        map.insert(MESSAGE_ID_FIELD.to_string(), Uuid::new_v4().to_string().into());
+    }
+
+    value.to_string()
+}
+
+/// The id `encode` gave to the message. It stays the same when the message is redelivered or redriven, so the
+/// consumers use it to recognize the messages they have already handled.
+///
+/// # Returns
+/// * `None` if the body has no id, e.g. it was sent before the ids were added, or it is not JSON
+pub fn message_id(body: &str) -> Option<String> {
+    let value: serde_json::Value = serde_json::from_str(body).ok()?;
+    value.get(MESSAGE_ID_FIELD)?.as_str().map(|id| id.to_string())
+}
+
+/// Parses a queue message body.
+///
+/// # Returns
+/// * `Err` if the body does not match the message type, or it is of a newer version than the one known here
+pub fn decode<T: QueueMessage>(body: &str) -> Result<T, MessageError> {
+    let mut value: serde_json::Value = serde_json::from_str(body).map_err(MessageError::Invalid)?;
+
+    let version = match &mut value {
+        serde_json::Value::Object(map) => {
+            map.remove(MESSAGE_ID_FIELD);
+            map.remove("version").and_then(|version| version.as_u64()).unwrap_or(1)
+        }
+        _ => 1,
+    };
+
+    if version > T::VERSION as u64 {
+        return Err(MessageError::UnsupportedVersion { version, supported: T::VERSION });
+    }
+
+    serde_json::from_value(value).map_err(MessageError::Invalid)
+}
@@ -0,0 +1,69 @@
+use serde::{Deserialize, Serialize};
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AudioData {
+    pub duration: f64, // Duration in seconds
+    pub bitrate: u32, // Bitrate in kbps
+    pub sample_rate: u32, // Sample rate in Hz
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VideoData {
+    pub duration: f64, // Duration in seconds
+    pub width: u32,   // Width in pixels
+    pub height: u32,  // Height in pixels
+    pub bitrate: u32, // Bitrate in kbps
+    pub frame_rate: f64, // Frame rate in frames per second
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageData {
+    pub width: u32,   // Width in pixels
+    pub height: u32,  // Height in pixels
+}
+
+/// The type of an uploaded file, together with its media information
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum FileType {
+    Video { video: VideoData, audio: Option<AudioData> },
+    Audio { audio: AudioData },
+    Image { image: ImageData },
+    Other,
+}
+
+impl FileType {
+    pub fn resource_type(&self) -> ResourceType {
+        match self {
+            FileType::Video { .. } => ResourceType::Video,
+            FileType::Audio { .. } => ResourceType::Audio,
+            FileType::Image { .. } => ResourceType::Image,
+            FileType::Other => ResourceType::Other,
+        }
+    }
+
+    pub fn is_media_type(&self) -> bool {
+        matches!(self, FileType::Video { .. } | FileType::Audio { .. } | FileType::Image { .. })
+    }
+}
+
+/// The resource type, as stored by the resource server
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResourceType {
+    Video,
+    Audio,
+    Image,
+    Other,
+}
+
+impl ResourceType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResourceType::Video => "video",
+            ResourceType::Audio => "audio",
+            ResourceType::Image => "image",
+            ResourceType::Other => "other",
+        }
+    }
+}
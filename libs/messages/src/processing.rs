@@ -0,0 +1,44 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{FileType, QueueMessage};
+
+
+/// Sent to the `video-processing`, `audio-processing` or `image-processing` queue by the metadata service,
+/// depending on the file type. Consumed by the transcoding services.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProcessingRequest {
+    pub presigned_url: String,
+    pub object_name: String,
+    pub file_type: FileType,
+    #[serde(default)]
+    pub run: ProcessingRun,
+}
+
+// version 2 added the run
+impl QueueMessage for ProcessingRequest {
+    const VERSION: u32 = 2;
+}
+
+/// Which run of the pipeline a message belongs to. The upload is revision 0, and every reprocessing of the
+/// resource by the resource server starts a new revision. Passed on from stage to stage.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ProcessingRun {
+    /// The outputs of each revision are stored in their own folder, see `output_folder`
+    pub revision: u32,
+    /// Only produce these renditions, e.g. `["480p", "720p"]`, instead of every rendition that fits the video
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub renditions: Option<Vec<String>>,
+}
+
+/// The folder of the outputs of a revision, under the resource folder of the bucket. Revision 0 uses the folder
+/// of the resource itself, as before the revisions, so the renditions of the earlier uploads are still found.
+///
+/// # Arguments
+/// * `object_name` - The object name of the resource, which is also its id
+pub fn output_folder(object_name: &str, revision: u32) -> String {
+    if revision == 0 {
+        object_name.to_string()
+    } else {
+        format!("{}/v{}", object_name, revision)
+    }
+}
@@ -0,0 +1,152 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{AudioData, FileType, ImageData, QueueMessage, ResourceType, VideoData};
+
+
+/// Sent to the `resource-status` queue by every stage of the pipeline. Consumed by the resource server,
+/// which keeps track of the resource status.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ResourceStatusUpdate {
+    Uploaded {
+        user_id: String,
+        object_name: String,
+        file_name: String,
+        origin_file_path: String,
+    },
+    Processing {
+        object_name: String,
+        /// The revision of the run the update is from, see `ProcessingRun`
+        #[serde(default)]
+        revision: u32,
+    },
+    TypeResolved {
+        object_name: String,
+        resource_type: ResourceType,
+        /// The media information, so that the resource can be transcoded again without resolving it again
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        file_type: Option<FileType>,
+        #[serde(default)]
+        revision: u32,
+    },
+    Processed {
+        object_name: String,
+        metadata: ProducedResourceMetadata,
+        #[serde(default)]
+        revision: u32,
+    },
+    Failed {
+        object_name: String,
+        #[serde(default)]
+        revision: u32,
+    },
+    /// A stage of the pipeline started, finished or failed, see `ProcessingStage`
+    StageChanged {
+        object_name: String,
+        stage: ProcessingStage,
+        state: StageState,
+        /// How many times the stage has been attempted, including this time
+        attempt: u32,
+        /// Why the stage failed
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+        #[serde(default)]
+        revision: u32,
+    },
+    /// How far the transcoding has got, sent every few seconds while the transcode stage is running. Only shown
+    /// to the owner while it happens, it is not stored.
+    TranscodeProgress {
+        object_name: String,
+        /// 0 to 100
+        percent: u8,
+        #[serde(default)]
+        revision: u32,
+    },
+}
+
+// version 2 added StageChanged, version 3 the revisions and the file type of TypeResolved, version 4 TranscodeProgress
+impl QueueMessage for ResourceStatusUpdate {
+    const VERSION: u32 = 4;
+}
+
+impl ResourceStatusUpdate {
+    pub fn object_name(&self) -> &str {
+        match self {
+            ResourceStatusUpdate::Uploaded { object_name, .. }
+            | ResourceStatusUpdate::Processing { object_name, .. }
+            | ResourceStatusUpdate::TypeResolved { object_name, .. }
+            | ResourceStatusUpdate::Processed { object_name, .. }
+            | ResourceStatusUpdate::Failed { object_name, .. }
+            | ResourceStatusUpdate::StageChanged { object_name, .. }
+            | ResourceStatusUpdate::TranscodeProgress { object_name, .. } => object_name,
+        }
+    }
+}
+
+/// The stages of the processing pipeline, in the order a resource goes through them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessingStage {
+    /// The file was uploaded to the ingestion service
+    Upload,
+    VirusScan,
+    /// The file type and media information were resolved
+    Metadata,
+    Transcode,
+    /// The produced media was stored by the resource server, and the resource can be viewed
+    Publish,
+}
+
+impl ProcessingStage {
+    pub const ALL: [ProcessingStage; 5] = [
+        ProcessingStage::Upload,
+        ProcessingStage::VirusScan,
+        ProcessingStage::Metadata,
+        ProcessingStage::Transcode,
+        ProcessingStage::Publish,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProcessingStage::Upload => "upload",
+            ProcessingStage::VirusScan => "virus_scan",
+            ProcessingStage::Metadata => "metadata",
+            ProcessingStage::Transcode => "transcode",
+            ProcessingStage::Publish => "publish",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StageState {
+    Running,
+    Succeeded,
+    Failed,
+    /// Not needed for the resource, e.g. the virus scan of a file that is too large to scan
+    Skipped,
+}
+
+impl StageState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StageState::Running => "running",
+            StageState::Succeeded => "succeeded",
+            StageState::Failed => "failed",
+            StageState::Skipped => "skipped",
+        }
+    }
+
+    /// The stage will not change anymore, unless it is attempted again
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, StageState::Running)
+    }
+}
+
+/// The media produced by the processing, e.g. one `VideoData` per transcoded quality
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ProducedResourceMetadata {
+    Video(Vec<VideoData>),
+    Audio(AudioData),
+    Image(ImageData),
+}
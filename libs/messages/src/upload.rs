@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{ProcessingRun, QueueMessage};
+
+
+/// Sent to the `upload-finished` queue by the ingestion service, once a file has been uploaded.
+/// Consumed by the virus scanner.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct UploadFinished {
+    pub presigned_url: String,
+    pub file_size: usize,
+    pub object_name: String,
+    #[serde(default)]
+    pub run: ProcessingRun,
+}
+
+// version 2 added the run
+impl QueueMessage for UploadFinished {
+    const VERSION: u32 = 2;
+}
+
+/// Sent to the `virus-scan-clear` queue by the virus scanner, once a file has been found clean (or was
+/// too large to scan). Consumed by the metadata service.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VirusScanClear {
+    pub presigned_url: String,
+    pub object_name: String,
+    #[serde(default)]
+    pub run: ProcessingRun,
+}
+
+// version 2 added the run
+impl QueueMessage for VirusScanClear {
+    const VERSION: u32 = 2;
+}
@@ -0,0 +1,135 @@
+mod memory;
+mod sqs;
+
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use config::Source;
+
+pub use memory::MemoryQueue;
+pub use sqs::SqsQueue;
+
+
+/// A message received from the queue. It stays invisible to the other consumers until the visibility
+/// timeout expires, and it is redelivered after that, unless it has been deleted.
+#[derive(Debug, Clone)]
+pub struct ReceivedMessage {
+    /// Identifies the message, it stays the same when the message is redelivered
+    pub message_id: String,
+    pub body: String,
+    /// Identifies this delivery of the message, for deleting it or changing its visibility
+    pub receipt_handle: String,
+    /// When the message was sent, in milliseconds since the epoch
+    pub sent_timestamp: Option<i64>,
+    /// How many times the message has been received, including this time
+    pub receive_count: u32,
+}
+
+#[derive(Debug)]
+pub enum QueueError {
+    /// The queue could not be reached, or it rejected the request
+    Backend(String),
+    /// The receipt handle does not belong to a message that is currently in flight
+    InvalidReceiptHandle(String),
+}
+
+impl std::fmt::Display for QueueError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QueueError::Backend(err) => write!(f, "Queue error: {}", err),
+            QueueError::InvalidReceiptHandle(receipt_handle) => write!(f, "Invalid receipt handle {}", receipt_handle),
+        }
+    }
+}
+
+impl std::error::Error for QueueError {}
+
+
+/// A queue between the services, with at-least-once delivery.
+#[async_trait]
+pub trait MessageQueue: Debug + Send + Sync {
+    async fn send(&self, body: &str) -> Result<(), QueueError>;
+
+    /// Receives up to `max_messages` messages, waiting up to `wait` for the first one to arrive.
+    ///
+    /// # Returns
+    /// * The received messages, empty if none arrived in time
+    async fn receive(&self, max_messages: usize, wait: Duration) -> Result<Vec<ReceivedMessage>, QueueError>;
+
+    /// Deletes a received message, so that it is not redelivered
+    async fn delete(&self, receipt_handle: &str) -> Result<(), QueueError>;
+
+    /// Deletes several received messages.
+    ///
+    /// # Returns
+    /// * `Err` with the first failure, the messages that could not be deleted are redelivered later
+    async fn delete_batch(&self, receipt_handles: &[String]) -> Result<(), QueueError> {
+        let mut result = Ok(());
+        for receipt_handle in receipt_handles {
+            if let Err(err) = self.delete(receipt_handle).await {
+                result = result.and(Err(err));
+            }
+        }
+        result
+    }
+
+    /// Changes how long a received message stays invisible, counted from now. Zero makes it visible right away.
+    async fn change_visibility(&self, receipt_handle: &str, timeout: Duration) -> Result<(), QueueError>;
+}
+
+
+/// Where a queue is, read from the settings of a service. The queue is created once at startup with `open`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueueSettings {
+    Sqs { queue_url: String },
+    /// An in-process queue, shared by everything in the process that opens the same name
+    Memory { name: String },
+}
+
+impl QueueSettings {
+    /// Reads the queue whose SQS URL is in the `url_var` setting.
+    ///
+    /// With `QUEUE_BACKEND=memory` an in-process queue is used instead, so that the services can be run without
+    /// localstack. The in-process queues are named by `url_var`, so everything in the same process that uses the
+    /// same setting shares the queue.
+    pub fn from_source(source: &mut Source, url_var: &str) -> Self {
+        match backend(source) {
+            Backend::Sqs => QueueSettings::Sqs { queue_url: source.required(url_var) },
+            Backend::Memory => QueueSettings::Memory { name: url_var.to_string() },
+        }
+    }
+
+    /// Like `from_source`, for a queue that may be left out, e.g. a dead-letter queue.
+    ///
+    /// # Returns
+    /// * `None` if `url_var` is not set, with SQS
+    pub fn optional_from_source(source: &mut Source, url_var: &str) -> Option<Self> {
+        match backend(source) {
+            Backend::Sqs => source.optional(url_var).map(|queue_url| QueueSettings::Sqs { queue_url }),
+            Backend::Memory => Some(QueueSettings::Memory { name: url_var.to_string() }),
+        }
+    }
+
+    pub fn open(&self) -> Arc<dyn MessageQueue> {
+        match self {
+            QueueSettings::Sqs { queue_url } => Arc::new(SqsQueue::new(queue_url.clone())),
+            QueueSettings::Memory { name } => MemoryQueue::shared(name),
+        }
+    }
+}
+
+enum Backend {
+    Sqs,
+    Memory,
+}
+
+fn backend(source: &mut Source) -> Backend {
+    source.parsed_with("QUEUE_BACKEND", Backend::Sqs, |value| match value {
+        "sqs" => Ok(Backend::Sqs),
+        "memory" => Ok(Backend::Memory),
+        _ => Err("expected sqs or memory".to_string()),
+    })
+}
@@ -0,0 +1,200 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::{MessageQueue, QueueError, ReceivedMessage};
+
+
+// the SQS default
+const DEFAULT_VISIBILITY_TIMEOUT: Duration = Duration::from_secs(30);
+
+static SHARED_QUEUES: OnceLock<Mutex<HashMap<String, Arc<MemoryQueue>>>> = OnceLock::new();
+
+
+#[derive(Clone)]
+struct StoredMessage {
+    message_id: String,
+    body: String,
+    sent_timestamp: i64,
+    receive_count: u32,
+}
+
+#[derive(Default)]
+struct QueueState {
+    visible: VecDeque<StoredMessage>,
+    // receipt handle -> message, and when it becomes visible again
+    in_flight: HashMap<String, (StoredMessage, Instant)>,
+    next_receipt: u64,
+    next_message_id: u64,
+}
+
+impl QueueState {
+    // moves the messages whose visibility timeout has expired back to the queue
+    fn release_expired(&mut self, now: Instant) {
+        let expired: Vec<String> = self.in_flight.iter()
+            .filter(|(_, (_, visible_at))| *visible_at <= now)
+            .map(|(receipt_handle, _)| receipt_handle.clone())
+            .collect();
+
+        for receipt_handle in expired {
+            if let Some((message, _)) = self.in_flight.remove(&receipt_handle) {
+                self.visible.push_back(message);
+            }
+        }
+    }
+}
+
+/// An in-process queue with the same delivery semantics as SQS: received messages are redelivered after
+/// the visibility timeout, unless they are deleted.
+///
+/// Used in the tests and in the single-process dev mode, see `QueueSettings`.
+pub struct MemoryQueue {
+    state: Mutex<QueueState>,
+    notify: Notify,
+    visibility_timeout: Duration,
+}
+
+impl std::fmt::Debug for MemoryQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MemoryQueue")
+            .field("visibility_timeout", &self.visibility_timeout)
+            .finish()
+    }
+}
+
+impl Default for MemoryQueue {
+    fn default() -> Self {
+        MemoryQueue::new()
+    }
+}
+
+impl MemoryQueue {
+    pub fn new() -> Self {
+        MemoryQueue::with_visibility_timeout(DEFAULT_VISIBILITY_TIMEOUT)
+    }
+
+    pub fn with_visibility_timeout(visibility_timeout: Duration) -> Self {
+        MemoryQueue {
+            state: Mutex::new(QueueState::default()),
+            notify: Notify::new(),
+            visibility_timeout,
+        }
+    }
+
+    /// The queue with the given name, shared by everything in the process
+    pub fn shared(name: &str) -> Arc<MemoryQueue> {
+        SHARED_QUEUES
+            .get_or_init(|| Mutex::new(HashMap::new()))
+            .lock()
+            .unwrap()
+            .entry(name.to_string())
+            .or_insert_with(|| Arc::new(MemoryQueue::new()))
+            .clone()
+    }
+
+    /// The bodies of the messages that have not been deleted yet, including the ones in flight
+    pub fn pending(&self) -> Vec<String> {
+        let state = self.state.lock().unwrap();
+        state.visible.iter()
+            .chain(state.in_flight.values().map(|(message, _)| message))
+            .map(|message| message.body.clone())
+            .collect()
+    }
+
+    fn try_receive(&self, max_messages: usize) -> (Vec<ReceivedMessage>, Option<Instant>) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.release_expired(now);
+
+        let mut received = vec![];
+        while received.len() < max_messages {
+            let Some(mut message) = state.visible.pop_front() else {
+                break;
+            };
+
+            message.receive_count += 1;
+            state.next_receipt += 1;
+            let receipt_handle = format!("memory-{}", state.next_receipt);
+
+            received.push(ReceivedMessage {
+                message_id: message.message_id.clone(),
+                body: message.body.clone(),
+                receipt_handle: receipt_handle.clone(),
+                sent_timestamp: Some(message.sent_timestamp),
+                receive_count: message.receive_count,
+            });
+            state.in_flight.insert(receipt_handle, (message, now + self.visibility_timeout));
+        }
+
+        let next_visible_at = state.in_flight.values().map(|(_, visible_at)| *visible_at).min();
+        (received, next_visible_at)
+    }
+}
+
+#[async_trait]
+impl MessageQueue for MemoryQueue {
+    async fn send(&self, body: &str) -> Result<(), QueueError> {
+        let sent_timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as i64)
+            .unwrap_or_default();
+
+        let mut state = self.state.lock().unwrap();
+        state.next_message_id += 1;
+        let message_id = format!("memory-message-{}", state.next_message_id);
+
+        state.visible.push_back(StoredMessage {
+            message_id,
+            body: body.to_string(),
+            sent_timestamp,
+            receive_count: 0,
+        });
+        drop(state);
+        self.notify.notify_one();
+
+        Ok(())
+    }
+
+    async fn receive(&self, max_messages: usize, wait: Duration) -> Result<Vec<ReceivedMessage>, QueueError> {
+        let deadline = Instant::now() + wait;
+
+        loop {
+            let (received, next_visible_at) = self.try_receive(max_messages.max(1));
+            if !received.is_empty() || Instant::now() >= deadline {
+                return Ok(received);
+            }
+
+            // wake up when a message is sent, or an in-flight message becomes visible again
+            let wake_at = next_visible_at.map_or(deadline, |visible_at| visible_at.min(deadline));
+            let _ = tokio::time::timeout_at(wake_at, self.notify.notified()).await;
+        }
+    }
+
+    async fn delete(&self, receipt_handle: &str) -> Result<(), QueueError> {
+        match self.state.lock().unwrap().in_flight.remove(receipt_handle) {
+            Some(_) => Ok(()),
+            None => Err(QueueError::InvalidReceiptHandle(receipt_handle.to_string())),
+        }
+    }
+
+    async fn change_visibility(&self, receipt_handle: &str, timeout: Duration) -> Result<(), QueueError> {
+        let mut state = self.state.lock().unwrap();
+
+        match state.in_flight.get_mut(receipt_handle) {
+            Some((_, visible_at)) => *visible_at = Instant::now() + timeout,
+            None => return Err(QueueError::InvalidReceiptHandle(receipt_handle.to_string())),
+        }
+
+        if timeout.is_zero() {
+            state.release_expired(Instant::now());
+            self.notify.notify_one();
+        }
+
+        Ok(())
+    }
+}
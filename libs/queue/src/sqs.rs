@@ -0,0 +1,163 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use aws_sdk_sqs::Client;
+use aws_sdk_sqs::error::DisplayErrorContext;
+use aws_sdk_sqs::types::{DeleteMessageBatchRequestEntry, MessageSystemAttributeName};
+
+use tokio::sync::OnceCell;
+
+use crate::{MessageQueue, QueueError, ReceivedMessage};
+
+
+// the maximum SQS allows, both for receiving and for deleting
+const MAX_BATCH_SIZE: usize = 10;
+
+// the maximum long polling time SQS allows
+const MAX_WAIT_SECONDS: u64 = 20;
+
+
+pub struct SqsQueue {
+    // loading the AWS config is async, so the client is created on the first use
+    client: OnceCell<Client>,
+    queue_url: String,
+}
+
+impl SqsQueue {
+    pub fn new(queue_url: String) -> Self {
+        SqsQueue {
+            client: OnceCell::new(),
+            queue_url,
+        }
+    }
+
+    async fn client(&self) -> &Client {
+        self.client
+            .get_or_init(|| async { Client::new(&aws_config::load_from_env().await) })
+            .await
+    }
+}
+
+impl std::fmt::Debug for SqsQueue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SqsQueue")
+            .field("queue_url", &self.queue_url)
+            .finish()
+    }
+}
+
+fn backend_error<E: std::error::Error>(err: E) -> QueueError {
+    QueueError::Backend(DisplayErrorContext(err).to_string())
+}
+
+#[async_trait]
+impl MessageQueue for SqsQueue {
+    async fn send(&self, body: &str) -> Result<(), QueueError> {
+        self.client().await
+            .send_message()
+            .queue_url(&self.queue_url)
+            .message_body(body)
+            .send()
+            .await
+            .map_err(backend_error)?;
+
+        Ok(())
+    }
+
+    async fn receive(&self, max_messages: usize, wait: Duration) -> Result<Vec<ReceivedMessage>, QueueError> {
+        let output = self.client().await
+            .receive_message()
+            .queue_url(&self.queue_url)
+            .message_system_attribute_names(MessageSystemAttributeName::SentTimestamp)
+            .message_system_attribute_names(MessageSystemAttributeName::ApproximateReceiveCount)
+            .max_number_of_messages(max_messages.clamp(1, MAX_BATCH_SIZE) as i32)
+            .wait_time_seconds(wait.as_secs().min(MAX_WAIT_SECONDS) as i32)
+            .send()
+            .await
+            .map_err(backend_error)?;
+
+        let messages = output.messages.unwrap_or_default()
+            .into_iter()
+            .map(|message| {
+                let attribute = |name: MessageSystemAttributeName| message.attributes()
+                    .and_then(|attributes| attributes.get(&name))
+                    .cloned();
+
+                ReceivedMessage {
+                    sent_timestamp: attribute(MessageSystemAttributeName::SentTimestamp).and_then(|v| v.parse().ok()),
+                    receive_count: attribute(MessageSystemAttributeName::ApproximateReceiveCount)
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(1),
+                    message_id: message.message_id.unwrap_or_default(),
+                    body: message.body.unwrap_or_default(),
+                    receipt_handle: message.receipt_handle.unwrap_or_default(),
+                }
+            })
+            .collect();
+
+        Ok(messages)
+    }
+
+    async fn delete(&self, receipt_handle: &str) -> Result<(), QueueError> {
+        self.client().await
+            .delete_message()
+            .queue_url(&self.queue_url)
+            .receipt_handle(receipt_handle)
+            .send()
+            .await
+            .map_err(backend_error)?;
+
+        Ok(())
+    }
+
+    /// Deletes the messages with DeleteMessageBatch calls of up to 10 messages.
+    async fn delete_batch(&self, receipt_handles: &[String]) -> Result<(), QueueError> {
+        let mut result = Ok(());
+
+        for chunk in receipt_handles.chunks(MAX_BATCH_SIZE) {
+            let entries = chunk.iter()
+                .enumerate()
+                .map(|(i, receipt_handle)| DeleteMessageBatchRequestEntry::builder()
+                    .id(i.to_string())
+                    .receipt_handle(receipt_handle)
+                    .build()
+                    .expect("Failed to build batch entry"))
+                .collect::<Vec<_>>();
+
+            match self.client().await
+                .delete_message_batch()
+                .queue_url(&self.queue_url)
+                .set_entries(Some(entries))
+                .send()
+                .await {
+                Ok(output) => {
+                    if let Some(failed) = output.failed().first() {
+                        result = result.and(Err(QueueError::Backend(format!(
+                            "{} of {} messages could not be deleted, first error: {}",
+                            output.failed().len(),
+                            chunk.len(),
+                            failed.message().unwrap_or(failed.code())
+                        ))));
+                    }
+                }
+                Err(err) => result = result.and(Err(backend_error(err))),
+            }
+        }
+
+        result
+    }
+
+    async fn change_visibility(&self, receipt_handle: &str, timeout: Duration) -> Result<(), QueueError> {
+        self.client().await
+            .change_message_visibility()
+            .queue_url(&self.queue_url)
+            .receipt_handle(receipt_handle)
+            .visibility_timeout(timeout.as_secs() as i32)
+            .send()
+            .await
+            .map_err(backend_error)?;
+
+        Ok(())
+    }
+}
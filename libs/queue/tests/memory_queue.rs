@@ -0,0 +1,111 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use queue::{MemoryQueue, MessageQueue, QueueError};
+
+
+#[tokio::test]
+async fn memory_queue_delivers_in_order() {
+    let queue = MemoryQueue::new();
+    queue.send("first").await.unwrap();
+    queue.send("second").await.unwrap();
+    queue.send("third").await.unwrap();
+
+    let received = queue.receive(2, Duration::ZERO).await.unwrap();
+    let bodies: Vec<&str> = received.iter().map(|message| message.body.as_str()).collect();
+    assert_eq!(bodies, vec!["first", "second"]);
+    assert!(received.iter().all(|message| message.receive_count == 1 && message.sent_timestamp.is_some()));
+
+    let received = queue.receive(10, Duration::ZERO).await.unwrap();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].body, "third");
+}
+
+#[tokio::test]
+async fn deleted_messages_are_not_redelivered() {
+    let queue = MemoryQueue::with_visibility_timeout(Duration::from_millis(50));
+    queue.send("message").await.unwrap();
+
+    let received = queue.receive(1, Duration::ZERO).await.unwrap();
+    queue.delete(&received[0].receipt_handle).await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(queue.receive(1, Duration::ZERO).await.unwrap().is_empty());
+    assert!(queue.pending().is_empty());
+
+    assert!(matches!(
+        queue.delete(&received[0].receipt_handle).await,
+        Err(QueueError::InvalidReceiptHandle(_))
+    ));
+}
+
+#[tokio::test]
+async fn undeleted_messages_are_redelivered_after_the_visibility_timeout() {
+    let queue = MemoryQueue::with_visibility_timeout(Duration::from_millis(50));
+    queue.send("message").await.unwrap();
+
+    let first = queue.receive(1, Duration::ZERO).await.unwrap();
+    assert!(queue.receive(1, Duration::ZERO).await.unwrap().is_empty());
+
+    // waits for the message to become visible again
+    let second = queue.receive(1, Duration::from_secs(1)).await.unwrap();
+    assert_eq!(second.len(), 1);
+    assert_eq!(second[0].body, "message");
+    assert_eq!(second[0].receive_count, 2);
+    assert_ne!(second[0].receipt_handle, first[0].receipt_handle);
+}
+
+#[tokio::test]
+async fn change_visibility_releases_and_extends_messages() {
+    let queue = MemoryQueue::with_visibility_timeout(Duration::from_millis(50));
+    queue.send("message").await.unwrap();
+
+    let received = queue.receive(1, Duration::ZERO).await.unwrap();
+    queue.change_visibility(&received[0].receipt_handle, Duration::from_secs(60)).await.unwrap();
+
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(queue.receive(1, Duration::ZERO).await.unwrap().is_empty());
+
+    queue.change_visibility(&received[0].receipt_handle, Duration::ZERO).await.unwrap();
+    let received = queue.receive(1, Duration::ZERO).await.unwrap();
+    assert_eq!(received.len(), 1);
+}
+
+#[tokio::test]
+async fn receive_waits_for_a_message_to_be_sent() {
+    let queue = Arc::new(MemoryQueue::new());
+
+    let sender = queue.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        sender.send("late").await.unwrap();
+    });
+
+    let received = queue.receive(10, Duration::from_secs(5)).await.unwrap();
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].body, "late");
+}
+
+#[tokio::test]
+async fn shared_queues_are_identified_by_name() {
+    let sender: Arc<dyn MessageQueue> = MemoryQueue::shared("TEST_QUEUE_URL");
+    let receiver: Arc<dyn MessageQueue> = MemoryQueue::shared("TEST_QUEUE_URL");
+    let other: Arc<dyn MessageQueue> = MemoryQueue::shared("OTHER_QUEUE_URL");
+
+    sender.send("shared").await.unwrap();
+
+    assert!(other.receive(1, Duration::ZERO).await.unwrap().is_empty());
+    assert_eq!(receiver.receive(1, Duration::ZERO).await.unwrap()[0].body, "shared");
+}
+
+#[tokio::test]
+async fn delete_batch_reports_unknown_receipt_handles() {
+    let queue = MemoryQueue::new();
+    queue.send("message").await.unwrap();
+
+    let received = queue.receive(1, Duration::ZERO).await.unwrap();
+    let result = queue.delete_batch(&[received[0].receipt_handle.clone(), "unknown".to_string()]).await;
+
+    assert!(matches!(result, Err(QueueError::InvalidReceiptHandle(handle)) if handle == "unknown"));
+    assert!(queue.pending().is_empty());
+}
@@ -0,0 +1,54 @@
+use config::Source;
+use queue::QueueSettings;
+
+
+fn load<T>(variables: &[(&str, &str)], build: impl FnOnce(&mut Source) -> T) -> Result<T, Vec<String>> {
+    let source = Source::from_variables(variables.iter().map(|(name, value)| (name.to_string(), value.to_string()))).unwrap();
+    config::load_from(source, build).map_err(|err| err.problems().to_vec())
+}
+
+
+#[test]
+fn reads_the_sqs_queue_url() {
+    let settings = load(&[("UPLOAD_QUEUE_URL", "http://localstack:4566/000000000000/upload")], |source| {
+        QueueSettings::from_source(source, "UPLOAD_QUEUE_URL")
+    });
+
+    assert_eq!(settings.unwrap(), QueueSettings::Sqs { queue_url: "http://localstack:4566/000000000000/upload".to_string() });
+}
+
+#[test]
+fn reports_a_missing_sqs_queue_url() {
+    let problems = load(&[], |source| QueueSettings::from_source(source, "UPLOAD_QUEUE_URL")).unwrap_err();
+
+    assert_eq!(problems, ["UPLOAD_QUEUE_URL is not set"]);
+}
+
+#[test]
+fn the_optional_queues_may_be_left_out() {
+    let settings = load(&[], |source| QueueSettings::optional_from_source(source, "UPLOAD_DLQ_URL"));
+
+    assert_eq!(settings.unwrap(), None);
+}
+
+#[test]
+fn names_the_in_process_queues_by_the_setting() {
+    let settings = load(&[("QUEUE_BACKEND", "memory")], |source| {
+        (QueueSettings::from_source(source, "UPLOAD_QUEUE_URL"), QueueSettings::optional_from_source(source, "UPLOAD_DLQ_URL"))
+    });
+
+    assert_eq!(settings.unwrap(), (
+        QueueSettings::Memory { name: "UPLOAD_QUEUE_URL".to_string() },
+        Some(QueueSettings::Memory { name: "UPLOAD_DLQ_URL".to_string() }),
+    ));
+}
+
+#[test]
+fn reports_an_unknown_backend() {
+    let problems = load(&[("QUEUE_BACKEND", "kafka"), ("UPLOAD_QUEUE_URL", "url")], |source| {
+        QueueSettings::from_source(source, "UPLOAD_QUEUE_URL")
+    }).unwrap_err();
+
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].starts_with("QUEUE_BACKEND is invalid"), "{}", problems[0]);
+}
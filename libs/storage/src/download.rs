@@ -0,0 +1,35 @@
+use futures_util::StreamExt;
+use tokio_util::io::ReaderStream;
+
+use crate::{ByteStream, StorageError};
+
+
+/// Reads the object behind a presigned URL, see `ObjectStore::presign_get`.
+///
+/// Both the `http(s)://` URLs of S3 and the `file://` URLs of the `LocalStore` are supported, so the services
+/// receiving the URLs in their messages work with either store.
+pub async fn open_url(url: &str) -> Result<ByteStream<'static>, StorageError> {
+    if url.starts_with("file://") {
+        let path = url::Url::parse(url)
+            .ok()
+            .and_then(|url| url.to_file_path().ok())
+            .ok_or_else(|| StorageError::Invalid(format!("Invalid file URL {}", url)))?;
+
+        let file = tokio::fs::File::open(&path).await.map_err(|err| match err.kind() {
+            std::io::ErrorKind::NotFound => StorageError::NotFound(path.display().to_string()),
+            _ => StorageError::Backend(err.to_string()),
+        })?;
+
+        let body = ReaderStream::new(file).map(|chunk| chunk.map_err(|err| StorageError::Backend(err.to_string())));
+        return Ok(Box::pin(body));
+    }
+
+    let response = reqwest::get(url).await.map_err(|err| StorageError::Backend(err.to_string()))?;
+    if response.status() == reqwest::StatusCode::NOT_FOUND {
+        return Err(StorageError::NotFound(response.url().path().to_string()));
+    }
+    let response = response.error_for_status().map_err(|err| StorageError::Backend(err.to_string()))?;
+
+    let body = response.bytes_stream().map(|chunk| chunk.map_err(|err| StorageError::Backend(err.to_string())));
+    Ok(Box::pin(body))
+}
@@ -0,0 +1,272 @@
+mod download;
+mod local;
+mod location;
+mod s3;
+
+use std::fmt::Debug;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use chrono::{DateTime, Utc};
+use futures_util::{Stream, StreamExt};
+
+use config::Source;
+
+pub use download::open_url;
+pub use local::LocalStore;
+pub use location::Location;
+pub use s3::S3Store;
+
+
+/// The smallest part S3 accepts in a multipart upload, except for the last one
+pub const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+
+
+/// The contents of an object, read or written a chunk at a time. The stored streams may borrow, e.g. from the
+/// request they are read from.
+pub type ByteStream<'a> = Pin<Box<dyn Stream<Item = Result<Bytes, StorageError>> + Send + 'a>>;
+
+#[derive(Debug)]
+pub enum StorageError {
+    /// The object, or the multipart upload, does not exist
+    NotFound(String),
+    /// The requested range is outside of the object
+    InvalidRange(String),
+    /// The request can never succeed, e.g. a key that is not allowed or a part that was not uploaded
+    Invalid(String),
+    /// The data to store could not be read
+    Body(String),
+    /// The store could not be reached, or it failed the request
+    Backend(String),
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::NotFound(key) => write!(f, "{} not found", key),
+            StorageError::InvalidRange(key) => write!(f, "Invalid range for {}", key),
+            StorageError::Invalid(err) => write!(f, "Invalid storage request: {}", err),
+            StorageError::Body(err) => write!(f, "Error reading the data to store: {}", err),
+            StorageError::Backend(err) => write!(f, "Storage error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+
+/// A range of bytes to read, like in an HTTP `Range` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ByteRange {
+    /// From the offset to the end of the object
+    From(u64),
+    /// Between the offsets, both included
+    Inclusive(u64, u64),
+    /// The given number of bytes at the end of the object
+    Suffix(u64),
+}
+
+impl ByteRange {
+    /// Parses an HTTP `Range` header with a single range, e.g. `bytes=0-1023`.
+    ///
+    /// # Returns
+    /// * `None` if the header is invalid or has several ranges, in which case the whole object should be sent
+    pub fn from_header(value: &str) -> Option<Self> {
+        let range = value.trim().strip_prefix("bytes=")?;
+        if range.contains(',') {
+            return None;
+        }
+
+        let (start, end) = range.split_once('-')?;
+        match (start.trim(), end.trim()) {
+            ("", suffix) => suffix.parse().ok().map(ByteRange::Suffix),
+            (start, "") => start.parse().ok().map(ByteRange::From),
+            (start, end) => {
+                let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+                (start <= end).then_some(ByteRange::Inclusive(start, end))
+            }
+        }
+    }
+
+    /// # Returns
+    /// * The first and the last offset of the range in an object of the given size, `None` if the range is not
+    ///   within the object
+    pub fn resolve(&self, size: u64) -> Option<(u64, u64)> {
+        if size == 0 {
+            return None;
+        }
+
+        match *self {
+            ByteRange::From(start) => (start < size).then_some((start, size - 1)),
+            ByteRange::Inclusive(start, end) => (start < size).then_some((start, end.min(size - 1))),
+            ByteRange::Suffix(0) => None,
+            ByteRange::Suffix(length) => Some((size.saturating_sub(length), size - 1)),
+        }
+    }
+
+    /// The value of an HTTP `Range` header with the range
+    pub fn to_header(&self) -> String {
+        match self {
+            ByteRange::From(start) => format!("bytes={}-", start),
+            ByteRange::Inclusive(start, end) => format!("bytes={}-{}", start, end),
+            ByteRange::Suffix(length) => format!("bytes=-{}", length),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjectMeta {
+    pub key: String,
+    pub size: u64,
+    pub last_modified: DateTime<Utc>,
+}
+
+/// An object read from the store
+pub struct Object {
+    pub body: ByteStream<'static>,
+    /// The size of the whole object, not only of the requested range
+    pub size: u64,
+    /// The first and the last offset of the body, if a range was requested
+    pub range: Option<(u64, u64)>,
+    pub last_modified: DateTime<Utc>,
+}
+
+impl Object {
+    /// Reads the whole body into memory
+    pub async fn bytes(mut self) -> Result<Bytes, StorageError> {
+        let mut data = BytesMut::new();
+        while let Some(chunk) = self.body.next().await {
+            data.extend_from_slice(&chunk?);
+        }
+        Ok(data.freeze())
+    }
+}
+
+/// A part of a multipart upload, the parts are listed when the upload is completed
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompletedPart {
+    pub part_number: i32,
+    pub e_tag: String,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CompletedUpload {
+    /// Only returned by S3, when the upload was created with a CRC32 checksum
+    pub checksum_crc32: Option<String>,
+}
+
+
+/// Where the files of the services are stored.
+///
+/// The keys are `/` separated paths, like S3 keys. A multipart upload may span several requests, the upload id
+/// and the completed parts are kept by the caller in the meantime.
+#[async_trait]
+pub trait ObjectStore: Debug + Send + Sync {
+    async fn put(&self, key: &str, data: Bytes, content_type: Option<&str>) -> Result<(), StorageError>;
+
+    /// Reads the object, or the given range of it.
+    ///
+    /// # Returns
+    /// * `Err(StorageError::InvalidRange)` if the range is not within the object
+    async fn get(&self, key: &str, range: Option<ByteRange>) -> Result<Object, StorageError>;
+
+    async fn head(&self, key: &str) -> Result<ObjectMeta, StorageError>;
+
+    /// Deletes the object. Deleting an object that does not exist is not an error, like in S3.
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+
+    /// # Returns
+    /// * The objects whose key starts with the prefix, ordered by the key
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, StorageError>;
+
+    async fn copy(&self, from_key: &str, to_key: &str) -> Result<(), StorageError>;
+
+    /// # Returns
+    /// * A URL the object can be read from without credentials until it expires, see `open_url`
+    async fn presign_get(&self, key: &str, expires_in: Duration) -> Result<String, StorageError>;
+
+    /// Starts a multipart upload.
+    ///
+    /// # Returns
+    /// * The id of the upload, for uploading the parts and completing or aborting it
+    async fn create_multipart(&self, key: &str) -> Result<String, StorageError>;
+
+    /// Uploads a part, numbered from 1. All the parts except the last one must be at least `MIN_PART_SIZE`.
+    /// Uploading a part again replaces it.
+    async fn upload_part(&self, key: &str, upload_id: &str, part_number: i32, data: Bytes) -> Result<CompletedPart, StorageError>;
+
+    /// Joins the parts, in the order of their numbers, into the object
+    async fn complete_multipart(&self, key: &str, upload_id: &str, parts: Vec<CompletedPart>) -> Result<CompletedUpload, StorageError>;
+
+    async fn abort_multipart(&self, key: &str, upload_id: &str) -> Result<(), StorageError>;
+
+    /// Stores the stream with a multipart upload, so that the whole object is never in memory. The upload is
+    /// aborted if the stream or a part fails.
+    ///
+    /// # Returns
+    /// * The size of the stored object
+    async fn put_stream(&self, key: &str, mut body: ByteStream<'_>) -> Result<u64, StorageError> {
+        let upload_id = self.create_multipart(key).await?;
+
+        let mut size = 0;
+        let mut parts = Vec::new();
+        let mut buffer = BytesMut::new();
+
+        loop {
+            let chunk = body.next().await.transpose();
+            let result = match chunk {
+                Ok(Some(chunk)) => {
+                    size += chunk.len() as u64;
+                    buffer.extend_from_slice(&chunk);
+                    if buffer.len() < MIN_PART_SIZE {
+                        continue;
+                    }
+                    self.upload_part(key, &upload_id, parts.len() as i32 + 1, buffer.split().freeze()).await
+                }
+                // an upload needs at least one part, even if it is empty
+                Ok(None) if !buffer.is_empty() || parts.is_empty() => {
+                    self.upload_part(key, &upload_id, parts.len() as i32 + 1, buffer.split().freeze()).await
+                }
+                Ok(None) => break,
+                Err(err) => Err(err),
+            };
+
+            match result {
+                Ok(part) => parts.push(part),
+                Err(err) => {
+                    self.abort_multipart(key, &upload_id).await.unwrap_or_else(|abort_err| {
+                        tracing::error!("Failed to abort the multipart upload of {}: {}", key, abort_err);
+                    });
+                    return Err(err);
+                }
+            }
+        }
+
+        self.complete_multipart(key, &upload_id, parts).await?;
+        Ok(size)
+    }
+}
+
+
+/// Creates the store selected by `STORAGE_BACKEND` in the settings of a service.
+///
+/// `s3`, the default, stores the objects in the `S3_BUCKET_NAME` bucket, with path-style addressing if
+/// `USE_PATH_STYLE_BUCKETS` is `true`. `local` stores them as files under the `STORAGE_LOCAL_ROOT` directory,
+/// so that the services can be run without localstack. The services must share the directory, the presigned
+/// URLs are `file://` URLs.
+pub fn store_from_source(source: &mut Source) -> Arc<dyn ObjectStore> {
+    let backend = source.parsed_with("STORAGE_BACKEND", "s3", |value| match value {
+        "s3" => Ok("s3"),
+        "local" => Ok("local"),
+        _ => Err("expected s3 or local".to_string()),
+    });
+
+    match backend {
+        "local" => Arc::new(LocalStore::new(source.required::<std::path::PathBuf>("STORAGE_LOCAL_ROOT"))),
+        _ => Arc::new(S3Store::new(source.required("S3_BUCKET_NAME"), source.flag("USE_PATH_STYLE_BUCKETS", false))),
+    }
+}
@@ -0,0 +1,291 @@
+use std::io::{ErrorKind, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+
+use crate::{ByteRange, CompletedPart, CompletedUpload, Object, ObjectMeta, ObjectStore, StorageError};
+
+
+// the files of the store itself, next to the objects. Keys cannot start with it.
+const INTERNAL_DIR: &str = ".storage";
+
+
+/// Stores the objects as files under a directory, the key being the path of the file.
+///
+/// The files are written to a temporary file first and then moved in place, so a reader never sees a partly
+/// written object. The presigned URLs are `file://` URLs, which do not expire, so the store is meant for
+/// development and tests.
+#[derive(Debug)]
+pub struct LocalStore {
+    root: PathBuf,
+}
+
+impl LocalStore {
+    /// # Arguments
+    /// * `root` - The directory of the objects, created when the first object is stored
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        LocalStore {
+            root: std::path::absolute(&root).unwrap_or(root),
+        }
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, StorageError> {
+        // every segment must be a plain name, so that two keys never map to the same file
+        let valid = key.split('/').all(|segment| !segment.is_empty() && segment != "." && segment != "..")
+            && key.split('/').next() != Some(INTERNAL_DIR);
+
+        if valid {
+            Ok(self.root.join(key))
+        } else {
+            Err(StorageError::Invalid(format!("Key {:?} is not allowed", key)))
+        }
+    }
+
+    fn upload_dir(&self, upload_id: &str) -> Result<PathBuf, StorageError> {
+        if upload_id.is_empty() || !upload_id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+            return Err(StorageError::NotFound(format!("Upload {}", upload_id)));
+        }
+        Ok(self.root.join(INTERNAL_DIR).join("uploads").join(upload_id))
+    }
+
+    // a file to write the object to, before it is moved in place
+    async fn temp_file(&self) -> Result<(PathBuf, tokio::fs::File), StorageError> {
+        let dir = self.root.join(INTERNAL_DIR).join("tmp");
+        tokio::fs::create_dir_all(&dir).await.map_err(io_error)?;
+
+        let path = dir.join(uuid::Uuid::new_v4().to_string());
+        let file = tokio::fs::File::create(&path).await.map_err(io_error)?;
+        Ok((path, file))
+    }
+
+    async fn move_in_place(&self, temp_path: &Path, path: &Path) -> Result<(), StorageError> {
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await.map_err(io_error)?;
+        }
+        tokio::fs::rename(temp_path, path).await.map_err(io_error)
+    }
+
+    fn meta(&self, key: &str, metadata: &std::fs::Metadata) -> ObjectMeta {
+        ObjectMeta {
+            key: key.to_string(),
+            size: metadata.len(),
+            last_modified: metadata.modified().map(DateTime::<Utc>::from).unwrap_or_default(),
+        }
+    }
+}
+
+fn io_error(err: std::io::Error) -> StorageError {
+    StorageError::Backend(err.to_string())
+}
+
+// NotFound for a missing file, instead of a backend error
+fn file_error(key: &str) -> impl FnOnce(std::io::Error) -> StorageError + '_ {
+    move |err| match err.kind() {
+        ErrorKind::NotFound => StorageError::NotFound(key.to_string()),
+        _ => io_error(err),
+    }
+}
+
+// the part files are named `<part number>.<e-tag>`, so that a part uploaded again replaces the earlier one
+fn part_file_name(part: &CompletedPart) -> String {
+    format!("{:05}.{}", part.part_number, part.e_tag)
+}
+
+#[async_trait]
+impl ObjectStore for LocalStore {
+    async fn put(&self, key: &str, data: Bytes, _content_type: Option<&str>) -> Result<(), StorageError> {
+        let path = self.path(key)?;
+
+        let (temp_path, mut file) = self.temp_file().await?;
+        file.write_all(&data).await.map_err(io_error)?;
+        file.sync_all().await.map_err(io_error)?;
+
+        self.move_in_place(&temp_path, &path).await
+    }
+
+    async fn get(&self, key: &str, range: Option<ByteRange>) -> Result<Object, StorageError> {
+        let path = self.path(key)?;
+
+        let mut file = tokio::fs::File::open(&path).await.map_err(file_error(key))?;
+        let metadata = file.metadata().await.map_err(io_error)?;
+        let meta = self.meta(key, &metadata);
+
+        let (start, length, range) = match range {
+            Some(range) => {
+                let (first, last) = range.resolve(meta.size).ok_or_else(|| StorageError::InvalidRange(key.to_string()))?;
+                (first, last - first + 1, Some((first, last)))
+            }
+            None => (0, meta.size, None),
+        };
+
+        file.seek(SeekFrom::Start(start)).await.map_err(io_error)?;
+        let body = ReaderStream::new(file.take(length)).map(|chunk| chunk.map_err(io_error));
+
+        Ok(Object {
+            body: Box::pin(body),
+            size: meta.size,
+            range,
+            last_modified: meta.last_modified,
+        })
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectMeta, StorageError> {
+        let path = self.path(key)?;
+
+        let metadata = tokio::fs::metadata(&path).await.map_err(file_error(key))?;
+        if !metadata.is_file() {
+            return Err(StorageError::NotFound(key.to_string()));
+        }
+
+        Ok(self.meta(key, &metadata))
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        let path = self.path(key)?;
+
+        match tokio::fs::remove_file(&path).await {
+            Err(err) if err.kind() != ErrorKind::NotFound => Err(io_error(err)),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, StorageError> {
+        let mut objects = Vec::new();
+        let mut dirs = vec![(self.root.clone(), String::new())];
+
+        while let Some((dir, dir_key)) = dirs.pop() {
+            let mut entries = match tokio::fs::read_dir(&dir).await {
+                Ok(entries) => entries,
+                // nothing has been stored yet
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(io_error(err)),
+            };
+
+            while let Some(entry) = entries.next_entry().await.map_err(io_error)? {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let key = format!("{}{}", dir_key, name);
+                let metadata = entry.metadata().await.map_err(io_error)?;
+
+                if metadata.is_dir() {
+                    if !(dir_key.is_empty() && name == INTERNAL_DIR) {
+                        dirs.push((entry.path(), format!("{}/", key)));
+                    }
+                } else if key.starts_with(prefix) {
+                    objects.push(self.meta(&key, &metadata));
+                }
+            }
+        }
+
+        objects.sort_by(|a, b| a.key.cmp(&b.key));
+        Ok(objects)
+    }
+
+    async fn copy(&self, from_key: &str, to_key: &str) -> Result<(), StorageError> {
+        let from_path = self.path(from_key)?;
+        let to_path = self.path(to_key)?;
+
+        let (temp_path, _) = self.temp_file().await?;
+        if let Err(err) = tokio::fs::copy(&from_path, &temp_path).await {
+            tokio::fs::remove_file(&temp_path).await.ok();
+            return Err(file_error(from_key)(err));
+        }
+
+        self.move_in_place(&temp_path, &to_path).await
+    }
+
+    async fn presign_get(&self, key: &str, _expires_in: Duration) -> Result<String, StorageError> {
+        let path = self.path(key)?;
+
+        url::Url::from_file_path(&path)
+            .map(|url| url.to_string())
+            .map_err(|_| StorageError::Invalid(format!("Cannot create a URL for {}", path.display())))
+    }
+
+    async fn create_multipart(&self, key: &str) -> Result<String, StorageError> {
+        self.path(key)?;
+
+        let upload_id = uuid::Uuid::new_v4().to_string();
+        let dir = self.upload_dir(&upload_id)?;
+
+        tokio::fs::create_dir_all(&dir).await.map_err(io_error)?;
+        tokio::fs::write(dir.join("key"), key).await.map_err(io_error)?;
+
+        Ok(upload_id)
+    }
+
+    async fn upload_part(&self, key: &str, upload_id: &str, part_number: i32, data: Bytes) -> Result<CompletedPart, StorageError> {
+        let dir = self.upload_dir(upload_id)?;
+        let upload_key = tokio::fs::read_to_string(dir.join("key")).await
+            .map_err(file_error(&format!("Upload {}", upload_id)))?;
+        if upload_key != key {
+            return Err(StorageError::Invalid(format!("Upload {} is not for {}", upload_id, key)));
+        }
+        if part_number < 1 {
+            return Err(StorageError::Invalid(format!("Invalid part number {}", part_number)));
+        }
+
+        let part = CompletedPart {
+            part_number,
+            e_tag: uuid::Uuid::new_v4().simple().to_string(),
+        };
+
+        let (temp_path, mut file) = self.temp_file().await?;
+        file.write_all(&data).await.map_err(io_error)?;
+        file.sync_all().await.map_err(io_error)?;
+
+        // replaces the earlier upload of the part
+        let mut entries = tokio::fs::read_dir(&dir).await.map_err(io_error)?;
+        let earlier_prefix = format!("{:05}.", part_number);
+        while let Some(entry) = entries.next_entry().await.map_err(io_error)? {
+            if entry.file_name().to_string_lossy().starts_with(&earlier_prefix) {
+                tokio::fs::remove_file(entry.path()).await.map_err(io_error)?;
+            }
+        }
+
+        tokio::fs::rename(&temp_path, dir.join(part_file_name(&part))).await.map_err(io_error)?;
+        Ok(part)
+    }
+
+    async fn complete_multipart(&self, key: &str, upload_id: &str, parts: Vec<CompletedPart>) -> Result<CompletedUpload, StorageError> {
+        let path = self.path(key)?;
+        let dir = self.upload_dir(upload_id)?;
+
+        if !tokio::fs::try_exists(dir.join("key")).await.map_err(io_error)? {
+            return Err(StorageError::NotFound(format!("Upload {}", upload_id)));
+        }
+        if parts.is_empty() || parts.windows(2).any(|pair| pair[0].part_number >= pair[1].part_number) {
+            return Err(StorageError::Invalid("The parts must be listed in ascending order".to_string()));
+        }
+
+        for part in &parts {
+            if !tokio::fs::try_exists(dir.join(part_file_name(part))).await.map_err(io_error)? {
+                return Err(StorageError::Invalid(format!("Part {} of upload {} was not uploaded", part.part_number, upload_id)));
+            }
+        }
+
+        let (temp_path, mut file) = self.temp_file().await?;
+        for part in &parts {
+            let mut part_file = tokio::fs::File::open(dir.join(part_file_name(part))).await.map_err(io_error)?;
+            tokio::io::copy(&mut part_file, &mut file).await.map_err(io_error)?;
+        }
+        file.sync_all().await.map_err(io_error)?;
+
+        self.move_in_place(&temp_path, &path).await?;
+        tokio::fs::remove_dir_all(&dir).await.map_err(io_error)?;
+
+        Ok(CompletedUpload::default())
+    }
+
+    async fn abort_multipart(&self, _key: &str, upload_id: &str) -> Result<(), StorageError> {
+        let dir = self.upload_dir(upload_id)?;
+
+        tokio::fs::remove_dir_all(&dir).await.map_err(file_error(&format!("Upload {}", upload_id)))
+    }
+}
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use crate::{LocalStore, ObjectStore, S3Store};
+
+
+/// A prefix in a store, given as `s3://<bucket>/<prefix>` or `file://<directory>`, e.g. where the audit service
+/// archives its events
+#[derive(Debug, Clone)]
+pub struct Location {
+    pub store: Arc<dyn ObjectStore>,
+    /// Empty, or ends with `/`
+    pub prefix: String,
+}
+
+impl Location {
+    /// # Arguments
+    /// * `use_path_style_buckets` - See `S3Store::new`
+    pub fn parse(url: &str, use_path_style_buckets: bool) -> Result<Self, String> {
+        if let Some(location) = url.strip_prefix("s3://") {
+            let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+            if bucket.is_empty() {
+                return Err("the bucket is missing".to_string());
+            }
+
+            let prefix = prefix.trim_matches('/');
+            return Ok(Location {
+                store: Arc::new(S3Store::new(bucket.to_string(), use_path_style_buckets)),
+                prefix: if prefix.is_empty() { String::new() } else { format!("{}/", prefix) },
+            });
+        }
+
+        if url.starts_with("file://") {
+            let directory = url::Url::parse(url)
+                .ok()
+                .and_then(|url| url.to_file_path().ok())
+                .ok_or("expected file://<absolute path>")?;
+
+            return Ok(Location {
+                store: Arc::new(LocalStore::new(directory)),
+                prefix: String::new(),
+            });
+        }
+
+        Err("expected s3://<bucket>/<prefix> or file://<directory>".to_string())
+    }
+
+    /// The key of the named object in the location
+    pub fn key(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+}
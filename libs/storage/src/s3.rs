@@ -0,0 +1,296 @@
+use std::time::Duration;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::StreamExt;
+use tokio::sync::OnceCell;
+use tokio_util::io::ReaderStream;
+
+use aws_sdk_s3::Client;
+use aws_sdk_s3::config::http::HttpResponse;
+use aws_sdk_s3::error::{DisplayErrorContext, SdkError};
+use aws_sdk_s3::presigning::PresigningConfig;
+use aws_sdk_s3::primitives::{ByteStream as S3ByteStream, DateTime as S3DateTime};
+use aws_sdk_s3::types::{CompletedMultipartUpload, CompletedPart as S3CompletedPart};
+
+use chrono::{DateTime, Utc};
+
+use crate::{ByteRange, CompletedPart, CompletedUpload, Object, ObjectMeta, ObjectStore, StorageError};
+
+
+/// Stores the objects in an S3 bucket
+pub struct S3Store {
+    // loading the AWS config is async, so the client is created on the first use
+    client: OnceCell<Client>,
+    bucket: String,
+    use_path_style_buckets: bool,
+}
+
+impl S3Store {
+    /// # Arguments
+    /// * `use_path_style_buckets` - Addresses the bucket in the path instead of the host name, e.g. for localstack
+    pub fn new(bucket: String, use_path_style_buckets: bool) -> Self {
+        S3Store {
+            client: OnceCell::new(),
+            bucket,
+            use_path_style_buckets,
+        }
+    }
+
+    async fn client(&self) -> &Client {
+        self.client
+            .get_or_init(|| async {
+                let client = Client::new(&aws_config::load_from_env().await);
+
+                if self.use_path_style_buckets {
+                    tracing::info!("Using path-style buckets");
+                    let config_builder = client.config().clone().to_builder();
+                    Client::from_conf(config_builder.force_path_style(true).build())
+                } else {
+                    client
+                }
+            })
+            .await
+    }
+}
+
+impl std::fmt::Debug for S3Store {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("S3Store")
+            .field("bucket", &self.bucket)
+            .field("use_path_style_buckets", &self.use_path_style_buckets)
+            .finish()
+    }
+}
+
+fn s3_error<E>(key: &str, err: SdkError<E, HttpResponse>) -> StorageError
+where
+    E: std::error::Error + Send + Sync + 'static,
+{
+    match err.raw_response().map(|response| response.status().as_u16()) {
+        Some(404) => StorageError::NotFound(key.to_string()),
+        Some(416) => StorageError::InvalidRange(key.to_string()),
+        _ => StorageError::Backend(DisplayErrorContext(err).to_string()),
+    }
+}
+
+fn to_chrono(date_time: Option<&S3DateTime>) -> DateTime<Utc> {
+    date_time
+        .and_then(|date_time| DateTime::from_timestamp(date_time.secs(), date_time.subsec_nanos()))
+        .unwrap_or_default()
+}
+
+// the total size from a `bytes <first>-<last>/<size>` content range
+fn size_from_content_range(content_range: &str) -> Option<u64> {
+    content_range.rsplit_once('/').and_then(|(_, size)| size.parse().ok())
+}
+
+// the copy source is URL encoded, except for the separators
+fn encode_copy_source(bucket: &str, key: &str) -> String {
+    let mut encoded = format!("{}/", bucket);
+    for byte in key.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+#[async_trait]
+impl ObjectStore for S3Store {
+    async fn put(&self, key: &str, data: Bytes, content_type: Option<&str>) -> Result<(), StorageError> {
+        self.client().await
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .set_content_type(content_type.map(|content_type| content_type.to_string()))
+            .body(S3ByteStream::from(data))
+            .send()
+            .await
+            .map_err(|err| s3_error(key, err))?;
+
+        Ok(())
+    }
+
+    async fn get(&self, key: &str, range: Option<ByteRange>) -> Result<Object, StorageError> {
+        let output = self.client().await
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .set_range(range.map(|range| range.to_header()))
+            .send()
+            .await
+            .map_err(|err| s3_error(key, err))?;
+
+        let length = output.content_length.unwrap_or(0) as u64;
+        let size = output.content_range.as_deref().and_then(size_from_content_range).unwrap_or(length);
+        let last_modified = to_chrono(output.last_modified.as_ref());
+
+        // S3 ignores a range it cannot satisfy partially, and sends the whole object
+        let range = range.and_then(|range| range.resolve(size));
+
+        let key = key.to_string();
+        let body = ReaderStream::new(output.body.into_async_read())
+            .map(move |chunk| chunk.map_err(|err| StorageError::Backend(format!("Error reading {}: {}", key, err))));
+
+        Ok(Object {
+            body: Box::pin(body),
+            size,
+            range,
+            last_modified,
+        })
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectMeta, StorageError> {
+        let output = self.client().await
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|err| s3_error(key, err))?;
+
+        Ok(ObjectMeta {
+            key: key.to_string(),
+            size: output.content_length.unwrap_or(0) as u64,
+            last_modified: to_chrono(output.last_modified.as_ref()),
+        })
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        self.client().await
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|err| s3_error(key, err))?;
+
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str) -> Result<Vec<ObjectMeta>, StorageError> {
+        let mut objects = Vec::new();
+        let mut continuation_token = None;
+
+        loop {
+            let output = self.client().await
+                .list_objects_v2()
+                .bucket(&self.bucket)
+                .prefix(prefix)
+                .set_continuation_token(continuation_token)
+                .send()
+                .await
+                .map_err(|err| s3_error(prefix, err))?;
+
+            objects.extend(output.contents().iter().map(|object| ObjectMeta {
+                key: object.key().unwrap_or_default().to_string(),
+                size: object.size().unwrap_or(0) as u64,
+                last_modified: to_chrono(object.last_modified()),
+            }));
+
+            match output.next_continuation_token {
+                Some(token) if output.is_truncated == Some(true) => continuation_token = Some(token),
+                _ => break,
+            }
+        }
+
+        Ok(objects)
+    }
+
+    async fn copy(&self, from_key: &str, to_key: &str) -> Result<(), StorageError> {
+        self.client().await
+            .copy_object()
+            .bucket(&self.bucket)
+            .key(to_key)
+            .copy_source(encode_copy_source(&self.bucket, from_key))
+            .send()
+            .await
+            .map_err(|err| s3_error(from_key, err))?;
+
+        Ok(())
+    }
+
+    async fn presign_get(&self, key: &str, expires_in: Duration) -> Result<String, StorageError> {
+        let presigning_config = PresigningConfig::expires_in(expires_in)
+            .map_err(|err| StorageError::Invalid(err.to_string()))?;
+
+        let request = self.client().await
+            .get_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .presigned(presigning_config)
+            .await
+            .map_err(|err| s3_error(key, err))?;
+
+        Ok(request.uri().to_string())
+    }
+
+    async fn create_multipart(&self, key: &str) -> Result<String, StorageError> {
+        let output = self.client().await
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+            .map_err(|err| s3_error(key, err))?;
+
+        output.upload_id
+            .ok_or_else(|| StorageError::Backend(format!("No upload id returned for {}", key)))
+    }
+
+    async fn upload_part(&self, key: &str, upload_id: &str, part_number: i32, data: Bytes) -> Result<CompletedPart, StorageError> {
+        let output = self.client().await
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(S3ByteStream::from(data))
+            .send()
+            .await
+            .map_err(|err| s3_error(key, err))?;
+
+        Ok(CompletedPart {
+            part_number,
+            e_tag: output.e_tag.unwrap_or_default(),
+        })
+    }
+
+    async fn complete_multipart(&self, key: &str, upload_id: &str, parts: Vec<CompletedPart>) -> Result<CompletedUpload, StorageError> {
+        let parts = parts.into_iter()
+            .map(|part| S3CompletedPart::builder()
+                .part_number(part.part_number)
+                .e_tag(part.e_tag)
+                .build())
+            .collect();
+
+        let output = self.client().await
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+            .send()
+            .await
+            .map_err(|err| s3_error(key, err))?;
+
+        Ok(CompletedUpload {
+            checksum_crc32: output.checksum_crc32,
+        })
+    }
+
+    async fn abort_multipart(&self, key: &str, upload_id: &str) -> Result<(), StorageError> {
+        self.client().await
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await
+            .map_err(|err| s3_error(key, err))?;
+
+        Ok(())
+    }
+}
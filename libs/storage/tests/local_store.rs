@@ -0,0 +1,255 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::{StreamExt, stream};
+
+use storage::{ByteRange, ByteStream, LocalStore, Location, MIN_PART_SIZE, ObjectStore, StorageError};
+
+
+// a directory of its own per test, as the tests run in parallel
+fn store(name: &str) -> (LocalStore, PathBuf) {
+    let root = std::env::temp_dir().join(format!("storage-test-{}-{}", std::process::id(), name));
+    std::fs::remove_dir_all(&root).ok();
+    (LocalStore::new(&root), root)
+}
+
+async fn read_all(mut body: ByteStream<'_>) -> Vec<u8> {
+    let mut data = Vec::new();
+    while let Some(chunk) = body.next().await {
+        data.extend_from_slice(&chunk.expect("Failed to read chunk"));
+    }
+    data
+}
+
+
+#[tokio::test]
+async fn stores_and_reads_objects() {
+    let (store, root) = store("put");
+
+    store.put("resource/abc/master.m3u8", Bytes::from("#EXTM3U"), Some("application/vnd.apple.mpegurl")).await.unwrap();
+
+    let object = store.get("resource/abc/master.m3u8", None).await.unwrap();
+    assert_eq!(object.size, 7);
+    assert_eq!(object.range, None);
+    assert_eq!(object.bytes().await.unwrap(), "#EXTM3U");
+
+    let meta = store.head("resource/abc/master.m3u8").await.unwrap();
+    assert_eq!(meta.size, 7);
+    assert!(root.join("resource/abc/master.m3u8").is_file());
+
+    assert!(matches!(store.get("resource/abc/missing", None).await, Err(StorageError::NotFound(_))));
+    assert!(matches!(store.head("resource/abc").await, Err(StorageError::NotFound(_))));
+
+    std::fs::remove_dir_all(root).ok();
+}
+
+#[tokio::test]
+async fn reads_ranges() {
+    let (store, root) = store("range");
+    store.put("file", Bytes::from("0123456789"), None).await.unwrap();
+
+    let object = store.get("file", Some(ByteRange::Inclusive(2, 4))).await.unwrap();
+    assert_eq!((object.size, object.range), (10, Some((2, 4))));
+    assert_eq!(object.bytes().await.unwrap(), "234");
+
+    let object = store.get("file", Some(ByteRange::From(7))).await.unwrap();
+    assert_eq!(object.bytes().await.unwrap(), "789");
+
+    let object = store.get("file", Some(ByteRange::Suffix(3))).await.unwrap();
+    assert_eq!(object.range, Some((7, 9)));
+    assert_eq!(object.bytes().await.unwrap(), "789");
+
+    // the end is clamped to the object, like in S3
+    let object = store.get("file", Some(ByteRange::Inclusive(8, 100))).await.unwrap();
+    assert_eq!(object.bytes().await.unwrap(), "89");
+
+    assert!(matches!(store.get("file", Some(ByteRange::From(10))).await, Err(StorageError::InvalidRange(_))));
+
+    std::fs::remove_dir_all(root).ok();
+}
+
+#[test]
+fn parses_range_headers() {
+    assert_eq!(ByteRange::from_header("bytes=0-1023"), Some(ByteRange::Inclusive(0, 1023)));
+    assert_eq!(ByteRange::from_header("bytes=500-"), Some(ByteRange::From(500)));
+    assert_eq!(ByteRange::from_header("bytes=-500"), Some(ByteRange::Suffix(500)));
+    assert_eq!(ByteRange::from_header("bytes=5-1"), None);
+    assert_eq!(ByteRange::from_header("bytes=0-1,4-5"), None);
+    assert_eq!(ByteRange::from_header("items=0-1"), None);
+
+    assert_eq!(ByteRange::Suffix(500).resolve(100), Some((0, 99)));
+    assert_eq!(ByteRange::From(0).resolve(0), None);
+    assert_eq!(ByteRange::Inclusive(0, 1023).to_header(), "bytes=0-1023");
+}
+
+#[tokio::test]
+async fn lists_copies_and_deletes_objects() {
+    let (store, root) = store("list");
+    store.put("upload/b", Bytes::from("b"), None).await.unwrap();
+    store.put("upload/a", Bytes::from("a"), None).await.unwrap();
+    store.put("resource/a/thumbnail.jpg", Bytes::from("jpg"), None).await.unwrap();
+    // an unfinished upload is not listed
+    store.create_multipart("upload/c").await.unwrap();
+
+    let keys = |objects: Vec<storage::ObjectMeta>| objects.into_iter().map(|object| object.key).collect::<Vec<_>>();
+    assert_eq!(keys(store.list("upload/").await.unwrap()), vec!["upload/a", "upload/b"]);
+    assert_eq!(keys(store.list("").await.unwrap()), vec!["resource/a/thumbnail.jpg", "upload/a", "upload/b"]);
+
+    store.copy("upload/a", "resource/b/original").await.unwrap();
+    assert_eq!(store.get("resource/b/original", None).await.unwrap().bytes().await.unwrap(), "a");
+    assert!(matches!(store.copy("upload/missing", "upload/d").await, Err(StorageError::NotFound(_))));
+    assert_eq!(std::fs::read_dir(root.join(".storage/tmp")).unwrap().count(), 0);
+
+    store.delete("upload/a").await.unwrap();
+    // like S3, deleting a missing object is fine
+    store.delete("upload/a").await.unwrap();
+    assert_eq!(keys(store.list("upload/").await.unwrap()), vec!["upload/b"]);
+
+    std::fs::remove_dir_all(root).ok();
+}
+
+#[tokio::test]
+async fn rejects_keys_outside_of_the_root() {
+    let (store, root) = store("keys");
+
+    for key in ["", "/etc/passwd", "../outside", "upload/../../outside", ".storage/tmp/x", "upload//a"] {
+        assert!(matches!(store.put(key, Bytes::from("x"), None).await, Err(StorageError::Invalid(_))), "{}", key);
+    }
+
+    std::fs::remove_dir_all(root).ok();
+}
+
+#[tokio::test]
+async fn completes_multipart_uploads_over_several_calls() {
+    let (store, root) = store("multipart");
+
+    let upload_id = store.create_multipart("upload/video").await.unwrap();
+    let first = store.upload_part("upload/video", &upload_id, 1, Bytes::from("hello ")).await.unwrap();
+    let second = store.upload_part("upload/video", &upload_id, 2, Bytes::from("wrong")).await.unwrap();
+    // uploading a part again replaces it, and the earlier e-tag is no longer valid
+    let second_again = store.upload_part("upload/video", &upload_id, 2, Bytes::from("world")).await.unwrap();
+    assert_ne!(second.e_tag, second_again.e_tag);
+
+    assert!(matches!(
+        store.complete_multipart("upload/video", &upload_id, vec![first.clone(), second]).await,
+        Err(StorageError::Invalid(_))
+    ));
+    assert!(matches!(store.head("upload/video").await, Err(StorageError::NotFound(_))));
+
+    store.complete_multipart("upload/video", &upload_id, vec![first, second_again]).await.unwrap();
+    assert_eq!(store.get("upload/video", None).await.unwrap().bytes().await.unwrap(), "hello world");
+
+    // the upload is gone once completed
+    assert!(matches!(
+        store.upload_part("upload/video", &upload_id, 3, Bytes::from("!")).await,
+        Err(StorageError::NotFound(_))
+    ));
+
+    std::fs::remove_dir_all(root).ok();
+}
+
+#[tokio::test]
+async fn aborts_multipart_uploads() {
+    let (store, root) = store("abort");
+
+    let upload_id = store.create_multipart("upload/video").await.unwrap();
+    store.upload_part("upload/video", &upload_id, 1, Bytes::from("data")).await.unwrap();
+    store.abort_multipart("upload/video", &upload_id).await.unwrap();
+
+    assert!(matches!(store.abort_multipart("upload/video", &upload_id).await, Err(StorageError::NotFound(_))));
+    assert!(store.list("").await.unwrap().is_empty());
+
+    std::fs::remove_dir_all(root).ok();
+}
+
+#[tokio::test]
+async fn streams_large_objects_in_parts() {
+    let (store, root) = store("stream");
+
+    // two full parts and a partial one, in chunks that do not line up with the parts
+    let chunk = Bytes::from(vec![7u8; 1024 * 1024 + 1]);
+    let chunk_size = chunk.len();
+    let chunks = 2 * MIN_PART_SIZE / chunk_size + 1;
+    let body: ByteStream<'_> = Box::pin(stream::iter((0..chunks).map(move |_| Ok(chunk.clone()))));
+
+    let size = store.put_stream("upload/large", body).await.unwrap();
+    assert_eq!(size, (chunks * chunk_size) as u64);
+    assert_eq!(store.head("upload/large").await.unwrap().size, size);
+
+    // an empty stream is stored as an empty object
+    let size = store.put_stream("upload/empty", Box::pin(stream::empty())).await.unwrap();
+    assert_eq!(size, 0);
+    assert_eq!(store.head("upload/empty").await.unwrap().size, 0);
+
+    std::fs::remove_dir_all(root).ok();
+}
+
+#[tokio::test]
+async fn aborts_the_upload_when_the_stream_fails() {
+    let (store, root) = store("stream-error");
+
+    let body: ByteStream<'_> = Box::pin(stream::iter(vec![
+        Ok(Bytes::from("partial")),
+        Err(StorageError::Body("connection reset".to_string())),
+    ]));
+
+    assert!(matches!(store.put_stream("upload/broken", body).await, Err(StorageError::Body(_))));
+    assert!(matches!(store.head("upload/broken").await, Err(StorageError::NotFound(_))));
+    assert_eq!(std::fs::read_dir(root.join(".storage/uploads")).unwrap().count(), 0);
+
+    std::fs::remove_dir_all(root).ok();
+}
+
+#[tokio::test]
+async fn presigned_urls_can_be_opened() {
+    let (store, root) = store("presign");
+    store.put("upload/with space", Bytes::from("contents"), None).await.unwrap();
+
+    let url = store.presign_get("upload/with space", Duration::from_secs(60)).await.unwrap();
+    assert!(url.starts_with("file://"), "{}", url);
+
+    assert_eq!(read_all(storage::open_url(&url).await.unwrap()).await, b"contents");
+
+    let missing = store.presign_get("upload/missing", Duration::from_secs(60)).await.unwrap();
+    assert!(matches!(storage::open_url(&missing).await, Err(StorageError::NotFound(_))));
+
+    std::fs::remove_dir_all(root).ok();
+}
+
+#[test]
+fn parses_locations() {
+    let location = Location::parse("s3://videosite-audit/archive/", true).unwrap();
+    assert_eq!(location.prefix, "archive/");
+    assert_eq!(location.key("manifest.json"), "archive/manifest.json");
+    assert!(format!("{:?}", location.store).contains("videosite-audit"));
+
+    assert_eq!(Location::parse("s3://videosite-audit", false).unwrap().prefix, "");
+    assert_eq!(Location::parse("file:///var/lib/audit", false).unwrap().prefix, "");
+
+    assert!(Location::parse("s3:///archive", false).is_err());
+    assert!(Location::parse("/var/lib/audit", false).is_err());
+}
+
+#[test]
+fn selects_the_store_from_the_settings() {
+    let source = |variables: &[(&str, &str)]| {
+        config::Source::from_variables(variables.iter().map(|(name, value)| (name.to_string(), value.to_string())))
+            .expect("Failed to create source")
+    };
+
+    let store = config::load_from(source(&[("S3_BUCKET_NAME", "videos")]), storage::store_from_source).unwrap();
+    assert!(format!("{:?}", store).starts_with("S3Store"));
+
+    let store = config::load_from(
+        source(&[("STORAGE_BACKEND", "local"), ("STORAGE_LOCAL_ROOT", "/var/lib/videosite")]),
+        storage::store_from_source,
+    ).unwrap();
+    assert!(format!("{:?}", store).contains("/var/lib/videosite"));
+
+    let err = config::load_from(source(&[("STORAGE_BACKEND", "local")]), storage::store_from_source).unwrap_err();
+    assert_eq!(err.problems(), ["STORAGE_LOCAL_ROOT is not set"]);
+
+    let err = config::load_from(source(&[("STORAGE_BACKEND", "gcs")]), storage::store_from_source).unwrap_err();
+    assert_eq!(err.problems().len(), 2, "{}", err);
+}
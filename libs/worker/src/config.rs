@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use config::Source;
+use queue::QueueSettings;
+
+
+/// The queues of a worker and its configuration, read from the settings of a service, see `Worker::with_settings`
+#[derive(Debug, Clone)]
+pub struct WorkerSettings {
+    /// The queue the worker receives its messages from
+    pub queue: QueueSettings,
+    /// Where the messages go when they cannot be handled, dropped if not set
+    pub dead_letter_queue: Option<QueueSettings>,
+    pub config: WorkerConfig,
+}
+
+impl WorkerSettings {
+    /// # Arguments
+    /// * `queue_var` - The setting with the URL of the queue, e.g. "UPLOAD_QUEUE_URL"
+    /// * `dead_letter_queue_var` - The setting with the URL of the dead-letter queue, e.g. "UPLOAD_DLQ_URL"
+    pub fn from_source(source: &mut Source, queue_var: &str, dead_letter_queue_var: &str) -> Self {
+        WorkerSettings {
+            queue: QueueSettings::from_source(source, queue_var),
+            dead_letter_queue: QueueSettings::optional_from_source(source, dead_letter_queue_var),
+            config: WorkerConfig::from_source(source),
+        }
+    }
+}
+
+
+/// How a worker receives and retries its messages.
+#[derive(Debug, Clone)]
+pub struct WorkerConfig {
+    /// How many messages, or batches with `Worker::batched`, are handled at once
+    pub concurrency: usize,
+    /// How long a receive waits for messages to arrive
+    pub receive_wait: Duration,
+    /// How long the message of a running job is kept invisible. It is extended every half of it, for as long
+    /// as the job runs, so a crashed worker's messages are redelivered after at most this long.
+    pub visibility_timeout: Duration,
+    /// After this many failed receives the message is dead-lettered instead of retried
+    pub max_receives: u32,
+    /// Delay before the first retry, doubled for every further receive
+    pub retry_base_delay: Duration,
+    pub retry_max_delay: Duration,
+    /// How long the running jobs are waited for on shutdown, before their messages are released to the other workers
+    pub shutdown_timeout: Duration,
+}
+
+impl Default for WorkerConfig {
+    fn default() -> Self {
+        WorkerConfig {
+            concurrency: 1,
+            receive_wait: Duration::from_secs(20),
+            visibility_timeout: Duration::from_secs(60),
+            max_receives: 5,
+            retry_base_delay: Duration::from_secs(10),
+            retry_max_delay: Duration::from_secs(15 * 60),
+            shutdown_timeout: Duration::from_secs(20),
+        }
+    }
+}
+
+impl WorkerConfig {
+    /// Reads the `WORKER_*` settings, using the defaults for the ones left out: `WORKER_CONCURRENCY`,
+    /// `WORKER_VISIBILITY_TIMEOUT_SECONDS`, `WORKER_MAX_RECEIVES`, `WORKER_RETRY_BASE_DELAY_SECONDS`,
+    /// `WORKER_RETRY_MAX_DELAY_SECONDS` and `WORKER_SHUTDOWN_TIMEOUT_SECONDS`. Each must be a positive number.
+    pub fn from_source(source: &mut Source) -> Self {
+        let defaults = WorkerConfig::default();
+
+        WorkerConfig {
+            concurrency: positive(source, "WORKER_CONCURRENCY", defaults.concurrency as u64) as usize,
+            receive_wait: defaults.receive_wait,
+            visibility_timeout: seconds(source, "WORKER_VISIBILITY_TIMEOUT_SECONDS", defaults.visibility_timeout),
+            max_receives: positive(source, "WORKER_MAX_RECEIVES", defaults.max_receives as u64) as u32,
+            retry_base_delay: seconds(source, "WORKER_RETRY_BASE_DELAY_SECONDS", defaults.retry_base_delay),
+            retry_max_delay: seconds(source, "WORKER_RETRY_MAX_DELAY_SECONDS", defaults.retry_max_delay),
+            shutdown_timeout: seconds(source, "WORKER_SHUTDOWN_TIMEOUT_SECONDS", defaults.shutdown_timeout),
+        }
+    }
+
+    /// The delay before the message is received again, after its `receive_count`th receive failed
+    pub fn retry_delay(&self, receive_count: u32) -> Duration {
+        let exponent = receive_count.saturating_sub(1).min(16);
+        self.retry_base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.retry_max_delay)
+    }
+}
+
+fn positive(source: &mut Source, name: &str, default: u64) -> u64 {
+    source.parsed_with(name, default, |value| match value.parse::<u64>() {
+        Ok(number) if number > 0 => Ok(number),
+        _ => Err("must be a positive number".to_string()),
+    })
+}
+
+fn seconds(source: &mut Source, name: &str, default: Duration) -> Duration {
+    Duration::from_secs(positive(source, name, default.as_secs()))
+}
@@ -0,0 +1,33 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use queue::ReceivedMessage;
+
+
+/// What the worker sends to the dead-letter queue: the original message, and why it could not be handled.
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct DeadLetter {
+    /// The worker that gave up on the message
+    pub worker: String,
+    pub error: String,
+    pub receive_count: u32,
+    /// When the message was dead-lettered, in milliseconds since the epoch
+    pub dead_lettered_at: i64,
+    /// The original message body
+    pub body: String,
+}
+
+impl DeadLetter {
+    pub fn new(worker: &str, message: &ReceivedMessage, error: String) -> Self {
+        DeadLetter {
+            worker: worker.to_string(),
+            error,
+            receive_count: message.receive_count,
+            dead_lettered_at: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis() as i64)
+                .unwrap_or_default(),
+            body: message.body.clone(),
+        }
+    }
+}
+
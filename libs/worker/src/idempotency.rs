@@ -0,0 +1,66 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use async_trait::async_trait;
+
+use queue::ReceivedMessage;
+
+
+/// Remembers which messages each consumer has handled, so that a message delivered more than once is only
+/// handled once. The queues deliver at least once, and a message can also be sent again by a retried job.
+///
+/// The worker checks the store before handling a message and records the message once it has been handled.
+/// A message delivered again while the first delivery is still running is not caught, so the handlers still
+/// have to be safe to run twice.
+///
+/// A crash after the handler has stored its results, but before the message is recorded, also has the message
+/// handled again. So a handler that stores its results in the same database as the store should record the
+/// message in the same transaction, `mark_handled` then finds it recorded already.
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    /// # Returns
+    /// * The ids of the given messages that the consumer has already handled
+    async fn handled(&self, consumer: &str, message_ids: &[String]) -> Result<HashSet<String>, String>;
+
+    async fn mark_handled(&self, consumer: &str, message_ids: &[String]) -> Result<(), String>;
+}
+
+/// The id the messages are recognized by: the id `messages::encode` puts in the body, or the id the queue gave
+/// to the message for the bodies without one.
+pub fn idempotency_key(message: &ReceivedMessage) -> String {
+    messages::message_id(&message.body).unwrap_or_else(|| message.message_id.clone())
+}
+
+
+/// An in-process `IdempotencyStore`, for the tests and the single-process dev mode. It forgets everything on restart.
+#[derive(Default)]
+pub struct MemoryIdempotencyStore {
+    handled: Mutex<HashSet<(String, String)>>,
+}
+
+impl MemoryIdempotencyStore {
+    pub fn new() -> Self {
+        MemoryIdempotencyStore::default()
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for MemoryIdempotencyStore {
+    async fn handled(&self, consumer: &str, message_ids: &[String]) -> Result<HashSet<String>, String> {
+        let handled = self.handled.lock().unwrap();
+
+        Ok(message_ids.iter()
+            .filter(|message_id| handled.contains(&(consumer.to_string(), message_id.to_string())))
+            .cloned()
+            .collect())
+    }
+
+    async fn mark_handled(&self, consumer: &str, message_ids: &[String]) -> Result<(), String> {
+        let mut handled = self.handled.lock().unwrap();
+
+        for message_id in message_ids {
+            handled.insert((consumer.to_string(), message_id.clone()));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+mod config;
+mod dead_letter;
+mod idempotency;
+mod redrive;
+mod runtime;
+mod shutdown;
+
+use async_trait::async_trait;
+
+use queue::{QueueError, ReceivedMessage};
+
+pub use config::{WorkerConfig, WorkerSettings};
+pub use dead_letter::DeadLetter;
+pub use idempotency::{IdempotencyStore, MemoryIdempotencyStore, idempotency_key};
+pub use redrive::{DeadLetterQueue, StoredDeadLetter};
+pub use runtime::Worker;
+pub use shutdown::shutdown_token;
+pub use tokio_util::sync::CancellationToken;
+
+
+/// Why a job failed, which decides what happens to its message.
+#[derive(Debug)]
+pub enum JobError {
+    /// Worth retrying, e.g. a dependency could not be reached. The message is redelivered after a backoff,
+    /// until it has been received `max_receives` times.
+    Retryable(String),
+    /// Retrying will not help, e.g. the message cannot be parsed. The message is dead-lettered right away.
+    Permanent(String),
+}
+
+impl std::fmt::Display for JobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JobError::Retryable(err) => write!(f, "{}", err),
+            JobError::Permanent(err) => write!(f, "{} (not retryable)", err),
+        }
+    }
+}
+
+impl std::error::Error for JobError {}
+
+// the follow-up messages can be sent again on the next attempt
+impl From<QueueError> for JobError {
+    fn from(err: QueueError) -> Self {
+        JobError::Retryable(err.to_string())
+    }
+}
+
+
+/// Handles the messages one at a time. Up to `concurrency` messages are handled at once.
+#[async_trait]
+pub trait JobHandler: Send + Sync + 'static {
+    async fn handle(&self, message: &ReceivedMessage) -> Result<(), JobError>;
+}
+
+/// Handles all the messages of a receive together, e.g. to store them in a single transaction.
+/// Up to `concurrency` batches are handled at once.
+#[async_trait]
+pub trait BatchJobHandler: Send + Sync + 'static {
+    /// # Returns
+    /// * One result per message, in the same order as the messages
+    async fn handle_batch(&self, messages: &[ReceivedMessage]) -> Vec<Result<(), JobError>>;
+}
+
+// so that the caller can keep a reference to the handler
+#[async_trait]
+impl<T: JobHandler> JobHandler for std::sync::Arc<T> {
+    async fn handle(&self, message: &ReceivedMessage) -> Result<(), JobError> {
+        self.as_ref().handle(message).await
+    }
+}
+
+#[async_trait]
+impl<T: BatchJobHandler> BatchJobHandler for std::sync::Arc<T> {
+    async fn handle_batch(&self, messages: &[ReceivedMessage]) -> Vec<Result<(), JobError>> {
+        self.as_ref().handle_batch(messages).await
+    }
+}
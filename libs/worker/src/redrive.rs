@@ -0,0 +1,152 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use queue::{MessageQueue, QueueError, ReceivedMessage};
+
+use crate::DeadLetter;
+
+
+// long polling, so that SQS looks at all of its servers instead of a sample, and we do not miss messages
+const RECEIVE_WAIT: Duration = Duration::from_secs(1);
+
+// the maximum SQS allows
+const RECEIVE_BATCH_SIZE: usize = 10;
+
+
+/// A message in a dead-letter queue
+#[derive(Debug, Clone)]
+pub struct StoredDeadLetter {
+    pub message_id: String,
+    pub dead_letter: DeadLetter,
+}
+
+impl StoredDeadLetter {
+    /// Reads the `DeadLetter` sent by the worker. Messages that the queue itself moved to the dead-letter queue,
+    /// because of its redrive policy, are not wrapped, so the whole message is the original body.
+    pub fn from_message(message: &ReceivedMessage) -> Self {
+        let dead_letter = serde_json::from_str::<DeadLetter>(&message.body).unwrap_or_else(|_| DeadLetter {
+            worker: String::new(),
+            error: "Moved by the queue after too many receives, the worker probably crashed or timed out".to_string(),
+            receive_count: 0,
+            dead_lettered_at: message.sent_timestamp.unwrap_or_default(),
+            body: message.body.clone(),
+        });
+
+        StoredDeadLetter {
+            message_id: message.message_id.clone(),
+            dead_letter,
+        }
+    }
+}
+
+
+/// A dead-letter queue, and the queue its messages came from, for inspecting the messages that could not be
+/// handled and sending them back once the problem is fixed.
+pub struct DeadLetterQueue {
+    dead_letters: Arc<dyn MessageQueue>,
+    source: Arc<dyn MessageQueue>,
+}
+
+impl DeadLetterQueue {
+    pub fn new(dead_letters: Arc<dyn MessageQueue>, source: Arc<dyn MessageQueue>) -> Self {
+        DeadLetterQueue { dead_letters, source }
+    }
+
+    /// All the messages in the dead-letter queue, oldest first. They stay in the queue.
+    pub async fn list(&self) -> Result<Vec<StoredDeadLetter>, QueueError> {
+        let messages = self.receive_all().await?;
+        let dead_letters = messages.iter().map(StoredDeadLetter::from_message).collect();
+
+        self.release(&messages).await;
+        Ok(sorted(dead_letters))
+    }
+
+    /// Sends the messages with the given ids back to the source queue, and removes them from the dead-letter queue.
+    ///
+    /// # Returns
+    /// * The redriven messages
+    pub async fn redrive(&self, message_ids: &[String]) -> Result<Vec<StoredDeadLetter>, QueueError> {
+        self.take(|message| message_ids.contains(&message.message_id), true).await
+    }
+
+    /// Sends all the messages back to the source queue
+    pub async fn redrive_all(&self) -> Result<Vec<StoredDeadLetter>, QueueError> {
+        self.take(|_| true, true).await
+    }
+
+    /// Removes the messages with the given ids from the dead-letter queue, for messages that can never be handled
+    ///
+    /// # Returns
+    /// * The removed messages
+    pub async fn discard(&self, message_ids: &[String]) -> Result<Vec<StoredDeadLetter>, QueueError> {
+        self.take(|message| message_ids.contains(&message.message_id), false).await
+    }
+
+    async fn take(&self, selected: impl Fn(&ReceivedMessage) -> bool, redrive: bool) -> Result<Vec<StoredDeadLetter>, QueueError> {
+        let messages = self.receive_all().await?;
+        let (selected, others): (Vec<_>, Vec<_>) = messages.into_iter().partition(|message| selected(message));
+        self.release(&others).await;
+
+        let mut taken = vec![];
+        let mut result = Ok(());
+
+        for message in &selected {
+            let stored = StoredDeadLetter::from_message(message);
+
+            // sent before it is deleted, so that a failure leaves the message in the dead-letter queue
+            if redrive && let Err(err) = self.source.send(&stored.dead_letter.body).await {
+                self.release(std::slice::from_ref(message)).await;
+                result = result.and(Err(err));
+                continue;
+            }
+
+            match self.dead_letters.delete(&message.receipt_handle).await {
+                Ok(()) => taken.push(stored),
+                Err(err) => result = result.and(Err(err)),
+            }
+        }
+
+        result.map(|()| sorted(taken))
+    }
+
+    // receives until the queue is empty, the received messages stay invisible until they are released or deleted
+    async fn receive_all(&self) -> Result<Vec<ReceivedMessage>, QueueError> {
+        let mut messages: Vec<ReceivedMessage> = vec![];
+        let mut seen = HashSet::new();
+
+        loop {
+            let received = match self.dead_letters.receive(RECEIVE_BATCH_SIZE, RECEIVE_WAIT).await {
+                Ok(received) => received,
+                Err(err) => {
+                    self.release(&messages).await;
+                    return Err(err);
+                }
+            };
+
+            if received.is_empty() {
+                return Ok(messages);
+            }
+
+            for message in received {
+                // received again after its visibility timeout expired, the old receipt handle is no longer valid
+                if !seen.insert(message.message_id.clone()) {
+                    messages.retain(|other| other.message_id != message.message_id);
+                }
+                messages.push(message);
+            }
+        }
+    }
+
+    async fn release(&self, messages: &[ReceivedMessage]) {
+        for message in messages {
+            self.dead_letters.change_visibility(&message.receipt_handle, Duration::ZERO).await
+                .unwrap_or_else(|err| tracing::warn!("Failed to release dead letter {}: {}", message.message_id, err));
+        }
+    }
+}
+
+fn sorted(mut dead_letters: Vec<StoredDeadLetter>) -> Vec<StoredDeadLetter> {
+    dead_letters.sort_by_key(|stored| stored.dead_letter.dead_lettered_at);
+    dead_letters
+}
@@ -0,0 +1,338 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::task::{self, JoinError, JoinSet};
+use tokio_util::sync::CancellationToken;
+
+use queue::{MessageQueue, QueueSettings, ReceivedMessage};
+
+use crate::{BatchJobHandler, DeadLetter, IdempotencyStore, JobError, JobHandler, WorkerConfig, WorkerSettings, idempotency_key};
+
+
+// the maximum SQS allows
+const MAX_RECEIVE_BATCH_SIZE: usize = 10;
+
+// how long we wait after the queue could not be reached
+const RECEIVE_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+
+enum Handler {
+    Single(Arc<dyn JobHandler>),
+    Batch(Arc<dyn BatchJobHandler>),
+}
+
+/// Receives messages from a queue and hands them over to a handler, until it is shut down.
+///
+/// A message is deleted when its job succeeds. When the job fails, the message is made visible again after
+/// an exponential backoff, and after `max_receives` attempts, or right away for a `JobError::Permanent`, it is
+/// sent to the dead-letter queue.
+///
+/// ```ignore
+/// let shutdown = worker::shutdown_token();
+/// Worker::new("metadata", settings.worker.queue.open(), MetadataHandler)
+///     .with_settings(&settings.worker)
+///     .run(shutdown)
+///     .await;
+/// ```
+pub struct Worker {
+    name: String,
+    queue: Arc<dyn MessageQueue>,
+    dead_letter_queue: Option<Arc<dyn MessageQueue>>,
+    idempotency_store: Option<Arc<dyn IdempotencyStore>>,
+    handler: Handler,
+    config: WorkerConfig,
+}
+
+impl Worker {
+    /// A worker that handles the messages one by one, with the default `WorkerConfig`
+    pub fn new(name: &str, queue: Arc<dyn MessageQueue>, handler: impl JobHandler) -> Self {
+        Worker::with_handler(name, queue, Handler::Single(Arc::new(handler)))
+    }
+
+    /// A worker that handles the messages of each receive together
+    pub fn batched(name: &str, queue: Arc<dyn MessageQueue>, handler: impl BatchJobHandler) -> Self {
+        Worker::with_handler(name, queue, Handler::Batch(Arc::new(handler)))
+    }
+
+    fn with_handler(name: &str, queue: Arc<dyn MessageQueue>, handler: Handler) -> Self {
+        Worker {
+            name: name.to_string(),
+            queue,
+            dead_letter_queue: None,
+            idempotency_store: None,
+            handler,
+            config: WorkerConfig::default(),
+        }
+    }
+
+    pub fn with_config(mut self, config: WorkerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Uses the dead-letter queue and the configuration read from the settings of the service
+    pub fn with_settings(self, settings: &WorkerSettings) -> Self {
+        self.with_dead_letter_queue(settings.dead_letter_queue.as_ref().map(QueueSettings::open))
+            .with_config(settings.config.clone())
+    }
+
+    /// Where the messages go when they cannot be handled. Without one, they are logged and dropped.
+    pub fn with_dead_letter_queue(mut self, dead_letter_queue: Option<Arc<dyn MessageQueue>>) -> Self {
+        self.dead_letter_queue = dead_letter_queue;
+        self
+    }
+
+    /// Skips the messages the store says this worker has already handled, see `IdempotencyStore`.
+    /// The worker name identifies the consumer in the store.
+    pub fn with_idempotency_store(mut self, idempotency_store: Arc<dyn IdempotencyStore>) -> Self {
+        self.idempotency_store = Some(idempotency_store);
+        self
+    }
+
+    /// Handles the messages until `shutdown` is cancelled.
+    ///
+    /// On shutdown no more messages are received, and the running jobs are waited for up to `shutdown_timeout`.
+    /// The jobs still running after that are aborted, and their messages are made visible again right away.
+    pub async fn run(self, shutdown: CancellationToken) {
+        let worker = Arc::new(self);
+        let semaphore = Arc::new(Semaphore::new(worker.config.concurrency.max(1)));
+
+        let mut jobs = JoinSet::new();
+        // receipt handles of the messages of each running job
+        let mut running: HashMap<task::Id, Vec<String>> = HashMap::new();
+
+        tracing::info!("Worker {} started, handling up to {} jobs at once", worker.name, worker.config.concurrency);
+
+        loop {
+            while let Some(result) = jobs.try_join_next_with_id() {
+                worker.job_finished(&mut running, result);
+            }
+
+            // wait for a free slot before receiving, so that the received messages do not wait in memory
+            let permit = tokio::select! {
+                _ = shutdown.cancelled() => break,
+                permit = semaphore.clone().acquire_owned() => permit.expect("Worker semaphore closed"),
+            };
+
+            let max_messages = match worker.handler {
+                Handler::Single(_) => (1 + semaphore.available_permits()).min(MAX_RECEIVE_BATCH_SIZE),
+                Handler::Batch(_) => MAX_RECEIVE_BATCH_SIZE,
+            };
+
+            let received = tokio::select! {
+                _ = shutdown.cancelled() => break,
+                received = worker.queue.receive(max_messages, worker.config.receive_wait) => received,
+            };
+
+            let messages = match received {
+                Ok(messages) => messages,
+                Err(err) => {
+                    tracing::error!("Worker {} failed to receive messages: {}", worker.name, err);
+                    tokio::select! {
+                        _ = shutdown.cancelled() => break,
+                        _ = tokio::time::sleep(RECEIVE_RETRY_INTERVAL) => continue,
+                    }
+                }
+            };
+
+            if messages.is_empty() {
+                continue;
+            }
+
+            let batches = match worker.handler {
+                Handler::Single(_) => messages.into_iter().map(|message| vec![message]).collect(),
+                Handler::Batch(_) => vec![messages],
+            };
+
+            let mut permit = Some(permit);
+            for batch in batches {
+                // we received at most as many messages as there were free slots, so this does not wait
+                let permit = match permit.take() {
+                    Some(permit) => permit,
+                    None => semaphore.clone().acquire_owned().await.expect("Worker semaphore closed"),
+                };
+
+                let receipt_handles = batch.iter().map(|message| message.receipt_handle.clone()).collect();
+                let handle = jobs.spawn(worker.clone().process(batch, permit));
+                running.insert(handle.id(), receipt_handles);
+            }
+        }
+
+        tracing::info!("Worker {} shutting down, waiting for {} running jobs", worker.name, jobs.len());
+
+        let drained = tokio::time::timeout(worker.config.shutdown_timeout, async {
+            while let Some(result) = jobs.join_next_with_id().await {
+                worker.job_finished(&mut running, result);
+            }
+        }).await;
+
+        if drained.is_err() {
+            tracing::warn!("Worker {} aborting {} jobs that did not finish in time", worker.name, jobs.len());
+            jobs.abort_all();
+            while jobs.join_next().await.is_some() {}
+
+            // let the other workers pick them up, instead of waiting for the visibility timeout
+            for receipt_handle in running.values().flatten() {
+                worker.queue.change_visibility(receipt_handle, Duration::ZERO).await
+                    .unwrap_or_else(|err| tracing::error!("Failed to release message: {}", err));
+            }
+        }
+
+        tracing::info!("Worker {} stopped", worker.name);
+    }
+
+    fn job_finished(&self, running: &mut HashMap<task::Id, Vec<String>>, result: Result<(task::Id, ()), JoinError>) {
+        match result {
+            Ok((id, ())) => {
+                running.remove(&id);
+            }
+            Err(err) => {
+                running.remove(&err.id());
+                if err.is_panic() {
+                    tracing::error!("Worker {} job panicked, its messages are redelivered after the visibility timeout", self.name);
+                }
+            }
+        }
+    }
+
+    async fn process(self: Arc<Self>, messages: Vec<ReceivedMessage>, _permit: OwnedSemaphorePermit) {
+        let messages = self.skip_handled(messages).await;
+        if messages.is_empty() {
+            return;
+        }
+
+        let results = tokio::select! {
+            results = self.handle(&messages) => results,
+            _ = self.keep_invisible(&messages) => unreachable!("The visibility heartbeat never finishes"),
+        };
+
+        let mut done = vec![];
+        let mut handled = vec![];
+        for (message, result) in messages.into_iter().zip(results) {
+            match result {
+                Ok(()) => {
+                    handled.push(idempotency_key(&message));
+                    done.push(message.receipt_handle);
+                }
+                Err(JobError::Retryable(err)) if message.receive_count < self.config.max_receives => {
+                    let delay = self.config.retry_delay(message.receive_count);
+                    tracing::warn!(
+                        "Worker {} job failed on receive {}, retrying in {:?}: {}",
+                        self.name, message.receive_count, delay, err
+                    );
+                    self.queue.change_visibility(&message.receipt_handle, delay).await
+                        .unwrap_or_else(|err| tracing::error!("Failed to delay the retry: {}", err));
+                }
+                Err(err) => {
+                    if self.dead_letter(&message, &err).await {
+                        done.push(message.receipt_handle);
+                    } else {
+                        self.queue.change_visibility(&message.receipt_handle, self.config.retry_delay(message.receive_count)).await
+                            .unwrap_or_else(|err| tracing::error!("Failed to delay the retry: {}", err));
+                    }
+                }
+            }
+        }
+
+        // recorded before the messages are deleted, so that a message is never deleted without being recorded
+        if let Some(idempotency_store) = &self.idempotency_store
+            && !handled.is_empty() {
+            idempotency_store.mark_handled(&self.name, &handled).await
+                .unwrap_or_else(|err| tracing::error!("Worker {} failed to record the handled messages: {}", self.name, err));
+        }
+
+        if !done.is_empty() {
+            self.queue.delete_batch(&done).await
+                .unwrap_or_else(|err| tracing::error!("Worker {} failed to delete messages: {}", self.name, err));
+        }
+    }
+
+    // deletes the messages that have already been handled, or that are in the same batch twice, and returns the rest
+    async fn skip_handled(&self, messages: Vec<ReceivedMessage>) -> Vec<ReceivedMessage> {
+        let Some(idempotency_store) = &self.idempotency_store else {
+            return messages;
+        };
+
+        let keys: Vec<String> = messages.iter().map(idempotency_key).collect();
+        let mut seen: HashSet<String> = match idempotency_store.handled(&self.name, &keys).await {
+            Ok(handled) => handled,
+            Err(err) => {
+                // the handlers are safe to run twice, so it is better to go on than to stop handling messages
+                tracing::warn!("Worker {} failed to check for already handled messages, handling them anyway: {}", self.name, err);
+                HashSet::new()
+            }
+        };
+
+        let mut duplicates = vec![];
+        let mut remaining = vec![];
+        for (message, key) in messages.into_iter().zip(keys) {
+            if seen.insert(key) {
+                remaining.push(message);
+            } else {
+                duplicates.push(message.receipt_handle);
+            }
+        }
+
+        if !duplicates.is_empty() {
+            tracing::info!("Worker {} skipping {} messages that were already handled", self.name, duplicates.len());
+            self.queue.delete_batch(&duplicates).await
+                .unwrap_or_else(|err| tracing::error!("Worker {} failed to delete messages: {}", self.name, err));
+        }
+
+        remaining
+    }
+
+    async fn handle(&self, messages: &[ReceivedMessage]) -> Vec<Result<(), JobError>> {
+        let mut results = match &self.handler {
+            Handler::Single(handler) => vec![handler.handle(&messages[0]).await],
+            Handler::Batch(handler) => handler.handle_batch(messages).await,
+        };
+
+        // a handler returning too few results is a bug, but the messages should not be lost because of it
+        while results.len() < messages.len() {
+            results.push(Err(JobError::Retryable("The handler returned no result".to_string())));
+        }
+        results
+    }
+
+    // extends the visibility timeout of the messages for as long as the job runs
+    async fn keep_invisible(&self, messages: &[ReceivedMessage]) {
+        let mut interval = tokio::time::interval(self.config.visibility_timeout / 2);
+
+        loop {
+            interval.tick().await;
+            for message in messages {
+                self.queue.change_visibility(&message.receipt_handle, self.config.visibility_timeout).await
+                    .unwrap_or_else(|err| tracing::warn!("Worker {} failed to extend the visibility timeout: {}", self.name, err));
+            }
+        }
+    }
+
+    // # Returns
+    // * `true` if the message can be deleted from the queue
+    async fn dead_letter(&self, message: &ReceivedMessage, err: &JobError) -> bool {
+        let Some(dead_letter_queue) = &self.dead_letter_queue else {
+            tracing::error!(
+                "Worker {} dropping message after {} receives, no dead-letter queue configured: {} (message: {})",
+                self.name, message.receive_count, err, message.body
+            );
+            return true;
+        };
+
+        let dead_letter = DeadLetter::new(&self.name, message, err.to_string());
+        let body = serde_json::to_string(&dead_letter).expect("Failed to serialize dead letter");
+
+        match dead_letter_queue.send(&body).await {
+            Ok(()) => {
+                tracing::error!("Worker {} dead-lettered message after {} receives: {}", self.name, message.receive_count, err);
+                true
+            }
+            Err(send_err) => {
+                tracing::error!("Worker {} failed to dead-letter message, keeping it in the queue: {}", self.name, send_err);
+                false
+            }
+        }
+    }
+}
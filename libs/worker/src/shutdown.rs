@@ -0,0 +1,28 @@
+use tokio::signal::unix::{SignalKind, signal};
+use tokio_util::sync::CancellationToken;
+
+
+/// A token that is cancelled when the process receives SIGTERM or SIGINT.
+///
+/// Pass it to `Worker::run`, and to `axum::serve(..).with_graceful_shutdown(..)`, so that everything
+/// in the service stops together.
+///
+/// # Panics
+/// * If called outside of a Tokio runtime
+pub fn shutdown_token() -> CancellationToken {
+    let token = CancellationToken::new();
+
+    let cancel = token.clone();
+    tokio::spawn(async move {
+        let mut sigterm = signal(SignalKind::terminate()).expect("Failed to install the SIGTERM handler");
+
+        tokio::select! {
+            _ = sigterm.recv() => tracing::info!("Received SIGTERM, shutting down"),
+            _ = tokio::signal::ctrl_c() => tracing::info!("Received SIGINT, shutting down"),
+        }
+
+        cancel.cancel();
+    });
+
+    token
+}
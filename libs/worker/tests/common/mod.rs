@@ -0,0 +1,31 @@
+use std::time::Duration;
+
+use worker::{CancellationToken, Worker, WorkerConfig};
+
+
+/// Short timeouts, so that the retries and redeliveries happen within the tests
+pub fn test_config(concurrency: usize) -> WorkerConfig {
+    WorkerConfig {
+        concurrency,
+        receive_wait: Duration::from_millis(20),
+        visibility_timeout: Duration::from_millis(100),
+        max_receives: 3,
+        retry_base_delay: Duration::from_millis(10),
+        retry_max_delay: Duration::from_millis(50),
+        shutdown_timeout: Duration::from_secs(5),
+    }
+}
+
+pub async fn wait_until(condition: impl Fn() -> bool) {
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while !condition() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.expect("Condition not met in time");
+}
+
+pub fn start(worker: Worker) -> (CancellationToken, tokio::task::JoinHandle<()>) {
+    let shutdown = CancellationToken::new();
+    let handle = tokio::spawn(worker.run(shutdown.clone()));
+    (shutdown, handle)
+}
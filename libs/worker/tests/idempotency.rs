@@ -0,0 +1,165 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use messages::ResourceStatusUpdate;
+use queue::{MemoryQueue, MessageQueue, ReceivedMessage};
+use worker::{BatchJobHandler, IdempotencyStore, JobError, JobHandler, MemoryIdempotencyStore, Worker, idempotency_key};
+
+mod common;
+
+use common::{start, test_config, wait_until};
+
+
+fn status_update(object_name: &str) -> String {
+    messages::encode(&ResourceStatusUpdate::Processing { object_name: object_name.to_string(), revision: 0 })
+}
+
+/// Counts how many times each message body was handled. The bodies starting with "retry" fail on their first attempt.
+#[derive(Default)]
+struct CountingHandler {
+    calls: Mutex<HashMap<String, usize>>,
+}
+
+impl CountingHandler {
+    fn calls(&self) -> HashMap<String, usize> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    fn count(&self, message: &ReceivedMessage) -> usize {
+        let mut calls = self.calls.lock().unwrap();
+        let count = calls.entry(message.body.clone()).or_default();
+        *count += 1;
+        *count
+    }
+}
+
+#[async_trait]
+impl JobHandler for CountingHandler {
+    async fn handle(&self, message: &ReceivedMessage) -> Result<(), JobError> {
+        let count = self.count(message);
+
+        if message.body.starts_with("retry") && count == 1 {
+            return Err(JobError::Retryable("try again".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BatchJobHandler for CountingHandler {
+    async fn handle_batch(&self, messages: &[ReceivedMessage]) -> Vec<Result<(), JobError>> {
+        messages.iter().map(|message| {
+            self.count(message);
+            Ok(())
+        }).collect()
+    }
+}
+
+
+
+#[tokio::test]
+async fn messages_delivered_twice_are_handled_once() {
+    let queue = Arc::new(MemoryQueue::new());
+    let handler = Arc::new(CountingHandler::default());
+    let bodies: Vec<String> = (0..5).map(|i| status_update(&format!("object {}", i))).collect();
+
+    for body in bodies.iter().chain(bodies.iter()) {
+        queue.send(body).await.unwrap();
+    }
+
+    let (shutdown, handle) = start(
+        Worker::new("test", queue.clone(), handler.clone())
+            .with_config(test_config(1))
+            .with_idempotency_store(Arc::new(MemoryIdempotencyStore::new()))
+    );
+
+    wait_until(|| queue.pending().is_empty()).await;
+    assert_eq!(handler.calls().len(), 5);
+    assert!(handler.calls().values().all(|count| *count == 1));
+
+    shutdown.cancel();
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn duplicates_in_the_same_batch_are_handled_once() {
+    let queue = Arc::new(MemoryQueue::new());
+    let handler = Arc::new(CountingHandler::default());
+    let bodies: Vec<String> = (0..4).map(|i| status_update(&format!("object {}", i))).collect();
+
+    for body in bodies.iter().chain(bodies.iter()) {
+        queue.send(body).await.unwrap();
+    }
+
+    let (shutdown, handle) = start(
+        Worker::batched("test", queue.clone(), handler.clone())
+            .with_config(test_config(1))
+            .with_idempotency_store(Arc::new(MemoryIdempotencyStore::new()))
+    );
+
+    wait_until(|| queue.pending().is_empty()).await;
+    assert_eq!(handler.calls().len(), 4);
+    assert!(handler.calls().values().all(|count| *count == 1));
+
+    shutdown.cancel();
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn messages_handled_before_a_restart_are_skipped() {
+    let queue = Arc::new(MemoryQueue::new());
+    let handler = Arc::new(CountingHandler::default());
+    let store = Arc::new(MemoryIdempotencyStore::new());
+
+    // e.g. the worker crashed after handling the message, but before deleting it
+    queue.send("handled before").await.unwrap();
+    let message = queue.receive(1, Duration::ZERO).await.unwrap().remove(0);
+    store.mark_handled("test", &[idempotency_key(&message)]).await.unwrap();
+    queue.change_visibility(&message.receipt_handle, Duration::ZERO).await.unwrap();
+
+    let (shutdown, handle) = start(
+        Worker::new("test", queue.clone(), handler.clone())
+            .with_config(test_config(1))
+            .with_idempotency_store(store)
+    );
+
+    wait_until(|| queue.pending().is_empty()).await;
+    assert!(handler.calls().is_empty());
+
+    shutdown.cancel();
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn failed_messages_are_not_recorded_as_handled() {
+    let queue = Arc::new(MemoryQueue::new());
+    let handler = Arc::new(CountingHandler::default());
+
+    queue.send("retry me").await.unwrap();
+
+    let (shutdown, handle) = start(
+        Worker::new("test", queue.clone(), handler.clone())
+            .with_config(test_config(1))
+            .with_idempotency_store(Arc::new(MemoryIdempotencyStore::new()))
+    );
+
+    wait_until(|| queue.pending().is_empty()).await;
+    assert_eq!(handler.calls()["retry me"], 2);
+
+    shutdown.cancel();
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn the_store_is_kept_per_consumer() {
+    let store = MemoryIdempotencyStore::new();
+    let ids = vec!["a".to_string(), "b".to_string()];
+
+    store.mark_handled("metadata", &ids[..1]).await.unwrap();
+
+    assert_eq!(store.handled("metadata", &ids).await.unwrap().len(), 1);
+    assert!(store.handled("resource-status", &ids).await.unwrap().is_empty());
+}
@@ -0,0 +1,115 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use queue::{MemoryQueue, MessageQueue, ReceivedMessage};
+use worker::{DeadLetter, DeadLetterQueue};
+
+
+fn dead_letter(body: &str, dead_lettered_at: i64) -> String {
+    serde_json::to_string(&DeadLetter {
+        worker: "metadata".to_string(),
+        error: format!("failed to handle {}", body),
+        receive_count: 5,
+        dead_lettered_at,
+        body: body.to_string(),
+    }).unwrap()
+}
+
+async fn receive_one(queue: &MemoryQueue) -> ReceivedMessage {
+    queue.receive(1, Duration::ZERO).await.unwrap().remove(0)
+}
+
+
+#[tokio::test]
+async fn listing_leaves_the_dead_letters_in_the_queue() {
+    let dead_letters = Arc::new(MemoryQueue::new());
+    let source = Arc::new(MemoryQueue::new());
+
+    dead_letters.send(&dead_letter("second", 2000)).await.unwrap();
+    dead_letters.send(&dead_letter("first", 1000)).await.unwrap();
+
+    let dead_letter_queue = DeadLetterQueue::new(dead_letters.clone(), source.clone());
+    let listed = dead_letter_queue.list().await.unwrap();
+
+    let bodies: Vec<_> = listed.iter().map(|stored| stored.dead_letter.body.as_str()).collect();
+    assert_eq!(bodies, vec!["first", "second"]);
+    assert_eq!(listed[0].dead_letter.error, "failed to handle first");
+    assert_eq!(listed[0].dead_letter.worker, "metadata");
+
+    // still there, and visible right away
+    assert_eq!(dead_letters.receive(10, Duration::ZERO).await.unwrap().len(), 2);
+    assert!(source.pending().is_empty());
+}
+
+#[tokio::test]
+async fn messages_moved_by_the_queue_are_listed_with_their_raw_body() {
+    let dead_letters = Arc::new(MemoryQueue::new());
+    let source = Arc::new(MemoryQueue::new());
+
+    dead_letters.send("{\"resource_id\": 1}").await.unwrap();
+
+    let listed = DeadLetterQueue::new(dead_letters, source).list().await.unwrap();
+    assert_eq!(listed.len(), 1);
+    assert_eq!(listed[0].dead_letter.body, "{\"resource_id\": 1}");
+    assert_eq!(listed[0].dead_letter.worker, "");
+}
+
+#[tokio::test]
+async fn redriving_sends_the_selected_original_bodies_back() {
+    let dead_letters = Arc::new(MemoryQueue::new());
+    let source = Arc::new(MemoryQueue::new());
+
+    dead_letters.send(&dead_letter("fixed", 1000)).await.unwrap();
+    dead_letters.send(&dead_letter("still broken", 2000)).await.unwrap();
+    dead_letters.send("raw body").await.unwrap();
+
+    let dead_letter_queue = DeadLetterQueue::new(dead_letters.clone(), source.clone());
+    let listed = dead_letter_queue.list().await.unwrap();
+    let selected: Vec<String> = listed.iter()
+        .filter(|stored| stored.dead_letter.body != "still broken")
+        .map(|stored| stored.message_id.clone())
+        .collect();
+
+    let redriven = dead_letter_queue.redrive(&selected).await.unwrap();
+    assert_eq!(redriven.len(), 2);
+
+    let mut bodies = source.pending();
+    bodies.sort();
+    assert_eq!(bodies, vec!["fixed", "raw body"]);
+
+    let remaining = receive_one(&dead_letters).await;
+    let remaining: DeadLetter = serde_json::from_str(&remaining.body).unwrap();
+    assert_eq!(remaining.body, "still broken");
+    assert_eq!(dead_letters.pending().len(), 1);
+}
+
+#[tokio::test]
+async fn redriving_everything_empties_the_dead_letter_queue() {
+    let dead_letters = Arc::new(MemoryQueue::new());
+    let source = Arc::new(MemoryQueue::new());
+
+    for i in 0..25 {
+        dead_letters.send(&dead_letter(&format!("message {}", i), i)).await.unwrap();
+    }
+
+    let redriven = DeadLetterQueue::new(dead_letters.clone(), source.clone()).redrive_all().await.unwrap();
+    assert_eq!(redriven.len(), 25);
+    assert!(dead_letters.pending().is_empty());
+    assert_eq!(source.pending().len(), 25);
+}
+
+#[tokio::test]
+async fn discarding_removes_the_messages_without_redriving_them() {
+    let dead_letters = Arc::new(MemoryQueue::new());
+    let source = Arc::new(MemoryQueue::new());
+
+    dead_letters.send(&dead_letter("hopeless", 1000)).await.unwrap();
+
+    let dead_letter_queue = DeadLetterQueue::new(dead_letters.clone(), source.clone());
+    let message_id = dead_letter_queue.list().await.unwrap()[0].message_id.clone();
+
+    let discarded = dead_letter_queue.discard(&[message_id]).await.unwrap();
+    assert_eq!(discarded.len(), 1);
+    assert!(dead_letters.pending().is_empty());
+    assert!(source.pending().is_empty());
+}
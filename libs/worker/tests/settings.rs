@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+use config::Source;
+use queue::QueueSettings;
+use worker::{WorkerConfig, WorkerSettings};
+
+
+fn load(variables: &[(&str, &str)]) -> Result<WorkerSettings, Vec<String>> {
+    let source = Source::from_variables(variables.iter().map(|(name, value)| (name.to_string(), value.to_string()))).unwrap();
+    config::load_from(source, |source| WorkerSettings::from_source(source, "UPLOAD_QUEUE_URL", "UPLOAD_DLQ_URL"))
+        .map_err(|err| err.problems().to_vec())
+}
+
+
+#[test]
+fn reads_the_queues_and_the_configuration() {
+    let settings = load(&[
+        ("UPLOAD_QUEUE_URL", "http://localstack:4566/000000000000/upload"),
+        ("WORKER_CONCURRENCY", "4"),
+        ("WORKER_RETRY_BASE_DELAY_SECONDS", "2"),
+    ]).unwrap();
+
+    assert_eq!(settings.queue, QueueSettings::Sqs { queue_url: "http://localstack:4566/000000000000/upload".to_string() });
+    assert_eq!(settings.dead_letter_queue, None);
+    assert_eq!(settings.config.concurrency, 4);
+    assert_eq!(settings.config.retry_base_delay, Duration::from_secs(2));
+    assert_eq!(settings.config.max_receives, WorkerConfig::default().max_receives);
+}
+
+#[test]
+fn reports_the_numbers_that_are_not_positive() {
+    let problems = load(&[
+        ("UPLOAD_QUEUE_URL", "http://localstack:4566/000000000000/upload"),
+        ("WORKER_CONCURRENCY", "0"),
+        ("WORKER_MAX_RECEIVES", "many"),
+    ]).unwrap_err();
+
+    assert_eq!(problems.len(), 2);
+    assert!(problems.iter().all(|problem| problem.ends_with("must be a positive number")), "{:?}", problems);
+}
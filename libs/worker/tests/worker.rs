@@ -0,0 +1,205 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use async_trait::async_trait;
+
+use queue::{MemoryQueue, MessageQueue, ReceivedMessage};
+use worker::{BatchJobHandler, DeadLetter, JobError, JobHandler, Worker, WorkerConfig};
+
+mod common;
+
+use common::{start, test_config, wait_until};
+
+
+/// Fails the messages whose body starts with "retry" or "permanent", and counts the calls
+#[derive(Default)]
+struct TestHandler {
+    calls: AtomicUsize,
+    delay: Duration,
+}
+
+#[async_trait]
+impl JobHandler for TestHandler {
+    async fn handle(&self, message: &ReceivedMessage) -> Result<(), JobError> {
+        self.calls.fetch_add(1, Ordering::SeqCst);
+        tokio::time::sleep(self.delay).await;
+
+        if message.body.starts_with("retry") {
+            Err(JobError::Retryable("try again".to_string()))
+        } else if message.body.starts_with("permanent") {
+            Err(JobError::Permanent("invalid message".to_string()))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+
+
+#[tokio::test]
+async fn handled_messages_are_deleted() {
+    let queue = Arc::new(MemoryQueue::new());
+    let handler = Arc::new(TestHandler::default());
+
+    for i in 0..10 {
+        queue.send(&format!("message {}", i)).await.unwrap();
+    }
+
+    let (shutdown, handle) = start(Worker::new("test", queue.clone(), handler.clone()).with_config(test_config(4)));
+
+    wait_until(|| queue.pending().is_empty()).await;
+    assert_eq!(handler.calls.load(Ordering::SeqCst), 10);
+
+    shutdown.cancel();
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn retryable_failures_are_dead_lettered_after_max_receives() {
+    let queue = Arc::new(MemoryQueue::new());
+    let dead_letter_queue = Arc::new(MemoryQueue::new());
+    let handler = Arc::new(TestHandler::default());
+
+    queue.send("retry me").await.unwrap();
+
+    let (shutdown, handle) = start(
+        Worker::new("test", queue.clone(), handler.clone())
+            .with_config(test_config(4))
+            .with_dead_letter_queue(Some(dead_letter_queue.clone()))
+    );
+
+    wait_until(|| queue.pending().is_empty()).await;
+    assert_eq!(handler.calls.load(Ordering::SeqCst), 3);
+
+    let dead_letters = dead_letter_queue.pending();
+    assert_eq!(dead_letters.len(), 1);
+    let dead_letter: DeadLetter = serde_json::from_str(&dead_letters[0]).unwrap();
+    assert_eq!(dead_letter.body, "retry me");
+    assert_eq!(dead_letter.receive_count, 3);
+    assert_eq!(dead_letter.worker, "test");
+    assert_eq!(dead_letter.error, "try again");
+
+    shutdown.cancel();
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn permanent_failures_are_dead_lettered_right_away() {
+    let queue = Arc::new(MemoryQueue::new());
+    let dead_letter_queue = Arc::new(MemoryQueue::new());
+    let handler = Arc::new(TestHandler::default());
+
+    queue.send("permanent failure").await.unwrap();
+
+    let (shutdown, handle) = start(
+        Worker::new("test", queue.clone(), handler.clone())
+            .with_config(test_config(4))
+            .with_dead_letter_queue(Some(dead_letter_queue.clone()))
+    );
+
+    wait_until(|| !dead_letter_queue.pending().is_empty()).await;
+    assert!(queue.pending().is_empty());
+    assert_eq!(handler.calls.load(Ordering::SeqCst), 1);
+
+    shutdown.cancel();
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn long_jobs_keep_their_messages_invisible() {
+    // the queue would redeliver the message after 50ms, if the worker did not extend it
+    let queue = Arc::new(MemoryQueue::with_visibility_timeout(Duration::from_millis(50)));
+    let handler = Arc::new(TestHandler { delay: Duration::from_millis(400), ..Default::default() });
+
+    queue.send("slow").await.unwrap();
+
+    let (shutdown, handle) = start(Worker::new("test", queue.clone(), handler.clone()).with_config(test_config(4)));
+
+    wait_until(|| queue.pending().is_empty()).await;
+    assert_eq!(handler.calls.load(Ordering::SeqCst), 1);
+
+    shutdown.cancel();
+    handle.await.unwrap();
+}
+
+#[tokio::test]
+async fn shutdown_waits_for_running_jobs() {
+    let queue = Arc::new(MemoryQueue::new());
+    let handler = Arc::new(TestHandler { delay: Duration::from_millis(200), ..Default::default() });
+
+    queue.send("slow").await.unwrap();
+
+    let (shutdown, handle) = start(Worker::new("test", queue.clone(), handler.clone()).with_config(test_config(4)));
+
+    wait_until(|| handler.calls.load(Ordering::SeqCst) == 1).await;
+    shutdown.cancel();
+    handle.await.unwrap();
+
+    // the job finished and its message was deleted, even though the shutdown started while it was running
+    assert!(queue.pending().is_empty());
+}
+
+#[tokio::test]
+async fn shutdown_releases_the_messages_of_unfinished_jobs() {
+    let queue = Arc::new(MemoryQueue::new());
+    let handler = Arc::new(TestHandler { delay: Duration::from_secs(60), ..Default::default() });
+
+    queue.send("very slow").await.unwrap();
+
+    let config = WorkerConfig { shutdown_timeout: Duration::from_millis(50), ..test_config(4) };
+    let (shutdown, handle) = start(Worker::new("test", queue.clone(), handler.clone()).with_config(config));
+
+    wait_until(|| handler.calls.load(Ordering::SeqCst) == 1).await;
+    shutdown.cancel();
+    handle.await.unwrap();
+
+    // available to the other workers right away
+    let received = queue.receive(1, Duration::ZERO).await.unwrap();
+    assert_eq!(received[0].body, "very slow");
+}
+
+struct CountingBatchHandler {
+    batch_sizes: std::sync::Mutex<Vec<usize>>,
+}
+
+#[async_trait]
+impl BatchJobHandler for CountingBatchHandler {
+    async fn handle_batch(&self, messages: &[ReceivedMessage]) -> Vec<Result<(), JobError>> {
+        self.batch_sizes.lock().unwrap().push(messages.len());
+        messages.iter().map(|_| Ok(())).collect()
+    }
+}
+
+#[tokio::test]
+async fn batched_workers_handle_the_received_messages_together() {
+    let queue = Arc::new(MemoryQueue::new());
+    let handler = Arc::new(CountingBatchHandler { batch_sizes: std::sync::Mutex::new(vec![]) });
+
+    for i in 0..15 {
+        queue.send(&format!("message {}", i)).await.unwrap();
+    }
+
+    let (shutdown, handle) = start(Worker::batched("test", queue.clone(), handler.clone()).with_config(test_config(4)));
+
+    wait_until(|| queue.pending().is_empty()).await;
+    assert_eq!(*handler.batch_sizes.lock().unwrap(), vec![10, 5]);
+
+    shutdown.cancel();
+    handle.await.unwrap();
+}
+
+#[test]
+fn retry_delay_grows_exponentially_up_to_the_maximum() {
+    let config = WorkerConfig {
+        retry_base_delay: Duration::from_secs(10),
+        retry_max_delay: Duration::from_secs(60),
+        ..WorkerConfig::default()
+    };
+
+    assert_eq!(config.retry_delay(1), Duration::from_secs(10));
+    assert_eq!(config.retry_delay(2), Duration::from_secs(20));
+    assert_eq!(config.retry_delay(3), Duration::from_secs(40));
+    assert_eq!(config.retry_delay(4), Duration::from_secs(60));
+    assert_eq!(config.retry_delay(100), Duration::from_secs(60));
+}
@@ -1,10 +1,9 @@
-use std::env;
+mod settings;
 
 use std::process::Stdio;
 
-use aws_sdk_sqs::Client;
+use async_trait::async_trait;
 use tracing_subscriber::filter;
-use serde_json;
 
 use tokio::io::AsyncBufReadExt;
 use tokio::select;
@@ -12,60 +11,18 @@ use tokio::process::Command;
 use tokio::io::BufReader;
 use tokio::io;
 
-#[derive(Debug, serde::Deserialize)]
-struct ScanMessage {
-    pub presigned_url: String,
-    pub object_name: String,
-}
-
-struct ScanEvent {
-    pub message: ScanMessage,
-    pub receipt_handle: String,
-}
+use messages::{
+    AudioData, FileType, ImageData, ProcessingRequest, ProcessingRun, ProcessingStage, ResourceStatusUpdate, StageState,
+    VideoData, VirusScanClear,
+};
+use queue::{QueueError, ReceivedMessage};
+use worker::{JobError, JobHandler, Worker};
 
-#[derive(Debug, serde::Serialize)]
-struct AudioData {
-    pub duration: f64, // Duration in seconds
-    pub bitrate: u32, // Bitrate in kbps
-    pub sample_rate: u32, // Sample rate in Hz
-}
+use settings::Settings;
 
-#[derive(Debug, serde::Serialize)]
-struct VideoData {
-    pub duration: f64, // Duration in seconds
-    pub width: u32,   // Width in pixels
-    pub height: u32,  // Height in pixels
-    pub bitrate: u32, // Bitrate in kbps
-    pub frame_rate: f64, // Frame rate in frames per second
-}
 
-#[derive(Debug, serde::Serialize)]
-struct ImageData {
-    pub width: u32,   // Width in pixels
-    pub height: u32,  // Height in pixels
-}
-
-#[derive(Debug, serde::Serialize)]
-enum FileType {
-    Video{ video: VideoData, audio: Option<AudioData>}, 
-    Audio{ audio: AudioData},
-    Image{ image: ImageData },
-    Other,
-}
-
-impl FileType {
-    fn as_str(&self) -> &str {
-        match self {
-            FileType::Video { .. } => "video",
-            FileType::Audio { .. } => "audio",
-            FileType::Image { .. } => "image",
-            FileType::Other => "other",
-        }
-    }
-
-    fn is_media_type(&self) -> bool {
-        matches!(self, FileType::Video { .. } | FileType::Audio { .. } | FileType::Image { .. })
-    }
+struct VirusScanClearHandler {
+    settings: Settings,
 }
 
 
@@ -82,6 +39,7 @@ struct Media {
 
 #[derive(Debug, serde::Deserialize)]
 #[serde(tag = "@type")]
+#[allow(dead_code)] // only the track counts of the General track are used
 enum Track {
     #[serde(rename = "General")]
     General {
@@ -145,83 +103,79 @@ async fn main() {
         .init();
 
 
-    let queue_url = env::var("VIRUS_SCAN_QUEUE_URL").expect("VIRUS_SCAN_QUEUE_URL not set");
-    let client = aws_sdk_sqs::Client::new(&aws_config::load_from_env().await);
+    let settings = Settings::load();
+    tracing::info!("Loaded settings {:?}", settings);
 
-    loop {
-       let scan_event_opt = receive_virus_scan_completed_notification(&client, &queue_url).await
-            .unwrap_or_else(|err| {
-                tracing::error!("Error receiving upload notification: {}", err);
-                None
-            });
-
-        if let Some(scan_event) = scan_event_opt {
-            tracing::info!("Received scan event for file: {}", scan_event.message.object_name);
-
-            let file_type = discover_filetype_and_metadata(&scan_event.message.presigned_url).await
-                .unwrap_or_else(|err| {
-                    tracing::error!("Error discovering file type: {}", err);
-                    FileType::Other // Default to Other if there's an error
-                });
-
-            if file_type.is_media_type() {
-                tracing::info!("File {} is a recognized media type ({:?})", scan_event.message.object_name, file_type);
-                queue_metadata_extraction_completed_event(&scan_event.message.presigned_url, &scan_event.message.object_name, &file_type).await;
-                queue_resource_type_update_event(&scan_event.message.object_name, &file_type).await
-            } else {
-                tracing::warn!("File {} is not a recognized media type, skipping further processing.", scan_event.message.object_name);
-                queue_resource_status_update_event(&scan_event.message.object_name, "failed").await;
-            }
+    let shutdown = worker::shutdown_token();
 
-
-            delete_message(&client, &queue_url, &scan_event.receipt_handle).await
-                .unwrap_or_else(|err| {
-                    tracing::error!("Error deleting message: {}", err);
-                });
-        }
-
-
-        // Sleep for a while before checking the queue again
-        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-    }
+    let worker_settings = settings.worker.clone();
+    Worker::new("metadata", worker_settings.queue.open(), VirusScanClearHandler { settings })
+        .with_settings(&worker_settings)
+        .run(shutdown)
+        .await;
 }
 
 
-async fn receive_virus_scan_completed_notification(client: &Client, queue_url: &str) -> Result<Option<ScanEvent>, aws_sdk_sqs::Error> {
-    let rcv_message_output = client
-        .receive_message()
-        .queue_url(queue_url)
-        .max_number_of_messages(1)
-        .send()
-        .await?;
+#[async_trait]
+impl JobHandler for VirusScanClearHandler {
+    async fn handle(&self, message: &ReceivedMessage) -> Result<(), JobError> {
+        let scan_message: VirusScanClear = messages::decode(&message.body)
+            .map_err(|err| JobError::Permanent(format!("Failed to parse virus scan message: {}", err)))?;
 
-    
-    for message in rcv_message_output.messages.unwrap_or_default() {
+        tracing::info!("Received scan event for file: {}", scan_message.object_name);
 
-        let body = match message.body {
-            Some(body) => body,
-            None => {
-                tracing::warn!("Received message with no body, skipping.");
-                continue;
-            }
-        };
+        let attempt = message.receive_count;
+        let revision = scan_message.run.revision;
+        self.report_metadata_started(&scan_message.object_name, attempt, revision).await;
 
-        let scan_message: ScanMessage = match serde_json::from_str(&body){
-            Ok(msg) => msg,
+        let (file_type, discovery_error) = match discover_filetype_and_metadata(&scan_message.presigned_url).await {
+            Ok(file_type) => (file_type, None),
             Err(err) => {
-                tracing::error!("Failed to parse message body as JSON: {}", err);
-                continue;
+                tracing::error!("Error discovering file type: {}", err);
+                (FileType::Other, Some(err.to_string())) // Default to Other if there's an error
             }
         };
 
-        return Ok(Some(ScanEvent {
-            message: scan_message,
-            receipt_handle: message.receipt_handle.unwrap_or_default(),
-        }));         
+        if file_type.is_media_type() {
+            tracing::info!("File {} is a recognized media type ({:?})", scan_message.object_name, file_type);
+            self.queue_resource_status_update_event(&ResourceStatusUpdate::StageChanged {
+                object_name: scan_message.object_name.clone(),
+                stage: ProcessingStage::Metadata,
+                state: StageState::Succeeded,
+                attempt,
+                error: None,
+                revision,
+            }).await?;
+            self.queue_metadata_extraction_completed_event(
+                &scan_message.presigned_url,
+                &scan_message.object_name,
+                &file_type,
+                &scan_message.run,
+            ).await?;
+            self.queue_resource_status_update_event(&ResourceStatusUpdate::TypeResolved {
+                object_name: scan_message.object_name.clone(),
+                resource_type: file_type.resource_type(),
+                file_type: Some(file_type.clone()),
+                revision,
+            }).await?;
+        } else {
+            tracing::warn!("File {} is not a recognized media type, skipping further processing.", scan_message.object_name);
+            self.queue_resource_status_update_event(&ResourceStatusUpdate::StageChanged {
+                object_name: scan_message.object_name.clone(),
+                stage: ProcessingStage::Metadata,
+                state: StageState::Failed,
+                attempt,
+                error: Some(discovery_error.unwrap_or_else(|| "Not a recognized media type".to_string())),
+                revision,
+            }).await?;
+            self.queue_resource_status_update_event(&ResourceStatusUpdate::Failed {
+                object_name: scan_message.object_name.clone(),
+                revision,
+            }).await?;
+        }
 
+        Ok(())
     }
-
-    Ok(None)
 }
 
 async fn discover_filetype_and_metadata(presigned_url: &str) -> Result<FileType, io::Error> {
@@ -241,7 +195,7 @@ async fn get_file_metadata(presigned_url: &str) -> Result<MediaInfo, io::Error>
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
-        .expect("Failed to start MediaInfo process");
+        .inspect_err(|err| tracing::error!("Failed to start MediaInfo process: {}", err))?;
 
     let stdout = child.stdout.take().expect("Failed to capture stdout");
     let stderr = child.stderr.take().expect("Failed to capture stderr");
@@ -273,7 +227,7 @@ async fn get_file_metadata(presigned_url: &str) -> Result<MediaInfo, io::Error>
     if !status.success() {
         let stderr_output = stderr_lines.join("\n");
         tracing::error!("MediaInfo command failed with status: {:?}\nStderr: {}", status, stderr_output);
-        return Err(io::Error::new(io::ErrorKind::Other, "MediaInfo command failed"));
+        return Err(io::Error::other("MediaInfo command failed"));
     }
 
     let stdout_output = stdout_lines.join("\n");
@@ -294,13 +248,7 @@ fn create_metadata_object(media_info: &MediaInfo) -> Result<FileType, io::Error>
     let general_track = media_info.media.track.iter().find(|track| matches!(track, Track::General { .. }));
 
     let (video_count, audio_count, image_count) = if let Some(
-        Track::General { 
-            file_size,
-            duration,
-            format,
-            video_count,
-            audio_count,
-            image_count,other_count }
+        Track::General { video_count, audio_count, image_count, .. }
         ) = general_track {
         
         // isizes in case we for SOME reason get negative values. This would be very unexpected, but who knows if MediaInfo might have bugs, or other funky
@@ -389,82 +337,138 @@ fn extract_image_information(image_track: &Track) -> FileType {
     }
 }
 
-async fn queue_metadata_extraction_completed_event(presigned_uri: &str, object_name: &str, file_type: &FileType) {
-    let sqs_client = aws_sdk_sqs::Client::new(&aws_config::load_from_env().await);
-
-    let json_msg = serde_json::json!({
-        "presigned_url": presigned_uri,
-        "object_name": object_name,
-        "file_type": file_type,
-    }).to_string(); 
-
-    let queue_url = match file_type {
-        FileType::Video { .. } => env::var("VIDEO_PROCESSING_QUEUE_URL").expect("VIDEO_PROCESSING_QUEUE_URL not set"),
-        FileType::Audio { .. } => env::var("AUDIO_PROCESSING_QUEUE_URL").expect("AUDIO_PROCESSING_QUEUE_URL not set"),
-        FileType::Image { .. } => env::var("IMAGE_PROCESSING_QUEUE_URL").expect("IMAGE_PROCESSING_QUEUE_URL not set"),
-        FileType::Other => {
-            tracing::warn!("File type is Other, not sending to processing queue.");
-            return;
-        }
-    };
-    
-    tracing::info!("Sending message {} to SQS queue: {}", json_msg, queue_url);
-
-    sqs_client.send_message()
-        .queue_url(queue_url)
-        .message_body(json_msg)
-        .send()
-        .await
-        .expect("Failed to send message to SQS");
-}
+impl VirusScanClearHandler {
+    async fn queue_metadata_extraction_completed_event(
+        &self,
+        presigned_uri: &str,
+        object_name: &str,
+        file_type: &FileType,
+        run: &ProcessingRun,
+    ) -> Result<(), QueueError> {
+        let json_msg = messages::encode(&ProcessingRequest {
+            presigned_url: presigned_uri.to_string(),
+            object_name: object_name.to_string(),
+            file_type: file_type.clone(),
+            run: run.clone(),
+        });
 
-async fn queue_resource_status_update_event(object_name: &str, status: &str) {
-    let sqs_client = aws_sdk_sqs::Client::new(&aws_config::load_from_env().await);
-    let queue_url = env::var("RESOURCE_STATUS_QUEUE_URL").expect("RESOURCE_STATUS_QUEUE_URL not set");
+        let (processing_queue, queue_name) = match file_type {
+            FileType::Video { .. } => (&self.settings.video_processing_queue, "video"),
+            FileType::Audio { .. } => (&self.settings.audio_processing_queue, "audio"),
+            FileType::Image { .. } => (&self.settings.image_processing_queue, "image"),
+            FileType::Other => {
+                tracing::warn!("File type is Other, not sending to processing queue.");
+                return Ok(());
+            }
+        };
 
-    let json_msg = serde_json::json!({
-        "object_name": object_name,
-        "status": status,
-    }).to_string(); 
+        tracing::info!("Sending message {} to the {} processing queue", json_msg, queue_name);
 
-    tracing::info!("Sending resource status update message {} to SQS queue: {}", json_msg, queue_url);
+        processing_queue.send(&json_msg).await
+    }
 
-    sqs_client.send_message()
-        .queue_url(queue_url)
-        .message_body(json_msg)
-        .send()
-        .await
-        .expect("Failed to send resource status update message to SQS");
+    /// Reports that the metadata extraction started. A failure to report it is only logged, it does not affect the
+    /// processing.
+    async fn report_metadata_started(&self, object_name: &str, attempt: u32, revision: u32) {
+        self.queue_resource_status_update_event(&ResourceStatusUpdate::StageChanged {
+            object_name: object_name.to_string(),
+            stage: ProcessingStage::Metadata,
+            state: StageState::Running,
+            attempt,
+            error: None,
+            revision,
+        }).await.unwrap_or_else(|err| {
+            tracing::error!("Failed to report the metadata stage: {}", err);
+        });
+    }
+
+    async fn queue_resource_status_update_event(&self, update: &ResourceStatusUpdate) -> Result<(), QueueError> {
+        let json_msg = messages::encode(update);
+
+        tracing::info!("Sending resource status update message {} to the resource status queue", json_msg);
+
+        self.settings.resource_status_queue.send(&json_msg).await
+    }
 }
 
-async fn queue_resource_type_update_event(object_name: &str, metadata: &FileType) {
-    let sqs_client = aws_sdk_sqs::Client::new(&aws_config::load_from_env().await);
-    let queue_url = env::var("RESOURCE_STATUS_QUEUE_URL").expect("RESOURCE_STATUS_QUEUE_URL not set");
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
 
-    let json_msg = serde_json::json!({
-        "object_name": object_name,
-        "status": "type_resolved",
-        "resource_type": metadata.as_str(),
-    }).to_string(); 
+    use messages::QueueMessage;
+    use queue::{MemoryQueue, QueueSettings};
+    use worker::{WorkerConfig, WorkerSettings};
 
-    tracing::info!("Sending resource type update message {} to SQS queue: {}", json_msg, queue_url);
+    use super::*;
 
-    sqs_client.send_message()
-        .queue_url(queue_url)
-        .message_body(json_msg)
-        .send()
-        .await
-        .expect("Failed to send resource type update message to SQS");
-}
+    struct Queues {
+        video_processing: Arc<MemoryQueue>,
+        resource_status: Arc<MemoryQueue>,
+    }
 
-async fn delete_message(client: &Client, queue_url: &str, receipt_handle: &str) -> Result<(), aws_sdk_sqs::Error> {
-    client
-        .delete_message()
-        .queue_url(queue_url)
-        .receipt_handle(receipt_handle)
-        .send()
-        .await?;
-
-    tracing::info!("Message deleted successfully");
-    Ok(())
-}
\ No newline at end of file
+    fn handler() -> (VirusScanClearHandler, Queues) {
+        let queues = Queues {
+            video_processing: Arc::new(MemoryQueue::new()),
+            resource_status: Arc::new(MemoryQueue::new()),
+        };
+        let settings = Settings {
+            worker: WorkerSettings {
+                queue: QueueSettings::Memory { name: "VIRUS_SCAN_QUEUE_URL".to_string() },
+                dead_letter_queue: None,
+                config: WorkerConfig::default(),
+            },
+            video_processing_queue: queues.video_processing.clone(),
+            audio_processing_queue: Arc::new(MemoryQueue::new()),
+            image_processing_queue: Arc::new(MemoryQueue::new()),
+            resource_status_queue: queues.resource_status.clone(),
+        };
+        (VirusScanClearHandler { settings }, queues)
+    }
+
+    fn delivery(message: &VirusScanClear) -> ReceivedMessage {
+        ReceivedMessage {
+            message_id: "virus-scan-clear".to_string(),
+            body: messages::encode(message),
+            receipt_handle: "receipt".to_string(),
+            sent_timestamp: None,
+            receive_count: 1,
+        }
+    }
+
+    // handles the message twice, as a redelivery by the queue would
+    async fn deliver_twice(handler: &VirusScanClearHandler, message: &ReceivedMessage) {
+        handler.handle(message).await.expect("First delivery failed");
+        handler.handle(message).await.expect("Second delivery failed");
+    }
+
+    // the messages of the first delivery, which the second one must repeat so that the resource server ignores them
+    fn repeated<T: QueueMessage + PartialEq + std::fmt::Debug>(sent: Vec<String>) -> Vec<T> {
+        let mut first: Vec<T> = sent.iter().map(|body| messages::decode(body).unwrap()).collect();
+        let second = first.split_off(first.len() / 2);
+        assert_eq!(first, second);
+        first
+    }
+
+    #[tokio::test]
+    async fn unrecognized_file_delivered_twice_reports_the_same_failure() {
+        let (handler, queues) = handler();
+        let path = std::env::temp_dir().join(format!("metadata-test-{}.txt", std::process::id()));
+        std::fs::write(&path, "not a media file").unwrap();
+
+        let message = delivery(&VirusScanClear {
+            presigned_url: format!("file://{}", path.display()),
+            object_name: "resource".to_string(),
+            run: ProcessingRun { revision: 1, renditions: None },
+        });
+        deliver_twice(&handler, &message).await;
+        std::fs::remove_file(&path).unwrap();
+
+        let updates: Vec<ResourceStatusUpdate> = repeated(queues.resource_status.pending());
+        assert!(matches!(&updates[..], [
+            ResourceStatusUpdate::StageChanged { stage: ProcessingStage::Metadata, state: StageState::Running, revision: 1, .. },
+            ResourceStatusUpdate::StageChanged { stage: ProcessingStage::Metadata, state: StageState::Failed, revision: 1, .. },
+            ResourceStatusUpdate::Failed { revision: 1, .. },
+        ]), "{:?}", updates);
+        assert!(queues.video_processing.pending().is_empty());
+    }
+}
@@ -0,0 +1,33 @@
+use std::sync::Arc;
+
+use queue::{MessageQueue, QueueSettings};
+use worker::WorkerSettings;
+
+
+/// The settings of the metadata extractor, loaded once at startup.
+#[derive(Debug)]
+pub struct Settings {
+    /// Receives the files the virus scanner found clean
+    pub worker: WorkerSettings,
+    /// Where the media files are sent for processing, by their type
+    pub video_processing_queue: Arc<dyn MessageQueue>,
+    pub audio_processing_queue: Arc<dyn MessageQueue>,
+    pub image_processing_queue: Arc<dyn MessageQueue>,
+    /// Where the progress of the processing is reported to the resource server
+    pub resource_status_queue: Arc<dyn MessageQueue>,
+}
+
+impl Settings {
+    /// Loads the settings from the environment and the optional `CONFIG_FILE`.
+    ///
+    /// Exits the process with every missing or invalid setting listed, if there are any.
+    pub fn load() -> Self {
+        config::load_or_exit(|source| Settings {
+            worker: WorkerSettings::from_source(source, "VIRUS_SCAN_QUEUE_URL", "VIRUS_SCAN_DLQ_URL"),
+            video_processing_queue: QueueSettings::from_source(source, "VIDEO_PROCESSING_QUEUE_URL").open(),
+            audio_processing_queue: QueueSettings::from_source(source, "AUDIO_PROCESSING_QUEUE_URL").open(),
+            image_processing_queue: QueueSettings::from_source(source, "IMAGE_PROCESSING_QUEUE_URL").open(),
+            resource_status_queue: QueueSettings::from_source(source, "RESOURCE_STATUS_QUEUE_URL").open(),
+        })
+    }
+}
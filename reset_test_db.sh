@@ -6,6 +6,7 @@ set -ueo pipefail
 ./drop_database.sh resource test
 ./drop_database.sh ingestion test
 ./drop_database.sh audit test
+./drop_database.sh webhook test
 ./apply_all_migrations.sh test
 
 DB_HOST="127.0.0.1"
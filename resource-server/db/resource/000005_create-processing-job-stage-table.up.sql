@@ -0,0 +1,12 @@
+-- one row per stage of the processing pipeline a resource has reached, the stages it has not reached are pending
+CREATE TABLE processing_job_stage (
+    resource_id UUID NOT NULL,
+    stage VARCHAR(32) NOT NULL,
+    stage_state VARCHAR(32) NOT NULL,
+    attempts INTEGER NOT NULL,
+    error_message TEXT,
+    started_at TIMESTAMPTZ,
+    finished_at TIMESTAMPTZ,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (resource_id, stage)
+);
\ No newline at end of file
@@ -0,0 +1,9 @@
+-- the queue messages each consumer has handled, so that a redelivered message is not handled twice
+CREATE TABLE processed_message (
+    consumer VARCHAR(255) NOT NULL,
+    message_id VARCHAR(255) NOT NULL,
+    processed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (consumer, message_id)
+);
+
+CREATE INDEX processed_message_processed_at_idx ON processed_message (processed_at);
\ No newline at end of file
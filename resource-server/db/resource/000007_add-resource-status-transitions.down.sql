@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS resource_status_transition;
+ALTER TABLE app_resource DROP CONSTRAINT IF EXISTS app_resource_status_check;
@@ -0,0 +1,13 @@
+ALTER TABLE app_resource ADD CONSTRAINT app_resource_status_check
+    CHECK (resource_status IN ('pending', 'processing', 'processed', 'failed'));
+
+-- the history of the status of each resource, from_status is NULL when the resource was created
+CREATE TABLE resource_status_transition (
+    id BIGSERIAL PRIMARY KEY,
+    resource_id UUID NOT NULL REFERENCES app_resource(id) ON DELETE CASCADE,
+    from_status VARCHAR(255),
+    to_status VARCHAR(255) NOT NULL,
+    transitioned_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX resource_status_transition_resource_id_idx ON resource_status_transition (resource_id, transitioned_at);
\ No newline at end of file
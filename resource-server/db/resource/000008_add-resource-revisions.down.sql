@@ -0,0 +1,18 @@
+DROP VIEW IF EXISTS active_resources;
+CREATE VIEW active_resources AS
+SELECT 
+    id, 
+    user_id,
+    is_public,
+    resource_name,
+    resource_type,
+    resource_status,
+    created_at,
+    updated_at
+FROM app_resource
+WHERE 
+    deleted_at IS NULL;
+ALTER TABLE app_resource DROP COLUMN IF EXISTS published_revision;
+ALTER TABLE app_resource DROP COLUMN IF EXISTS processing_revision;
+ALTER TABLE app_resource DROP COLUMN IF EXISTS file_type;
+ALTER TABLE app_resource DROP COLUMN IF EXISTS origin_file_path;
@@ -0,0 +1,25 @@
+-- the path of the uploaded file, and its media information, for reprocessing the resource
+ALTER TABLE app_resource ADD COLUMN origin_file_path VARCHAR(1024);
+ALTER TABLE app_resource ADD COLUMN file_type JSONB;
+
+-- every reprocessing of the resource starts a new revision, which is published once its outputs are ready.
+-- published_revision is NULL until the first outputs are published
+ALTER TABLE app_resource ADD COLUMN processing_revision INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE app_resource ADD COLUMN published_revision INTEGER;
+
+UPDATE app_resource SET published_revision = 0 WHERE resource_status = 'processed';
+
+CREATE OR REPLACE VIEW active_resources AS
+SELECT 
+    id, 
+    user_id,
+    is_public,
+    resource_name,
+    resource_type,
+    resource_status,
+    created_at,
+    updated_at,
+    published_revision
+FROM app_resource
+WHERE 
+    deleted_at IS NULL;
\ No newline at end of file
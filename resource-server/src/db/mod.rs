@@ -1,10 +1,21 @@
 mod schema;
 
+use std::sync::OnceLock;
+
 use diesel::prelude::*;
+use diesel::upsert::excluded;
 use uuid::Uuid;
 
 use schema::*;
 
+use crate::status::{ReprocessingStart, ResourceStatus, StatusTransition};
+
+
+// the queues keep a message for at most 14 days, so it cannot be delivered again after that
+const PROCESSED_MESSAGE_RETENTION_DAYS: i64 = 14;
+
+static DATABASE_URL: OnceLock<String> = OnceLock::new();
+
 
 // provided by the active resources view, which filters out deleted resources
 #[derive(Debug, Queryable, Selectable)]
@@ -19,6 +30,8 @@ pub struct Resource {
     pub resource_status: String,
     pub created_at: chrono::DateTime<chrono::Utc>,
     pub updated_at: chrono::DateTime<chrono::Utc>,
+    /// The revision of the outputs that are served, `None` until the first outputs are published
+    pub published_revision: Option<i32>,
 }
 
 // much like the Resource struct, but includes deleted_at field
@@ -32,6 +45,15 @@ pub struct NewResource {
     pub resource_name: String,
     pub resource_type: String,
     pub resource_status: String,
+    pub origin_file_path: Option<String>,
+}
+
+// what the pipeline of a resource is run again from
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = app_resource)]
+pub struct ResourceOrigin {
+    pub origin_file_path: Option<String>,
+    pub file_type: Option<serde_json::Value>,
 }
 
 #[derive(Insertable)]
@@ -57,6 +79,45 @@ pub struct NewVideoMetadata {
     pub updated_at: chrono::DateTime<chrono::Utc>,
 }
 
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = processing_job_stage)]
+#[allow(dead_code)]
+pub struct ProcessingJobStage {
+    pub resource_id: Uuid,
+    pub stage: String,
+    pub stage_state: String,
+    pub attempts: i32,
+    pub error_message: Option<String>,
+    pub started_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = processing_job_stage)]
+pub struct NewProcessingJobStage {
+    pub resource_id: Uuid,
+    pub stage: String,
+    pub stage_state: String,
+    pub attempts: i32,
+    pub error_message: Option<String>,
+    pub started_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub finished_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Queryable, Selectable)]
+#[diesel(table_name = resource_status_transition)]
+#[allow(dead_code)]
+pub struct ResourceStatusTransition {
+    pub id: i64,
+    pub resource_id: Uuid,
+    pub from_status: Option<String>,
+    pub to_status: String,
+    pub transitioned_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Clone)]
 pub struct VideoMetadata {
     pub width: i32,
     pub height: i32,
@@ -65,11 +126,23 @@ pub struct VideoMetadata {
     pub frame_rate: f32,
 }
 
+/// A queue message, recorded as handled in the same transaction as the update it made, see
+/// `worker::IdempotencyStore`
+pub struct HandledMessage<'a> {
+    pub consumer: &'a str,
+    pub message_id: &'a str,
+}
+
+/// Creates the resource, unless it already exists.
+///
+/// # Arguments
+/// * `origin_file_path` - Where the uploaded file is stored, the pipeline is run from it again on reprocessing
 pub fn create_resource(
     resource_uuid: String,
     user_id: String,
-    resource_name: String
-) {
+    resource_name: String,
+    origin_file_path: String,
+) -> Result<(), diesel::result::Error> {
 
     let mut conn = get_connection();
 
@@ -79,41 +152,239 @@ pub fn create_resource(
         is_public: false, 
         resource_name,
         resource_type: "unknown".to_string(), // resource type, can be set later
-        resource_status: "pending".to_string(), // resource status, can be set later
+        resource_status: ResourceStatus::Pending.as_str().to_string(), // resource status, can be set later
+        origin_file_path: Some(origin_file_path),
     };
 
-    diesel::insert_into(app_resource::table)
-        .values(&new_resource)
-        .execute(&mut conn)
-        .expect("Error creating new resource");
+    conn.transaction(|conn| {
+        // the upload message may be delivered more than once
+        let created = diesel::insert_into(app_resource::table)
+            .values(&new_resource)
+            .on_conflict(app_resource::id)
+            .do_nothing()
+            .execute(conn)?;
+
+        if created > 0 {
+            insert_status_transition(conn, new_resource.id, None, ResourceStatus::Pending)?;
+        }
+        Ok(())
+    })
 }
 
-pub fn update_resource_status(
-    resource_uuid: String,
-    resource_status: String,
-) {
+/// Moves the resource to a new status, if the transition is allowed from its current status, and records the
+/// transition in the status history.
+///
+/// The current status is locked until the transition has been recorded, so concurrent updates of the same
+/// resource are applied one after the other.
+///
+/// # Arguments
+/// * `revision` - The revision the update is from. The updates of the earlier revisions are not applied.
+/// * `handled` - The message of the update, recorded unless the resource is not found
+pub fn transition_resource_status(
+    resource_uuid: &str,
+    to: ResourceStatus,
+    revision: i32,
+    handled: Option<&HandledMessage>,
+) -> Result<StatusTransition, diesel::result::Error> {
     let mut conn = get_connection();
 
-    let resource_id = Uuid::parse_str(&resource_uuid).unwrap();
+    let resource_id = Uuid::parse_str(resource_uuid).unwrap();
+
+    conn.transaction(|conn| {
+        let transition = apply_status_transition(conn, resource_id, to, revision)?;
+
+        // the update is retried until the resource exists
+        if let Some(handled) = handled
+            && !matches!(transition, StatusTransition::NotFound) {
+            record_handled_message(conn, handled)?;
+        }
+        Ok(transition)
+    })
+}
+
+fn apply_status_transition(
+    conn: &mut PgConnection,
+    resource_id: Uuid,
+    to: ResourceStatus,
+    revision: i32,
+) -> Result<StatusTransition, diesel::result::Error> {
+    let current = app_resource::table
+        .select((app_resource::resource_status, app_resource::processing_revision))
+        .filter(app_resource::id.eq(resource_id))
+        .for_update()
+        .first::<(String, i32)>(conn)
+        .optional()?;
+
+    let Some((current, processing_revision)) = current else {
+        return Ok(StatusTransition::NotFound);
+    };
+    if revision < processing_revision {
+        return Ok(StatusTransition::Stale);
+    }
+    // the check constraint only allows the known statuses
+    let from: ResourceStatus = current.parse().expect("Invalid resource status in the database");
+
+    if from == to {
+        return Ok(StatusTransition::Unchanged);
+    }
+    if !from.can_transition_to(to) {
+        return Ok(StatusTransition::Rejected { from });
+    }
 
     diesel::update(app_resource::table.filter(app_resource::id.eq(resource_id)))
-        .set(app_resource::resource_status.eq(resource_status))
-        .execute(&mut conn)
-        .expect("Error updating resource status");
+        .set((
+            app_resource::resource_status.eq(to.as_str()),
+            app_resource::updated_at.eq(diesel::dsl::now),
+        ))
+        .execute(conn)?;
+    insert_status_transition(conn, resource_id, Some(from), to)?;
+
+    Ok(StatusTransition::Applied { from })
+}
+
+fn insert_status_transition(
+    conn: &mut PgConnection,
+    resource_id: Uuid,
+    from: Option<ResourceStatus>,
+    to: ResourceStatus,
+) -> Result<(), diesel::result::Error> {
+    diesel::insert_into(resource_status_transition::table)
+        .values((
+            resource_status_transition::resource_id.eq(resource_id),
+            resource_status_transition::from_status.eq(from.map(|from| from.as_str())),
+            resource_status_transition::to_status.eq(to.as_str()),
+        ))
+        .execute(conn)?;
+
+    Ok(())
+}
+
+/// Starts a new revision of the resource, if its pipeline has finished. The resource is moved back to
+/// `processing`, and the stages that are run again are forgotten, so that they start from their first attempt.
+///
+/// The outputs of the earlier revision are served until the new revision is published, see `publish_revision`.
+///
+/// # Arguments
+/// * `stages` - The stages that are run again
+pub fn start_reprocessing(
+    resource_uuid: &str,
+    stages: &[&str],
+) -> Result<ReprocessingStart, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    let resource_id = Uuid::parse_str(resource_uuid).unwrap();
+
+    conn.transaction(|conn| {
+        let (current, processing_revision) = app_resource::table
+            .select((app_resource::resource_status, app_resource::processing_revision))
+            .filter(app_resource::id.eq(resource_id))
+            .for_update()
+            .first::<(String, i32)>(conn)?;
+
+        let from: ResourceStatus = current.parse().expect("Invalid resource status in the database");
+        if !from.can_reprocess() {
+            return Ok(ReprocessingStart::Rejected { from });
+        }
+
+        let revision = processing_revision + 1;
+        diesel::update(app_resource::table.filter(app_resource::id.eq(resource_id)))
+            .set((
+                app_resource::resource_status.eq(ResourceStatus::Processing.as_str()),
+                app_resource::processing_revision.eq(revision),
+                app_resource::updated_at.eq(diesel::dsl::now),
+            ))
+            .execute(conn)?;
+        insert_status_transition(conn, resource_id, Some(from), ResourceStatus::Processing)?;
+
+        diesel::delete(
+            processing_job_stage::table
+                .filter(processing_job_stage::resource_id.eq(resource_id))
+                .filter(processing_job_stage::stage.eq_any(stages))
+        ).execute(conn)?;
+
+        Ok(ReprocessingStart::Started { revision })
+    })
+}
+
+pub fn get_resource_origin(resource_uuid: &str) -> Result<ResourceOrigin, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    app_resource::table
+        .filter(app_resource::id.eq(Uuid::parse_str(resource_uuid).unwrap()))
+        .select(ResourceOrigin::as_select())
+        .first(&mut conn)
+}
+
+/// # Returns
+/// * The revision that is being processed, or `None` if the resource does not exist
+pub fn get_processing_revision(resource_uuid: &str) -> Result<Option<i32>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    app_resource::table
+        .select(app_resource::processing_revision)
+        .filter(app_resource::id.eq(Uuid::parse_str(resource_uuid).unwrap()))
+        .first(&mut conn)
+        .optional()
+}
+
+/// The ids of the resources whose pipeline has finished, oldest first, for reprocessing all of them
+pub fn get_reprocessable_resource_ids() -> Vec<Uuid> {
+    let mut conn = get_connection();
+
+    active_resources::table
+        .select(active_resources::id)
+        .filter(active_resources::resource_status.eq_any([
+            ResourceStatus::Processed.as_str(),
+            ResourceStatus::Failed.as_str(),
+        ]))
+        .order(active_resources::created_at.asc())
+        .load(&mut conn)
+        .expect("Error loading resources")
 }
 
+pub fn get_resource_status_transitions(resource_uuid: &str) -> Vec<ResourceStatusTransition> {
+    let mut conn = get_connection();
+
+    resource_status_transition::table
+        .filter(resource_status_transition::resource_id.eq(Uuid::parse_str(resource_uuid).unwrap()))
+        .order(resource_status_transition::id.asc())
+        .load::<ResourceStatusTransition>(&mut conn)
+        .expect("Error loading resource status transitions")
+}
+
+/// # Arguments
+/// * `file_type` - The media information, the earlier one is kept if it is not given
+/// * `handled` - The message of the update
 pub fn update_resource_type(
     resource_uuid: String,
     resource_type: String,
-) {
+    file_type: Option<serde_json::Value>,
+    handled: Option<&HandledMessage>,
+) -> Result<(), diesel::result::Error> {
     let mut conn = get_connection();
 
     let resource_id = Uuid::parse_str(&resource_uuid).unwrap();
 
-    diesel::update(app_resource::table.filter(app_resource::id.eq(resource_id)))
-        .set(app_resource::resource_type.eq(resource_type))
-        .execute(&mut conn)
-        .expect("Error updating resource type");
+    let resource = app_resource::table.filter(app_resource::id.eq(resource_id));
+
+    conn.transaction(|conn| {
+        match file_type {
+            Some(file_type) => diesel::update(resource)
+                .set((
+                    app_resource::resource_type.eq(resource_type),
+                    app_resource::file_type.eq(file_type),
+                ))
+                .execute(conn)?,
+            None => diesel::update(resource)
+                .set(app_resource::resource_type.eq(resource_type))
+                .execute(conn)?,
+        };
+
+        if let Some(handled) = handled {
+            record_handled_message(conn, handled)?;
+        }
+        Ok(())
+    })
 }
 
 // highest quality defined where width x height is the largest
@@ -132,10 +403,10 @@ pub fn get_highest_quality_video_metadata(resource_uuid: &str) -> Option<VideoMe
         ))
         .first::<(i32, i32, i32, i32, f32)>(&mut conn)
         .map(|(width, height, duration_seconds, bit_rate, frame_rate)| VideoMetadata {
-            width: width,
-            height: height,
+            width,
+            height,
             duration_seconds,
-            bit_rate: bit_rate,
+            bit_rate,
             frame_rate,
         });
 
@@ -149,33 +420,46 @@ pub fn get_highest_quality_video_metadata(resource_uuid: &str) -> Option<VideoMe
     }
 }
 
-pub fn insert_video_metadata(
+/// Publishes the outputs of a revision, so that they are served instead of the outputs of the earlier revision.
+/// The metadata of the earlier video qualities is replaced by the qualities of the revision.
+///
+/// # Arguments
+/// * `video_metadata` - The transcoded qualities, empty for the resources that are not videos
+pub fn publish_revision(
     resource_uuid: &str,
-    width: u32,
-    height: u32,
-    duration_seconds: f64,
-    bit_rate: u32,
-    frame_rate: f64,
-) {
+    revision: i32,
+    video_metadata: &[VideoMetadata],
+) -> Result<(), diesel::result::Error> {
     let mut conn = get_connection();
 
-    let resource_id = Uuid::parse_str(&resource_uuid).unwrap();
+    let resource_id = Uuid::parse_str(resource_uuid).unwrap();
     let now = chrono::Utc::now();
-    let new_metadata = NewVideoMetadata {
-        resource_id,
-        width: width as i32,
-        height: height as i32,
-        duration_seconds: duration_seconds as i32,
-        bit_rate: bit_rate as i32,
-        frame_rate: frame_rate as f32,
-        created_at: now,
-        updated_at: now,
-    };
-
-    diesel::insert_into(video_metadata::table)
-        .values(&new_metadata)
-        .execute(&mut conn)
-        .expect("Error inserting video metadata");
+    let new_metadata: Vec<NewVideoMetadata> = video_metadata.iter()
+        .map(|metadata| NewVideoMetadata {
+            resource_id,
+            width: metadata.width,
+            height: metadata.height,
+            duration_seconds: metadata.duration_seconds,
+            bit_rate: metadata.bit_rate,
+            frame_rate: metadata.frame_rate,
+            created_at: now,
+            updated_at: now,
+        })
+        .collect();
+
+    conn.transaction(|conn| {
+        diesel::delete(video_metadata::table.filter(video_metadata::resource_id.eq(resource_id)))
+            .execute(conn)?;
+        diesel::insert_into(video_metadata::table)
+            .values(&new_metadata)
+            .execute(conn)?;
+
+        diesel::update(app_resource::table.filter(app_resource::id.eq(resource_id)))
+            .set(app_resource::published_revision.eq(revision))
+            .execute(conn)?;
+
+        Ok(())
+    })
 }
 
 pub fn update_resource_public_status(
@@ -184,7 +468,7 @@ pub fn update_resource_public_status(
 ) {
     let mut conn = get_connection();
 
-    let resource_id = Uuid::parse_str(&resource_uuid).unwrap();
+    let resource_id = Uuid::parse_str(resource_uuid).unwrap();
 
     diesel::update(app_resource::table.filter(app_resource::id.eq(resource_id)))
         .set(app_resource::is_public.eq(is_public))
@@ -192,6 +476,29 @@ pub fn update_resource_public_status(
         .expect("Error updating resource public status");
 }
 
+/// Marks the resource as deleted, which hides it from the active resources. The files are kept in the bucket.
+///
+/// # Returns
+/// * `false` if the resource was already deleted
+pub fn delete_resource(resource_uuid: &str) -> Result<bool, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    let resource_id = Uuid::parse_str(resource_uuid).unwrap();
+
+    let deleted = diesel::update(
+        app_resource::table
+            .filter(app_resource::id.eq(resource_id))
+            .filter(app_resource::deleted_at.is_null())
+    )
+        .set((
+            app_resource::deleted_at.eq(diesel::dsl::now),
+            app_resource::updated_at.eq(diesel::dsl::now),
+        ))
+        .execute(&mut conn)?;
+
+    Ok(deleted > 0)
+}
+
 pub fn get_active_resources_by_user_id(user_id: &str) -> Vec<Resource> {
     let mut conn = get_connection();
 
@@ -213,6 +520,141 @@ pub fn get_active_resource_by_id(resource_id: &str) -> Option<Resource> {
 
 
 
+/// Records the state of a processing stage of a resource.
+///
+/// The updates come from several services through a queue, so they can arrive out of order. An update is only
+/// applied if it is for a later attempt than the stored one, or if it finishes the stored attempt. A late
+/// `running` update does not undo a finished stage.
+///
+/// # Arguments
+/// * `stage_state` - `running`, `succeeded`, `failed` or `skipped`
+/// * `attempt` - Which attempt of the stage the update is for, starting from 1
+/// * `error_message` - Why the stage failed
+/// * `handled` - The message of the update
+pub fn update_processing_stage(
+    resource_uuid: &str,
+    stage: &str,
+    stage_state: &str,
+    attempt: i32,
+    error_message: Option<String>,
+    handled: Option<&HandledMessage>,
+) -> Result<(), diesel::result::Error> {
+    // the filter of the upsert, for the WHERE clause of its DO UPDATE
+    use diesel::query_dsl::methods::FilterDsl;
+
+    let mut conn = get_connection();
+
+    let now = chrono::Utc::now();
+    let running = stage_state == "running";
+    let new_stage = NewProcessingJobStage {
+        resource_id: Uuid::parse_str(resource_uuid).unwrap(),
+        stage: stage.to_string(),
+        stage_state: stage_state.to_string(),
+        attempts: attempt,
+        error_message,
+        started_at: if running { Some(now) } else { None },
+        finished_at: if running { None } else { Some(now) },
+        updated_at: now,
+    };
+
+    let newer = processing_job_stage::attempts.lt(excluded(processing_job_stage::attempts))
+        .or(processing_job_stage::attempts.eq(excluded(processing_job_stage::attempts))
+            .and(processing_job_stage::stage_state.eq("running")));
+
+    let upsert = diesel::insert_into(processing_job_stage::table)
+        .values(&new_stage)
+        .on_conflict((processing_job_stage::resource_id, processing_job_stage::stage));
+
+    conn.transaction(|conn| {
+        if running {
+            upsert.do_update()
+                .set((
+                    processing_job_stage::stage_state.eq(excluded(processing_job_stage::stage_state)),
+                    processing_job_stage::attempts.eq(excluded(processing_job_stage::attempts)),
+                    processing_job_stage::error_message.eq(None::<String>),
+                    processing_job_stage::started_at.eq(excluded(processing_job_stage::started_at)),
+                    processing_job_stage::finished_at.eq(None::<chrono::DateTime<chrono::Utc>>),
+                    processing_job_stage::updated_at.eq(excluded(processing_job_stage::updated_at)),
+                ))
+                .filter(newer)
+                .execute(conn)?;
+        } else {
+            // keeps started_at, the stage started when the running update of this attempt arrived
+            upsert.do_update()
+                .set((
+                    processing_job_stage::stage_state.eq(excluded(processing_job_stage::stage_state)),
+                    processing_job_stage::attempts.eq(excluded(processing_job_stage::attempts)),
+                    processing_job_stage::error_message.eq(excluded(processing_job_stage::error_message)),
+                    processing_job_stage::finished_at.eq(excluded(processing_job_stage::finished_at)),
+                    processing_job_stage::updated_at.eq(excluded(processing_job_stage::updated_at)),
+                ))
+                .filter(newer)
+                .execute(conn)?;
+        }
+
+        if let Some(handled) = handled {
+            record_handled_message(conn, handled)?;
+        }
+        Ok(())
+    })
+}
+
+pub fn get_processing_job_stages(resource_uuid: &str) -> Vec<ProcessingJobStage> {
+    let mut conn = get_connection();
+
+    processing_job_stage::table
+        .filter(processing_job_stage::resource_id.eq(Uuid::parse_str(resource_uuid).unwrap()))
+        .load::<ProcessingJobStage>(&mut conn)
+        .expect("Error loading processing stages")
+}
+
+/// # Returns
+/// * The ids of the given messages that the consumer has already handled
+pub fn get_processed_messages(consumer: &str, message_ids: &[String]) -> Result<Vec<String>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    processed_message::table
+        .select(processed_message::message_id)
+        .filter(processed_message::consumer.eq(consumer))
+        .filter(processed_message::message_id.eq_any(message_ids))
+        .load(&mut conn)
+}
+
+/// Records the messages as handled by the consumer, and forgets the messages too old to be delivered again.
+pub fn insert_processed_messages(consumer: &str, message_ids: &[String]) -> Result<(), diesel::result::Error> {
+    let mut conn = get_connection();
+
+    record_processed_messages(&mut conn, consumer, message_ids)
+}
+
+fn record_handled_message(conn: &mut PgConnection, handled: &HandledMessage) -> Result<(), diesel::result::Error> {
+    record_processed_messages(conn, handled.consumer, &[handled.message_id.to_string()])
+}
+
+fn record_processed_messages(
+    conn: &mut PgConnection,
+    consumer: &str,
+    message_ids: &[String],
+) -> Result<(), diesel::result::Error> {
+    let rows: Vec<_> = message_ids.iter()
+        .map(|message_id| (
+            processed_message::consumer.eq(consumer),
+            processed_message::message_id.eq(message_id),
+        ))
+        .collect();
+
+    diesel::insert_into(processed_message::table)
+        .values(&rows)
+        .on_conflict_do_nothing()
+        .execute(conn)?;
+
+    let cutoff = chrono::Utc::now() - chrono::Duration::days(PROCESSED_MESSAGE_RETENTION_DAYS);
+    diesel::delete(processed_message::table.filter(processed_message::processed_at.lt(cutoff)))
+        .execute(conn)?;
+
+    Ok(())
+}
+
 pub fn get_used_daily_quota() -> Option<i64> {
     let mut conn = get_connection();
 
@@ -279,7 +721,25 @@ pub fn update_daily_quota(amount_used: i64) -> Result<(), String> {
 }  
 
 
+/// Notifies the listeners of the channel, on every replica. The payload must be shorter than 8000 bytes.
+pub fn notify(channel: &str, payload: &str) -> Result<(), diesel::result::Error> {
+    let mut conn = get_connection();
+
+    diesel::sql_query("SELECT pg_notify($1, $2)")
+        .bind::<diesel::sql_types::Text, _>(channel)
+        .bind::<diesel::sql_types::Text, _>(payload)
+        .execute(&mut conn)?;
+
+    Ok(())
+}
+
+/// Sets the database the functions connect to, must be called once at startup
+pub fn init(database_url: &str) {
+    DATABASE_URL.set(database_url.to_string()).expect("The database is already set");
+}
+
+// the URL is left out of the messages, it contains the password
 fn get_connection() -> PgConnection {
-    let database_url = std::env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-    PgConnection::establish(&database_url).expect(&format!("Error connecting to {}", database_url))
+    let database_url = DATABASE_URL.get().expect("db::init was not called");
+    PgConnection::establish(database_url).unwrap_or_else(|err| panic!("Error connecting to the database: {}", err))
 }
\ No newline at end of file
@@ -11,6 +11,10 @@ diesel::table! {
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
         deleted_at -> Nullable<Timestamptz>,
+        origin_file_path -> Nullable<Varchar>,
+        file_type -> Nullable<Jsonb>,
+        processing_revision -> Integer,
+        published_revision -> Nullable<Integer>,
     }
 }
 
@@ -39,6 +43,7 @@ diesel::table! {
         resource_status -> Varchar,
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
+        published_revision -> Nullable<Integer>,
     }    
 }
 
@@ -49,4 +54,38 @@ diesel::table! {
         created_at -> Timestamptz,
         updated_at -> Timestamptz,
     }
+}
+
+// processing pipeline stages of each resource
+diesel::table! {
+    processing_job_stage (resource_id, stage) {
+        resource_id -> Uuid,
+        stage -> Varchar,
+        stage_state -> Varchar,
+        attempts -> Integer,
+        error_message -> Nullable<Text>,
+        started_at -> Nullable<Timestamptz>,
+        finished_at -> Nullable<Timestamptz>,
+        updated_at -> Timestamptz,
+    }
+}
+
+// queue messages handled by each consumer
+diesel::table! {
+    processed_message (consumer, message_id) {
+        consumer -> Varchar,
+        message_id -> Varchar,
+        processed_at -> Timestamptz,
+    }
+}
+
+// status history of each resource
+diesel::table! {
+    resource_status_transition (id) {
+        id -> BigInt,
+        resource_id -> Uuid,
+        from_status -> Nullable<Varchar>,
+        to_status -> Varchar,
+        transitioned_at -> Timestamptz,
+    }
 }
\ No newline at end of file
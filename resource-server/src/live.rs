@@ -0,0 +1,248 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::extract::Extension;
+use axum::http::header::{HeaderName, HeaderValue};
+use axum::response::{IntoResponse, Response};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures_util::{Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{broadcast, mpsc};
+use tokio_postgres::AsyncMessage;
+
+use auth_check::UserInfo;
+use worker::CancellationToken;
+
+use crate::db;
+
+
+// the Postgres channel the updates are sent over, every replica listens to it
+const CHANNEL: &str = "resource_updates";
+
+// a client that falls further behind than this misses updates, and is told to load its resources again
+const BUFFER_SIZE: usize = 1024;
+
+const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(60);
+
+
+/// A change of one of the user's resources, as `resource_status_listener` handles it. Sent to the owner as a
+/// Server-Sent Event named after the variant, with the fields as its JSON data.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum LiveUpdate {
+    Status {
+        resource_id: String,
+        status: String,
+        revision: u32,
+    },
+    /// A stage of the pipeline started, finished or failed, see `/resource/{resource_id}/processing`
+    Stage {
+        resource_id: String,
+        stage: String,
+        state: String,
+        attempt: u32,
+        revision: u32,
+    },
+    Progress {
+        resource_id: String,
+        stage: String,
+        /// 0 to 100
+        percent: u8,
+        revision: u32,
+    },
+}
+
+impl LiveUpdate {
+    fn event_name(&self) -> &'static str {
+        match self {
+            LiveUpdate::Status { .. } => "status",
+            LiveUpdate::Stage { .. } => "stage",
+            LiveUpdate::Progress { .. } => "progress",
+        }
+    }
+}
+
+// what is sent over the channel
+#[derive(Debug, Serialize, Deserialize)]
+struct Notification {
+    user_id: String,
+    #[serde(flatten)]
+    update: LiveUpdate,
+}
+
+#[derive(Debug, Clone)]
+enum Broadcast {
+    Update(Arc<Notification>),
+    /// Updates may have been missed, e.g. while the listener was reconnecting
+    Resync,
+}
+
+
+/// Sends the update to the owner of the resource, through whichever replica the owner is connected to.
+///
+/// The updates are only a convenience for the clients that are connected at the time, so a failure to send one
+/// is only logged.
+pub fn notify(user_id: &str, update: LiveUpdate) {
+    let notification = Notification { user_id: user_id.to_string(), update };
+    let payload = serde_json::to_string(&notification).expect("Failed to serialize live update");
+
+    db::notify(CHANNEL, &payload).unwrap_or_else(|err| {
+        tracing::error!("Failed to send live update {:?}: {}", notification.update, err);
+    });
+}
+
+
+/// The updates received by this replica, for the `/resource/events` streams connected to it
+#[derive(Clone)]
+pub struct LiveUpdates {
+    sender: broadcast::Sender<Broadcast>,
+    shutdown: CancellationToken,
+}
+
+impl LiveUpdates {
+    /// # Arguments
+    /// * `shutdown` - Ends the listener and the open streams, so that they do not hold up the graceful shutdown
+    pub fn new(shutdown: CancellationToken) -> Self {
+        let (sender, _) = broadcast::channel(BUFFER_SIZE);
+        LiveUpdates { sender, shutdown }
+    }
+
+    /// Listens to the updates sent by every replica until shutdown, and passes them on to the open streams.
+    ///
+    /// Uses a connection of its own to the database, as diesel cannot receive notifications. The connection is
+    /// made again whenever it is lost, and the streams are told to resync, since they may have missed updates.
+    pub async fn listen(self, database_url: &str) {
+        let mut reconnect_delay = MIN_RECONNECT_DELAY;
+        let mut connected_before = false;
+
+        while !self.shutdown.is_cancelled() {
+            let result = self.listen_once(database_url, || {
+                if connected_before {
+                    let _ = self.sender.send(Broadcast::Resync);
+                }
+                connected_before = true;
+                reconnect_delay = MIN_RECONNECT_DELAY;
+            }).await;
+
+            if let Err(err) = result {
+                tracing::error!("Lost the connection for the live updates, reconnecting in {:?}: {}", reconnect_delay, err);
+                tokio::select! {
+                    _ = tokio::time::sleep(reconnect_delay) => {}
+                    _ = self.shutdown.cancelled() => {}
+                }
+                reconnect_delay = (reconnect_delay * 2).min(MAX_RECONNECT_DELAY);
+            }
+        }
+    }
+
+    /// # Returns
+    /// * `Ok` on shutdown, `Err` if the connection could not be made or was lost
+    async fn listen_once(&self, database_url: &str, on_connected: impl FnOnce()) -> Result<(), String> {
+        let tls = native_tls::TlsConnector::new().map_err(|err| format!("Failed to create TLS connector: {}", err))?;
+        let (client, mut connection) = tokio_postgres::connect(database_url, postgres_native_tls::MakeTlsConnector::new(tls))
+            .await
+            .map_err(|err| format!("Failed to connect: {}", err))?;
+
+        // the notifications arrive through the connection, which also has to be polled for the client to work
+        let (notifications, mut received) = mpsc::unbounded_channel();
+        let mut connection = tokio::spawn(async move {
+            let mut messages = futures_util::stream::poll_fn(move |cx| connection.poll_message(cx));
+            while let Some(message) = messages.next().await {
+                match message {
+                    Ok(AsyncMessage::Notification(notification)) => {
+                        if notifications.send(notification.payload().to_string()).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(err) => return Err(err.to_string()),
+                }
+            }
+            Err("the connection was closed".to_string())
+        });
+
+        client.batch_execute(&format!("LISTEN {}", CHANNEL))
+            .await
+            .map_err(|err| format!("Failed to listen to {}: {}", CHANNEL, err))?;
+
+        tracing::info!("Listening to the live updates");
+        on_connected();
+
+        loop {
+            tokio::select! {
+                payload = received.recv() => match payload {
+                    Some(payload) => self.dispatch(&payload),
+                    None => break,
+                },
+                _ = self.shutdown.cancelled() => {
+                    connection.abort();
+                    return Ok(());
+                }
+            }
+        }
+
+        match (&mut connection).await {
+            Ok(Err(err)) => Err(err),
+            Ok(Ok(())) => Err("the connection was closed".to_string()),
+            Err(err) => Err(format!("The connection task failed: {}", err)),
+        }
+    }
+
+    fn dispatch(&self, payload: &str) {
+        match serde_json::from_str::<Notification>(payload) {
+            // fails only if no stream is open
+            Ok(notification) => { let _ = self.sender.send(Broadcast::Update(Arc::new(notification))); }
+            Err(err) => tracing::warn!("Ignoring invalid live update {}: {}", payload, err),
+        }
+    }
+
+    /// The updates of the user's resources, until shutdown. A `resync` event says that updates were missed, and
+    /// the resources should be loaded again.
+    fn subscribe(&self, user_id: String) -> impl Stream<Item = Result<Event, Infallible>> + use<> {
+        let receiver = self.sender.subscribe();
+
+        futures_util::stream::unfold(receiver, move |mut receiver| {
+            let user_id = user_id.clone();
+            async move {
+                loop {
+                    let event = match receiver.recv().await {
+                        Ok(Broadcast::Update(notification)) if notification.user_id == user_id => {
+                            Event::default()
+                                .event(notification.update.event_name())
+                                .json_data(&notification.update)
+                                .expect("Failed to serialize live update")
+                        }
+                        Ok(Broadcast::Update(_)) => continue,
+                        Ok(Broadcast::Resync) | Err(broadcast::error::RecvError::Lagged(_)) => {
+                            Event::default().event("resync").data("{}")
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    };
+                    return Some((Ok(event), receiver));
+                }
+            }
+        })
+        .take_until(self.shutdown.clone().cancelled_owned())
+    }
+}
+
+
+/// Streams the status changes and the transcoding progress of the caller's resources as Server-Sent Events,
+/// while they are processed. Works with any number of replicas, each one receives every update through
+/// Postgres LISTEN/NOTIFY and passes on the ones of the users connected to it.
+///
+/// The events are `status`, `stage` and `progress`, see `LiveUpdate`, and `resync` when updates were missed.
+pub async fn resource_events(
+    Extension(live_updates): Extension<LiveUpdates>,
+    user_info: Extension<UserInfo>,
+) -> Response {
+    let stream = live_updates.subscribe(user_info.user_id.clone());
+
+    (
+        // stops nginx from buffering the events
+        [(HeaderName::from_static("x-accel-buffering"), HeaderValue::from_static("no"))],
+        Sse::new(stream).keep_alive(KeepAlive::default()),
+    ).into_response()
+}
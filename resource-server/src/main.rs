@@ -1,49 +1,57 @@
 mod db;
+mod live;
 mod model;
+mod reprocess;
+mod settings;
+mod status;
 
+use std::collections::HashSet;
 use std::env;
+use std::sync::Arc;
 
-use aws_sdk_s3::{Client as S3Client};
-use aws_sdk_s3::error::DisplayErrorContext;
-use aws_sdk_s3::primitives::ByteStream;
-use aws_sdk_sqs::Client;
-use aws_smithy_types_convert::date_time::DateTimeExt;
-
+use async_trait::async_trait;
 use tracing_subscriber::filter;
-use serde_json;
-use tokio_util::io::ReaderStream;
 use tower::ServiceBuilder;
 
 
 use axum::{
     body::{Body}, 
-    extract::{Extension, Json, Query}, 
-    http::{StatusCode},
-    middleware::from_fn, 
-    response::{IntoResponse},
-    routing::{get, post}, 
+    extract::{Extension, Json, Query, State}, 
+    http::{header, HeaderMap, StatusCode},
+    middleware::from_fn_with_state, 
+    response::{IntoResponse, Response},
+    routing::{delete, get, post}, 
     Router
 };
 
-use axum_client_ip::{ ClientIp, ClientIpSource };
+use axum_client_ip::ClientIp;
 
 use http_body_util::StreamBody;
 
 
+use storage::{ByteRange, StorageError};
 use url::Url;
+use uuid::Uuid;
 
 use db::*;
+use live::{LiveUpdate, LiveUpdates};
 use model::*;
-
-use auth_check::{auth_middleware, add_user_info_to_request, UserInfo};
-use audit::{AuditEvent, send_audit_event};
+use reprocess::ReprocessError;
+use settings::Settings;
+use status::{ResourceStatus, StatusTransition};
+
+use messages::{ProcessingStage, ProducedResourceMetadata, ResourceStatusUpdate, StageState, output_folder};
+use queue::ReceivedMessage;
+use worker::{CancellationToken, IdempotencyStore, JobError, JobHandler, Worker, WorkerSettings};
+use auth_check::{auth_middleware, add_user_info_to_request, service_auth_middleware, UserInfo};
+use audit::{AuditEvent, AuditEventKind, AuditTarget, ResourcePublicStatusUpdatedDetails, send_audit_event};
+use events::{
+    DomainEvent, DomainEventKind, ResourceDeleted, ResourceFailed, ResourceProcessed, ResourceUploaded,
+    ResourceVisibilityChanged,
+};
 
 const RESOURCE_FOLDER: &str = "resource";
 
-fn s3_bucket() -> String {
-    env::var("S3_BUCKET_NAME").expect("S3_BUCKET_NAME must be set")
-}
-
 
 /// List resources of the current user.
 /// 
@@ -69,16 +77,20 @@ async fn list_resources(user_info: Extension<UserInfo>) -> impl IntoResponse{
 /// 
 #[axum::debug_handler]
 async fn get_video_master_playlist(
+    State(settings): State<Arc<Settings>>,
     user_info: Extension<Option<UserInfo>>,
+    headers: HeaderMap,
     params: axum::extract::Path<String>,
 ) -> impl IntoResponse {
     let resource_id = params.0;
-    send_resource(user_info.0, resource_id, "master.m3u8".to_string(), "video").await
+    send_resource(&settings, user_info.0, &headers, resource_id, "master.m3u8".to_string(), "video").await
 }
 
 #[axum::debug_handler]
 async fn get_stream_asset(
+    State(settings): State<Arc<Settings>>,
     user_info: Extension<Option<UserInfo>>,
+    headers: HeaderMap,
     params: axum::extract::Path<(String, String, String)>,
 ) -> impl IntoResponse {
     let resource_id = params.0.0;
@@ -86,16 +98,18 @@ async fn get_stream_asset(
     let file_name = params.0.2;
 
     let file_in_directory = format!("stream_{}/{}", index, file_name);
-    send_resource(user_info.0, resource_id, file_in_directory, &"video", ).await
+    send_resource(&settings, user_info.0, &headers, resource_id, file_in_directory, "video", ).await
 }
 
 #[axum::debug_handler]
 async fn get_video_thumnail(
+    State(settings): State<Arc<Settings>>,
     user_info: Extension<Option<UserInfo>>,
+    headers: HeaderMap,
     params: axum::extract::Path<String>,
 ) -> impl IntoResponse {
     let resource_id = params.0;
-    send_resource(user_info.0, resource_id, "thumbnail.jpg".to_string(), "video").await
+    send_resource(&settings, user_info.0, &headers, resource_id, "thumbnail.jpg".to_string(), "video").await
 }
 
 #[axum::debug_handler]
@@ -108,32 +122,95 @@ async fn update_resource_public_status(
     let resource_id = params.0;
     let resource = db::get_active_resource_by_id(&resource_id);
 
-    if let Some(resource) = resource {
-        if resource.user_id.to_string() == user_info.user_id {
-            db::update_resource_public_status(&resource_id, update.is_public);
-
-            send_audit_event(AuditEvent {
-                event_type: "resource_public_status_updated".to_string(),
-                user_id: Some(&user_info.user_id),
-                client_ip: &client_ip.to_string(),
-                target: Some(&resource_id),
-                event_details: Some(serde_json::json!({
-                    "is_public": update.is_public,
-                })),
-            }).await.unwrap_or_else(|err| {
-                tracing::error!("Failed to send audit event: {}", err);
-            });
-
-            return StatusCode::OK
+    if let Some(resource) = resource
+        && resource.user_id.to_string() == user_info.user_id {
+        db::update_resource_public_status(&resource_id, update.is_public);
+
+        send_audit_event(AuditEvent::new(
+            AuditEventKind::ResourcePublicStatusUpdated(ResourcePublicStatusUpdatedDetails {
+                is_public: update.is_public,
+            }),
+            Some(&user_info.user_id),
+            &client_ip.to_string(),
+            Some(AuditTarget::resource(&resource_id)),
+        )).await.unwrap_or_else(|err| {
+            tracing::error!("Failed to send audit event: {}", err);
+        });
+
+        if resource.is_public != update.is_public {
+            publish_event(DomainEvent::new(
+                DomainEventKind::ResourceVisibilityChanged(ResourceVisibilityChanged {
+                    resource_id: resource_id.clone(),
+                    is_public: update.is_public,
+                }),
+                &user_info.user_id,
+            )).await;
         }
+
+        return StatusCode::OK
     }
 
-    return StatusCode::NOT_FOUND;
+    StatusCode::NOT_FOUND
+}
+
+/// Deletes a resource of the current user. The resource is only marked as deleted, so it is not listed or
+/// served anymore, its files stay in the bucket.
+#[axum::debug_handler]
+async fn delete_resource(
+    user_info: Extension<UserInfo>,
+    params: axum::extract::Path<String>,
+    ClientIp(client_ip): ClientIp,
+) -> impl IntoResponse {
+    let resource_id = params.0;
+    if Uuid::parse_str(&resource_id).is_err() {
+        return StatusCode::NOT_FOUND;
+    }
+
+    let resource = db::get_active_resource_by_id(&resource_id);
+
+    if let Some(resource) = resource
+        && resource.user_id.to_string() == user_info.user_id {
+        match db::delete_resource(&resource_id) {
+            Ok(true) => {}
+            // deleted by a concurrent request
+            Ok(false) => return StatusCode::NOT_FOUND,
+            Err(err) => {
+                tracing::error!("Failed to delete resource {}: {}", resource_id, err);
+                return StatusCode::INTERNAL_SERVER_ERROR;
+            }
+        }
+
+        send_audit_event(AuditEvent::new(
+            AuditEventKind::ResourceDeleted,
+            Some(&user_info.user_id),
+            &client_ip.to_string(),
+            Some(AuditTarget::resource(&resource_id)),
+        )).await.unwrap_or_else(|err| {
+            tracing::error!("Failed to send audit event: {}", err);
+        });
+
+        publish_event(DomainEvent::new(
+            DomainEventKind::ResourceDeleted(ResourceDeleted { resource_id: resource_id.clone() }),
+            &user_info.user_id,
+        )).await;
+
+        return StatusCode::OK
+    }
+
+    StatusCode::NOT_FOUND
+}
+
+// the change has been made already, so a lost event is only logged
+async fn publish_event(event: DomainEvent) {
+    if let Err(err) = events::publish(&event).await {
+        tracing::error!("Failed to publish {} event {}: {}", event.kind.event_type(), event.id, err);
+    }
 }
 
 
 #[axum::debug_handler]
 async fn oembed_response(
+    State(settings): State<Arc<Settings>>,
     query_params: Query<std::collections::HashMap<String, String>>,
     user_info: Extension<Option<UserInfo>>,
 ) -> impl IntoResponse {
@@ -165,7 +242,7 @@ async fn oembed_response(
             return StatusCode::NOT_FOUND.into_response();
         }
 
-        let domain = env::var("DOMAIN_URL").expect("DOMAIN_URL must be set");
+        let domain = &settings.domain_url;
 
         let video_metadata = db::get_highest_quality_video_metadata(&resource_id);
         if video_metadata.is_none() {
@@ -187,7 +264,7 @@ async fn oembed_response(
             author_name: None, // TODO - implement fetching user info internally from auth service
             author_url: None, // likewise
             provider_name: Some("Hipsutuubi".to_string()),
-            provider_url: Some(domain.clone()),
+            provider_url: Some(domain.to_string()),
             cache_age: Some(3600), // 1 hour
             thumbnail_url: Some(format!("{}/resource/{}/thumbnail.jpg", domain, resource.id)),
             thumbnail_width: None,
@@ -263,129 +340,330 @@ async fn resource_metadata(
     StatusCode::NOT_FOUND.into_response()
 }
 
-async fn resource_status_listener() {
-    let queue_url = env::var("RESOURCE_STATUS_QUEUE_URL").expect("RESOURCE_STATUS_QUEUE_URL not set");
-    let client = aws_sdk_sqs::Client::new(&aws_config::load_from_env().await);
+/// The progress of the processing pipeline of a resource, stage by stage. Only visible to the owner.
+#[axum::debug_handler]
+async fn resource_processing(
+    user_info: Extension<UserInfo>,
+    params: axum::extract::Path<String>,
+) -> impl IntoResponse {
+    let resource_id = params.0;
+    if Uuid::parse_str(&resource_id).is_err() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
 
-    loop {
-        let resource_status_update_opt = receive_resource_status_update_message(&client, &queue_url).await
-            .unwrap_or_else(|err| {
-                tracing::error!("Error receiving resource status update: {}", err);
-                None
-            });
-        
-        if let Some(resource_status_update) = resource_status_update_opt {
-            tracing::info!("Received resource status update: {:?}", resource_status_update.message);
+    let resource = db::get_active_resource_by_id(&resource_id);
 
+    if let Some(resource) = resource
+        && resource.user_id.to_string() == user_info.user_id {
+        let revision = db::get_processing_revision(&resource_id).ok().flatten().unwrap_or(0);
+        let stages = db::get_processing_job_stages(&resource_id);
+        let transitions = db::get_resource_status_transitions(&resource_id);
+        return (StatusCode::OK, Json(model::ProcessingJob::new(&resource, revision, stages, transitions))).into_response();
+    }
 
-            match resource_status_update.message {
-                ResourceStatusUpdateMessage::ResourceUploaded { user_id, object_name, file_name} => {
-                    create_resource(
-                        object_name,
-                        user_id, 
-                        file_name,
-                    );
-                },
-                ResourceStatusUpdateMessage::ResourceProcessingFailed { object_name } => {
-                    update_resource_status(object_name, "failed".to_string());
-                },
-                ResourceStatusUpdateMessage::ResourceProcessingStarted { object_name } => {
-                    update_resource_status(object_name, "processing".to_string());
-                },
-                ResourceStatusUpdateMessage::ResourceTypeResolved { object_name, resource_type } => {
-                            update_resource_type(object_name.clone(),  resource_type.to_string()); 
-                } 
-                ResourceStatusUpdateMessage::ResourceProcessed { object_name, metadata} => {
-                    update_resource_status(object_name.clone(), "processed".to_string());
-                    match metadata {
-                        model::ProducedResourceMetadata::Video(
-                            quality_versions                            
-                        ) => {
-
-                            for video_data in &quality_versions {
-                                db::insert_video_metadata(
-                                    &object_name, 
-                                    video_data.width, 
-                                    video_data.height, 
-                                    video_data.duration, 
-                                    video_data.bitrate, 
-                                    video_data.frame_rate);
-                            }
-                        },
-                        model::ProducedResourceMetadata::Audio(_) => {
-                            tracing::warn!("Audio files are not yet supported");
-                        },
-                        model::ProducedResourceMetadata::Image(_)=> {
-                            tracing::warn!("Image files are not yet supported");
-                        },
-                    }
-                },
-            };        
+    StatusCode::NOT_FOUND.into_response()
+}
 
+/// Runs the processing pipeline of the resource again from the uploaded file, e.g. after the encoding ladder has
+/// changed. Only for the owner and the admins. The outputs of the earlier revision are served until the new ones
+/// are ready, the progress is shown by `/resource/{resource_id}/processing`.
+#[axum::debug_handler]
+async fn reprocess_resource(
+    State(settings): State<Arc<Settings>>,
+    user_info: Extension<UserInfo>,
+    params: axum::extract::Path<String>,
+    ClientIp(client_ip): ClientIp,
+    Json(request): Json<ReprocessRequest>,
+) -> impl IntoResponse {
+    let resource_id = params.0;
+    if Uuid::parse_str(&resource_id).is_err() {
+        return StatusCode::NOT_FOUND.into_response();
+    }
 
+    let resource = db::get_active_resource_by_id(&resource_id);
 
-            delete_message(&client, &queue_url, &resource_status_update.receipt_handle)
-                .await
-                .unwrap_or_else(|err| {
-                    tracing::error!("Error deleting message: {}", err);
-                });
-        }
-        // await 5 seconds before checking for new events
-        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+    if let Some(resource) = resource
+        && (resource.user_id.to_string() == user_info.user_id || settings.is_admin(&user_info.user_id)) {
+        return match reprocess::start(&settings, &resource, &request).await {
+            Ok(revision) => {
+                reprocess::send_reprocessed_audit_event(
+                    &resource_id, &request, revision, Some(&user_info.user_id), &client_ip.to_string(),
+                ).await;
+
+                (StatusCode::ACCEPTED, Json(ReprocessResponse {
+                    resource_id,
+                    revision,
+                    from_stage: request.from_stage,
+                })).into_response()
+            }
+            Err(err) => {
+                tracing::warn!("Failed to reprocess resource {}: {}", resource_id, err);
+                match err {
+                    ReprocessError::InvalidStage(_) | ReprocessError::NoRenditions => StatusCode::BAD_REQUEST,
+                    ReprocessError::Busy(_)
+                    | ReprocessError::NotScanned
+                    | ReprocessError::UnknownMedia
+                    | ReprocessError::NotVideo
+                    | ReprocessError::MissingUpload(_) => StatusCode::CONFLICT,
+                    ReprocessError::Database(_) | ReprocessError::Queue(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                }.into_response()
+            }
+        };
     }
 
+    StatusCode::NOT_FOUND.into_response()
 }
 
-async fn receive_resource_status_update_message(client: &Client, queue_url: &str) -> Result<Option<ResourceStatusUpdateEvent>, aws_sdk_sqs::Error> {
-    let rcv_message_output = client
-        .receive_message()
-        .queue_url(queue_url)
-        .max_number_of_messages(1)
-        .send()
-        .await?;
+struct ResourceStatusHandler;
 
-    
-    for message in rcv_message_output.messages.unwrap_or_default() {
+// the worker name, under which the handled status updates are recorded
+const RESOURCE_STATUS_CONSUMER: &str = "resource-status";
 
-        let body = match message.body {
-            Some(body) => body,
-            None => {
-                tracing::warn!("Received message with no body, skipping.");
-                continue;
-            }
-        };
+/// Keeps track of the handled queue messages in the resource database
+struct DbIdempotencyStore;
 
-        let resource_update_message: ResourceStatusUpdateMessage = match serde_json::from_str(&body){
-            Ok(msg) => msg,
-            Err(err) => {
-                tracing::error!("Failed to parse message body as JSON: {} (message: {})", err, body);
-                continue;
-            }
-        };
+async fn resource_status_listener(settings: &WorkerSettings, shutdown: CancellationToken) {
+    Worker::new(RESOURCE_STATUS_CONSUMER, settings.queue.open(), ResourceStatusHandler)
+        .with_settings(settings)
+        .with_idempotency_store(Arc::new(DbIdempotencyStore))
+        .run(shutdown)
+        .await;
+}
+
+#[async_trait]
+impl IdempotencyStore for DbIdempotencyStore {
+    async fn handled(&self, consumer: &str, message_ids: &[String]) -> Result<HashSet<String>, String> {
+        db::get_processed_messages(consumer, message_ids)
+            .map(|handled| handled.into_iter().collect())
+            .map_err(|err| err.to_string())
+    }
+
+    async fn mark_handled(&self, consumer: &str, message_ids: &[String]) -> Result<(), String> {
+        db::insert_processed_messages(consumer, message_ids).map_err(|err| err.to_string())
+    }
+}
+
+/// Moves the resource to the status, if the transition is allowed. The transitions that are not are logged and
+/// dropped, since retrying them would not help.
+///
+/// # Arguments
+/// * `revision` - The revision the update is from, the updates of the earlier revisions are dropped
+/// * `handled` - The message of the update, to record in the same transaction
+///
+/// # Returns
+/// * `true` if the resource has the status now
+fn transition_status(
+    object_name: &str,
+    status: ResourceStatus,
+    revision: u32,
+    handled: Option<&HandledMessage>,
+) -> Result<bool, JobError> {
+    match db::transition_resource_status(object_name, status, revision as i32, handled).map_err(db_error)? {
+        StatusTransition::Applied { from } => {
+            tracing::info!("Resource {} status changed from {} to {}", object_name, from.as_str(), status.as_str());
+            Ok(true)
+        }
+        StatusTransition::Unchanged => Ok(true),
+        StatusTransition::Rejected { from } => {
+            tracing::warn!(
+                "Ignoring status update of resource {} from {} to {}, the transition is not allowed",
+                object_name, from.as_str(), status.as_str()
+            );
+            Ok(false)
+        }
+        // the upload message that creates it may still be on its way
+        StatusTransition::NotFound => Err(JobError::Retryable(format!("Resource {} does not exist", object_name))),
+        StatusTransition::Stale => {
+            tracing::info!(
+                "Ignoring status update of resource {} to {} from revision {}, it has been reprocessed since",
+                object_name, status.as_str(), revision
+            );
+            Ok(false)
+        }
+    }
+}
+
+/// # Returns
+/// * `true` if the update is from an earlier revision than the one being processed
+fn is_stale(object_name: &str, revision: u32) -> Result<bool, JobError> {
+    let processing_revision = db::get_processing_revision(object_name).map_err(db_error)?;
+    Ok(processing_revision.is_some_and(|processing_revision| (revision as i32) < processing_revision))
+}
+
+// the updates are safe to apply again, so the message is retried
+fn db_error(err: diesel::result::Error) -> JobError {
+    JobError::Retryable(format!("Failed to store resource status update: {}", err))
+}
+
+/// Publishes the event the status update produced. The event gets the id of the update message, so the event
+/// published again when the message is retried or redelivered is recognized as the same event.
+async fn publish_status_event(message: &ReceivedMessage, kind: DomainEventKind, user_id: &str) -> Result<(), JobError> {
+    let event = DomainEvent::new(kind, user_id).with_id(&worker::idempotency_key(message));
 
-        return Ok(Some(ResourceStatusUpdateEvent {
-            message: resource_update_message,
-            receipt_handle: message.receipt_handle.unwrap_or_default(),
-        }));  
+    events::publish(&event).await.map_err(|err| {
+        JobError::Retryable(format!("Failed to publish {} event {}: {}", event.kind.event_type(), event.id, err))
+    })
+}
 
+/// Sends the live update to the owner of the resource, unless the resource has been deleted
+fn notify_owner(object_name: &str, update: LiveUpdate) {
+    if let Some(resource) = db::get_active_resource_by_id(object_name) {
+        live::notify(&resource.user_id.to_string(), update);
     }
+}
 
-    Ok(None)
+fn status_update(object_name: &str, status: ResourceStatus, revision: u32) -> LiveUpdate {
+    LiveUpdate::Status {
+        resource_id: object_name.to_string(),
+        status: status.as_str().to_string(),
+        revision,
+    }
 }
 
+fn stage_update(object_name: &str, stage: ProcessingStage, state: StageState, attempt: u32, revision: u32) -> LiveUpdate {
+    LiveUpdate::Stage {
+        resource_id: object_name.to_string(),
+        stage: stage.as_str().to_string(),
+        state: state.as_str().to_string(),
+        attempt,
+        revision,
+    }
+}
 
-async fn delete_message(client: &Client, queue_url: &str, receipt_handle: &str) -> Result<(), aws_sdk_sqs::Error> {
-    client
-        .delete_message()
-        .queue_url(queue_url)
-        .receipt_handle(receipt_handle)
-        .send()
-        .await?;
+#[async_trait]
+impl JobHandler for ResourceStatusHandler {
+    async fn handle(&self, message: &ReceivedMessage) -> Result<(), JobError> {
+        let update: ResourceStatusUpdate = messages::decode(&message.body)
+            .map_err(|err| JobError::Permanent(format!("Failed to decode resource status update: {}", err)))?;
+
+        tracing::info!("Received resource status update: {:?}", update);
+
+        // The updates record their message in the transaction of their last write. The ones that publish an event
+        // after the write leave it to the worker, which records the message once the event has been published,
+        // so that the publishing is retried if it fails.
+        let message_id = worker::idempotency_key(message);
+        let handled = HandledMessage { consumer: RESOURCE_STATUS_CONSUMER, message_id: &message_id };
+
+        match update {
+            ResourceStatusUpdate::Uploaded { user_id, object_name, file_name, origin_file_path } => {
+                create_resource(
+                    object_name.clone(),
+                    user_id.clone(),
+                    file_name.clone(),
+                    origin_file_path,
+                ).map_err(db_error)?;
+                update_processing_stage(&object_name, ProcessingStage::Upload.as_str(), StageState::Succeeded.as_str(), 1, None, None)
+                    .map_err(db_error)?;
+                live::notify(&user_id, status_update(&object_name, ResourceStatus::Pending, 0));
+                live::notify(&user_id, stage_update(&object_name, ProcessingStage::Upload, StageState::Succeeded, 1, 0));
+                publish_status_event(
+                    message,
+                    DomainEventKind::ResourceUploaded(ResourceUploaded { resource_id: object_name, file_name }),
+                    &user_id,
+                ).await?;
+            },
+            ResourceStatusUpdate::Failed { object_name, revision } => {
+                // a failed reprocessing leaves the outputs of the earlier revision in place
+                let resource = db::get_active_resource_by_id(&object_name);
+                let published_revision = resource.as_ref().and_then(|resource| resource.published_revision);
+                let status = ResourceStatus::after_failure(published_revision);
+                let failed = transition_status(&object_name, status, revision, None)?;
+
+                if let Some(resource) = &resource
+                    && failed {
+                    live::notify(&resource.user_id.to_string(), status_update(&object_name, status, revision));
+                }
 
-    tracing::info!("Message deleted successfully");
-    Ok(())
+                // a late failure of a revision that has been published already is not reported
+                if let Some(resource) = resource
+                    && failed
+                    && published_revision != Some(revision as i32) {
+                    publish_status_event(
+                        message,
+                        DomainEventKind::ResourceFailed(ResourceFailed { resource_id: object_name, revision }),
+                        &resource.user_id.to_string(),
+                    ).await?;
+                }
+            },
+            ResourceStatusUpdate::Processing { object_name, revision } => {
+                if transition_status(&object_name, ResourceStatus::Processing, revision, Some(&handled))? {
+                    notify_owner(&object_name, status_update(&object_name, ResourceStatus::Processing, revision));
+                }
+            },
+            ResourceStatusUpdate::TypeResolved { object_name, resource_type, file_type, .. } => {
+                let file_type = file_type.map(|file_type| serde_json::to_value(file_type).expect("Failed to serialize file type"));
+                update_resource_type(object_name, resource_type.as_str().to_string(), file_type, Some(&handled))
+                    .map_err(db_error)?;
+            } 
+            ResourceStatusUpdate::Processed { object_name, metadata, revision } => {
+                // e.g. the resource failed already, the produced media is not published
+                if !transition_status(&object_name, ResourceStatus::Processed, revision, None)? {
+                    return Ok(());
+                }
+                let video_metadata = match metadata {
+                    ProducedResourceMetadata::Video(
+                        quality_versions                            
+                    ) => {
+                        quality_versions.iter().map(|video_data| db::VideoMetadata {
+                            width: video_data.width as i32,
+                            height: video_data.height as i32,
+                            duration_seconds: video_data.duration as i32,
+                            bit_rate: video_data.bitrate as i32,
+                            frame_rate: video_data.frame_rate as f32,
+                        }).collect()
+                    },
+                    ProducedResourceMetadata::Audio(_) => {
+                        tracing::warn!("Audio files are not yet supported");
+                        vec![]
+                    },
+                    ProducedResourceMetadata::Image(_)=> {
+                        tracing::warn!("Image files are not yet supported");
+                        vec![]
+                    },
+                };
+                db::publish_revision(&object_name, revision as i32, &video_metadata).map_err(db_error)?;
+                update_processing_stage(&object_name, ProcessingStage::Publish.as_str(), StageState::Succeeded.as_str(), 1, None, None)
+                    .map_err(db_error)?;
+
+                // not reported for the resources deleted while they were processed
+                if let Some(resource) = db::get_active_resource_by_id(&object_name) {
+                    let user_id = resource.user_id.to_string();
+                    live::notify(&user_id, stage_update(&object_name, ProcessingStage::Publish, StageState::Succeeded, 1, revision));
+                    live::notify(&user_id, status_update(&object_name, ResourceStatus::Processed, revision));
+                    publish_status_event(
+                        message,
+                        DomainEventKind::ResourceProcessed(ResourceProcessed { resource_id: object_name, revision }),
+                        &user_id,
+                    ).await?;
+                }
+            },
+            ResourceStatusUpdate::StageChanged { object_name, stage, state, attempt, error, revision } => {
+                if is_stale(&object_name, revision)? {
+                    tracing::info!("Ignoring {} stage update of resource {} from revision {}", stage.as_str(), object_name, revision);
+                    return Ok(());
+                }
+                update_processing_stage(&object_name, stage.as_str(), state.as_str(), attempt as i32, error, Some(&handled))
+                    .map_err(db_error)?;
+                notify_owner(&object_name, stage_update(&object_name, stage, state, attempt, revision));
+            },
+            ResourceStatusUpdate::TranscodeProgress { object_name, percent, revision } => {
+                if is_stale(&object_name, revision)? {
+                    return Ok(());
+                }
+                notify_owner(&object_name, LiveUpdate::Progress {
+                    resource_id: object_name.clone(),
+                    stage: ProcessingStage::Transcode.as_str().to_string(),
+                    percent,
+                    revision,
+                });
+            },
+        };
+
+        Ok(())
+    }
 }
 
+
 #[tokio::main]
 async fn main() {
 
@@ -397,18 +675,23 @@ async fn main() {
         .with_max_level(filter::LevelFilter::INFO)
         .init();
 
-    tracing::info!("Starting resource server...");
+    let settings = Arc::new(Settings::load());
+    db::init(settings.database_url.expose());
+    audit::start(&settings.audit);
+    events::start(&settings.event_bus);
+
+    // e.g. `resource-server reprocess ...`, see reprocess::run_command
+    let args: Vec<String> = env::args().skip(1).collect();
+    if !args.is_empty() {
+        let exit_code = reprocess::run_command(&settings, &args).await;
+        audit::flush().await;
+        std::process::exit(exit_code);
+    }
+
+    tracing::info!("Starting resource server with settings {:?}", settings);
 
-    let ip_source_env = env::var("IP_SOURCE").unwrap_or_else(|_| "nginx".to_string());
-    let ip_source = match ip_source_env.as_str() {
-        "nginx" => ClientIpSource::RightmostXForwardedFor,
-        "amazon" => ClientIpSource::CloudFrontViewerAddress,
-        "cloudflare" => ClientIpSource::CfConnectingIp,
-        _ => { 
-            tracing::warn!("Unknown IP source: {}, defaulting to Nginx", ip_source_env);
-            ClientIpSource::RightmostXForwardedFor
-        } 
-    };
+    let shutdown = worker::shutdown_token();
+    let live_updates = LiveUpdates::new(shutdown.clone());
 
     let app = Router::new()
         .route("/resource/health", get(|| async { "OK" }))
@@ -416,10 +699,14 @@ async fn main() {
             "/resource",
             Router::new()
                 .route("/list", get(list_resources))
+                .route("/events", get(live::resource_events).layer(Extension(live_updates.clone())))
+                .route("/{resource_id}", delete(delete_resource))
                 .route("/{resource_id}/public", post(update_resource_public_status))
+                .route("/{resource_id}/processing", get(resource_processing))
+                .route("/{resource_id}/reprocess", post(reprocess_resource))
             .layer(
                 ServiceBuilder::new()
-                    .layer(from_fn(auth_middleware))
+                    .layer(from_fn_with_state(settings.auth.clone(), auth_middleware))
             )
         )
         .nest(
@@ -428,7 +715,7 @@ async fn main() {
                 .route("/oembed.json", get(oembed_response))
                 .layer(
                     ServiceBuilder::new()
-                        .layer(from_fn(add_user_info_to_request))
+                        .layer(from_fn_with_state(settings.auth.clone(), add_user_info_to_request))
                 )
         )
         .nest(
@@ -437,61 +724,106 @@ async fn main() {
                 .route("/master.m3u8", get(get_video_master_playlist))
                 .route("/thumbnail.jpg", get(get_video_thumnail))
                 .route("/stream_{index}/{file_in_directory}", get(get_stream_asset))
+                .layer(
+                    ServiceBuilder::new()
+                        .layer(from_fn_with_state(settings.auth.clone(), add_user_info_to_request))
+            )
+        )
+        // internal-only, called by the embed tag service. It forwards the user cookies, so we still
+        // resolve the user info to allow access to private resources.
+        .nest(
+            "/resource/{resource_id}",
+            Router::new()
                 .route("/metadata", get(resource_metadata))
                 .layer(
                     ServiceBuilder::new()
-                        .layer(from_fn(add_user_info_to_request))
+                        .layer(from_fn_with_state(settings.auth.service.clone(), service_auth_middleware))
+                        .layer(from_fn_with_state(settings.auth.clone(), add_user_info_to_request))
             )
-        ).layer(ip_source.into_extension());
+        ).layer(settings.ip_source.clone().into_extension())
+        .with_state(settings.clone());
         
         
-    let port = env::var("PORT").unwrap_or_else(|_| "3000".to_string());
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", port)).await
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", settings.port)).await
         .expect("Failed to bind TCP listener");
 
-    let resource_status_listener_task = resource_status_listener();
+    let resource_status_listener_task = resource_status_listener(&settings.resource_status_worker, shutdown.clone());
 
-    tracing::info!("Listening on port {}", port);
+    tracing::info!("Listening on port {}", settings.port);
     tokio::join!(
         resource_status_listener_task,
+        live_updates.listen(settings.database_url.expose()),
         axum::serve(
             listener, 
             app)
-    ).1.unwrap();
+            .with_graceful_shutdown(shutdown.cancelled_owned())
+    ).2.unwrap();
+
+    audit::flush().await;
 }
 
 
+/// Sends the file of the resource, or the part of it requested in a single range `Range` header
 async fn send_resource(
+    settings: &Settings,
     user_info: Option<UserInfo>,
+    headers: &HeaderMap,
     resource_id: String, 
     file_in_directory: String,
     resource_type: &str,
-) -> impl IntoResponse {
+) -> Response {
     let resource = db::get_active_resource_by_id(&resource_id);
     if let Some(resource) = resource {
         if resource.resource_type == resource_type && has_access_to_resource(&user_info, &resource) {
 
-            if transfer_quota_exceeded() {
+            if transfer_quota_exceeded(settings) {
                 tracing::warn!("Transfer quota exceeded for user {}", user_info.as_ref().map_or("unknown", |u| &u.user_id));
                 // Hey, bandwidth is expensive. 
                 return StatusCode::PAYMENT_REQUIRED.into_response();
             }
 
-            let object_name = format!("{}/{}/{}", RESOURCE_FOLDER, resource.id, file_in_directory);
-            let s3_client = get_s3_client().await;
-          
-
-            match get_object_stream(
-                s3_client,
-                &object_name,
-            ).await {
-                Ok((stream, file_size, _modified)) => {
-                    update_quota_used(file_size as i64).unwrap_or_else(|err| {
+            // the outputs of the published revision, the later revisions may still be processed
+            let revision = resource.published_revision.unwrap_or(0) as u32;
+            let object_name = format!("{}/{}/{}", RESOURCE_FOLDER, output_folder(&resource.id.to_string(), revision), file_in_directory);
+            // several ranges are not supported, the whole file is sent instead
+            let range = headers.get(header::RANGE)
+                .and_then(|value| value.to_str().ok())
+                .and_then(ByteRange::from_header);
+
+            tracing::info!("Getting object stream for object: {}", object_name);
+            match settings.storage.get(&object_name, range).await {
+                Ok(object) => {
+                    let sent_size = match object.range {
+                        Some((first, last)) => last - first + 1,
+                        None => object.size,
+                    };
+                    update_quota_used(settings, sent_size as i64).unwrap_or_else(|err| {
                         tracing::error!("Failed to update transfer quota: {}", err);
                     });
-                    
-                    let reader_stream = ReaderStream::new(stream.into_async_read());                
-                    return (StatusCode::OK, Body::from_stream(StreamBody::new(reader_stream))).into_response();
+
+                    let body = Body::from_stream(StreamBody::new(object.body));
+                    return match object.range {
+                        Some((first, last)) => (
+                            StatusCode::PARTIAL_CONTENT,
+                            [
+                                (header::ACCEPT_RANGES, "bytes".to_string()),
+                                (header::CONTENT_RANGE, format!("bytes {}-{}/{}", first, last, object.size)),
+                                (header::CONTENT_LENGTH, sent_size.to_string()),
+                            ],
+                            body,
+                        ).into_response(),
+                        None => (
+                            StatusCode::OK,
+                            [
+                                (header::ACCEPT_RANGES, "bytes".to_string()),
+                                (header::CONTENT_LENGTH, sent_size.to_string()),
+                            ],
+                            body,
+                        ).into_response(),
+                    };
+                },
+                Err(StorageError::InvalidRange(_)) => {
+                    return StatusCode::RANGE_NOT_SATISFIABLE.into_response();
                 },
                 Err(err) => {
                     tracing::error!("Failed to get object stream: {}", err);
@@ -508,25 +840,18 @@ async fn send_resource(
     StatusCode::NOT_FOUND.into_response()
 }
 
-fn transfer_quota_exceeded() -> bool {
-    if let Ok(var) = env::var("ENABLE_DATA_QUOTAS") {
-        if var.to_lowercase() == "true" {
-            let daily_quota_mb: i64 = env::var("DAILY_DATA_QUOTA_MEGABYTES")
-                .unwrap_or_else(|_| "1024".to_string())
-                .parse()
-                .unwrap_or(1024);
+fn transfer_quota_exceeded(settings: &Settings) -> bool {
+    if let Some(daily_quota_mb) = settings.daily_data_quota_megabytes {
+        let daily_quota_bytes = daily_quota_mb * 1024 * 1024;
 
-            let daily_quota_bytes = daily_quota_mb * 1024 * 1024;
-
-            let used_quota = db::get_used_daily_quota().unwrap_or(0);
-            return used_quota > daily_quota_bytes;
-        }
+        let used_quota = db::get_used_daily_quota().unwrap_or(0);
+        return used_quota > daily_quota_bytes;
     }
     false
 }
 
-fn update_quota_used(amount: i64) -> Result<(), String> {
-    if transfer_quota_exceeded() {
+fn update_quota_used(settings: &Settings, amount: i64) -> Result<(), String> {
+    if transfer_quota_exceeded(settings) {
         return Err("Transfer quota exceeded".to_string());
     }
 
@@ -546,51 +871,231 @@ fn has_access_to_resource(user_info: &Option<UserInfo>, resource: &db::Resource)
     false
 }
 
-async fn get_s3_client() -> S3Client {
-    let config = aws_config::load_from_env().await;
-    let client = S3Client::new(&config);
+#[cfg(test)]
+mod tests {
+    use std::sync::OnceLock;
 
-    let client = if let Ok(var) = env::var("USE_PATH_STYLE_BUCKETS") {
-        if var.to_lowercase() == "true" {
-            let config_builder = client.config().clone().to_builder();
-            S3Client::from_conf(config_builder.force_path_style(true).build())
-        } else {
-            client
+    use events::{DomainEvent, MemoryBus};
+    use messages::{FileType, ResourceType, VideoData};
+
+    use super::*;
+    use crate::status::ReprocessingStart;
+
+    static BUS: OnceLock<Arc<MemoryBus>> = OnceLock::new();
+
+    // the database is shared by all the tests, so each test works on resources of its own
+    fn bus() -> Arc<MemoryBus> {
+        BUS.get_or_init(|| {
+            db::init(&env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL not set"));
+
+            let bus = Arc::new(MemoryBus::new());
+            events::init(bus.clone());
+            bus
+        }).clone()
+    }
+
+    fn delivery(update: &ResourceStatusUpdate) -> ReceivedMessage {
+        ReceivedMessage {
+            message_id: Uuid::new_v4().to_string(),
+            body: messages::encode(update),
+            receipt_handle: Uuid::new_v4().to_string(),
+            sent_timestamp: None,
+            receive_count: 1,
         }
-    } else {
-        client
-    };
+    }
 
-    client
-}
+    // handles the message twice, as a redelivery by the queue would, without the worker's idempotency store
+    async fn deliver_twice(update: &ResourceStatusUpdate) -> ReceivedMessage {
+        let message = delivery(update);
 
+        ResourceStatusHandler.handle(&message).await.expect("First delivery failed");
+        ResourceStatusHandler.handle(&message).await.expect("Second delivery failed");
+        message
+    }
 
-async fn get_object_stream(
-    s3_client: S3Client,
-    object_name: &str,
-) -> Result<(ByteStream, i64, chrono::DateTime<chrono::Utc>), DisplayErrorContext<impl std::error::Error>> {
-    tracing::info!("Getting object stream for object: {}", object_name);
-    let get_object_output = match s3_client
-        .get_object()
-        .bucket(s3_bucket())
-        .key(object_name)
-        .send()
-        .await {
-            Ok(output) => output,
-            Err(err) => {
-                let error_with_context = DisplayErrorContext(err);
-                return Err(error_with_context);
-            }
-        };
+    fn published(bus: &MemoryBus, message: &ReceivedMessage) -> Vec<DomainEvent> {
+        let id = worker::idempotency_key(message);
+        bus.events().into_iter().filter(|event| event.id == id).collect()
+    }
+
+    fn handled(message: &ReceivedMessage) -> bool {
+        let message_id = worker::idempotency_key(message);
+        db::get_processed_messages(RESOURCE_STATUS_CONSUMER, std::slice::from_ref(&message_id)).unwrap() == [message_id]
+    }
 
-    if get_object_output.content_length.is_none() {
-        tracing::warn!("Object {} has no content length", object_name);
+    fn uploaded(object_name: &str) -> ResourceStatusUpdate {
+        ResourceStatusUpdate::Uploaded {
+            user_id: Uuid::new_v4().to_string(),
+            object_name: object_name.to_string(),
+            file_name: "video.mp4".to_string(),
+            origin_file_path: format!("uploads/{}", object_name),
+        }
     }
 
-    let modified = get_object_output
-        .last_modified
-        .map(|dt| dt.to_chrono_utc())
-        .unwrap().unwrap();
+    #[tokio::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn resource_uploaded_delivered_twice_creates_the_resource_once() {
+        let bus = bus();
+        let object_name = Uuid::new_v4().to_string();
+
+        let message = deliver_twice(&uploaded(&object_name)).await;
 
-    Ok((get_object_output.body, get_object_output.content_length.unwrap_or(0), modified))
-}
\ No newline at end of file
+        assert!(db::get_active_resource_by_id(&object_name).is_some());
+        assert_eq!(db::get_resource_status_transitions(&object_name).len(), 1);
+        assert_eq!(db::get_processing_job_stages(&object_name).len(), 1);
+
+        // published again, with the same id, so that the consumers recognize it
+        let events = published(&bus, &message);
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| event.kind.event_type() == "resource.uploaded"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn resource_processed_delivered_twice_publishes_the_revision_once() {
+        let bus = bus();
+        let object_name = Uuid::new_v4().to_string();
+
+        deliver_twice(&uploaded(&object_name)).await;
+        deliver_twice(&ResourceStatusUpdate::Processing { object_name: object_name.clone(), revision: 0 }).await;
+
+        let video = VideoData { duration: 12.0, width: 1280, height: 720, bitrate: 2500, frame_rate: 30.0 };
+        let message = deliver_twice(&ResourceStatusUpdate::Processed {
+            object_name: object_name.clone(),
+            metadata: ProducedResourceMetadata::Video(vec![video]),
+            revision: 0,
+        }).await;
+
+        let resource = db::get_active_resource_by_id(&object_name).unwrap();
+        assert_eq!(resource.resource_status, ResourceStatus::Processed.as_str());
+        assert_eq!(resource.published_revision, Some(0));
+        // pending, processing and processed
+        assert_eq!(db::get_resource_status_transitions(&object_name).len(), 3);
+
+        let metadata = db::get_highest_quality_video_metadata(&object_name).unwrap();
+        assert_eq!((metadata.width, metadata.height), (1280, 720));
+
+        let events = published(&bus, &message);
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| event.kind.event_type() == "resource.processed"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn status_updates_of_a_reprocessed_revision_are_stale() {
+        bus();
+        let object_name = Uuid::new_v4().to_string();
+        deliver_twice(&uploaded(&object_name)).await;
+
+        let transition = |status, revision| db::transition_resource_status(&object_name, status, revision, None).unwrap();
+
+        assert_eq!(transition(ResourceStatus::Processing, 0), StatusTransition::Applied { from: ResourceStatus::Pending });
+        assert_eq!(transition(ResourceStatus::Processing, 0), StatusTransition::Unchanged);
+        assert_eq!(transition(ResourceStatus::Processed, 0), StatusTransition::Applied { from: ResourceStatus::Processing });
+        assert_eq!(transition(ResourceStatus::Processing, 0), StatusTransition::Rejected { from: ResourceStatus::Processed });
+
+        assert_eq!(db::start_reprocessing(&object_name, &[ProcessingStage::Transcode.as_str()]).unwrap(), ReprocessingStart::Started { revision: 1 });
+
+        // a late failure of the earlier revision
+        assert_eq!(transition(ResourceStatus::Failed, 0), StatusTransition::Stale);
+        // the reprocessing has moved the resource to processing already
+        assert_eq!(transition(ResourceStatus::Processing, 1), StatusTransition::Unchanged);
+        assert_eq!(transition(ResourceStatus::Processed, 1), StatusTransition::Applied { from: ResourceStatus::Processing });
+
+        // pending, processing, processed, processing again and processed again
+        assert_eq!(db::get_resource_status_transitions(&object_name).len(), 5);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn resource_failed_delivered_twice_fails_the_resource_once() {
+        let bus = bus();
+        let object_name = Uuid::new_v4().to_string();
+
+        deliver_twice(&uploaded(&object_name)).await;
+        deliver_twice(&ResourceStatusUpdate::Processing { object_name: object_name.clone(), revision: 0 }).await;
+        let message = deliver_twice(&ResourceStatusUpdate::Failed { object_name: object_name.clone(), revision: 0 }).await;
+
+        let resource = db::get_active_resource_by_id(&object_name).unwrap();
+        assert_eq!(resource.resource_status, ResourceStatus::Failed.as_str());
+        // pending, processing and failed
+        assert_eq!(db::get_resource_status_transitions(&object_name).len(), 3);
+
+        // published again, with the same id, in case the first publish was not recorded as handled
+        let events = published(&bus, &message);
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|event| event.kind.event_type() == "resource.failed"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn resource_processing_delivered_twice_records_the_transition_once() {
+        bus();
+        let object_name = Uuid::new_v4().to_string();
+
+        deliver_twice(&uploaded(&object_name)).await;
+        let message = deliver_twice(&ResourceStatusUpdate::Processing { object_name: object_name.clone(), revision: 0 }).await;
+
+        let resource = db::get_active_resource_by_id(&object_name).unwrap();
+        assert_eq!(resource.resource_status, ResourceStatus::Processing.as_str());
+        // pending and processing
+        assert_eq!(db::get_resource_status_transitions(&object_name).len(), 2);
+        assert!(handled(&message));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn stage_changed_delivered_twice_keeps_the_stage_as_reported() {
+        bus();
+        let object_name = Uuid::new_v4().to_string();
+
+        deliver_twice(&uploaded(&object_name)).await;
+        let running = deliver_twice(&ResourceStatusUpdate::StageChanged {
+            object_name: object_name.clone(),
+            stage: ProcessingStage::VirusScan,
+            state: StageState::Running,
+            attempt: 1,
+            error: None,
+            revision: 0,
+        }).await;
+        let succeeded = deliver_twice(&ResourceStatusUpdate::StageChanged {
+            object_name: object_name.clone(),
+            stage: ProcessingStage::VirusScan,
+            state: StageState::Succeeded,
+            attempt: 1,
+            error: None,
+            revision: 0,
+        }).await;
+        // a late redelivery of the start does not reopen the finished stage
+        ResourceStatusHandler.handle(&running).await.expect("Late delivery failed");
+
+        let stages = db::get_processing_job_stages(&object_name);
+        let virus_scan = stages.iter().find(|stage| stage.stage == ProcessingStage::VirusScan.as_str()).unwrap();
+        assert_eq!(virus_scan.stage_state, StageState::Succeeded.as_str());
+        assert_eq!(virus_scan.attempts, 1);
+        // upload and virus scan
+        assert_eq!(stages.len(), 2);
+        assert!(handled(&running) && handled(&succeeded));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a migrated database at TEST_DATABASE_URL"]
+    async fn type_resolved_delivered_twice_sets_the_type() {
+        bus();
+        let object_name = Uuid::new_v4().to_string();
+
+        deliver_twice(&uploaded(&object_name)).await;
+        let video = VideoData { duration: 12.0, width: 1280, height: 720, bitrate: 2500, frame_rate: 30.0 };
+        let message = deliver_twice(&ResourceStatusUpdate::TypeResolved {
+            object_name: object_name.clone(),
+            resource_type: ResourceType::Video,
+            file_type: Some(FileType::Video { video, audio: None }),
+            revision: 0,
+        }).await;
+
+        let resource = db::get_active_resource_by_id(&object_name).unwrap();
+        assert_eq!(resource.resource_type, ResourceType::Video.as_str());
+        assert_eq!(resource.resource_status, ResourceStatus::Pending.as_str());
+        assert!(handled(&message));
+    }
+}
@@ -1,74 +1,9 @@
 use crate::db;
 
+use messages::ProcessingStage;
 use serde::{Deserialize, Serialize};
 
 
-pub struct ResourceStatusUpdateEvent {
-    pub message: ResourceStatusUpdateMessage,
-    pub receipt_handle: String,
-}
-
-#[derive(Debug, serde::Deserialize)]
-#[serde(tag = "status")]
-pub enum ResourceStatusUpdateMessage {
-    #[serde(rename = "uploaded")]
-    ResourceUploaded {
-        user_id: String,
-        object_name: String,
-        file_name: String,
-    },
-    #[serde(rename = "failed")]
-    ResourceProcessingFailed {
-        object_name: String,
-    },
-    #[serde(rename = "processing")]
-    ResourceProcessingStarted {
-        object_name: String,
-    },
-    #[serde(rename = "type_resolved")]
-    ResourceTypeResolved {
-        object_name: String,
-        resource_type: String,
-    },
-    #[serde(rename = "processed")]
-    ResourceProcessed {
-        object_name: String,
-        metadata: ProducedResourceMetadata,
-    },
-}
-
-#[derive(Debug, serde::Deserialize)]
-#[allow(dead_code)] // Audio, Image variants not used yet
-pub enum ProducedResourceMetadata {
-    Video(Vec<VideoData>),
-    Audio(AudioData),
-    Image(ImageData),
-}
-
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)] // Not used yet
-pub struct AudioData {
-    pub duration: f64, 
-    pub bitrate: u32, 
-    pub sample_rate: u32, // Sample rate in Hz
-}
-
-#[derive(Debug, Deserialize)]
-pub struct VideoData {
-    pub duration: f64, 
-    pub width: u32,   
-    pub height: u32,  
-    pub bitrate: u32, 
-    pub frame_rate: f64, 
-}
-
-#[derive(Debug, Deserialize)]
-#[allow(dead_code)] // Not used yet
-pub struct ImageData {
-    pub width: u32,   // Width in pixels
-    pub height: u32,  // Height in pixels
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Resource {
     pub id: String,
@@ -94,11 +29,136 @@ impl From<db::Resource> for Resource {
 
 
 
+/// The processing pipeline of a resource, with every stage in order. The stages the resource has not reached
+/// yet are `pending`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessingJob {
+    pub resource_id: String,
+    pub status: String,
+    /// The revision the stages are for, 0 for the upload and 1+ for the reprocessing runs
+    pub revision: i32,
+    /// The revision whose outputs are served, `None` until the first outputs are published
+    pub published_revision: Option<i32>,
+    pub stages: Vec<ProcessingJobStage>,
+    /// The first stage that failed, and why
+    pub failure: Option<ProcessingFailure>,
+    /// The status changes of the resource, oldest first
+    pub status_history: Vec<StatusChange>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessingJobStage {
+    pub stage: String,
+    pub state: String,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub started_at: Option<String>,
+    pub finished_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessingFailure {
+    pub stage: String,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StatusChange {
+    /// `None` when the resource was created
+    pub from: Option<String>,
+    pub to: String,
+    pub at: String,
+}
+
+impl From<db::ResourceStatusTransition> for StatusChange {
+    fn from(transition: db::ResourceStatusTransition) -> Self {
+        StatusChange {
+            from: transition.from_status,
+            to: transition.to_status,
+            at: transition.transitioned_at.to_rfc3339(),
+        }
+    }
+}
+
+impl ProcessingJob {
+    pub fn new(
+        resource: &db::Resource,
+        revision: i32,
+        stored_stages: Vec<db::ProcessingJobStage>,
+        transitions: Vec<db::ResourceStatusTransition>,
+    ) -> Self {
+        let stages: Vec<ProcessingJobStage> = ProcessingStage::ALL.iter()
+            .map(|stage| match stored_stages.iter().find(|stored| stored.stage == stage.as_str()) {
+                Some(stored) => ProcessingJobStage {
+                    stage: stored.stage.clone(),
+                    state: stored.stage_state.clone(),
+                    attempts: stored.attempts,
+                    error: stored.error_message.clone(),
+                    started_at: stored.started_at.map(|time| time.to_rfc3339()),
+                    finished_at: stored.finished_at.map(|time| time.to_rfc3339()),
+                },
+                None => ProcessingJobStage {
+                    stage: stage.as_str().to_string(),
+                    state: "pending".to_string(),
+                    attempts: 0,
+                    error: None,
+                    started_at: None,
+                    finished_at: None,
+                },
+            })
+            .collect();
+
+        let failure = stages.iter()
+            .find(|stage| stage.state == "failed")
+            .map(|stage| ProcessingFailure {
+                stage: stage.stage.clone(),
+                reason: stage.error.clone(),
+            });
+
+        ProcessingJob {
+            resource_id: resource.id.to_string(),
+            status: resource.resource_status.clone(),
+            revision,
+            published_revision: resource.published_revision,
+            stages,
+            failure,
+            status_history: transitions.into_iter().map(StatusChange::from).collect(),
+        }
+    }
+}
+
+
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ResourcePublicStatusUpdate {
     pub is_public: bool,
 }
 
+/// How to run the processing pipeline of a resource again
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReprocessRequest {
+    /// `virus_scan`, `metadata` or `transcode`. The earlier stages are not run again.
+    #[serde(default = "ReprocessRequest::default_stage")]
+    pub from_stage: ProcessingStage,
+    /// Only produce these renditions, e.g. `["480p", "720p"]`, instead of every rendition that fits the video
+    #[serde(default)]
+    pub renditions: Option<Vec<String>>,
+}
+
+impl ReprocessRequest {
+    fn default_stage() -> ProcessingStage {
+        ProcessingStage::VirusScan
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReprocessResponse {
+    pub resource_id: String,
+    /// The revision the outputs are produced for, see `/resource/{resource_id}/processing` for its progress
+    pub revision: i32,
+    pub from_stage: ProcessingStage,
+}
+
 
 #[derive(Debug, Clone, Serialize)]
 pub struct OEmbedResponse {
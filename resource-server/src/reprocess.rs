@@ -0,0 +1,303 @@
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use audit::{AuditEvent, AuditEventKind, AuditTarget, ResourceReprocessedDetails, send_audit_event};
+use messages::{FileType, ProcessingRequest, ProcessingRun, ProcessingStage, StageState, UploadFinished, VirusScanClear};
+use queue::QueueError;
+
+use crate::db;
+use crate::model::ReprocessRequest;
+use crate::status::{ReprocessingStart, ResourceStatus};
+use crate::settings::Settings;
+
+
+// where the ingestion service stores the uploads, for the resources created before their path was stored
+const UPLOAD_FOLDER: &str = "upload";
+
+// as long as for the uploads, processing a video can take a while
+const PRESIGNED_URL_EXPIRY: Duration = Duration::from_secs(7 * 60 * 60);
+
+const USAGE: &str = "Usage:
+  resource-server reprocess <resource-id>... [--from <stage>] [--renditions <rendition>,...]
+  resource-server reprocess --all [--from <stage>] [--renditions <rendition>,...]
+
+Runs the processing pipeline of the resources again from the uploaded file, e.g. after the encoding ladder has changed.
+--all reprocesses every resource whose pipeline has finished.
+
+  --from         virus_scan (the default), metadata or transcode
+  --renditions   only produce these renditions, e.g. 480p,720p
+
+The outputs of the earlier revision are served until the new ones are ready.";
+
+
+#[derive(Debug)]
+pub enum ReprocessError {
+    /// Only the virus scan, metadata and transcode stages can be started from
+    InvalidStage(ProcessingStage),
+    /// The renditions were given, but the list is empty
+    NoRenditions,
+    /// The pipeline of the resource has not finished yet
+    Busy(ResourceStatus),
+    /// The file has not passed the virus scan, so the pipeline cannot be started after it
+    NotScanned,
+    /// The media information needed for transcoding was not stored for the resource
+    UnknownMedia,
+    /// Only the videos are transcoded
+    NotVideo,
+    /// The uploaded file is not in the bucket anymore
+    MissingUpload(String),
+    Database(diesel::result::Error),
+    Queue(QueueError),
+}
+
+impl std::fmt::Display for ReprocessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReprocessError::InvalidStage(stage) => write!(f, "Cannot reprocess from the {} stage", stage.as_str()),
+            ReprocessError::NoRenditions => write!(f, "No renditions given"),
+            ReprocessError::Busy(status) => write!(f, "The resource is still {}", status.as_str()),
+            ReprocessError::NotScanned => write!(f, "The file has not passed the virus scan, reprocess from virus_scan"),
+            ReprocessError::UnknownMedia => write!(f, "The media information is not known, reprocess from metadata"),
+            ReprocessError::NotVideo => write!(f, "Only videos can be transcoded"),
+            ReprocessError::MissingUpload(err) => write!(f, "The uploaded file is not available: {}", err),
+            ReprocessError::Database(err) => write!(f, "Database error: {}", err),
+            ReprocessError::Queue(err) => write!(f, "Failed to queue the reprocessing: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ReprocessError {}
+
+impl From<diesel::result::Error> for ReprocessError {
+    fn from(err: diesel::result::Error) -> Self {
+        ReprocessError::Database(err)
+    }
+}
+
+
+/// Runs the processing pipeline of the resource again, from the uploaded file. The pipeline starts from the
+/// requested stage, with a new revision, so the outputs of the earlier revision are served until the new outputs
+/// are published.
+///
+/// # Returns
+/// * The new revision
+pub async fn start(settings: &Settings, resource: &db::Resource, request: &ReprocessRequest) -> Result<i32, ReprocessError> {
+    let resource_id = resource.id.to_string();
+    let from_stage = request.from_stage;
+
+    if !matches!(from_stage, ProcessingStage::VirusScan | ProcessingStage::Metadata | ProcessingStage::Transcode) {
+        return Err(ReprocessError::InvalidStage(from_stage));
+    }
+    if request.renditions.as_ref().is_some_and(|renditions| renditions.is_empty()) {
+        return Err(ReprocessError::NoRenditions);
+    }
+
+    // checked again when the revision is started, this is to fail before touching the bucket
+    let status: ResourceStatus = resource.resource_status.parse().expect("Invalid resource status in the database");
+    if !status.can_reprocess() {
+        return Err(ReprocessError::Busy(status));
+    }
+    if from_stage != ProcessingStage::VirusScan && !passed_virus_scan(&resource_id, status) {
+        return Err(ReprocessError::NotScanned);
+    }
+
+    let origin = db::get_resource_origin(&resource_id)?;
+    let file_type = if from_stage == ProcessingStage::Transcode {
+        let file_type: FileType = origin.file_type
+            .and_then(|file_type| serde_json::from_value(file_type).ok())
+            .ok_or(ReprocessError::UnknownMedia)?;
+        if !matches!(file_type, FileType::Video { .. }) {
+            return Err(ReprocessError::NotVideo);
+        }
+        Some(file_type)
+    } else {
+        None
+    };
+
+    let origin_file_path = origin.origin_file_path
+        .unwrap_or_else(|| format!("{}/{}", UPLOAD_FOLDER, resource_id));
+    let (presigned_url, file_size) = presign_upload(settings, &origin_file_path).await?;
+
+    let stages: Vec<&str> = ProcessingStage::ALL.iter()
+        .skip_while(|stage| **stage != from_stage)
+        .map(|stage| stage.as_str())
+        .collect();
+
+    let revision = match db::start_reprocessing(&resource_id, &stages)? {
+        ReprocessingStart::Started { revision } => revision,
+        ReprocessingStart::Rejected { from } => return Err(ReprocessError::Busy(from)),
+    };
+
+    let run = ProcessingRun {
+        revision: revision as u32,
+        renditions: request.renditions.clone(),
+    };
+
+    // the message each stage is started by
+    let (queue, body) = match file_type {
+        Some(file_type) => (&settings.video_processing_queue, messages::encode(&ProcessingRequest {
+            presigned_url,
+            object_name: resource_id.clone(),
+            file_type,
+            run,
+        })),
+        None if from_stage == ProcessingStage::Metadata => (&settings.virus_scan_queue, messages::encode(&VirusScanClear {
+            presigned_url,
+            object_name: resource_id.clone(),
+            run,
+        })),
+        None => (&settings.upload_queue, messages::encode(&UploadFinished {
+            presigned_url,
+            file_size,
+            object_name: resource_id.clone(),
+            run,
+        })),
+    };
+
+    if let Err(err) = queue.send(&body).await {
+        let err = ReprocessError::Queue(err);
+        fail_revision(resource, from_stage, revision, &err.to_string());
+        return Err(err);
+    }
+
+    tracing::info!("Reprocessing resource {} from {} as revision {}", resource_id, from_stage.as_str(), revision);
+    Ok(revision)
+}
+
+/// The virus scan passed, or was skipped, for the file. The resources processed before the stages were tracked
+/// have passed it if they were processed.
+fn passed_virus_scan(resource_id: &str, status: ResourceStatus) -> bool {
+    let stages = db::get_processing_job_stages(resource_id);
+
+    match stages.iter().find(|stage| stage.stage == ProcessingStage::VirusScan.as_str()) {
+        Some(stage) => stage.stage_state == StageState::Succeeded.as_str() || stage.stage_state == StageState::Skipped.as_str(),
+        None => status == ResourceStatus::Processed,
+    }
+}
+
+/// # Returns
+/// * A presigned URL of the uploaded file, for the pipeline to download it, and the size of the file
+async fn presign_upload(settings: &Settings, origin_file_path: &str) -> Result<(String, usize), ReprocessError> {
+    let head = settings.storage.head(origin_file_path)
+        .await
+        .map_err(|err| ReprocessError::MissingUpload(err.to_string()))?;
+
+    let presigned_uri = settings.storage.presign_get(origin_file_path, PRESIGNED_URL_EXPIRY)
+        .await
+        .map_err(|err| ReprocessError::MissingUpload(err.to_string()))?;
+
+    Ok((presigned_uri, head.size as usize))
+}
+
+// the revision was started, but its pipeline was not, so the resource would stay in processing
+fn fail_revision(resource: &db::Resource, from_stage: ProcessingStage, revision: i32, error: &str) {
+    let resource_id = resource.id.to_string();
+
+    db::update_processing_stage(&resource_id, from_stage.as_str(), StageState::Failed.as_str(), 1, Some(error.to_string()), None)
+        .unwrap_or_else(|err| tracing::error!("Failed to record the failed reprocessing of {}: {}", resource_id, err));
+
+    let status = ResourceStatus::after_failure(resource.published_revision);
+    if let Err(err) = db::transition_resource_status(&resource_id, status, revision, None) {
+        tracing::error!("Failed to restore the status of {}: {}", resource_id, err);
+    }
+}
+
+
+pub async fn send_reprocessed_audit_event(
+    resource_id: &str,
+    request: &ReprocessRequest,
+    revision: i32,
+    user_id: Option<&str>,
+    client_ip: &str,
+) {
+    send_audit_event(AuditEvent::new(
+        AuditEventKind::ResourceReprocessed(ResourceReprocessedDetails {
+            from_stage: request.from_stage.as_str().to_string(),
+            revision: revision as u32,
+            renditions: request.renditions.clone(),
+        }),
+        user_id,
+        client_ip,
+        Some(AuditTarget::resource(resource_id)),
+    )).await.unwrap_or_else(|err| {
+        tracing::error!("Failed to send audit event: {}", err);
+    });
+}
+
+
+/// `resource-server reprocess ...`, for the admins, see `USAGE`. Run in the container of the service, as it needs
+/// the same environment.
+///
+/// # Returns
+/// * The exit code
+pub async fn run_command(settings: &Settings, args: &[String]) -> i32 {
+    let Some((command, args)) = args.split_first() else {
+        return usage();
+    };
+    if command != "reprocess" {
+        return usage();
+    }
+
+    let mut resource_ids = vec![];
+    let mut all = false;
+    let mut request = ReprocessRequest { from_stage: ProcessingStage::VirusScan, renditions: None };
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--all" => all = true,
+            "--from" => {
+                let stage = args.next()
+                    .and_then(|name| ProcessingStage::ALL.into_iter().find(|stage| stage.as_str() == name));
+                match stage {
+                    Some(stage) => request.from_stage = stage,
+                    None => return usage(),
+                }
+            }
+            "--renditions" => match args.next() {
+                Some(renditions) => request.renditions = Some(renditions.split(',').map(|name| name.trim().to_string()).collect()),
+                None => return usage(),
+            },
+            resource_id if !resource_id.starts_with("--") => resource_ids.push(resource_id.to_string()),
+            _ => return usage(),
+        }
+    }
+
+    // either the resource ids or --all
+    if all != resource_ids.is_empty() {
+        return usage();
+    }
+    if all {
+        resource_ids = db::get_reprocessable_resource_ids().iter().map(|id| id.to_string()).collect();
+    }
+
+    let mut failures = 0;
+    for resource_id in &resource_ids {
+        let resource = Uuid::parse_str(resource_id).ok().and_then(|_| db::get_active_resource_by_id(resource_id));
+        let Some(resource) = resource else {
+            eprintln!("{}: no such resource", resource_id);
+            failures += 1;
+            continue;
+        };
+
+        match start(settings, &resource, &request).await {
+            Ok(revision) => {
+                send_reprocessed_audit_event(resource_id, &request, revision, None, "N/A (command line)").await;
+                println!("{}: reprocessing as revision {}", resource_id, revision);
+            }
+            Err(err) => {
+                eprintln!("{}: {}", resource_id, err);
+                failures += 1;
+            }
+        }
+    }
+
+    println!("Reprocessing {} of {} resources", resource_ids.len() - failures, resource_ids.len());
+    if failures > 0 { 1 } else { 0 }
+}
+
+fn usage() -> i32 {
+    eprintln!("{}", USAGE);
+    2
+}
@@ -0,0 +1,88 @@
+use std::sync::Arc;
+
+use axum_client_ip::ClientIpSource;
+
+use audit::AuditSettings;
+use auth_check::UserAuth;
+use config::{Secret, Source};
+use events::BusSettings;
+use queue::{MessageQueue, QueueSettings};
+use storage::ObjectStore;
+use worker::WorkerSettings;
+
+
+/// The settings of the resource server, loaded once at startup and shared with the handlers.
+#[derive(Debug)]
+pub struct Settings {
+    pub port: u16,
+    pub database_url: Secret<String>,
+    /// Where the uploads and the processed outputs are stored, see `storage::store_from_source`
+    pub storage: Arc<dyn ObjectStore>,
+    /// Receives the status updates of the processing services
+    pub resource_status_worker: WorkerSettings,
+    /// Where the reprocessing starts, depending on the stage it starts from, see `reprocess::start`
+    pub upload_queue: Arc<dyn MessageQueue>,
+    pub virus_scan_queue: Arc<dyn MessageQueue>,
+    pub video_processing_queue: Arc<dyn MessageQueue>,
+    /// Where the changes of the resources are published, see `events::start`
+    pub event_bus: BusSettings,
+    /// Where the audit events are sent, see `audit::start`
+    pub audit: AuditSettings,
+    /// The public URL of the site, the oEmbed responses link to it
+    pub domain_url: String,
+    /// Can reprocess any resource, from the comma separated `ADMIN_USER_IDS`
+    pub admin_user_ids: Vec<String>,
+    /// How many megabytes can be served per day, from `DAILY_DATA_QUOTA_MEGABYTES` if `ENABLE_DATA_QUOTAS` is
+    /// `true`, or unlimited
+    pub daily_data_quota_megabytes: Option<i64>,
+    /// Where the client IP is taken from: `nginx` (the default), `amazon` or `cloudflare`
+    pub ip_source: ClientIpSource,
+    /// Verifies the users with the auth service, and the service tokens of the internal endpoints
+    pub auth: Arc<UserAuth>,
+}
+
+impl Settings {
+    /// Loads the settings from the environment and the optional `CONFIG_FILE`.
+    ///
+    /// Exits the process with every missing or invalid setting listed, if there are any.
+    pub fn load() -> Self {
+        config::load_or_exit(|source| {
+            let quotas_enabled = source.flag("ENABLE_DATA_QUOTAS", false);
+            let daily_data_quota_megabytes = source.with_default("DAILY_DATA_QUOTA_MEGABYTES", 1024);
+            if daily_data_quota_megabytes < 0 {
+                source.invalid("DAILY_DATA_QUOTA_MEGABYTES", "must not be negative");
+            }
+
+            Settings {
+                port: source.with_default("PORT", 3000),
+                database_url: source.secret("DATABASE_URL"),
+                storage: storage::store_from_source(source),
+                resource_status_worker: WorkerSettings::from_source(source, "RESOURCE_STATUS_QUEUE_URL", "RESOURCE_STATUS_DLQ_URL"),
+                upload_queue: QueueSettings::from_source(source, "UPLOAD_QUEUE_URL").open(),
+                virus_scan_queue: QueueSettings::from_source(source, "VIRUS_SCAN_QUEUE_URL").open(),
+                video_processing_queue: QueueSettings::from_source(source, "VIDEO_PROCESSING_QUEUE_URL").open(),
+                event_bus: BusSettings::from_source(source),
+                audit: AuditSettings::from_source(source),
+                domain_url: source.required("DOMAIN_URL"),
+                admin_user_ids: source.list("ADMIN_USER_IDS"),
+                daily_data_quota_megabytes: quotas_enabled.then_some(daily_data_quota_megabytes),
+                ip_source: ip_source(source),
+                auth: Arc::new(UserAuth::from_source(source)),
+            }
+        })
+    }
+
+    pub fn is_admin(&self, user_id: &str) -> bool {
+        self.admin_user_ids.iter().any(|admin| admin == user_id)
+    }
+}
+
+
+fn ip_source(source: &mut Source) -> ClientIpSource {
+    source.parsed_with("IP_SOURCE", ClientIpSource::RightmostXForwardedFor, |value| match value {
+        "nginx" => Ok(ClientIpSource::RightmostXForwardedFor),
+        "amazon" => Ok(ClientIpSource::CloudFrontViewerAddress),
+        "cloudflare" => Ok(ClientIpSource::CfConnectingIp),
+        _ => Err("expected nginx, amazon or cloudflare".to_string()),
+    })
+}
@@ -0,0 +1,146 @@
+use std::str::FromStr;
+
+
+/// The lifecycle of a resource, as stored in `app_resource.resource_status`.
+///
+/// ```text
+/// pending ──> processing ──> processed
+///    │             │
+///    └─────────────┴──> failed
+/// ```
+///
+/// The status updates come from several services through a queue, so they can arrive late or out of order.
+/// A transition that is not allowed, e.g. a late `processing` after `processed`, is not applied.
+///
+/// A `processed` or `failed` resource can be reprocessed, which moves it back to `processing` for a new revision,
+/// see `db::start_reprocessing`. The status updates of the earlier revisions are not applied after that.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceStatus {
+    Pending,
+    Processing,
+    Processed,
+    Failed,
+}
+
+impl ResourceStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ResourceStatus::Pending => "pending",
+            ResourceStatus::Processing => "processing",
+            ResourceStatus::Processed => "processed",
+            ResourceStatus::Failed => "failed",
+        }
+    }
+
+    pub fn can_transition_to(&self, next: ResourceStatus) -> bool {
+        matches!(
+            (self, next),
+            (ResourceStatus::Pending, ResourceStatus::Processing)
+                | (ResourceStatus::Pending, ResourceStatus::Failed)
+                | (ResourceStatus::Processing, ResourceStatus::Processed)
+                | (ResourceStatus::Processing, ResourceStatus::Failed)
+        )
+    }
+
+    /// The pipeline of the resource has finished, so it can be run again
+    pub fn can_reprocess(&self) -> bool {
+        matches!(self, ResourceStatus::Processed | ResourceStatus::Failed)
+    }
+
+    /// The status of a resource whose run of the pipeline failed. A resource with published outputs stays
+    /// `processed`, as the outputs of its earlier revision are still served.
+    pub fn after_failure(published_revision: Option<i32>) -> ResourceStatus {
+        match published_revision {
+            Some(_) => ResourceStatus::Processed,
+            None => ResourceStatus::Failed,
+        }
+    }
+}
+
+impl FromStr for ResourceStatus {
+    type Err = String;
+
+    fn from_str(status: &str) -> Result<Self, Self::Err> {
+        match status {
+            "pending" => Ok(ResourceStatus::Pending),
+            "processing" => Ok(ResourceStatus::Processing),
+            "processed" => Ok(ResourceStatus::Processed),
+            "failed" => Ok(ResourceStatus::Failed),
+            other => Err(format!("Unknown resource status: {}", other)),
+        }
+    }
+}
+
+/// What came of a status update, see `db::transition_resource_status`
+#[derive(Debug, PartialEq, Eq)]
+pub enum StatusTransition {
+    Applied { from: ResourceStatus },
+    /// The resource already had the status, e.g. the update was delivered twice
+    Unchanged,
+    /// The transition is not allowed from the current status
+    Rejected { from: ResourceStatus },
+    /// The resource does not exist, e.g. the update arrived before the upload message that creates it
+    NotFound,
+    /// The update is from an earlier revision, the resource has been reprocessed since
+    Stale,
+}
+
+/// What came of a reprocessing request, see `db::start_reprocessing`
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReprocessingStart {
+    Started { revision: i32 },
+    /// The resource is still being processed
+    Rejected { from: ResourceStatus },
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ResourceStatus::*;
+
+    const ALL: [ResourceStatus; 4] = [Pending, Processing, Processed, Failed];
+
+    #[test]
+    fn allows_only_the_forward_transitions() {
+        let allowed = [
+            (Pending, Processing),
+            (Pending, Failed),
+            (Processing, Processed),
+            (Processing, Failed),
+        ];
+
+        for from in ALL {
+            for to in ALL {
+                assert_eq!(
+                    from.can_transition_to(to),
+                    allowed.contains(&(from, to)),
+                    "{} -> {}", from.as_str(), to.as_str()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn only_the_finished_resources_can_be_reprocessed() {
+        for (status, can_reprocess) in [(Pending, false), (Processing, false), (Processed, true), (Failed, true)] {
+            assert_eq!(status.can_reprocess(), can_reprocess, "{}", status.as_str());
+        }
+    }
+
+    #[test]
+    fn a_failed_run_keeps_the_published_outputs() {
+        for (published_revision, status) in [(None, Failed), (Some(0), Processed), (Some(3), Processed)] {
+            assert_eq!(ResourceStatus::after_failure(published_revision), status, "{:?}", published_revision);
+        }
+    }
+
+    #[test]
+    fn parses_the_stored_statuses() {
+        for status in ALL {
+            assert_eq!(status.as_str().parse::<ResourceStatus>(), Ok(status));
+        }
+        assert_eq!("deleted".parse::<ResourceStatus>(), Err("Unknown resource status: deleted".to_string()));
+    }
+}
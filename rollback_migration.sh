@@ -34,6 +34,9 @@ case "$1" in
   audit)
     ./audit/db/rollback_migration.sh
     ;;
+  webhook)
+    ./webhooks/db/rollback_migration.sh
+    ;;
   *)
     echo "Unknown project: $1"
     exit 1
@@ -1,74 +1,38 @@
+mod settings;
+
 use std::cmp::min;
-use std::env;
 use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use std::process::Stdio;
 
-use aws_sdk_s3::Client as S3Client;
-use aws_sdk_s3::primitives::ByteStream;
-use aws_sdk_s3::types::{CompletedPart, CompletedMultipartUpload};
-use aws_sdk_sqs::Client;
-use tokio::io::AsyncReadExt;
+use async_trait::async_trait;
 use tracing_subscriber::filter;
-use serde_json;
 
 use tokio::io::{AsyncWriteExt, BufReader, AsyncBufReadExt};
 use tokio::select;
 use tokio::process::Command;
 
 use futures_util::StreamExt;
-
-use reqwest;
-
-use shlex;
-
-#[allow(dead_code)]
-struct MetadataEvent {
-    pub message: MetadataMessage,
-    pub receipt_handle: String,
-}
+use tokio_util::io::ReaderStream;
 
 
-#[derive(Debug, serde::Deserialize)]
-#[allow(dead_code)]
-struct MetadataMessage {
-    pub presigned_url: String,
-    pub object_name: String,
-    pub file_type: FileType,
-}
 
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
-#[allow(dead_code)]
-struct AudioData {
-    pub duration: f64, // Duration in seconds
-    pub bitrate: u32, // Bitrate in kbps
-    pub sample_rate: u32, // Sample rate in Hz
-}
-
-#[derive(Debug, serde::Deserialize, serde::Serialize)]
-#[allow(dead_code)]
-struct VideoData {
-    pub duration: f64, // Duration in seconds
-    pub width: u32,   // Width in pixels
-    pub height: u32,  // Height in pixels
-    pub bitrate: u32, // Bitrate in kbps
-    pub frame_rate: f64, // Frame rate in frames per second
-}
+use messages::{
+    AudioData, FileType, ProcessingRequest, ProcessingStage, ProducedResourceMetadata, ResourceStatusUpdate, StageState,
+    VideoData, output_folder,
+};
+use queue::{MessageQueue, QueueError, ReceivedMessage};
+use storage::StorageError;
+use worker::{JobError, JobHandler, Worker};
 
-#[derive(Debug, serde::Deserialize)]
-#[allow(dead_code)]
-enum FileType {
-    Video{ video: VideoData, audio: Option<AudioData>}, 
-}
+use settings::Settings;
 
-// single variant enum - the other transcoding services will
-// have other variants, consumer will handle all variants
-#[derive(Debug, serde::Serialize)]
-enum ProducedResourceMetadata {
-    Video(Vec<VideoData>), 
+struct VideoProcessingHandler {
+    settings: Settings,
 }
 
-#[allow(dead_code)]
 struct TranscodingOptions {
     width: u32,
     height: u32,
@@ -76,8 +40,8 @@ struct TranscodingOptions {
     video_bitrate: u32,
 }
 
-const VIDEO_CODEC: &'static str = "libx264"; 
-const AUDIO_CODEC: &'static str = "aac";
+const VIDEO_CODEC: &str = "libx264"; 
+const AUDIO_CODEC: &str = "aac";
 const SEGMENT_LENGTH_SECONDS: u32 = 5; // Length of each segment in seconds
 
 const AUDIO_BITRATE: u32 = 128*1024;
@@ -117,16 +81,28 @@ const TRANSCODING_OPTIONS_1080P: TranscodingOptions = TranscodingOptions {
     video_bitrate: 8*1024*1024,
 };
 
-const RESOURCE_FOLDER_NAME: &'static str = "resource";
+// every rendition we can produce, smallest first
+const TRANSCODING_LADDER: [&TranscodingOptions; 5] = [
+    &TRANSCODING_OPTIONS_144P,
+    &TRANSCODING_OPTIONS_270P,
+    &TRANSCODING_OPTIONS_480P,
+    &TRANSCODING_OPTIONS_720P,
+    &TRANSCODING_OPTIONS_1080P,
+];
+
+impl TranscodingOptions {
+    /// e.g. "720p", as the renditions are named in the `renditions` of a reprocessing run
+    fn name(&self) -> String {
+        format!("{}p", self.height)
+    }
+}
+
+const RESOURCE_FOLDER_NAME: &str = "resource";
 
 fn get_object_path(object_name: &str) -> String {
     format!("{}/{}", RESOURCE_FOLDER_NAME, object_name)
 }
 
-fn s3_bucket() -> String {
-    env::var("S3_BUCKET_NAME").expect("S3_BUCKET_NAME must be set")
-}
-
 #[tokio::main]
 async fn main() {
     tracing_subscriber::fmt()
@@ -135,92 +111,71 @@ async fn main() {
         .init();
     tracing::info!("Starting video transcoding service");
 
-    let queue_url = env::var("VIDEO_PROCESSING_QUEUE_URL").expect("VIDEO_PROCESSING_QUEUE_URL not set");
-    let client = aws_sdk_sqs::Client::new(&aws_config::load_from_env().await);
+    let settings = Settings::load();
+    tracing::info!("Loaded settings {:?}", settings);
 
-    loop {
-        let video_metadata_opt = receive_video_metadata_event(&client, &queue_url).await
-            .unwrap_or_else(|err| {
-                tracing::error!("Error receiving metadata notification: {}", err);
-                None
-            });
-
-        if let Some(video_metadata) = video_metadata_opt {
-            tracing::info!("Received video metadata for {}: {:?}", video_metadata.message.object_name, video_metadata.message.file_type);
-
-            if let Ok(produced_video_metadatas) = process_video(&video_metadata.message).await {
-                tracing::info!("Video transcoding was completed successfully for {}", video_metadata.message.object_name);
-                queue_resource_processing_completed_event(&video_metadata.message.object_name, ProducedResourceMetadata::Video(produced_video_metadatas)).await;
-            } else {
-                tracing::error!("Video transcoding failed for {}", video_metadata.message.object_name);
-                queue_resource_status_update_event(&video_metadata.message.object_name, "failed").await;
-            }
-            
-            delete_message(&client, &queue_url, &video_metadata.receipt_handle)
-                .await
-                .unwrap_or_else(|err| {
-                    tracing::error!("Failed to delete message: {}", err);
-            });
-            tracing::info!("Message deleted successfully");
-        }
-        // Sleep for a while before checking the queue again
-        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-    }
+    let shutdown = worker::shutdown_token();
 
+    // the worker keeps extending the visibility timeout while the video is transcoded, however long it takes
+    let worker_settings = settings.worker.clone();
+    Worker::new("video-transcoding", worker_settings.queue.open(), VideoProcessingHandler { settings })
+        .with_settings(&worker_settings)
+        .run(shutdown)
+        .await;
 }
 
 
-async fn receive_video_metadata_event(client: &Client, queue_url: &str) -> Result<Option<MetadataEvent>, aws_sdk_sqs::Error> {
-    let rcv_message_output = match client
-        .receive_message()
-        .queue_url(queue_url)
-        .max_number_of_messages(1)
-        .visibility_timeout(3600*6) // video processing can take a long time, so we give it 6 hours
-        .send()
-        .await {
-            Ok(output) => output,
-            Err(err) => {
-                tracing::error!("Failed to receive message from SQS queue: {}", err);
-                return Ok(None);    
-            }
-        };
-
-    
-    for message in rcv_message_output.messages.unwrap_or_default() {
-
-        let body = match message.body {
-            Some(body) => body,
-            None => {
-                tracing::warn!("Received message with no body, skipping.");
-                continue;
-            }
-        };
-
-        let metadata_message: MetadataMessage = match serde_json::from_str(&body){
-            Ok(msg) => msg,
-            Err(err) => {
-                tracing::error!("Failed to parse message body as JSON: {}", err);
-                continue;
-            }
-        };
-
-        return Ok(Some(MetadataEvent {
-            message: metadata_message,
-            receipt_handle: message.receipt_handle.unwrap_or_default(),
-        }));         
+#[async_trait]
+impl JobHandler for VideoProcessingHandler {
+    async fn handle(&self, message: &ReceivedMessage) -> Result<(), JobError> {
+        let processing_request: ProcessingRequest = messages::decode(&message.body)
+            .map_err(|err| JobError::Permanent(format!("Failed to parse processing request: {}", err)))?;
+
+        tracing::info!("Received video metadata for {}: {:?}", processing_request.object_name, processing_request.file_type);
+
+        let attempt = message.receive_count;
+        let revision = processing_request.run.revision;
+        report_transcode_started(&self.settings, &processing_request.object_name, attempt, revision).await;
+
+        let result = process_video(&self.settings, &processing_request).await;
+
+        queue_resource_status_update_event(&self.settings, &ResourceStatusUpdate::StageChanged {
+            object_name: processing_request.object_name.clone(),
+            stage: ProcessingStage::Transcode,
+            state: if result.is_ok() { StageState::Succeeded } else { StageState::Failed },
+            attempt,
+            error: result.as_ref().err().map(|err| err.to_string()),
+            revision,
+        }).await?;
+
+        if let Ok(produced_video_metadatas) = result {
+            tracing::info!("Video transcoding was completed successfully for {}", processing_request.object_name);
+            queue_resource_processing_completed_event(
+                &self.settings,
+                &processing_request.object_name,
+                ProducedResourceMetadata::Video(produced_video_metadatas),
+                revision,
+            ).await?;
+        } else {
+            tracing::error!("Video transcoding failed for {}", processing_request.object_name);
+            queue_resource_status_update_event(&self.settings, &ResourceStatusUpdate::Failed {
+                object_name: processing_request.object_name.clone(),
+                revision,
+            }).await?;
+        }
 
+        Ok(())
     }
-
-    Ok(None)
 }
 
 
 async fn process_video(
-    msg: &MetadataMessage,
+    settings: &Settings,
+    msg: &ProcessingRequest,
 ) -> Result<Vec<VideoData>, &'static str> {
 
-    // create directory at /transcoding<object_name> to store the transcoded files
-    let workdir = format!("/transcoding/{}", msg.object_name);
+    // create directory at <work_dir>/<object_name> to store the transcoded files
+    let workdir = format!("{}/{}", settings.work_dir.display(), msg.object_name);
     std::fs::create_dir_all(&workdir).map_err(|_| "Failed to create work directory")?;
     
     let input_file_path = download_input_file(&msg.presigned_url, &msg.object_name, &workdir).await;
@@ -232,32 +187,32 @@ async fn process_video(
         tracing::info!("Workdir {} deleted", workdir);
     }
 
-    if let Ok(_) = extract_first_frame_as_jpeg(msg, &input_file_path, &workdir).await {
+    if extract_first_frame_as_jpeg(msg, &input_file_path, &workdir).await.is_ok() {
         tracing::info!("First frame extraction completed successfully for {}", msg.object_name);
     } else {
         tracing::error!("First frame extraction failed for {}", msg.object_name);
-        queue_resource_status_update_event(&msg.object_name, "failed").await;
         delete_workdir(&workdir);
         return Err("First frame extraction failed");
     }
 
-    let result = transcode_video(msg, &input_file_path, &workdir).await;
+    let result = transcode_video(settings, msg, &input_file_path, &workdir).await;
 
     if let Err(err) = result {
         tracing::error!("Video transcoding failed for {}: {}", msg.object_name, err);
         delete_workdir(&workdir);
-        return Err("Video transcoding failed");
+        return Err(err);
     }    
-    let res = transfer_files_to_s3(&workdir, &msg.object_name)
+    // each revision has its own folder, so the renditions of the earlier revision are served until these are published
+    let res = transfer_files_to_storage(settings, &workdir, &output_folder(&msg.object_name, msg.run.revision))
         .await
-        .map_err(|_| "Failed to transfer files to S3");
+        .map_err(|_| "Failed to transfer files to the object store");
     
     
     delete_workdir(&workdir);
 
     if res.is_err() {
-        tracing::error!("Failed to transfer files to S3 for {}", msg.object_name);
-        return Err("Failed to transfer files to S3");
+        tracing::error!("Failed to transfer files to the object store for {}", msg.object_name);
+        return Err("Failed to transfer files to the object store");
     }
 
 
@@ -266,7 +221,7 @@ async fn process_video(
 }
 
 async fn extract_first_frame_as_jpeg(
-    msg: &MetadataMessage,
+    msg: &ProcessingRequest,
     input_file_path: &str,
     workdir: &str,
 ) -> Result<(), &'static str> {
@@ -277,7 +232,7 @@ async fn extract_first_frame_as_jpeg(
         output_jpeg=output_jpeg_path
     );
 
-    run_ffmpeg(&workdir, &ffmpeg_str)
+    run_ffmpeg(workdir, &ffmpeg_str, None)
         .await.map_err(|_| "FFMPEG process failed for first frame extraction")?;
 
 
@@ -285,12 +240,16 @@ async fn extract_first_frame_as_jpeg(
 }
 
 async fn transcode_video(
-    msg: &MetadataMessage,
+    settings: &Settings,
+    msg: &ProcessingRequest,
     input_file_path: &str,
     workdir: &str,
 ) -> Result<Vec<VideoData>, &'static str> {
     tracing::info!("Transcoding video: {}", msg.object_name);
-    let FileType::Video { video, audio } = &msg.file_type;
+    let FileType::Video { video, audio } = &msg.file_type else {
+        tracing::error!("{} is not a video: {:?}", msg.object_name, msg.file_type);
+        return Err("Not a video");
+    };
 
     tracing::info!("Video stats: {:?}", video);
     if let Some(audio_data) = audio {
@@ -300,17 +259,22 @@ async fn transcode_video(
     }
 
 
+    let encodings_to_use = select_renditions(video, msg.run.renditions.as_deref())?;
+
     let (ffmpeg_str, video_metadatas) = construct_video_transcoding_options_for_ffmpeg(
+        &encodings_to_use,
         video,
         audio,
-        &input_file_path);
+        input_file_path);
 
-    run_ffmpeg(&workdir, &ffmpeg_str)
+    let mut progress = ProgressReporter::new(settings, &msg.object_name, msg.run.revision, video.duration);
+
+    run_ffmpeg(workdir, &ffmpeg_str, Some(&mut progress))
         .await.map_err(|_| "FFMPEG process failed")?;
 
-    // delete the input file after processing, as we will not need it anymore, and we will upload the transcoded files to S3
+    // delete the input file after processing, as we will not need it anymore, and we will upload the transcoded files to the object store
     // and the presence of this file would force us to filter it out
-    std::fs::remove_file(&input_file_path).expect("Failed to delete input file after processing");
+    std::fs::remove_file(input_file_path).expect("Failed to delete input file after processing");
     tracing::info!("Input file {} deleted after processing", input_file_path);
 
 
@@ -323,6 +287,8 @@ async fn transcode_video(
 /// occasionally fails due to IO errors, and downloading the file first
 /// has been observed to be more reliable.
 /// 
+/// With the local object store the URL is a `file://` URL, see `storage::open_url`.
+/// 
 /// # Arguments
 /// * `presigned_url` - The presigned URL to download the file from
 /// * `object_name` - The name to save the downloaded file as
@@ -332,10 +298,9 @@ async fn download_input_file(presigned_url: &str, object_name: &str, workdir: &s
     let output_path = format!("{}/{}", workdir, object_name);
     let mut output_file = tokio::fs::File::create(&output_path).await.expect("Failed to create output file");
 
-    let mut stream = reqwest::get(presigned_url)
+    let mut stream = storage::open_url(presigned_url)
         .await
-        .expect("Failed to download file")
-        .bytes_stream();
+        .expect("Failed to download file");
 
     while let Some(chunk_result) = stream.next().await {
         let chunk = chunk_result.expect("Failed to read chunk");
@@ -349,6 +314,56 @@ async fn download_input_file(presigned_url: &str, object_name: &str, workdir: &s
 }
 
 
+/// Chooses the renditions to produce for the video. They depend on the input video's width, and can be limited
+/// to the `renditions` requested for the run.
+///
+/// # Arguments
+/// * `video_stats` - Statistics about the video to be transcoded
+/// * `renditions` - The names of the renditions to produce, e.g. "720p", or `None` for all that fit the video
+///
+/// # Returns
+/// * `Err` if a requested rendition does not exist, or none of them fit the video
+fn select_renditions(
+    video_stats: &VideoData,
+    renditions: Option<&[String]>,
+) -> Result<Vec<&'static TranscodingOptions>, &'static str> {
+    let mut encodings_to_use = vec![];
+
+    encodings_to_use.push(&TRANSCODING_OPTIONS_144P);
+
+    if video_stats.width >= TRANSCODING_OPTIONS_270P.width {
+        encodings_to_use.push(&TRANSCODING_OPTIONS_270P);
+    }
+
+    if video_stats.width >= TRANSCODING_OPTIONS_480P.width {
+        encodings_to_use.push(&TRANSCODING_OPTIONS_480P);
+    }
+
+    if video_stats.width >= TRANSCODING_OPTIONS_720P.width {
+        encodings_to_use.push(&TRANSCODING_OPTIONS_720P);
+    }
+
+    if video_stats.width >= TRANSCODING_OPTIONS_1080P.width {
+        encodings_to_use.push(&TRANSCODING_OPTIONS_1080P);
+    }
+
+    if let Some(renditions) = renditions {
+        if !renditions.iter().all(|name| TRANSCODING_LADDER.iter().any(|options| &options.name() == name)) {
+            tracing::error!("Unknown renditions requested: {:?}", renditions);
+            return Err("Unknown rendition requested");
+        }
+
+        encodings_to_use.retain(|options| renditions.contains(&options.name()));
+        if encodings_to_use.is_empty() {
+            tracing::error!("None of the renditions {:?} fit a video of width {}", renditions, video_stats.width);
+            return Err("None of the requested renditions fit the video");
+        }
+    }
+
+    Ok(encodings_to_use)
+}
+
+
 /// Constructs the ffmpeg command line options for transcoding a video file
 /// 
 /// This function generates a ffmpeg command that:
@@ -356,10 +371,9 @@ async fn download_input_file(presigned_url: &str, object_name: &str, workdir: &s
 /// * Applies scaling and frame rate adjustments to each stream
 /// * Maps the video and audio streams to the appropriate codecs and bitrates
 /// * Outputs the transcoded video in HLS format with independent segments
-/// Generated video streams will depend on the input video's width 
-/// 
+///
 /// # Arguments
-/// * `object_name` - The name of the output file (used for naming segments)
+/// * `encodings_to_use` - The renditions to produce, see `select_renditions`
 /// * `video_stats` - Statistics about the video to be transcoded
 /// * `audio_stats` - Optional statistics about the audio stream (if available)
 /// * `input_file` - The path to the input video file
@@ -368,6 +382,7 @@ async fn download_input_file(presigned_url: &str, object_name: &str, workdir: &s
 /// A string containing the ffmpeg command line options for transcoding the video
 /// 
 fn construct_video_transcoding_options_for_ffmpeg(
+    encodings_to_use: &[&TranscodingOptions],
     video_stats: &VideoData,
     audio_stats: &Option<AudioData>,
     input_file: &str,
@@ -375,27 +390,8 @@ fn construct_video_transcoding_options_for_ffmpeg(
 
     let aspect_ration = video_stats.width as f64 / video_stats.height as f64;
 
-    let mut encodings_to_use = vec![];
     let mut video_metadatas = vec![];
 
-    encodings_to_use.push(&TRANSCODING_OPTIONS_144P);
-
-    if video_stats.width >= TRANSCODING_OPTIONS_270P.width {
-        encodings_to_use.push(&TRANSCODING_OPTIONS_270P);
-    }
-
-    if video_stats.width >= TRANSCODING_OPTIONS_480P.width {
-        encodings_to_use.push(&TRANSCODING_OPTIONS_480P);
-    }
-
-    if video_stats.width >= TRANSCODING_OPTIONS_720P.width {
-        encodings_to_use.push(&TRANSCODING_OPTIONS_720P);
-    }
-
-    if video_stats.width >= TRANSCODING_OPTIONS_1080P.width {
-        encodings_to_use.push(&TRANSCODING_OPTIONS_1080P);
-    }
-
 
     let mut filter_strs = vec![];
     let mut map_strings = vec![];
@@ -454,7 +450,7 @@ fn construct_video_transcoding_options_for_ffmpeg(
         if i > 0 {
             stream_map_str += " ";
         }
-        if let Some(_) = audio_stats {
+        if audio_stats.is_some() {
             stream_map_str += &format!("v:{i},a:{i}", i=i);
         } else {
             stream_map_str += &format!("v:{i}", i=i);
@@ -531,8 +527,8 @@ fn construct_transcoding_options_with_parameters(
     );
 
     let video_metadata = VideoData {
-        width: width,
-        height: height,
+        width,
+        height,
         duration: video_stats.duration,
         bitrate: target_video_bitrate,
         frame_rate: target_fps as f64,
@@ -543,19 +539,28 @@ fn construct_transcoding_options_with_parameters(
 }
 
 
-async fn run_ffmpeg(workdir: &str, ffmpeg_string: &str) -> Result<(), io::Error> {
+/// Runs ffmpeg with the given options in the work directory.
+///
+/// # Arguments
+/// * `progress` - If given, ffmpeg writes its progress to stdout instead, and it is reported from there
+async fn run_ffmpeg(workdir: &str, ffmpeg_string: &str, mut progress: Option<&mut ProgressReporter>) -> Result<(), io::Error> {
 
-    let args = shlex::split(ffmpeg_string).expect("Failed to split ffmpeg command string");
+    let mut args = shlex::split(ffmpeg_string).expect("Failed to split ffmpeg command string");
+    if progress.is_some() {
+        args.splice(0..0, ["-progress".to_string(), "pipe:1".to_string()]);
+    }
 
     tracing::info!("Running ffmpeg ");
     let mut child = Command::new("ffmpeg")
         .args(args)
         .current_dir(workdir)
+        // the job is aborted if it does not finish before the shutdown timeout
+        .kill_on_drop(true)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
         .spawn()
-        .expect("Failed to start FFMPEG process");
+        .inspect_err(|err| tracing::error!("Failed to start FFMPEG process: {}", err))?;
 
     let stdout = child.stdout.take().expect("Failed to capture stdout");
     let stderr = child.stderr.take().expect("Failed to capture stderr");
@@ -568,9 +573,10 @@ async fn run_ffmpeg(workdir: &str, ffmpeg_string: &str) -> Result<(), io::Error>
     loop {
         select! {
             line = stdout_reader.next_line() => {
-                match line? {
-                    Some(line) => tracing::info!("{}", line),
-                    None => break, // EOF on stdout
+                match (line?, progress.as_deref_mut()) {
+                    (Some(line), Some(progress)) => progress.update(&line).await,
+                    (Some(line), None) => tracing::info!("{}", line),
+                    (None, _) => break, // EOF on stdout
                 }
             }
             line = stderr_reader.next_line() => {
@@ -586,7 +592,7 @@ async fn run_ffmpeg(workdir: &str, ffmpeg_string: &str) -> Result<(), io::Error>
     if !status.success() {
         let stderr_output = stderr_lines.join("\n");
         tracing::error!("ffmpeg command failed with status: {:?}\nStderr: {}", status, stderr_output);
-        return Err(io::Error::new(io::ErrorKind::Other, "FFMPEG command failed"));
+        return Err(io::Error::other("FFMPEG command failed"));
     }
 
 
@@ -595,32 +601,81 @@ async fn run_ffmpeg(workdir: &str, ffmpeg_string: &str) -> Result<(), io::Error>
 }
 
 
+/// Reports how far the transcoding has got to the resource server, from the `key=value` lines ffmpeg writes with
+/// `-progress`. Reported at most every `TRANSCODE_PROGRESS_INTERVAL_SECONDS` (5 by default), and only when the
+/// percentage has grown.
+struct ProgressReporter {
+    resource_status_queue: Arc<dyn MessageQueue>,
+    object_name: String,
+    revision: u32,
+    duration_us: f64,
+    interval: Duration,
+    reported_percent: u8,
+    reported_at: Option<Instant>,
+}
+
+impl ProgressReporter {
+    fn new(settings: &Settings, object_name: &str, revision: u32, duration_seconds: f64) -> Self {
+        ProgressReporter {
+            resource_status_queue: settings.resource_status_queue.clone(),
+            object_name: object_name.to_string(),
+            revision,
+            duration_us: duration_seconds * 1_000_000.0,
+            interval: Duration::from_secs(settings.progress_interval_seconds),
+            reported_percent: 0,
+            reported_at: None,
+        }
+    }
+
+    /// Handles a line of ffmpeg's progress output. A failure to report the progress is only logged, it does not
+    /// affect the transcoding.
+    async fn update(&mut self, line: &str) {
+        // out_time_ms is in microseconds as well, the older ffmpeg versions only write that one
+        let Some(out_time) = line.strip_prefix("out_time_us=").or_else(|| line.strip_prefix("out_time_ms=")) else {
+            return;
+        };
+        // N/A until the first frame is written
+        let Ok(out_time_us) = out_time.trim().parse::<f64>() else {
+            return;
+        };
+        if self.duration_us <= 0.0 {
+            return;
+        }
+
+        // 100 is left for when the outputs have been uploaded, the transcode stage succeeding says that
+        let percent = (out_time_us / self.duration_us * 100.0).clamp(0.0, 99.0) as u8;
+        if percent <= self.reported_percent || self.reported_at.is_some_and(|at| at.elapsed() < self.interval) {
+            return;
+        }
+
+        self.reported_percent = percent;
+        self.reported_at = Some(Instant::now());
+
+        let json_msg = messages::encode(&ResourceStatusUpdate::TranscodeProgress {
+            object_name: self.object_name.clone(),
+            percent,
+            revision: self.revision,
+        });
+
+        self.resource_status_queue.send(&json_msg).await.unwrap_or_else(|err| {
+            tracing::error!("Failed to report the transcoding progress of {}: {}", self.object_name, err);
+        });
+    }
+}
+
+
 /// Create a foler using the file name (which should be unique) and upload the generated HLS files to this folder
 /// 
 /// # Arguments
 /// * `workdir` - The directory where the transcoded files are stored
-/// * `object_name` - The name of the file to be uploaded (used for naming the S3 object)
+/// * `object_name` - The name of the file to be uploaded (used for naming the objects)
 ///
 /// 
-async fn transfer_files_to_s3(workdir: &str, object_name: &str) -> Result<(), Box<dyn std::error::Error>> {
-    let config = aws_config::load_from_env().await;
-    let s3_client = S3Client::new(&config);
-    tracing::info!("Creating S3 client");
-    let s3_client = if let Ok(var) = env::var("USE_PATH_STYLE_BUCKETS") {
-        if var.to_lowercase() == "true" {
-            let config_builder = s3_client.config().clone().to_builder();
-            S3Client::from_conf(config_builder.force_path_style(true).build())
-        } else {
-            s3_client
-        }
-    } else {
-        s3_client
-    };
-
+async fn transfer_files_to_storage(settings: &Settings, workdir: &str, object_name: &str) -> Result<(), Box<dyn std::error::Error>> {
     let files_to_upload = list_files_for_uploading(workdir).await?;
 
     for file_path in files_to_upload {
-        upload_file(&s3_client, object_name, workdir, file_path).await?;
+        upload_file(settings, object_name, workdir, file_path).await?;
     }
 
 
@@ -648,9 +703,9 @@ async fn list_files_for_uploading(workdir: &str) -> Result<Vec<std::path::PathBu
     Ok(files_to_upload)
 }
 
-async fn upload_file(client: &S3Client, object_name: &str, workdir: &str, path: std::path::PathBuf) -> Result<(), Box<dyn std::error::Error>> {
+async fn upload_file(settings: &Settings, object_name: &str, workdir: &str, path: std::path::PathBuf) -> Result<(), Box<dyn std::error::Error>> {
 
-    // create the S3 object key: Strip the workdir prefix and replace with the file name
+    // create the object key: Strip the workdir prefix and replace with the file name
     // this should result into key like "abcd/master.m3u8"
     let object_name = format!(
         "{}/{}",
@@ -661,142 +716,134 @@ async fn upload_file(client: &S3Client, object_name: &str, workdir: &str, path:
             .expect("Failed to convert path to str")
     );
 
-    tracing::info!("Uploading file {} to S3 with object name: {}", path.display(), object_name);
-
-   
-    let multi_part_upload = client.create_multipart_upload()
-        .bucket(s3_bucket()) 
-        .key(get_object_path(&object_name))
-        .send()
-        .await
-        .expect("Failed to create multipart upload");
-
-    let upload_id = multi_part_upload.upload_id().expect("Upload ID not found");
-
-
-    let mut part_number = 1;
-    let mut completed_parts: Vec<CompletedPart> = Vec::new();
-    
-
-    const CHUNK_SIZE: usize = 5 * 1024 * 1024; // 5 MB, S3 minimum part size
-    const BYTES_TO_READ: usize = 5*1024; // 5 kb
-
-    let mut read_buffer: [u8; BYTES_TO_READ] = [0; BYTES_TO_READ];
-    let mut buffer = vec![];
-
+    tracing::info!("Uploading file {} to the object store with object name: {}", path.display(), object_name);
 
     let file = tokio::fs::File::open(&path).await.expect("Failed to open file for uploading");
-    let mut reader = BufReader::new(file);
-
-    loop {
-        let read_bytes = reader.read(&mut read_buffer).await.expect("Failed to read from file");
-        if read_bytes == 0 {
-            break; // EOF reached
-        }
-
-        buffer.extend_from_slice(&read_buffer[..read_bytes]);
-
-        if buffer.len() > CHUNK_SIZE {
-            upload_chunk(&client, buffer, &object_name, upload_id, part_number, &mut completed_parts).await;
-            buffer = vec![];
-            part_number += 1;
-        }
-    }
-
-    // upload any remaining data in the buffer
-    if !buffer.is_empty() {
-        upload_chunk(&client, buffer, &object_name, upload_id, part_number, &mut completed_parts).await;
-    }
-
-
-    let completed_multipart_upload: CompletedMultipartUpload = CompletedMultipartUpload::builder()
-        .set_parts(Some(completed_parts))
-        .build();
+    let body = ReaderStream::new(file).map(|chunk| chunk.map_err(|err| StorageError::Body(err.to_string())));
 
-    client.complete_multipart_upload()
-        .bucket(s3_bucket())
-        .key(get_object_path(&object_name))
-        .multipart_upload(completed_multipart_upload)
-        .upload_id(upload_id)
-        .send()
-        .await
-        .expect("Failed to complete multipart upload");
+    // uploaded in parts, the renditions can be large
+    settings.storage.put_stream(&get_object_path(&object_name), Box::pin(body)).await?;
 
     Ok(())
 }
-        
-async fn upload_chunk(
-    client: &S3Client,
-    buffer: Vec<u8>,
-    object_name: &str,
-    upload_id: &str,
-    part_number: i32,
-    completed_parts: &mut Vec<CompletedPart>
-) {
-    let bytes = ByteStream::from(buffer);
-    let part: aws_sdk_s3::operation::upload_part::UploadPartOutput = client.upload_part()
-        .bucket(s3_bucket()) 
-        .key(get_object_path(&object_name))
-        .part_number(part_number)
-        .upload_id(upload_id)
-        .body(bytes.into())
-        .send()
-        .await
-        .expect("Failed to upload part");
 
-    completed_parts.push(CompletedPart::builder()
-        .part_number(part_number)
-        .e_tag(part.e_tag().unwrap_or("not set").to_string())
-        .build());
+async fn queue_resource_processing_completed_event(
+    settings: &Settings,
+    object_name: &str,
+    metadata: ProducedResourceMetadata,
+    revision: u32,
+) -> Result<(), QueueError> {
+    let json_msg = messages::encode(&ResourceStatusUpdate::Processed {
+        object_name: object_name.to_string(),
+        metadata,
+        revision,
+    });
+
+    tracing::info!("Sending resource processing completed message {} to the resource status queue", json_msg);
+
+    settings.resource_status_queue.send(&json_msg).await
 }
 
-async fn queue_resource_processing_completed_event(object_name: &str, metadata: ProducedResourceMetadata) {
-    let sqs_client = aws_sdk_sqs::Client::new(&aws_config::load_from_env().await);
-    let queue_url = env::var("RESOURCE_STATUS_QUEUE_URL").expect("RESOURCE_STATUS_QUEUE_URL not set");
+/// Reports that the transcoding started. A failure to report it is only logged, it does not affect the
+/// transcoding.
+async fn report_transcode_started(settings: &Settings, object_name: &str, attempt: u32, revision: u32) {
+    queue_resource_status_update_event(settings, &ResourceStatusUpdate::StageChanged {
+        object_name: object_name.to_string(),
+        stage: ProcessingStage::Transcode,
+        state: StageState::Running,
+        attempt,
+        error: None,
+        revision,
+    }).await.unwrap_or_else(|err| {
+        tracing::error!("Failed to report the transcode stage: {}", err);
+    });
+}
 
-    let json_msg = serde_json::json!({
-        "object_name": object_name,
-        "status": "processed",
-        "metadata": metadata,
-    }).to_string(); 
+async fn queue_resource_status_update_event(settings: &Settings, update: &ResourceStatusUpdate) -> Result<(), QueueError> {
+    let json_msg = messages::encode(update);
 
-    tracing::info!("Sending resource processing completed message {} to SQS queue: {}", json_msg, queue_url);
+    tracing::info!("Sending resource status update message {} to the resource status queue", json_msg);
 
-    sqs_client.send_message()
-        .queue_url(queue_url)
-        .message_body(json_msg)
-        .send()
-        .await
-        .expect("Failed to send resource status update message to SQS");
+    settings.resource_status_queue.send(&json_msg).await
 }
 
-async fn queue_resource_status_update_event(object_name: &str, status: &str) {
-    let sqs_client = aws_sdk_sqs::Client::new(&aws_config::load_from_env().await);
-    let queue_url = env::var("RESOURCE_STATUS_QUEUE_URL").expect("RESOURCE_STATUS_QUEUE_URL not set");
-
-    let json_msg = serde_json::json!({
-        "object_name": object_name,
-        "status": status
-    }).to_string(); 
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use messages::{ProcessingRun, QueueMessage};
+    use queue::{MemoryQueue, QueueSettings};
+    use storage::LocalStore;
+    use worker::{WorkerConfig, WorkerSettings};
+
+    use super::*;
+
+    fn handler(root: &std::path::Path) -> (VideoProcessingHandler, Arc<MemoryQueue>) {
+        let resource_status_queue = Arc::new(MemoryQueue::new());
+        let settings = Settings {
+            worker: WorkerSettings {
+                queue: QueueSettings::Memory { name: "VIDEO_PROCESSING_QUEUE_URL".to_string() },
+                dead_letter_queue: None,
+                config: WorkerConfig::default(),
+            },
+            resource_status_queue: resource_status_queue.clone(),
+            storage: Arc::new(LocalStore::new(root.join("storage"))),
+            progress_interval_seconds: 5,
+            work_dir: root.join("transcoding"),
+        };
+        (VideoProcessingHandler { settings }, resource_status_queue)
+    }
 
-    tracing::info!("Sending resource status update message {} to SQS queue: {}", json_msg, queue_url);
+    fn delivery(message: &ProcessingRequest) -> ReceivedMessage {
+        ReceivedMessage {
+            message_id: "processing-request".to_string(),
+            body: messages::encode(message),
+            receipt_handle: "receipt".to_string(),
+            sent_timestamp: None,
+            receive_count: 1,
+        }
+    }
 
-    sqs_client.send_message()
-        .queue_url(queue_url)
-        .message_body(json_msg)
-        .send()
-        .await
-        .expect("Failed to send resource status update message to SQS");
-}
+    // handles the message twice, as a redelivery by the queue would
+    async fn deliver_twice(handler: &VideoProcessingHandler, message: &ReceivedMessage) {
+        handler.handle(message).await.expect("First delivery failed");
+        handler.handle(message).await.expect("Second delivery failed");
+    }
 
-async fn delete_message(client: &Client, queue_url: &str, receipt_handle: &str) -> Result<(), aws_sdk_sqs::Error> {
-    client
-        .delete_message()
-        .queue_url(queue_url)
-        .receipt_handle(receipt_handle)
-        .send()
-        .await?;
+    // the messages of the first delivery, which the second one must repeat so that the resource server ignores them
+    fn repeated<T: QueueMessage + PartialEq + std::fmt::Debug>(sent: Vec<String>) -> Vec<T> {
+        let mut first: Vec<T> = sent.iter().map(|body| messages::decode(body).unwrap()).collect();
+        let second = first.split_off(first.len() / 2);
+        assert_eq!(first, second);
+        first
+    }
 
-    tracing::info!("Message deleted successfully");
-    Ok(())
-}
\ No newline at end of file
+    #[tokio::test]
+    async fn broken_video_delivered_twice_reports_the_same_failure() {
+        let root: PathBuf = std::env::temp_dir().join(format!("video-transcoding-test-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let input = root.join("upload");
+        std::fs::write(&input, "not a video").unwrap();
+        let (handler, resource_status_queue) = handler(&root);
+
+        let video = VideoData { duration: 12.0, width: 1280, height: 720, bitrate: 2500, frame_rate: 30.0 };
+        let message = delivery(&ProcessingRequest {
+            presigned_url: format!("file://{}", input.display()),
+            object_name: "resource".to_string(),
+            file_type: FileType::Video { video, audio: None },
+            run: ProcessingRun { revision: 1, renditions: None },
+        });
+        deliver_twice(&handler, &message).await;
+
+        // the work directory is cleaned up after each attempt
+        assert!(!root.join("transcoding/resource").exists());
+        std::fs::remove_dir_all(&root).unwrap();
+
+        let updates: Vec<ResourceStatusUpdate> = repeated(resource_status_queue.pending());
+        assert!(matches!(&updates[..], [
+            ResourceStatusUpdate::StageChanged { stage: ProcessingStage::Transcode, state: StageState::Running, revision: 1, .. },
+            ResourceStatusUpdate::StageChanged { stage: ProcessingStage::Transcode, state: StageState::Failed, revision: 1, error: Some(_), .. },
+            ResourceStatusUpdate::Failed { revision: 1, .. },
+        ]), "{:?}", updates);
+    }
+}
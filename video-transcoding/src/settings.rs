@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use queue::{MessageQueue, QueueSettings};
+use storage::ObjectStore;
+use worker::WorkerSettings;
+
+
+/// The settings of the video transcoder, loaded once at startup.
+#[derive(Debug)]
+pub struct Settings {
+    /// Receives the videos to transcode
+    pub worker: WorkerSettings,
+    /// Where the progress and the outputs of the transcoding are reported to the resource server
+    pub resource_status_queue: Arc<dyn MessageQueue>,
+    /// Where the transcoded outputs are stored, see `storage::store_from_source`
+    pub storage: Arc<dyn ObjectStore>,
+    /// How often the transcoding progress is reported at most, in seconds
+    pub progress_interval_seconds: u64,
+    /// Where the videos are downloaded and transcoded, in a directory per video
+    pub work_dir: PathBuf,
+}
+
+impl Settings {
+    /// Loads the settings from the environment and the optional `CONFIG_FILE`.
+    ///
+    /// Exits the process with every missing or invalid setting listed, if there are any.
+    pub fn load() -> Self {
+        config::load_or_exit(|source| Settings {
+            worker: WorkerSettings::from_source(source, "VIDEO_PROCESSING_QUEUE_URL", "VIDEO_PROCESSING_DLQ_URL"),
+            resource_status_queue: QueueSettings::from_source(source, "RESOURCE_STATUS_QUEUE_URL").open(),
+            storage: storage::store_from_source(source),
+            progress_interval_seconds: source.with_default("TRANSCODE_PROGRESS_INTERVAL_SECONDS", 5),
+            work_dir: source.with_default("TRANSCODE_WORK_DIR", PathBuf::from("/transcoding")),
+        })
+    }
+}
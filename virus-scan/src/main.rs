@@ -1,27 +1,22 @@
-use std::env;
+mod settings;
+
 use std::io;
 
-use aws_sdk_sqs::Client;
-use clamav_client;
+use async_trait::async_trait;
 use tracing_subscriber::filter;
-use reqwest;
-use serde_json;
 use futures_util::stream::StreamExt;
 
-use audit::{send_audit_event, AuditEvent};
+use audit::{send_audit_event, AuditEvent, AuditEventKind, AuditTarget, VirusScanDetails};
+use messages::{ProcessingRun, ProcessingStage, ResourceStatusUpdate, StageState, UploadFinished, VirusScanClear};
+use queue::{QueueError, ReceivedMessage};
+use worker::{JobError, JobHandler, Worker};
 
+use settings::Settings;
 
-#[derive(Debug, serde::Deserialize)]
-struct UploadMessage {
-    pub presigned_url: String,
-    pub file_size: usize,
-    pub object_name: String,
- }
 
-struct UploadEvent {
-    pub message: UploadMessage,
-    pub receipt_handle: String,
-} 
+struct UploadHandler {
+    settings: Settings,
+}
 
 
 #[tokio::main]
@@ -36,127 +31,111 @@ async fn main() {
         .init();
 
 
-    let queue_url = env::var("UPLOAD_QUEUE_URL").expect("UPLOAD_QUEUE_URL not set");
-    let client = aws_sdk_sqs::Client::new(&aws_config::load_from_env().await);
+    let settings = Settings::load();
+    tracing::info!("Loaded settings {:?}", settings);
 
-    loop {
-       let upload_event_opt = receive_upload_notification(&client, &queue_url).await
-            .unwrap_or_else(|err| {
-                tracing::error!("Error receiving upload notification: {}", err);
-                None
-            });
+    audit::start(&settings.audit);
 
-        if let Some(upload_event) = upload_event_opt {
-            let max_size_str = env::var("SCAN_MAX_SIZE_MEGABYTES").unwrap_or("100".to_string());
-
-            let max_size = usize::from_str_radix(&max_size_str, 10).unwrap_or(100) * 1024 * 1024; // Convert to bytes
-
-            let mut scan_success = false;
-            if upload_event.message.file_size < max_size {
-
-                tracing::info!("Scanning file: {}", upload_event.message.object_name);
-                if let Ok(_) = scan_file(&upload_event.message.presigned_url, &upload_event.message.object_name).await {
-                    tracing::debug!("File scan completed successfully, no viruses found.");
-                    scan_success = true;
-                    
-                    send_audit_event(AuditEvent {
-                        event_type: "virus_scan".to_string(),
-                        user_id: None,
-                        client_ip: &"N/A (internal service)",
-                        target: Some(&upload_event.message.object_name),
-                        event_details: Some(serde_json::json!({
-                            "status": "clean"
-                        })),
-                    }).await.unwrap_or_else(|err| {
-                        tracing::error!("Failed to send audit event: {}", err);
-                    });
-
-                } else {
-                    tracing::warn!("File scan failed or file is infected with a virus.");
-                }
-            } else {
-                tracing::warn!("File {} size {} exceeds the maximum allowed size of {} bytes, skipping scan.",
-                    upload_event.message.object_name,
-                    upload_event.message.file_size,
-                    max_size
-                );
-                scan_success = true; // Treat as clean if we skip the scan
-
-                send_audit_event(AuditEvent {
-                    event_type: "virus_scan".to_string(),
-                    user_id: None,
-                    client_ip: &"N/A (internal service)",
-                    target: Some(&upload_event.message.object_name),
-                    event_details: Some(serde_json::json!({
-                        "status": "skipped",
-                        "reason": "file size exceeds maximum allowed size",
-                        "file_size": upload_event.message.file_size,
-                        "max_size": max_size,
-                    })),
-                }).await.unwrap_or_else(|err| {
-                    tracing::error!("Failed to send audit event: {}", err);
-                });
-            }
+    let shutdown = worker::shutdown_token();
 
-            if scan_success {
-                tracing::debug!("File scan completed successfully, queuing virus scan completed event.");
-                queue_virus_scan_completed_event(&upload_event.message.presigned_url, &upload_event.message.object_name).await;
-                queue_resource_status_update_event(&upload_event.message.object_name, "processing").await;
-            } else {
-                tracing::error!("File scan failed, queuing resource status update event with status 'failed'.");
-                queue_resource_status_update_event(&upload_event.message.object_name, "failed").await;
-            }
-
-
-            delete_message(&client, &queue_url, &upload_event.receipt_handle)
-                .await
-                .unwrap_or_else(|err| {
-                    tracing::error!("Error deleting message: {}", err);
-                });
-        }
-
-        // Sleep for a while before checking the queue again
-        tokio::time::sleep(std::time::Duration::from_secs(5)).await;
-    }
+    let worker_settings = settings.worker.clone();
+    Worker::new("virus-scan", worker_settings.queue.open(), UploadHandler { settings })
+        .with_settings(&worker_settings)
+        .run(shutdown)
+        .await;
 
+    // the scan results of the last jobs may still be buffered
+    audit::flush().await;
 }
 
 
-async fn receive_upload_notification(client: &Client, queue_url: &str) -> Result<Option<UploadEvent>, aws_sdk_sqs::Error> {
-    let rcv_message_output = client
-        .receive_message()
-        .queue_url(queue_url)
-        .max_number_of_messages(1)
-        .send()
-        .await?;
-
-    
-    for message in rcv_message_output.messages.unwrap_or_default() {
-
-        let body = match message.body {
-            Some(body) => body,
-            None => {
-                tracing::warn!("Received message with no body, skipping.");
-                continue;
-            }
-        };
+#[async_trait]
+impl JobHandler for UploadHandler {
+    async fn handle(&self, message: &ReceivedMessage) -> Result<(), JobError> {
+        let upload_message: UploadFinished = messages::decode(&message.body)
+            .map_err(|err| JobError::Permanent(format!("Failed to parse upload message: {}", err)))?;
+
+        let attempt = message.receive_count;
+        let revision = upload_message.run.revision;
+        report_scan_started(&self.settings, &upload_message.object_name, attempt, revision).await;
+
+        let max_size = self.settings.max_scan_size_megabytes * 1024 * 1024; // Convert to bytes
+
+        let mut scan_success = false;
+        let mut scan_state = StageState::Failed;
+        let mut scan_error = None;
+        if upload_message.file_size < max_size {
+
+            tracing::info!("Scanning file: {}", upload_message.object_name);
+            let scan_result = scan_file(&upload_message.presigned_url, &upload_message.object_name).await
+                .map_err(|err| err.to_string());
+            if scan_result.is_ok() {
+                tracing::debug!("File scan completed successfully, no viruses found.");
+                scan_success = true;
+                scan_state = StageState::Succeeded;
+                
+                send_audit_event(AuditEvent::new(
+                    AuditEventKind::VirusScan(VirusScanDetails::Clean),
+                    None,
+                    "N/A (internal service)",
+                    Some(AuditTarget::resource(&upload_message.object_name)),
+                )).await.unwrap_or_else(|err| {
+                    tracing::error!("Failed to send audit event: {}", err);
+                });
 
-        let upload_message: UploadMessage = match serde_json::from_str(&body){
-            Ok(msg) => msg,
-            Err(err) => {
-                tracing::error!("Failed to parse message body as JSON: {}", err);
-                continue;
+            } else {
+                tracing::warn!("File scan failed or file is infected with a virus.");
+                scan_error = scan_result.err();
             }
-        };
+        } else {
+            tracing::warn!("File {} size {} exceeds the maximum allowed size of {} bytes, skipping scan.",
+                upload_message.object_name,
+                upload_message.file_size,
+                max_size
+            );
+            scan_success = true; // Treat as clean if we skip the scan
+            scan_state = StageState::Skipped;
+
+            send_audit_event(AuditEvent::new(
+                AuditEventKind::VirusScan(VirusScanDetails::Skipped {
+                    reason: "file size exceeds maximum allowed size".to_string(),
+                    file_size: upload_message.file_size,
+                    max_size,
+                }),
+                None,
+                "N/A (internal service)",
+                Some(AuditTarget::resource(&upload_message.object_name)),
+            )).await.unwrap_or_else(|err| {
+                tracing::error!("Failed to send audit event: {}", err);
+            });
+        }
 
-        return Ok(Some(UploadEvent {
-            message: upload_message,
-            receipt_handle: message.receipt_handle.unwrap_or_default(),
-        }));         
+        queue_resource_status_update_event(&self.settings, &ResourceStatusUpdate::StageChanged {
+            object_name: upload_message.object_name.clone(),
+            stage: ProcessingStage::VirusScan,
+            state: scan_state,
+            attempt,
+            error: scan_error,
+            revision,
+        }).await?;
+
+        if scan_success {
+            tracing::debug!("File scan completed successfully, queuing virus scan completed event.");
+            queue_virus_scan_completed_event(&self.settings, &upload_message.presigned_url, &upload_message.object_name, &upload_message.run).await?;
+            queue_resource_status_update_event(&self.settings, &ResourceStatusUpdate::Processing {
+                object_name: upload_message.object_name.clone(),
+                revision,
+            }).await?;
+        } else {
+            tracing::error!("File scan failed, queuing resource status update event with status 'failed'.");
+            queue_resource_status_update_event(&self.settings, &ResourceStatusUpdate::Failed {
+                object_name: upload_message.object_name.clone(),
+                revision,
+            }).await?;
+        }
 
+        Ok(())
     }
-
-    Ok(None)
 }
 
 /// Scans the file at the given presigned URL using ClamAV.
@@ -168,7 +147,6 @@ async fn receive_upload_notification(client: &Client, queue_url: &str) -> Result
 ///
 async fn scan_file(presigned_url: &str, object_name: &str) -> Result<(), Box<dyn std::error::Error>> {
     
-    let http_client = reqwest::Client::new(); 
     let clamd_tcp = clamav_client::tokio::Tcp{ host_address: "localhost:3310" };
 
     let clamd_available =  clamav_client::tokio::ping(clamd_tcp).await;
@@ -181,14 +159,12 @@ async fn scan_file(presigned_url: &str, object_name: &str) -> Result<(), Box<dyn
         }
     }
 
-    let reqwest_stream = http_client.get(presigned_url)
-        .send()
-        .await?
-        .bytes_stream();
+    // a `file://` URL with the local object store
+    let download_stream = storage::open_url(presigned_url).await?;
 
 
-    let stream = reqwest_stream.map(|result| {
-    result.map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+    let stream = download_stream.map(|result| {
+    result.map_err(io::Error::other)
     });
 
     let scan_response = clamav_client::tokio::scan_stream(stream, clamd_tcp, None).await
@@ -208,23 +184,20 @@ async fn scan_file(presigned_url: &str, object_name: &str) -> Result<(), Box<dyn
     } else {
         tracing::warn!("File is infected with a virus!");
 
-        send_audit_event(AuditEvent {
-            event_type: "virus_scan".to_string(),
-            user_id: None,
-            client_ip: &"N/A (internal service)",
-            target: Some(object_name),
-            event_details: Some(serde_json::json!({
-                "status": "infected",
+        send_audit_event(AuditEvent::new(
+            AuditEventKind::VirusScan(VirusScanDetails::Infected {
                 // in practice this SHOULD be an ASCII so no need to worry about encoding issues,
                 // but just in case of wonkiness we do a lossy conversion
-                "scan_response": String::from_utf8_lossy(&scan_response).to_string(),
-            })),
-        }).await.unwrap_or_else(|err| {
+                scan_response: String::from_utf8_lossy(&scan_response).to_string(),
+            }),
+            None,
+            "N/A (internal service)",
+            Some(AuditTarget::resource(object_name)),
+        )).await.unwrap_or_else(|err| {
             tracing::error!("Failed to send audit event: {}", err);
         });
 
-        return Err(Box::new(std::io::Error::new(
-            std::io::ErrorKind::Other,
+        return Err(Box::new(std::io::Error::other(
             "File is infected with a virus",
         )));
     }
@@ -232,54 +205,130 @@ async fn scan_file(presigned_url: &str, object_name: &str) -> Result<(), Box<dyn
     Ok(())
 }
 
-async fn delete_message(client: &Client, queue_url: &str, receipt_handle: &str) -> Result<(), aws_sdk_sqs::Error> {
-    client
-        .delete_message()
-        .queue_url(queue_url)
-        .receipt_handle(receipt_handle)
-        .send()
-        .await?;
+async fn queue_virus_scan_completed_event(
+    settings: &Settings,
+    presigned_uri: &str,
+    object_name: &str,
+    run: &ProcessingRun,
+) -> Result<(), QueueError> {
+    let json_msg = messages::encode(&VirusScanClear {
+        presigned_url: presigned_uri.to_string(),
+        object_name: object_name.to_string(),
+        run: run.clone(),
+    });
+    
+    tracing::info!("Sending message {} to the virus scan queue", json_msg);
 
-    tracing::info!("Message deleted successfully");
-    Ok(())
+    settings.virus_scan_queue.send(&json_msg).await
 }
 
+/// Reports that the scan started. A failure to report it is only logged, the result of the scan is what matters.
+async fn report_scan_started(settings: &Settings, object_name: &str, attempt: u32, revision: u32) {
+    queue_resource_status_update_event(settings, &ResourceStatusUpdate::StageChanged {
+        object_name: object_name.to_string(),
+        stage: ProcessingStage::VirusScan,
+        state: StageState::Running,
+        attempt,
+        error: None,
+        revision,
+    }).await.unwrap_or_else(|err| {
+        tracing::error!("Failed to report the virus scan stage: {}", err);
+    });
+}
 
-async fn queue_virus_scan_completed_event(presigned_uri: &str, object_name: &str) {
-    let sqs_client = aws_sdk_sqs::Client::new(&aws_config::load_from_env().await);
-    let queue_url = env::var("VIRUS_SCAN_QUEUE_URL").expect("VIRUS_SCAN_QUEUE_URL not set");
+async fn queue_resource_status_update_event(settings: &Settings, update: &ResourceStatusUpdate) -> Result<(), QueueError> {
+    let json_msg = messages::encode(update);
 
+    tracing::info!("Sending resource status update message {} to the resource status queue", json_msg);
 
-    let json_msg = serde_json::json!({
-        "presigned_url": presigned_uri,
-        "object_name": object_name,
-    }).to_string(); 
-    
-    tracing::info!("Sending message {} to SQS queue: {}", json_msg, queue_url);
-
-    sqs_client.send_message()
-        .queue_url(queue_url)
-        .message_body(json_msg)
-        .send()
-        .await
-        .expect("Failed to send message to SQS");
+    settings.resource_status_queue.send(&json_msg).await
 }
 
-async fn queue_resource_status_update_event(object_name: &str, status: &str) {
-    let sqs_client = aws_sdk_sqs::Client::new(&aws_config::load_from_env().await);
-    let queue_url = env::var("RESOURCE_STATUS_QUEUE_URL").expect("RESOURCE_STATUS_QUEUE_URL not set");
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use audit::AuditSettings;
+    use messages::QueueMessage;
+    use queue::{MemoryQueue, QueueSettings};
+    use worker::{WorkerConfig, WorkerSettings};
 
-    let json_msg = serde_json::json!({
-        "object_name": object_name,
-        "status": status,
-    }).to_string(); 
+    use super::*;
 
-    tracing::info!("Sending resource status update message {} to SQS queue: {}", json_msg, queue_url);
+    struct Queues {
+        virus_scan: Arc<MemoryQueue>,
+        resource_status: Arc<MemoryQueue>,
+    }
 
-    sqs_client.send_message()
-        .queue_url(queue_url)
-        .message_body(json_msg)
-        .send()
-        .await
-        .expect("Failed to send resource status update message to SQS");
-}
\ No newline at end of file
+    fn handler(max_scan_size_megabytes: usize) -> (UploadHandler, Queues) {
+        let queues = Queues {
+            virus_scan: Arc::new(MemoryQueue::new()),
+            resource_status: Arc::new(MemoryQueue::new()),
+        };
+        let settings = Settings {
+            worker: WorkerSettings {
+                queue: QueueSettings::Memory { name: "UPLOAD_QUEUE_URL".to_string() },
+                dead_letter_queue: None,
+                config: WorkerConfig::default(),
+            },
+            virus_scan_queue: queues.virus_scan.clone(),
+            resource_status_queue: queues.resource_status.clone(),
+            audit: AuditSettings::default(),
+            max_scan_size_megabytes,
+        };
+        (UploadHandler { settings }, queues)
+    }
+
+    fn delivery(message: &UploadFinished) -> ReceivedMessage {
+        ReceivedMessage {
+            message_id: "upload-finished".to_string(),
+            body: messages::encode(message),
+            receipt_handle: "receipt".to_string(),
+            sent_timestamp: None,
+            receive_count: 1,
+        }
+    }
+
+    // handles the message twice, as a redelivery by the queue would
+    async fn deliver_twice(handler: &UploadHandler, message: &ReceivedMessage) {
+        handler.handle(message).await.expect("First delivery failed");
+        handler.handle(message).await.expect("Second delivery failed");
+    }
+
+    // the messages of the first delivery, which the second one must repeat so that the consumers ignore them
+    fn repeated<T: QueueMessage + PartialEq + std::fmt::Debug>(sent: Vec<String>) -> Vec<T> {
+        let mut first: Vec<T> = sent.iter().map(|body| messages::decode(body).unwrap()).collect();
+        let second = first.split_off(first.len() / 2);
+        assert_eq!(first, second);
+        first
+    }
+
+    #[tokio::test]
+    async fn skipped_scan_delivered_twice_passes_the_file_on_the_same_way() {
+        let (handler, queues) = handler(1);
+        let run = ProcessingRun { revision: 2, renditions: None };
+
+        // too large to scan, so no ClamAV is needed
+        let message = delivery(&UploadFinished {
+            presigned_url: "file:///uploads/resource".to_string(),
+            file_size: 2 * 1024 * 1024,
+            object_name: "resource".to_string(),
+            run: run.clone(),
+        });
+        deliver_twice(&handler, &message).await;
+
+        let cleared: Vec<VirusScanClear> = repeated(queues.virus_scan.pending());
+        assert_eq!(cleared, [VirusScanClear {
+            presigned_url: "file:///uploads/resource".to_string(),
+            object_name: "resource".to_string(),
+            run,
+        }]);
+
+        let updates: Vec<ResourceStatusUpdate> = repeated(queues.resource_status.pending());
+        assert!(matches!(&updates[..], [
+            ResourceStatusUpdate::StageChanged { stage: ProcessingStage::VirusScan, state: StageState::Running, revision: 2, .. },
+            ResourceStatusUpdate::StageChanged { stage: ProcessingStage::VirusScan, state: StageState::Skipped, revision: 2, .. },
+            ResourceStatusUpdate::Processing { revision: 2, .. },
+        ]), "{:?}", updates);
+    }
+}
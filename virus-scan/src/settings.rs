@@ -0,0 +1,36 @@
+use std::sync::Arc;
+
+use audit::AuditSettings;
+use queue::{MessageQueue, QueueSettings};
+use worker::WorkerSettings;
+
+
+/// The settings of the virus scanner, loaded once at startup.
+#[derive(Debug)]
+pub struct Settings {
+    /// Receives the uploaded files to scan
+    pub worker: WorkerSettings,
+    /// Where the clean files are sent for the metadata extraction
+    pub virus_scan_queue: Arc<dyn MessageQueue>,
+    /// Where the scan results are reported to the resource server
+    pub resource_status_queue: Arc<dyn MessageQueue>,
+    /// Where the infected files are reported, see `audit::start`
+    pub audit: AuditSettings,
+    /// Larger files are not scanned, and treated as clean
+    pub max_scan_size_megabytes: usize,
+}
+
+impl Settings {
+    /// Loads the settings from the environment and the optional `CONFIG_FILE`.
+    ///
+    /// Exits the process with every missing or invalid setting listed, if there are any.
+    pub fn load() -> Self {
+        config::load_or_exit(|source| Settings {
+            worker: WorkerSettings::from_source(source, "UPLOAD_QUEUE_URL", "UPLOAD_DLQ_URL"),
+            virus_scan_queue: QueueSettings::from_source(source, "VIRUS_SCAN_QUEUE_URL").open(),
+            resource_status_queue: QueueSettings::from_source(source, "RESOURCE_STATUS_QUEUE_URL").open(),
+            audit: AuditSettings::from_source(source),
+            max_scan_size_megabytes: source.with_default("SCAN_MAX_SIZE_MEGABYTES", 100),
+        })
+    }
+}
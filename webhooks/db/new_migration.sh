@@ -0,0 +1,7 @@
+#!/bin/bash
+
+set -ueo pipefail
+
+migration_name="$1"
+
+migrate create -ext sql -dir webhook -seq "$migration_name"
\ No newline at end of file
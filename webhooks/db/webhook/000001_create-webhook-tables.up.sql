@@ -0,0 +1,64 @@
+-- the HTTPS endpoints the users have registered for the domain events of their resources
+CREATE TABLE webhook_endpoint (
+    id UUID PRIMARY KEY,
+    user_id UUID NOT NULL,
+    url VARCHAR(2048) NOT NULL,
+    -- the deliveries are signed with it, so it is stored as is
+    secret VARCHAR(256) NOT NULL,
+    -- NULL subscribes to every event type, including the ones added later
+    event_types TEXT[],
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX webhook_endpoint_user_id_idx ON webhook_endpoint (user_id);
+
+-- an event to be delivered to an endpoint, and how its delivery went
+CREATE TABLE webhook_delivery (
+    id BIGSERIAL PRIMARY KEY,
+    endpoint_id UUID NOT NULL REFERENCES webhook_endpoint(id) ON DELETE CASCADE,
+    event_id VARCHAR(255) NOT NULL,
+    event_type VARCHAR(64) NOT NULL,
+    -- the exact body that is signed and sent, so that the retries and replays send the same bytes
+    payload TEXT NOT NULL,
+    delivery_status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- when the pending delivery is attempted next
+    next_attempt_at TIMESTAMPTZ,
+    -- the delivery this one replays. Not a foreign key, so that the original can expire before the replay.
+    replay_of BIGINT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- the events are delivered once to each endpoint, even when the bus delivers them twice
+CREATE UNIQUE INDEX webhook_delivery_event_idx ON webhook_delivery (endpoint_id, event_id) WHERE replay_of IS NULL;
+CREATE INDEX webhook_delivery_endpoint_id_idx ON webhook_delivery (endpoint_id, id);
+CREATE INDEX webhook_delivery_due_idx ON webhook_delivery (next_attempt_at) WHERE delivery_status = 'pending';
+CREATE INDEX webhook_delivery_created_at_idx ON webhook_delivery (created_at);
+
+CREATE TABLE webhook_delivery_attempt (
+    id BIGSERIAL PRIMARY KEY,
+    delivery_id BIGINT NOT NULL REFERENCES webhook_delivery(id) ON DELETE CASCADE,
+    attempt INTEGER NOT NULL,
+    -- NULL when no response was received
+    response_status INTEGER,
+    -- the start of the response body, for debugging the endpoint
+    response_body TEXT,
+    error_message TEXT,
+    duration_ms INTEGER NOT NULL,
+    attempted_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX webhook_delivery_attempt_delivery_id_idx ON webhook_delivery_attempt (delivery_id);
+
+-- the queue messages each consumer has handled, so that a redelivered event is not handled twice
+CREATE TABLE processed_message (
+    consumer VARCHAR(255) NOT NULL,
+    message_id VARCHAR(255) NOT NULL,
+    processed_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (consumer, message_id)
+);
+
+CREATE INDEX processed_message_processed_at_idx ON processed_message (processed_at);
@@ -0,0 +1,318 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use axum::{
+    extract::{Extension, Json, Path, Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Response},
+};
+use uuid::Uuid;
+
+use auth_check::UserInfo;
+use events::DomainEventKind;
+
+use crate::db::{self, NewWebhookEndpoint, WebhookEndpointChanges};
+use crate::delivery;
+use crate::models::*;
+use crate::settings::Settings;
+
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 100;
+
+const MIN_SECRET_LENGTH: usize = 16;
+const MAX_SECRET_LENGTH: usize = 256;
+
+
+/// Lists the caller's endpoints, oldest first. The secrets are never returned.
+pub async fn list_endpoints(user_info: Extension<UserInfo>) -> Response {
+    let Some(user_id) = user_uuid(&user_info) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    match db::get_endpoints(user_id) {
+        Ok(endpoints) => (StatusCode::OK, Json(endpoints)).into_response(),
+        Err(err) => {
+            tracing::error!("Error querying the webhook endpoints of user {}: {}", user_id, err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Registers an endpoint for the caller. It receives the caller's events from then on.
+///
+/// A user can have at most `WEBHOOK_MAX_ENDPOINTS_PER_USER` endpoints, 10 by default.
+pub async fn create_endpoint(
+    State(settings): State<Arc<Settings>>,
+    user_info: Extension<UserInfo>,
+    Json(request): Json<CreateEndpointRequest>,
+) -> Response {
+    let Some(user_id) = user_uuid(&user_info) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let validated = validate_url(&settings, &request.url)
+        .and_then(|_| validate_secret(&request.secret))
+        .and_then(|_| request.event_types.map(validate_event_types).transpose());
+    let event_types = match validated {
+        Ok(event_types) => event_types,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+
+    let max_endpoints = settings.max_endpoints_per_user;
+
+    match db::count_endpoints(user_id) {
+        Ok(count) if count >= max_endpoints => {
+            return (StatusCode::CONFLICT, format!("A user can have at most {} webhook endpoints", max_endpoints)).into_response();
+        }
+        Ok(_) => {}
+        Err(err) => {
+            tracing::error!("Error counting the webhook endpoints of user {}: {}", user_id, err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    let endpoint = NewWebhookEndpoint {
+        id: Uuid::new_v4(),
+        user_id,
+        url: request.url,
+        secret: request.secret,
+        event_types,
+    };
+
+    match db::insert_endpoint(&endpoint) {
+        Ok(endpoint) => {
+            tracing::info!("User {} registered webhook endpoint {}", user_id, endpoint.id);
+            (StatusCode::CREATED, Json(endpoint)).into_response()
+        }
+        Err(err) => {
+            tracing::error!("Error inserting a webhook endpoint for user {}: {}", user_id, err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn get_endpoint(user_info: Extension<UserInfo>, Path(endpoint_id): Path<String>) -> Response {
+    let Some(user_id) = user_uuid(&user_info) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    match find_endpoint(user_id, &endpoint_id) {
+        Ok(endpoint) => (StatusCode::OK, Json(endpoint)).into_response(),
+        Err(status) => status.into_response(),
+    }
+}
+
+/// Changes the given fields of the caller's endpoint. A disabled endpoint gets no new deliveries, and its pending
+/// ones wait until it is enabled again.
+pub async fn update_endpoint(
+    State(settings): State<Arc<Settings>>,
+    user_info: Extension<UserInfo>,
+    Path(endpoint_id): Path<String>,
+    Json(request): Json<UpdateEndpointRequest>,
+) -> Response {
+    let Some(user_id) = user_uuid(&user_info) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let Ok(endpoint_id) = Uuid::parse_str(&endpoint_id) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let validated = request.url.as_deref().map(|url| validate_url(&settings, url)).transpose()
+        .and_then(|_| request.secret.as_deref().map(validate_secret).transpose())
+        .and_then(|_| request.event_types.map(|event_types| event_types.map(validate_event_types).transpose()).transpose());
+    let event_types = match validated {
+        Ok(event_types) => event_types,
+        Err(err) => return (StatusCode::BAD_REQUEST, err).into_response(),
+    };
+
+    let changes = WebhookEndpointChanges {
+        url: request.url,
+        secret: request.secret,
+        event_types,
+        enabled: request.enabled,
+    };
+
+    match db::update_endpoint(user_id, endpoint_id, &changes) {
+        Ok(Some(endpoint)) => (StatusCode::OK, Json(endpoint)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            tracing::error!("Error updating webhook endpoint {}: {}", endpoint_id, err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Deletes the caller's endpoint, together with its deliveries
+pub async fn delete_endpoint(user_info: Extension<UserInfo>, Path(endpoint_id): Path<String>) -> StatusCode {
+    let Some(user_id) = user_uuid(&user_info) else {
+        return StatusCode::BAD_REQUEST;
+    };
+
+    let Ok(endpoint_id) = Uuid::parse_str(&endpoint_id) else {
+        return StatusCode::NOT_FOUND;
+    };
+
+    match db::delete_endpoint(user_id, endpoint_id) {
+        Ok(true) => {
+            tracing::info!("User {} deleted webhook endpoint {}", user_id, endpoint_id);
+            StatusCode::NO_CONTENT
+        }
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(err) => {
+            tracing::error!("Error deleting webhook endpoint {}: {}", endpoint_id, err);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Lists the deliveries to the caller's endpoint, newest first.
+///
+/// Pass `next_cursor` as the `cursor` query parameter to get the next page.
+pub async fn list_deliveries(
+    user_info: Extension<UserInfo>,
+    Path(endpoint_id): Path<String>,
+    Query(page): Query<PageQuery>,
+) -> Response {
+    let Some(user_id) = user_uuid(&user_info) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let endpoint = match find_endpoint(user_id, &endpoint_id) {
+        Ok(endpoint) => endpoint,
+        Err(status) => return status.into_response(),
+    };
+
+    let limit = page.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE);
+
+    match db::get_deliveries(endpoint.id, page.cursor, limit) {
+        Ok(deliveries) => {
+            let next_cursor = if deliveries.len() as i64 == limit {
+                deliveries.last().map(|delivery| delivery.id)
+            } else {
+                None
+            };
+
+            (StatusCode::OK, Json(DeliveryPage { deliveries, next_cursor })).into_response()
+        }
+        Err(err) => {
+            tracing::error!("Error querying the deliveries of webhook endpoint {}: {}", endpoint.id, err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Returns a delivery to the caller's endpoint, with its payload and the log of its attempts
+pub async fn get_delivery(
+    user_info: Extension<UserInfo>,
+    Path((endpoint_id, delivery_id)): Path<(String, i64)>,
+) -> Response {
+    let Some(user_id) = user_uuid(&user_info) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let endpoint = match find_endpoint(user_id, &endpoint_id) {
+        Ok(endpoint) => endpoint,
+        Err(status) => return status.into_response(),
+    };
+
+    let details = db::get_delivery(endpoint.id, delivery_id).and_then(|delivery| match delivery {
+        Some((delivery, payload)) => db::get_delivery_attempts(delivery.id).map(|attempt_log| Some(DeliveryDetails {
+            delivery,
+            payload: serde_json::from_str(&payload).unwrap_or(serde_json::Value::String(payload)),
+            attempt_log,
+        })),
+        None => Ok(None),
+    });
+
+    match details {
+        Ok(Some(details)) => (StatusCode::OK, Json(details)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            tracing::error!("Error querying webhook delivery {}: {}", delivery_id, err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Delivers the event of a delivery to the caller's endpoint again, as a new delivery that is attempted right
+/// away. The receiver sees the same event id, so it can recognize the event if it did receive it before.
+pub async fn replay_delivery(
+    user_info: Extension<UserInfo>,
+    Path((endpoint_id, delivery_id)): Path<(String, i64)>,
+) -> Response {
+    let Some(user_id) = user_uuid(&user_info) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+
+    let endpoint = match find_endpoint(user_id, &endpoint_id) {
+        Ok(endpoint) => endpoint,
+        Err(status) => return status.into_response(),
+    };
+
+    if !endpoint.enabled {
+        return (StatusCode::CONFLICT, "The endpoint is disabled").into_response();
+    }
+
+    match db::replay_delivery(endpoint.id, delivery_id) {
+        Ok(Some(replay)) => {
+            tracing::info!("User {} replayed webhook delivery {} as {}", user_id, delivery_id, replay.id);
+            (StatusCode::ACCEPTED, Json(replay)).into_response()
+        }
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(err) => {
+            tracing::error!("Error replaying webhook delivery {}: {}", delivery_id, err);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+
+fn user_uuid(user_info: &UserInfo) -> Option<Uuid> {
+    let user_id = Uuid::parse_str(&user_info.user_id).ok();
+    if user_id.is_none() {
+        tracing::warn!("Invalid user ID {} in the webhook request", user_info.user_id);
+    }
+    user_id
+}
+
+// the endpoints of the other users are not found, the same as the ones that do not exist
+fn find_endpoint(user_id: Uuid, endpoint_id: &str) -> Result<db::WebhookEndpoint, StatusCode> {
+    let endpoint_id = Uuid::parse_str(endpoint_id).map_err(|_| StatusCode::NOT_FOUND)?;
+
+    match db::get_endpoint(user_id, endpoint_id) {
+        Ok(Some(endpoint)) => Ok(endpoint),
+        Ok(None) => Err(StatusCode::NOT_FOUND),
+        Err(err) => {
+            tracing::error!("Error querying webhook endpoint {}: {}", endpoint_id, err);
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+    }
+}
+
+fn validate_url(settings: &Settings, url: &str) -> Result<(), String> {
+    delivery::validate_endpoint_url(url, settings.delivery.allow_unsafe_endpoints).map(|_| ())
+}
+
+fn validate_secret(secret: &str) -> Result<(), String> {
+    let length = secret.chars().count();
+    if !(MIN_SECRET_LENGTH..=MAX_SECRET_LENGTH).contains(&length) {
+        return Err(format!("The secret must be {} to {} characters", MIN_SECRET_LENGTH, MAX_SECRET_LENGTH));
+    }
+    Ok(())
+}
+
+// an empty list would subscribe to nothing, leave the event types out to subscribe to all of them
+fn validate_event_types(event_types: Vec<String>) -> Result<Vec<String>, String> {
+    if event_types.is_empty() {
+        return Err("The event types must not be empty, leave them out to subscribe to every event type".to_string());
+    }
+
+    if let Some(unknown) = event_types.iter().find(|event_type| !DomainEventKind::EVENT_TYPES.contains(&event_type.as_str())) {
+        return Err(format!("Unknown event type {}, expected one of {}", unknown, DomainEventKind::EVENT_TYPES.join(", ")));
+    }
+
+    let mut seen = HashSet::new();
+    Ok(event_types.into_iter().filter(|event_type| seen.insert(event_type.clone())).collect())
+}
@@ -0,0 +1,444 @@
+mod schema;
+
+use std::sync::OnceLock;
+
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool, PooledConnection};
+use diesel::upsert::DecoratableTarget;
+use uuid::Uuid;
+
+use schema::*;
+
+use crate::models::DeliveryStatus;
+
+
+// the queue keeps a message for at most 14 days, so it cannot be delivered again after that
+const PROCESSED_MESSAGE_RETENTION_DAYS: i64 = 14;
+
+static POOL: OnceLock<Pool<ConnectionManager<PgConnection>>> = OnceLock::new();
+
+
+/// An endpoint, without its secret. Only the deliveries use the secret, it is never returned.
+#[derive(Debug, Queryable, Selectable, serde::Serialize)]
+#[diesel(table_name = webhook_endpoint)]
+pub struct WebhookEndpoint {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub url: String,
+    /// `None` for every event type
+    pub event_types: Option<Vec<String>>,
+    pub enabled: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = webhook_endpoint)]
+pub struct NewWebhookEndpoint {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub url: String,
+    pub secret: String,
+    pub event_types: Option<Vec<String>>,
+}
+
+/// The changes to an endpoint, the fields that are `None` are left as they are
+#[derive(Debug, Default, AsChangeset)]
+#[diesel(table_name = webhook_endpoint)]
+pub struct WebhookEndpointChanges {
+    pub url: Option<String>,
+    pub secret: Option<String>,
+    /// `Some(None)` subscribes to every event type
+    pub event_types: Option<Option<Vec<String>>>,
+    pub enabled: Option<bool>,
+}
+
+/// A delivery, without the payload
+#[derive(Debug, Queryable, Selectable, serde::Serialize)]
+#[diesel(table_name = webhook_delivery)]
+pub struct DeliveryRecord {
+    pub id: i64,
+    pub endpoint_id: Uuid,
+    pub event_id: String,
+    pub event_type: String,
+    pub delivery_status: String,
+    pub attempts: i32,
+    pub next_attempt_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub replay_of: Option<i64>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = webhook_delivery)]
+pub struct NewDelivery {
+    pub endpoint_id: Uuid,
+    pub event_id: String,
+    pub event_type: String,
+    pub payload: String,
+    pub next_attempt_at: Option<chrono::DateTime<chrono::Utc>>,
+    pub replay_of: Option<i64>,
+}
+
+#[derive(Debug, Queryable, Selectable, serde::Serialize)]
+#[diesel(table_name = webhook_delivery_attempt)]
+pub struct DeliveryAttemptRecord {
+    pub attempt: i32,
+    pub response_status: Option<i32>,
+    pub response_body: Option<String>,
+    pub error_message: Option<String>,
+    pub duration_ms: i32,
+    pub attempted_at: chrono::DateTime<chrono::Utc>,
+}
+
+#[derive(Debug, Insertable)]
+#[diesel(table_name = webhook_delivery_attempt)]
+pub struct NewDeliveryAttempt {
+    pub delivery_id: i64,
+    pub attempt: i32,
+    pub response_status: Option<i32>,
+    pub response_body: Option<String>,
+    pub error_message: Option<String>,
+    pub duration_ms: i32,
+}
+
+/// A delivery to be attempted, together with where it goes
+#[derive(Debug, Queryable)]
+pub struct DueDelivery {
+    pub id: i64,
+    pub event_type: String,
+    pub payload: String,
+    pub attempts: i32,
+    pub url: String,
+    pub secret: String,
+}
+
+
+pub fn insert_endpoint(endpoint: &NewWebhookEndpoint) -> Result<WebhookEndpoint, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    diesel::insert_into(webhook_endpoint::table)
+        .values(endpoint)
+        .returning(WebhookEndpoint::as_returning())
+        .get_result(&mut conn)
+}
+
+pub fn count_endpoints(user_id: Uuid) -> Result<i64, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    webhook_endpoint::table
+        .filter(webhook_endpoint::user_id.eq(user_id))
+        .count()
+        .get_result(&mut conn)
+}
+
+/// The endpoints of the user, oldest first
+pub fn get_endpoints(user_id: Uuid) -> Result<Vec<WebhookEndpoint>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    webhook_endpoint::table
+        .select(WebhookEndpoint::as_select())
+        .filter(webhook_endpoint::user_id.eq(user_id))
+        .order(webhook_endpoint::created_at.asc())
+        .load(&mut conn)
+}
+
+/// # Returns
+/// * `None` if the endpoint does not exist, or it belongs to another user
+pub fn get_endpoint(user_id: Uuid, endpoint_id: Uuid) -> Result<Option<WebhookEndpoint>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    webhook_endpoint::table
+        .select(WebhookEndpoint::as_select())
+        .filter(webhook_endpoint::id.eq(endpoint_id))
+        .filter(webhook_endpoint::user_id.eq(user_id))
+        .first(&mut conn)
+        .optional()
+}
+
+/// # Returns
+/// * The updated endpoint, `None` if the endpoint does not exist, or it belongs to another user
+pub fn update_endpoint(
+    user_id: Uuid,
+    endpoint_id: Uuid,
+    changes: &WebhookEndpointChanges,
+) -> Result<Option<WebhookEndpoint>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    diesel::update(
+        webhook_endpoint::table
+            .filter(webhook_endpoint::id.eq(endpoint_id))
+            .filter(webhook_endpoint::user_id.eq(user_id))
+    )
+        .set((changes, webhook_endpoint::updated_at.eq(diesel::dsl::now)))
+        .returning(WebhookEndpoint::as_returning())
+        .get_result(&mut conn)
+        .optional()
+}
+
+/// Deletes the endpoint together with its deliveries.
+///
+/// # Returns
+/// * `false` if the endpoint does not exist, or it belongs to another user
+pub fn delete_endpoint(user_id: Uuid, endpoint_id: Uuid) -> Result<bool, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    let deleted = diesel::delete(
+        webhook_endpoint::table
+            .filter(webhook_endpoint::id.eq(endpoint_id))
+            .filter(webhook_endpoint::user_id.eq(user_id))
+    )
+        .execute(&mut conn)?;
+
+    Ok(deleted > 0)
+}
+
+/// The ids of the user's enabled endpoints that subscribe to the event type
+pub fn get_subscribed_endpoint_ids(user_id: Uuid, event_type: &str) -> Result<Vec<Uuid>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    webhook_endpoint::table
+        .select(webhook_endpoint::id)
+        .filter(webhook_endpoint::user_id.eq(user_id))
+        .filter(webhook_endpoint::enabled.eq(true))
+        .filter(
+            webhook_endpoint::event_types.is_null()
+                .or(webhook_endpoint::event_types.contains(vec![event_type.to_string()]))
+        )
+        .load(&mut conn)
+}
+
+
+/// Inserts the deliveries of an event. The ones the event already has, e.g. because the bus delivered the event
+/// twice, are skipped.
+///
+/// # Returns
+/// * The number of deliveries inserted
+pub fn insert_deliveries(deliveries: &[NewDelivery]) -> Result<usize, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    diesel::insert_into(webhook_delivery::table)
+        .values(deliveries)
+        .on_conflict((webhook_delivery::endpoint_id, webhook_delivery::event_id))
+        .filter_target(webhook_delivery::replay_of.is_null())
+        .do_nothing()
+        .execute(&mut conn)
+}
+
+/// Queries the deliveries to the endpoint, newest first.
+///
+/// # Arguments
+/// * `cursor` - If set, only deliveries older than the delivery with this id are returned (the id of the last delivery of the previous page)
+/// * `limit` - The maximum number of deliveries to return
+pub fn get_deliveries(endpoint_id: Uuid, cursor: Option<i64>, limit: i64) -> Result<Vec<DeliveryRecord>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    let mut query = webhook_delivery::table
+        .select(DeliveryRecord::as_select())
+        .filter(webhook_delivery::endpoint_id.eq(endpoint_id))
+        .into_boxed();
+
+    if let Some(cursor) = cursor {
+        query = query.filter(webhook_delivery::id.lt(cursor));
+    }
+
+    query
+        .order(webhook_delivery::id.desc())
+        .limit(limit)
+        .load(&mut conn)
+}
+
+/// # Returns
+/// * The delivery and its payload, `None` if the endpoint has no such delivery
+pub fn get_delivery(endpoint_id: Uuid, delivery_id: i64) -> Result<Option<(DeliveryRecord, String)>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    webhook_delivery::table
+        .select((DeliveryRecord::as_select(), webhook_delivery::payload))
+        .filter(webhook_delivery::id.eq(delivery_id))
+        .filter(webhook_delivery::endpoint_id.eq(endpoint_id))
+        .first(&mut conn)
+        .optional()
+}
+
+/// The attempts of the delivery, in the order they were made
+pub fn get_delivery_attempts(delivery_id: i64) -> Result<Vec<DeliveryAttemptRecord>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    webhook_delivery_attempt::table
+        .select(DeliveryAttemptRecord::as_select())
+        .filter(webhook_delivery_attempt::delivery_id.eq(delivery_id))
+        .order(webhook_delivery_attempt::id.asc())
+        .load(&mut conn)
+}
+
+/// Delivers the event of the delivery again, as a new delivery with the same payload. It is attempted right away.
+///
+/// # Returns
+/// * The new delivery, `None` if the endpoint has no such delivery
+pub fn replay_delivery(endpoint_id: Uuid, delivery_id: i64) -> Result<Option<DeliveryRecord>, diesel::result::Error> {
+    let Some((original, payload)) = get_delivery(endpoint_id, delivery_id)? else {
+        return Ok(None);
+    };
+
+    let mut conn = get_connection();
+
+    diesel::insert_into(webhook_delivery::table)
+        .values(NewDelivery {
+            endpoint_id,
+            event_id: original.event_id,
+            event_type: original.event_type,
+            payload,
+            next_attempt_at: Some(chrono::Utc::now()),
+            replay_of: Some(original.id),
+        })
+        .returning(DeliveryRecord::as_returning())
+        .get_result(&mut conn)
+        .map(Some)
+}
+
+/// Claims up to `limit` pending deliveries that are due, oldest first, skipping the ones of disabled endpoints.
+///
+/// The claimed deliveries are not due again until `lease` has passed, so the other instances leave them alone
+/// while they are attempted. If the instance dies, they are attempted again after that.
+pub fn claim_due_deliveries(limit: i64, lease: chrono::Duration) -> Result<Vec<DueDelivery>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    conn.transaction(|conn| {
+        let now = chrono::Utc::now();
+
+        let ids: Vec<i64> = webhook_delivery::table
+            .select(webhook_delivery::id)
+            .filter(webhook_delivery::delivery_status.eq(DeliveryStatus::Pending.as_str()))
+            .filter(webhook_delivery::next_attempt_at.le(now))
+            .filter(webhook_delivery::endpoint_id.eq_any(
+                webhook_endpoint::table
+                    .select(webhook_endpoint::id)
+                    .filter(webhook_endpoint::enabled.eq(true))
+            ))
+            .order(webhook_delivery::next_attempt_at.asc())
+            .limit(limit)
+            .for_update()
+            .skip_locked()
+            .load(conn)?;
+
+        if ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        diesel::update(webhook_delivery::table.filter(webhook_delivery::id.eq_any(&ids)))
+            .set(webhook_delivery::next_attempt_at.eq(now + lease))
+            .execute(conn)?;
+
+        webhook_delivery::table
+            .inner_join(webhook_endpoint::table)
+            .select((
+                webhook_delivery::id,
+                webhook_delivery::event_type,
+                webhook_delivery::payload,
+                webhook_delivery::attempts,
+                webhook_endpoint::url,
+                webhook_endpoint::secret,
+            ))
+            .filter(webhook_delivery::id.eq_any(&ids))
+            .order(webhook_delivery::next_attempt_at.asc())
+            .load(conn)
+    })
+}
+
+/// Records an attempt of the delivery, and what happens to the delivery next.
+///
+/// # Arguments
+/// * `status` - `pending` if the delivery is attempted again, at `next_attempt_at`
+pub fn record_delivery_attempt(
+    attempt: &NewDeliveryAttempt,
+    status: DeliveryStatus,
+    next_attempt_at: Option<chrono::DateTime<chrono::Utc>>,
+) -> Result<(), diesel::result::Error> {
+    let mut conn = get_connection();
+
+    conn.transaction(|conn| {
+        diesel::insert_into(webhook_delivery_attempt::table)
+            .values(attempt)
+            .execute(conn)?;
+
+        diesel::update(webhook_delivery::table.find(attempt.delivery_id))
+            .set((
+                webhook_delivery::delivery_status.eq(status.as_str()),
+                webhook_delivery::attempts.eq(attempt.attempt),
+                webhook_delivery::next_attempt_at.eq(next_attempt_at),
+                webhook_delivery::updated_at.eq(diesel::dsl::now),
+            ))
+            .execute(conn)?;
+
+        Ok(())
+    })
+}
+
+/// Deletes the deliveries created before `cutoff`, together with their attempts.
+///
+/// # Returns
+/// * The number of deliveries deleted
+pub fn delete_deliveries_before(cutoff: chrono::DateTime<chrono::Utc>) -> Result<usize, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    diesel::delete(webhook_delivery::table.filter(webhook_delivery::created_at.lt(cutoff)))
+        .execute(&mut conn)
+}
+
+
+/// # Returns
+/// * The ids of the given messages that the consumer has already handled
+pub fn get_processed_messages(consumer: &str, message_ids: &[String]) -> Result<Vec<String>, diesel::result::Error> {
+    let mut conn = get_connection();
+
+    processed_message::table
+        .select(processed_message::message_id)
+        .filter(processed_message::consumer.eq(consumer))
+        .filter(processed_message::message_id.eq_any(message_ids))
+        .load(&mut conn)
+}
+
+/// Records the messages as handled by the consumer, and forgets the messages too old to be delivered again.
+pub fn insert_processed_messages(consumer: &str, message_ids: &[String]) -> Result<(), diesel::result::Error> {
+    let mut conn = get_connection();
+
+    let rows: Vec<_> = message_ids.iter()
+        .map(|message_id| (
+            processed_message::consumer.eq(consumer),
+            processed_message::message_id.eq(message_id),
+        ))
+        .collect();
+
+    diesel::insert_into(processed_message::table)
+        .values(&rows)
+        .on_conflict_do_nothing()
+        .execute(&mut conn)?;
+
+    let cutoff = chrono::Utc::now() - chrono::Duration::days(PROCESSED_MESSAGE_RETENTION_DAYS);
+    diesel::delete(processed_message::table.filter(processed_message::processed_at.lt(cutoff)))
+        .execute(&mut conn)?;
+
+    Ok(())
+}
+
+/// Creates the shared pool the functions take their connections from, must be called once at startup.
+///
+/// The connections are made when they are first needed.
+pub fn init(database_url: &str, pool_size: u32) {
+    let pool = Pool::builder()
+        .max_size(pool_size)
+        .build_unchecked(ConnectionManager::new(database_url));
+
+    POOL.set(pool).unwrap_or_else(|_| panic!("The database pool is already created"));
+}
+
+/// Returns a connection from the shared pool.
+///
+/// # Panics
+/// * If `init` was not called, or no connection could be made within the pool timeout
+fn get_connection() -> PooledConnection<ConnectionManager<PgConnection>> {
+    let pool = POOL.get().expect("db::init was not called");
+
+    pool.get().unwrap_or_else(|err| panic!("Error getting a database connection: {}", err))
+}
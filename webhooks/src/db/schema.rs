@@ -0,0 +1,58 @@
+diesel::table! {
+    webhook_endpoint (id) {
+        id -> Uuid,
+        user_id -> Uuid,
+        url -> Varchar,
+        secret -> Varchar,
+        event_types -> Nullable<Array<Text>>,
+        enabled -> Bool,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    webhook_delivery (id) {
+        id -> BigInt,
+        endpoint_id -> Uuid,
+        event_id -> Varchar,
+        event_type -> Varchar,
+        payload -> Text,
+        delivery_status -> Varchar,
+        attempts -> Integer,
+        next_attempt_at -> Nullable<Timestamptz>,
+        replay_of -> Nullable<BigInt>,
+        created_at -> Timestamptz,
+        updated_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    webhook_delivery_attempt (id) {
+        id -> BigInt,
+        delivery_id -> BigInt,
+        attempt -> Integer,
+        response_status -> Nullable<Integer>,
+        response_body -> Nullable<Text>,
+        error_message -> Nullable<Text>,
+        duration_ms -> Integer,
+        attempted_at -> Timestamptz,
+    }
+}
+
+diesel::table! {
+    processed_message (consumer, message_id) {
+        consumer -> Varchar,
+        message_id -> Varchar,
+        processed_at -> Timestamptz,
+    }
+}
+
+diesel::joinable!(webhook_delivery -> webhook_endpoint (endpoint_id));
+diesel::joinable!(webhook_delivery_attempt -> webhook_delivery (delivery_id));
+
+diesel::allow_tables_to_appear_in_same_query!(
+    webhook_endpoint,
+    webhook_delivery,
+    webhook_delivery_attempt,
+);
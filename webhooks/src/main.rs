@@ -0,0 +1,147 @@
+mod api;
+mod db;
+mod delivery;
+mod models;
+mod settings;
+
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use tracing_subscriber::filter;
+
+use axum::{
+    middleware::from_fn_with_state,
+    routing::{get, post},
+    Router
+};
+
+use auth_check::auth_middleware;
+use queue::ReceivedMessage;
+use worker::{CancellationToken, IdempotencyStore, JobError, JobHandler, Worker, WorkerSettings};
+
+
+use models::WebhookPayload;
+use settings::Settings;
+
+
+#[tokio::main]
+async fn main() {
+
+    tracing_subscriber::fmt()
+        .with_level(true)
+        .with_max_level(filter::LevelFilter::INFO)
+        .init();
+
+    let settings = Arc::new(Settings::load());
+    tracing::info!("Loaded settings {:?}", settings);
+
+    db::init(settings.database_url.expose(), settings.database_pool_size);
+
+    let app = Router::new()
+        .route("/webhooks/health", get(|| async { "OK" }))
+        .nest(
+            "/webhooks",
+            Router::new()
+                .route("/endpoints", get(api::list_endpoints).post(api::create_endpoint))
+                .route(
+                    "/endpoints/{endpoint_id}",
+                    get(api::get_endpoint).patch(api::update_endpoint).delete(api::delete_endpoint)
+                )
+                .route("/endpoints/{endpoint_id}/deliveries", get(api::list_deliveries))
+                .route("/endpoints/{endpoint_id}/deliveries/{delivery_id}", get(api::get_delivery))
+                .route("/endpoints/{endpoint_id}/deliveries/{delivery_id}/replay", post(api::replay_delivery))
+                .layer(from_fn_with_state(settings.auth.clone(), auth_middleware))
+        )
+        .with_state(settings.clone());
+
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{}", settings.port)).await
+        .expect("Failed to bind TCP listener");
+
+    let shutdown = worker::shutdown_token();
+
+    tracing::info!("Listening on port {}", settings.port);
+    tokio::join!(
+        domain_event_listener(&settings.worker, shutdown.clone()),
+        delivery::deliver_pending(settings.delivery.clone(), shutdown.clone()),
+        async {
+            axum::serve(listener, app)
+                .with_graceful_shutdown(shutdown.cancelled_owned())
+                .await
+                .expect("Failed to start server");
+        }
+    );
+}
+
+
+/// Receives the domain events the bus routes to the `DOMAIN_EVENT_QUEUE_URL` queue
+async fn domain_event_listener(settings: &WorkerSettings, shutdown: CancellationToken) {
+    Worker::new("webhooks", settings.queue.open(), DomainEventHandler)
+        .with_settings(settings)
+        .with_idempotency_store(Arc::new(DbIdempotencyStore))
+        .run(shutdown)
+        .await;
+}
+
+
+/// Keeps track of the handled queue messages in the webhook database
+struct DbIdempotencyStore;
+
+#[async_trait]
+impl IdempotencyStore for DbIdempotencyStore {
+    async fn handled(&self, consumer: &str, message_ids: &[String]) -> Result<HashSet<String>, String> {
+        db::get_processed_messages(consumer, message_ids)
+            .map(|handled| handled.into_iter().collect())
+            .map_err(|err| err.to_string())
+    }
+
+    async fn mark_handled(&self, consumer: &str, message_ids: &[String]) -> Result<(), String> {
+        db::insert_processed_messages(consumer, message_ids).map_err(|err| err.to_string())
+    }
+}
+
+
+struct DomainEventHandler;
+
+#[async_trait]
+impl JobHandler for DomainEventHandler {
+    /// Creates a delivery of the event for each of the owner's endpoints that subscribe to it, they are then
+    /// attempted by `delivery::deliver_pending`. An event that was already received creates no new deliveries.
+    async fn handle(&self, message: &ReceivedMessage) -> Result<(), JobError> {
+        let event = events::decode_eventbridge_message(&message.body)
+            .map_err(|err| JobError::Permanent(err.to_string()))?;
+
+        let user_id = uuid::Uuid::parse_str(&event.user_id)
+            .map_err(|_| JobError::Permanent(format!("Invalid user ID {} in domain event {}", event.user_id, event.id)))?;
+
+        let event_type = event.kind.event_type();
+
+        let endpoint_ids = db::get_subscribed_endpoint_ids(user_id, event_type)
+            .map_err(|err| JobError::Retryable(format!("Error querying the webhook endpoints of user {}: {}", user_id, err)))?;
+
+        if endpoint_ids.is_empty() {
+            return Ok(());
+        }
+
+        let payload = serde_json::to_string(&WebhookPayload::from(&event)).expect("Failed to serialize webhook payload");
+        let now = chrono::Utc::now();
+
+        let deliveries: Vec<db::NewDelivery> = endpoint_ids.into_iter()
+            .map(|endpoint_id| db::NewDelivery {
+                endpoint_id,
+                event_id: event.id.clone(),
+                event_type: event_type.to_string(),
+                payload: payload.clone(),
+                next_attempt_at: Some(now),
+                replay_of: None,
+            })
+            .collect();
+
+        let inserted = db::insert_deliveries(&deliveries)
+            .map_err(|err| JobError::Retryable(format!("Error inserting the deliveries of domain event {}: {}", event.id, err)))?;
+
+        tracing::info!("Created {} webhook deliveries of {} event {}", inserted, event_type, event.id);
+
+        Ok(())
+    }
+}
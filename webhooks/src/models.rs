@@ -0,0 +1,98 @@
+use serde::{Deserialize, Deserializer, Serialize};
+
+use events::{DomainEvent, DomainEventKind};
+
+use crate::db::{DeliveryAttemptRecord, DeliveryRecord};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    /// Not attempted yet, or attempted again at `next_attempt_at`
+    Pending,
+    Succeeded,
+    /// Every attempt failed, it is only attempted again if it is replayed
+    Failed,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeliveryStatus::Pending => "pending",
+            DeliveryStatus::Succeeded => "succeeded",
+            DeliveryStatus::Failed => "failed",
+        }
+    }
+}
+
+
+/// What is delivered to the endpoints: the domain event, without the user it belongs to
+#[derive(Debug, Serialize)]
+pub struct WebhookPayload<'a> {
+    pub id: &'a str,
+    #[serde(flatten)]
+    pub kind: &'a DomainEventKind,
+    pub occurred_at: chrono::DateTime<chrono::Utc>,
+}
+
+impl<'a> From<&'a DomainEvent> for WebhookPayload<'a> {
+    fn from(event: &'a DomainEvent) -> Self {
+        WebhookPayload {
+            id: &event.id,
+            kind: &event.kind,
+            occurred_at: event.occurred_at,
+        }
+    }
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct CreateEndpointRequest {
+    pub url: String,
+    /// The key the deliveries are signed with
+    pub secret: String,
+    /// The event types to deliver, every event type if not given
+    pub event_types: Option<Vec<String>>,
+}
+
+/// The fields that are not given are left as they are
+#[derive(Debug, Deserialize)]
+pub struct UpdateEndpointRequest {
+    pub url: Option<String>,
+    pub secret: Option<String>,
+    /// `null` subscribes the endpoint to every event type
+    #[serde(default, deserialize_with = "deserialize_present")]
+    pub event_types: Option<Option<Vec<String>>>,
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PageQuery {
+    pub cursor: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeliveryPage {
+    pub deliveries: Vec<DeliveryRecord>,
+    pub next_cursor: Option<i64>,
+}
+
+/// A delivery with what was delivered, and how each attempt went
+#[derive(Debug, Serialize)]
+pub struct DeliveryDetails {
+    #[serde(flatten)]
+    pub delivery: DeliveryRecord,
+    pub payload: serde_json::Value,
+    pub attempt_log: Vec<DeliveryAttemptRecord>,
+}
+
+
+// tells a field that is `null` apart from one that is missing: `Some(None)` for `null`, and `None`
+// (the default) for missing
+fn deserialize_present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
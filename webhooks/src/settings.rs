@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use auth_check::UserAuth;
+use config::Secret;
+use worker::WorkerSettings;
+
+use crate::delivery::DeliveryConfig;
+
+
+/// The settings of the webhook service, loaded once at startup and shared with the handlers
+#[derive(Debug)]
+pub struct Settings {
+    pub port: u16,
+    pub database_url: Secret<String>,
+    pub database_pool_size: u32,
+    /// How many endpoints a user can register
+    pub max_endpoints_per_user: i64,
+    pub delivery: DeliveryConfig,
+    /// Receives the domain events the bus routes to the webhooks
+    pub worker: WorkerSettings,
+    /// Verifies the users with the auth service
+    pub auth: Arc<UserAuth>,
+}
+
+impl Settings {
+    /// Loads the settings from the environment and the optional `CONFIG_FILE`.
+    ///
+    /// Exits the process with every missing or invalid setting listed, if there are any.
+    pub fn load() -> Self {
+        config::load_or_exit(|source| Settings {
+            port: source.with_default("PORT", 3000),
+            database_url: source.secret("DATABASE_URL"),
+            database_pool_size: source.with_default("DATABASE_POOL_SIZE", 10),
+            max_endpoints_per_user: source.with_default("WEBHOOK_MAX_ENDPOINTS_PER_USER", 10),
+            delivery: DeliveryConfig::from_source(source),
+            worker: WorkerSettings::from_source(source, "DOMAIN_EVENT_QUEUE_URL", "DOMAIN_EVENT_DLQ_URL"),
+            auth: Arc::new(UserAuth::from_source(source)),
+        })
+    }
+}